@@ -0,0 +1,147 @@
+// Rate limiting, lockouts and an access log for endpoints other nodes can call.
+// Sources are locked out after repeated rejections, and so are share link tokens,
+// so guesses spread across many nodes still run out.
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+const WINDOW_SECS: u64 = 60;
+const MAX_REQUESTS_PER_WINDOW: usize = 30;
+const FAILURE_WINDOW_SECS: u64 = 600;
+const MAX_FAILURES: usize = 5; // Rejected attempts within the failure window before a lockout
+const LOCKOUT_SECS: u64 = 900;
+pub const MAX_CODE_GUESSES: u32 = 3; // Wrong guesses before a one-time code is thrown away
+const LOG_CAPACITY: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AccessOutcome {
+    Allowed,
+    Denied,
+    RateLimited,
+    LockedOut,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccessLogEntry {
+    pub timestamp: u64,
+    pub source: String,
+    pub endpoint: String,
+    pub outcome: AccessOutcome,
+    pub reason: Option<String>,
+}
+
+// Unguessable token for links handed to people outside the app
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Request windows and lockouts only live in memory; the log is persisted
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessGuard {
+    #[serde(default)]
+    pub log: VecDeque<AccessLogEntry>,
+    #[serde(skip)]
+    requests: HashMap<String, VecDeque<u64>>,
+    #[serde(skip)]
+    failures: HashMap<String, VecDeque<u64>>,
+    #[serde(skip)]
+    locked_until: HashMap<String, u64>,
+    #[serde(skip)]
+    link_failures: HashMap<String, VecDeque<u64>>, // Key is link token
+    #[serde(skip)]
+    link_locked_until: HashMap<String, u64>,
+}
+
+impl AccessGuard {
+    // Call before doing any work for a request; errors if the source is locked out
+    // or over its request budget
+    pub fn admit(&mut self, source: &str, endpoint: &str, now: u64) -> Result<(), String> {
+        if let Some(&until) = self.locked_until.get(source) {
+            if now < until {
+                self.push_log(source, endpoint, now, AccessOutcome::LockedOut, None);
+                return Err(format!("Too many rejected requests; try again in {} seconds", until - now));
+            }
+            self.locked_until.remove(source);
+        }
+
+        let requests = self.requests.entry(source.to_string()).or_default();
+        while requests.front().map(|&t| t + WINDOW_SECS <= now).unwrap_or(false) {
+            requests.pop_front();
+        }
+        if requests.len() >= MAX_REQUESTS_PER_WINDOW {
+            self.push_log(source, endpoint, now, AccessOutcome::RateLimited, None);
+            return Err("Rate limit exceeded; slow down".to_string());
+        }
+        requests.push_back(now);
+        Ok(())
+    }
+
+    // As admit, for a request made through a share link; also errors while the
+    // link itself is locked
+    pub fn admit_link(&mut self, source: &str, endpoint: &str, token: &str, now: u64) -> Result<(), String> {
+        self.admit(source, endpoint, now)?;
+        if let Some(&until) = self.link_locked_until.get(token) {
+            if now < until {
+                self.push_log(source, endpoint, now, AccessOutcome::LockedOut, Some("link locked".to_string()));
+                return Err(format!("This link is locked after too many rejected requests; try again in {} seconds", until - now));
+            }
+            self.link_locked_until.remove(token);
+        }
+        Ok(())
+    }
+
+    pub fn allow(&mut self, source: &str, endpoint: &str, now: u64) {
+        self.push_log(source, endpoint, now, AccessOutcome::Allowed, None);
+    }
+
+    // Records a rejected request; repeated rejections lock the source out
+    pub fn deny(&mut self, source: &str, endpoint: &str, now: u64, reason: &str) {
+        self.push_log(source, endpoint, now, AccessOutcome::Denied, Some(reason.to_string()));
+
+        if record_failure(&mut self.failures, source, now) {
+            self.locked_until.insert(source.to_string(), now + LOCKOUT_SECS);
+        }
+    }
+
+    // As deny, also counting the rejection against the link, whichever node made
+    // it. True when this locks the link.
+    pub fn deny_link(&mut self, source: &str, endpoint: &str, token: &str, now: u64, reason: &str) -> bool {
+        self.deny(source, endpoint, now, reason);
+        if record_failure(&mut self.link_failures, token, now) {
+            self.link_locked_until.insert(token.to_string(), now + LOCKOUT_SECS);
+            return true;
+        }
+        false
+    }
+
+    fn push_log(&mut self, source: &str, endpoint: &str, now: u64, outcome: AccessOutcome, reason: Option<String>) {
+        self.log.push_back(AccessLogEntry {
+            timestamp: now,
+            source: source.to_string(),
+            endpoint: endpoint.to_string(),
+            outcome,
+            reason,
+        });
+        while self.log.len() > LOG_CAPACITY {
+            self.log.pop_front();
+        }
+    }
+}
+
+// Adds a rejection to the key's window; true once there are enough for a lockout,
+// which starts the count again
+fn record_failure(failures: &mut HashMap<String, VecDeque<u64>>, key: &str, now: u64) -> bool {
+    let failures = failures.entry(key.to_string()).or_default();
+    while failures.front().map(|&t| t + FAILURE_WINDOW_SECS <= now).unwrap_or(false) {
+        failures.pop_front();
+    }
+    failures.push_back(now);
+    if failures.len() >= MAX_FAILURES {
+        failures.clear();
+        return true;
+    }
+    false
+}
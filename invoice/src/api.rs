@@ -0,0 +1,149 @@
+// Version 1 of the public API. Integrations call a fixed set of operations through
+// one endpoint, naming the version they were written against, and get back an
+// envelope with either the result or a coded error. Within a version, payloads and
+// results only change in ways old callers can ignore: fields are added, never
+// renamed or removed. The unversioned endpoints stay as they are for the UI and can
+// change with any release; each v1 operation is a shim over one of them, so when
+// an endpoint changes its shim is what keeps v1 callers working.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value, // Null for operations that take nothing
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum ErrorCode {
+    InvalidRequest, // Not a v1 envelope
+    UnknownMethod,
+    Failed, // The operation ran and returned an error
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Response {
+    pub version: u32,
+    pub result: Option<Value>,
+    pub error: Option<Error>,
+}
+
+impl Response {
+    pub fn ok(result: Value) -> Self {
+        Response { version: VERSION, result: Some(result), error: None }
+    }
+
+    pub fn error(code: ErrorCode, message: String) -> Self {
+        Response { version: VERSION, result: None, error: Some(Error { code, message }) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Operation {
+    pub method: &'static str,
+    pub description: &'static str,
+    pub params: &'static str, // What params holds; schemas are listed by get_api_contract
+    pub result: &'static str,
+}
+
+// Results that aren't JSON (e.g. "Invoice deleted") are given to v1 callers as null
+pub fn result(output: &str) -> Value {
+    serde_json::from_str(output).unwrap_or(Value::Null)
+}
+
+pub const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: "get_settings",
+        description: "Current settings",
+        params: "Nothing",
+        result: "InvoiceSettings, or null before they are first saved",
+    },
+    Operation {
+        method: "update_settings",
+        description: "Replaces the settings",
+        params: "InvoiceSettings",
+        result: "null",
+    },
+    Operation {
+        method: "list_invoices",
+        description: "Every invoice, in no particular order",
+        params: "Nothing",
+        result: "Array of InvoiceSummary",
+    },
+    Operation {
+        method: "find_invoices",
+        description: "Invoices matching every filter given, oldest first",
+        params: "Object with optional client, statuses, from, to (ISO dates) and project",
+        result: "Array of InvoiceSummary",
+    },
+    Operation {
+        method: "get_invoice",
+        description: "One invoice in full",
+        params: "Invoice ID as a string",
+        result: "Invoice",
+    },
+    Operation {
+        method: "create_invoice",
+        description: "Creates a draft invoice numbered from settings",
+        params: "NewInvoice, or null for a blank draft",
+        result: "Invoice",
+    },
+    Operation {
+        method: "update_invoice",
+        description: "Replaces an invoice",
+        params: "Invoice",
+        result: "The saved Invoice",
+    },
+    Operation {
+        method: "delete_invoice",
+        description: "Deletes an invoice; its number is never reused",
+        params: "Invoice ID as a string",
+        result: "null",
+    },
+    Operation {
+        method: "add_line_items",
+        description: "Appends line items to the open invoice",
+        params: "Array of NewLineItem",
+        result: "Invoice",
+    },
+    Operation {
+        method: "record_payment",
+        description: "Records a payment made outside the app and marks the invoice Paid",
+        params: "Object with invoice_id, amount, and optional method_id, paid_on (ISO date) and reference",
+        result: "Invoice",
+    },
+    Operation {
+        method: "set_invoice_status",
+        description: "Moves an invoice to a new status if the rules allow it, recording the change",
+        params: "Object with id, status, and optional metadata (string keys and values)",
+        result: "Invoice",
+    },
+    Operation {
+        method: "import_time_entries",
+        description: "Imports time entries to bill later",
+        params: "Array of TimeEntryImport",
+        result: "Object with the number of entries imported, updated and skipped",
+    },
+    Operation {
+        method: "get_outstanding_report",
+        description: "Unpaid invoices with what is due and how overdue",
+        params: "Nothing",
+        result: "Outstanding report object",
+    },
+    Operation {
+        method: "health",
+        description: "Whether the app can read and write its invoices",
+        params: "Nothing",
+        result: "Object with status \"ok\" or \"degraded\"",
+    },
+];
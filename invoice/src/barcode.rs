@@ -0,0 +1,119 @@
+// Code 128 barcodes of invoice numbers, rendered as inline SVG for document-scanning AP systems
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// How the invoice number is printed for scanning, next to the number itself
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum NumberBarcode {
+    Code128,
+    Qr,
+}
+
+// Bar and space widths in modules of each Code 128 symbol, by value; 103-105 are the
+// start codes for sets A, B and C
+const PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232",
+];
+const STOP: &str = "2331112";
+
+const START_B: u8 = 104;
+const START_C: u8 = 105;
+const CODE_B: u8 = 100; // Switch to set B from set C
+const CODE_C: u8 = 99; // Switch to set C from set B
+
+const QUIET_ZONE: usize = 10; // Modules of white space required on each side
+
+fn digit_run(chars: &[u8]) -> usize {
+    chars.iter().take_while(|c| c.is_ascii_digit()).count()
+}
+
+// Symbol values for printable ASCII text, check symbol included. Set B is used for
+// text and set C, two digits per symbol, for runs of digits long enough to be shorter.
+fn encode(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    if bytes.is_empty() {
+        return Err("Nothing to encode".to_string());
+    }
+    if let Some(c) = text.chars().find(|c| !(' '..='~').contains(c)) {
+        return Err(format!("{:?} can't be encoded in a Code 128 barcode", c));
+    }
+
+    let run = digit_run(bytes);
+    let mut set_c = run >= 4 || run == bytes.len() && run.is_multiple_of(2);
+    let mut values = vec![if set_c { START_C } else { START_B }];
+    let mut i = 0;
+    while i < bytes.len() {
+        if set_c {
+            if digit_run(&bytes[i..]) >= 2 {
+                values.push((bytes[i] - b'0') * 10 + (bytes[i + 1] - b'0'));
+                i += 2;
+            } else {
+                values.push(CODE_B);
+                set_c = false;
+            }
+            continue;
+        }
+        // Switch to set C for an even run of six or more digits, or four at the end;
+        // an odd digit goes first in set B
+        let run = digit_run(&bytes[i..]);
+        if (run >= 6 || (run >= 4 && i + run == bytes.len())) && run.is_multiple_of(2) {
+            values.push(CODE_C);
+            set_c = true;
+            continue;
+        }
+        values.push(bytes[i] - b' ');
+        i += 1;
+    }
+
+    let checksum = values.iter()
+        .enumerate()
+        .map(|(position, value)| position.max(1) as u32 * *value as u32)
+        .sum::<u32>() % 103;
+    values.push(checksum as u8);
+    Ok(values)
+}
+
+// Inline SVG of the barcode with the text printed underneath
+pub fn code128_svg(text: &str) -> Result<String, String> {
+    let values = encode(text)?;
+    let widths: Vec<usize> = values.iter()
+        .map(|value| PATTERNS[*value as usize])
+        .chain(std::iter::once(STOP))
+        .flat_map(|pattern| pattern.bytes().map(|w| (w - b'0') as usize))
+        .collect();
+
+    let module = 2; // Pixels per module
+    let bar_height = 50;
+    let width = (widths.iter().sum::<usize>() + 2 * QUIET_ZONE) * module;
+    let mut x = QUIET_ZONE * module;
+    let mut bars = String::new();
+    for (index, w) in widths.iter().enumerate() {
+        // Widths alternate bar, space, bar, ...
+        if index % 2 == 0 {
+            bars.push_str(&format!(r#"<rect x="{}" y="0" width="{}" height="{}"/>"#, x, w * module, bar_height));
+        }
+        x += w * module;
+    }
+
+    let escaped = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+    Ok(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" role="img" aria-label="{escaped}"><rect width="{width}" height="{height}" fill="#fff"/><g fill="#000">{bars}</g><text x="{center}" y="{text_y}" font-family="monospace" font-size="12" text-anchor="middle">{escaped}</text></svg>"##,
+        width = width,
+        height = bar_height + 16,
+        escaped = escaped,
+        bars = bars,
+        center = width / 2,
+        text_y = bar_height + 13,
+    ))
+}
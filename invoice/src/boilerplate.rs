@@ -0,0 +1,79 @@
+// Notes, terms and thank-you text new invoices start with, set in settings and
+// overridden per client. The text may hold tokens such as {due_date}, which are
+// filled in each time the invoice is rendered, so they follow later edits to it.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct DefaultText {
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub terms: Option<String>,
+    #[serde(default)]
+    pub thank_you: Option<String>,
+}
+
+// Tokens the text can use, each written in braces
+pub const TOKENS: &[&str] = &["invoice_number", "client_name", "due_date", "payment_terms", "total", "late_fee_policy"];
+
+impl DefaultText {
+    // A client's text in place of this, field by field; empty text counts as unset
+    pub fn with_overrides(&self, client: Option<&DefaultText>) -> DefaultText {
+        let pick = |general: &Option<String>, client: Option<&Option<String>>| {
+            client.and_then(non_empty)
+                .or_else(|| non_empty(general))
+        };
+        DefaultText {
+            notes: pick(&self.notes, client.map(|c| &c.notes)),
+            terms: pick(&self.terms, client.map(|c| &c.terms)),
+            thank_you: pick(&self.thank_you, client.map(|c| &c.thank_you)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (label, text) in [("notes", &self.notes), ("terms", &self.terms), ("thank-you text", &self.thank_you)] {
+            if let Some(token) = text.iter().flat_map(|t| tokens_in(t)).find(|t| !TOKENS.contains(t)) {
+                return Err(format!("Unknown token {{{}}} in the default {}", token, label));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn non_empty(text: &Option<String>) -> Option<String> {
+    text.clone().filter(|t| !t.trim().is_empty())
+}
+
+// Replaces each known token with its value; anything else in braces is left as written
+pub fn substitute(text: &str, values: &[(&str, String)]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| {
+            values.iter().find(|(name, _)| *name == &after[..end]).map(|(_, value)| (end, value))
+        }) {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Words written in braces, e.g. "due_date" in "Pay by {due_date}"
+fn tokens_in(text: &str) -> impl Iterator<Item = &str> {
+    text.split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(token, _)| token))
+        .filter(|token| !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
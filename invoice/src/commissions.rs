@@ -0,0 +1,78 @@
+// Commissions owed to referral partners: a percentage of each referred invoice,
+// earned once the invoice is paid, less what has already been paid out
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+// One referred invoice and the partner's share of it
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct CommissionLine {
+    pub invoice_id: String,
+    pub number: String,
+    pub date: String,
+    pub client: String,
+    pub base: f64, // Invoice amount before tax, after discounts, in the home currency
+    pub percent: f64,
+    pub amount: f64, // In the home currency, like payouts
+    pub paid: bool, // Whether the client has paid, which is when the commission is earned
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct PartnerStatement {
+    pub partner_id: String,
+    pub name: String,
+    pub earned: f64, // On paid invoices
+    pub pending: f64, // On invoices not paid yet
+    pub paid_out: f64,
+    pub owed: f64, // Earned less paid out
+    pub lines: Vec<CommissionLine>,
+}
+
+// Statements for every partner with referred invoices or payouts, in the order
+// the partners are given. Lines and payouts of unknown partners are left out.
+pub fn statements(
+    partners: &[(String, String)], // ID and name
+    lines: Vec<(String, CommissionLine)>, // Partner ID and line
+    payouts: &[(String, f64)], // Partner ID and amount
+) -> Vec<PartnerStatement> {
+    let mut statements: Vec<PartnerStatement> = partners.iter()
+        .map(|(id, name)| PartnerStatement {
+            partner_id: id.clone(),
+            name: name.clone(),
+            earned: 0.0,
+            pending: 0.0,
+            paid_out: 0.0,
+            owed: 0.0,
+            lines: Vec::new(),
+        })
+        .collect();
+    for (partner_id, line) in lines {
+        let Some(statement) = statements.iter_mut().find(|s| s.partner_id == partner_id) else {
+            continue;
+        };
+        if line.paid {
+            statement.earned += line.amount;
+        } else {
+            statement.pending += line.amount;
+        }
+        statement.lines.push(line);
+    }
+    for (partner_id, amount) in payouts {
+        if let Some(statement) = statements.iter_mut().find(|s| &s.partner_id == partner_id) {
+            statement.paid_out += amount;
+        }
+    }
+    for statement in &mut statements {
+        statement.earned = round_cents(statement.earned);
+        statement.pending = round_cents(statement.pending);
+        statement.paid_out = round_cents(statement.paid_out);
+        statement.owed = round_cents(statement.earned - statement.paid_out);
+        statement.lines.sort_by(|a, b| a.date.cmp(&b.date));
+    }
+    statements.retain(|s| !s.lines.is_empty() || s.paid_out != 0.0);
+    statements
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
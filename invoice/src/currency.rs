@@ -0,0 +1,74 @@
+// ISO 4217 currencies: the symbol amounts are shown with and how many decimal
+// places they have. Codes not listed here are still accepted and shown as a suffix.
+
+pub struct Currency {
+    pub code: &'static str,
+    pub symbol: &'static str,
+    pub decimals: usize,
+    pub symbol_after: bool, // e.g. "100.00 kr"
+}
+
+const fn currency(code: &'static str, symbol: &'static str, decimals: usize, symbol_after: bool) -> Currency {
+    Currency { code, symbol, decimals, symbol_after }
+}
+
+const CURRENCIES: &[Currency] = &[
+    currency("USD", "$", 2, false),
+    currency("EUR", "€", 2, false),
+    currency("GBP", "£", 2, false),
+    currency("CAD", "CA$", 2, false),
+    currency("AUD", "A$", 2, false),
+    currency("NZD", "NZ$", 2, false),
+    currency("CHF", "CHF ", 2, false),
+    currency("JPY", "¥", 0, false),
+    currency("CNY", "CN¥", 2, false),
+    currency("HKD", "HK$", 2, false),
+    currency("SGD", "S$", 2, false),
+    currency("INR", "₹", 2, false),
+    currency("KRW", "₩", 0, false),
+    currency("BRL", "R$", 2, false),
+    currency("MXN", "MX$", 2, false),
+    currency("ZAR", "R", 2, false),
+    currency("ILS", "₪", 2, false),
+    currency("TRY", "₺", 2, false),
+    currency("SEK", "kr", 2, true),
+    currency("NOK", "kr", 2, true),
+    currency("DKK", "kr", 2, true),
+    currency("PLN", "zł", 2, true),
+    currency("CZK", "Kč", 2, true),
+    currency("HUF", "Ft", 2, true),
+    currency("SAR", "SAR", 2, true),
+    currency("AED", "AED", 2, true),
+    currency("KWD", "KWD", 3, true),
+    currency("BHD", "BHD", 3, true),
+    currency("OMR", "OMR", 3, true),
+    currency("JOD", "JOD", 3, true),
+];
+
+pub const DEFAULT_CODE: &str = "USD";
+
+pub fn find(code: &str) -> Option<&'static Currency> {
+    CURRENCIES.iter().find(|c| c.code == code)
+}
+
+// Three uppercase letters, as ISO 4217 codes are
+pub fn is_valid_code(code: &str) -> bool {
+    code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase())
+}
+
+// Decimal places amounts in a currency are shown with; 2 for unlisted codes
+pub fn decimals(code: &str) -> usize {
+    find(code).map(|c| c.decimals).unwrap_or(2)
+}
+
+// e.g. "$1234.50", "1234.50 kr", or "1234.50 NGN" for codes not listed. The
+// sign goes in front, "-$5.00", unless the amount rounds to zero.
+pub fn format_amount(amount: f64, code: &str) -> String {
+    let digits = format!("{:.*}", decimals(code), amount.abs());
+    let sign = if amount < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+    match find(code) {
+        Some(currency) if currency.symbol_after => format!("{}{} {}", sign, digits, currency.symbol),
+        Some(currency) => format!("{}{}{}", sign, currency.symbol, digits),
+        None => format!("{}{} {}", sign, digits, code),
+    }
+}
@@ -0,0 +1,97 @@
+// Custom fields defined in settings, for the extra details some clients and
+// jurisdictions ask to see on an invoice or on each line. Values are kept by field
+// name and checked against the field's type whenever they are saved.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum FieldType {
+    Text,
+    Number,
+    Date, // ISO date string
+    Boolean,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum Scope {
+    Invoice,
+    LineItem,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct FieldDefinition {
+    pub name: String, // Label printed on the invoice and key of the stored value
+    pub field_type: FieldType,
+    pub scope: Scope,
+}
+
+pub type Values = HashMap<String, Value>;
+
+pub fn validate_definitions(definitions: &[FieldDefinition]) -> Result<(), String> {
+    for (index, definition) in definitions.iter().enumerate() {
+        if definition.name.trim().is_empty() {
+            return Err("Custom fields need a name".to_string());
+        }
+        if definitions[..index].iter().any(|d| d.name == definition.name && d.scope == definition.scope) {
+            return Err(format!("Custom field {} is defined twice", definition.name));
+        }
+    }
+    Ok(())
+}
+
+// Every value must belong to a field of this scope and have its type; null is
+// the same as leaving the field out
+pub fn validate(definitions: &[FieldDefinition], scope: Scope, values: &Values) -> Result<(), String> {
+    for (name, value) in values {
+        let definition = definitions.iter()
+            .find(|d| d.scope == scope && &d.name == name)
+            .ok_or_else(|| format!("No custom field named {}", name))?;
+        let valid = match (definition.field_type, value) {
+            (_, Value::Null) => true,
+            (FieldType::Text, Value::String(_)) => true,
+            (FieldType::Number, Value::Number(_)) => true,
+            (FieldType::Date, Value::String(date)) => is_iso_date(date),
+            (FieldType::Boolean, Value::Bool(_)) => true,
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("Custom field {} must be {}", name, match definition.field_type {
+                FieldType::Text => "text",
+                FieldType::Number => "a number",
+                FieldType::Date => "an ISO date",
+                FieldType::Boolean => "true or false",
+            }));
+        }
+    }
+    Ok(())
+}
+
+// Fields of the scope that have a value, in the order they are defined, with the
+// value as printed (not yet escaped for HTML)
+pub fn displayed<'a>(definitions: &'a [FieldDefinition], scope: Scope, values: &Values) -> Vec<(&'a str, String)> {
+    definitions.iter()
+        .filter(|d| d.scope == scope)
+        .filter_map(|d| display(values.get(&d.name)?).map(|value| (d.name.as_str(), value)))
+        .collect()
+}
+
+pub fn display(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        Value::Bool(true) => Some("Yes".to_string()),
+        Value::Bool(false) => Some("No".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+fn is_iso_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    parts.len() == 3
+        && [4, 2, 2].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.parse::<u32>().is_ok())
+        && (1..=12).contains(&parts[1].parse::<u32>().unwrap_or(0))
+        && (1..=31).contains(&parts[2].parse::<u32>().unwrap_or(0))
+}
@@ -0,0 +1,98 @@
+// Versions of the invoice summary list. Every change to a summary bumps the version,
+// so an editor holding thousands of invoices can fetch only the summaries created,
+// changed or deleted since the version it last saw instead of the whole list.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MAX_TOMBSTONES: usize = 1000; // Deleted IDs remembered, newest kept
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct SummaryLog {
+    version: u64,
+    changes: HashMap<String, Change>, // Key is invoice ID
+    deleted: Vec<Tombstone>, // Oldest first
+    complete_from: u64, // Deletions after this version are all still remembered
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+struct Change {
+    created: u64,
+    updated: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+struct Tombstone {
+    id: String,
+    version: u64,
+}
+
+// Invoice IDs changed since a version. A reset means the version can't be caught up
+// from, and created lists every invoice so the list is rebuilt from scratch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub version: u64,
+    pub reset: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl SummaryLog {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // Stores a summary, bumping the version unless it is unchanged
+    pub fn insert<T: PartialEq>(&mut self, summaries: &mut HashMap<String, T>, id: String, summary: T) {
+        if summaries.get(&id) == Some(&summary) {
+            return;
+        }
+        self.version += 1;
+        let version = self.version;
+        // Invoices stored before the log knew them count as updated, not created
+        let existed = summaries.contains_key(&id);
+        self.changes.entry(id.clone())
+            .and_modify(|c| c.updated = version)
+            .or_insert(Change { created: if existed { 0 } else { version }, updated: version });
+        summaries.insert(id, summary);
+    }
+
+    pub fn remove<T>(&mut self, summaries: &mut HashMap<String, T>, id: &str) -> Option<T> {
+        let summary = summaries.remove(id)?;
+        self.version += 1;
+        self.changes.remove(id);
+        self.deleted.push(Tombstone { id: id.to_string(), version: self.version });
+        if self.deleted.len() > MAX_TOMBSTONES {
+            let dropped = self.deleted.remove(0);
+            self.complete_from = dropped.version;
+        }
+        Some(summary)
+    }
+
+    // Changes since a version; 0 fetches every summary
+    pub fn since<'a>(&self, version: u64, ids: impl Iterator<Item = &'a String>) -> Delta {
+        if version == 0 || version < self.complete_from || version > self.version {
+            let mut created: Vec<String> = ids.cloned().collect();
+            created.sort();
+            return Delta { version: self.version, reset: true, created, updated: Vec::new(), deleted: Vec::new() };
+        }
+        let mut created = Vec::new();
+        let mut updated = Vec::new();
+        for (id, change) in &self.changes {
+            if change.created > version {
+                created.push(id.clone());
+            } else if change.updated > version {
+                updated.push(id.clone());
+            }
+        }
+        created.sort();
+        updated.sort();
+        let deleted = self.deleted.iter()
+            .filter(|t| t.version > version)
+            .map(|t| t.id.clone())
+            .collect();
+        Delta { version: self.version, reset: false, created, updated, deleted }
+    }
+}
@@ -0,0 +1,120 @@
+// Leveled log of what went wrong or is worth knowing, tagged with the area, the
+// operation and the invoice, so a user reporting a persistence failure can send
+// get_diagnostics output instead of node logs. Recent entries are kept in memory
+// only; every entry is also printed to the node's terminal as before.
+//
+// Each incoming request gets a request ID for as long as its handler runs. Entries
+// logged meanwhile carry it, and persistence errors quote it, so the error a user
+// reports leads to the log entries behind it.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+const MAX_ENTRIES: usize = 200;
+const MAX_ERRORS: usize = 20; // Kept apart so routine entries can't push them out
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub at: u64,
+    pub level: Level,
+    pub module: &'static str, // e.g. "store", "payments", "render"
+    pub operation: &'static str, // Endpoint or task, e.g. "check_reminders"
+    pub invoice_id: Option<String>,
+    pub request_id: Option<String>, // Request being handled when this was logged
+    pub message: String,
+}
+
+struct Log {
+    entries: VecDeque<LogEntry>,
+    errors: VecDeque<LogEntry>,
+    error_counts: BTreeMap<&'static str, u64>, // Module -> errors since the process started
+}
+
+static LOG: Mutex<Log> = Mutex::new(Log {
+    entries: VecDeque::new(),
+    errors: VecDeque::new(),
+    error_counts: BTreeMap::new(),
+});
+
+fn push(queue: &mut VecDeque<LogEntry>, entry: LogEntry, max: usize) {
+    queue.push_back(entry);
+    if queue.len() > max {
+        queue.pop_front();
+    }
+}
+
+pub fn record(mut entry: LogEntry) {
+    entry.request_id = request_id();
+    let request = entry.request_id.as_ref().map(|id| format!(" <{}>", id)).unwrap_or_default();
+    match entry.invoice_id {
+        Some(ref id) => println!("[{:?}]{} {}::{} ({}): {}", entry.level, request, entry.module, entry.operation, id, entry.message),
+        None => println!("[{:?}]{} {}::{}: {}", entry.level, request, entry.module, entry.operation, entry.message),
+    }
+    let mut log = LOG.lock().unwrap();
+    if entry.level == Level::Error {
+        *log.error_counts.entry(entry.module).or_default() += 1;
+        push(&mut log.errors, entry.clone(), MAX_ERRORS);
+    }
+    push(&mut log.entries, entry, MAX_ENTRIES);
+}
+
+// Newest first, at or above the level
+pub fn recent(min_level: Level) -> Vec<LogEntry> {
+    LOG.lock().unwrap().entries.iter().rev().filter(|e| e.level >= min_level).cloned().collect()
+}
+
+// Newest first
+pub fn last_errors() -> Vec<LogEntry> {
+    LOG.lock().unwrap().errors.iter().rev().cloned().collect()
+}
+
+// Errors since the process started, unlike last_errors not limited to the newest
+pub fn error_count(module: &str) -> u64 {
+    LOG.lock().unwrap().error_counts.get(module).copied().unwrap_or(0)
+}
+
+// Requests being handled, the newest last, and how many have been, for unique IDs
+static REQUEST: Mutex<(Vec<String>, u64)> = Mutex::new((Vec::new(), 0));
+
+// Marks a handler's request as current until dropped. Handlers that wait on the
+// node can interleave, so entries logged after an await may carry another
+// request's ID. Requests can finish in any order; dropping takes only its own ID
+// off the stack, leaving the newest one still running as the current request.
+pub struct Trace {
+    id: String,
+}
+
+// Starts a request, e.g. "req-1736942400000-7-create_invoice", given the time
+pub fn trace(operation: &str, now_millis: u64) -> Trace {
+    let mut request = REQUEST.lock().unwrap();
+    request.1 += 1;
+    let id = format!("req-{}-{}-{}", now_millis, request.1, operation);
+    request.0.push(id.clone());
+    Trace { id }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        REQUEST.lock().unwrap().0.retain(|id| id != &self.id);
+    }
+}
+
+pub fn request_id() -> Option<String> {
+    REQUEST.lock().unwrap().0.last().cloned()
+}
+
+// Quotes the current request in an error returned to the caller
+pub fn tagged(error: String) -> String {
+    match request_id() {
+        Some(id) => format!("{} (request {})", error, id),
+        None => error,
+    }
+}
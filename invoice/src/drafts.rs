@@ -0,0 +1,243 @@
+// Short-lived draft buffers for the open invoice, one per editing session (a browser
+// tab). Each session numbers its edits; the buffer holds the invoice as the session
+// last got it back and which fields it has sent which edit of. An edit then only
+// changes the fields the session changed since, and never one it has already sent a
+// newer edit of, so a slow save arriving late can't revert what was typed after it
+// was sent, and sessions editing different fields don't overwrite each other.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+const BUFFER_TTL_SECS: u64 = 600; // Idle sessions are forgotten after this long
+const MAX_WARNINGS: usize = 20; // Per session, newest kept
+const ORDER_PATH: &str = "line_items/order";
+
+// An edit that replaced or lost to a change made in another session
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct MergeWarning {
+    pub at: u64,
+    pub field: String, // e.g. "notes" or "line_items/<item ID>"
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DraftBuffers {
+    buffers: HashMap<String, Buffer>, // Key is session ID
+}
+
+#[derive(Debug)]
+struct Buffer {
+    invoice_id: String,
+    baseline: Value, // The invoice as last returned to the session
+    sequences: HashMap<String, u64>, // Field, or line_items/<item ID>, and the latest edit that set it
+    applied_sequence: u64, // Latest edit merged
+    saved_sequence: Option<u64>, // Latest edit written to storage
+    warnings: Vec<MergeWarning>,
+    touched_at: u64,
+}
+
+impl Buffer {
+    // Whether an edit with this number should set the field, recording it if so
+    fn claim(&mut self, path: &str, sequence: u64) -> bool {
+        match self.sequences.get(path) {
+            Some(&latest) if latest > sequence => false,
+            _ => {
+                self.sequences.insert(path.to_string(), sequence);
+                true
+            }
+        }
+    }
+
+    fn warn(&mut self, now: u64, field: &str, message: String) {
+        self.warnings.push(MergeWarning { at: now, field: field.to_string(), message });
+        if self.warnings.len() > MAX_WARNINGS {
+            self.warnings.remove(0);
+        }
+    }
+}
+
+// Where a session's edits stand, for an editor's saving/saved/conflict indicator
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct SessionState {
+    pub applied_sequence: u64,
+    pub saved_sequence: Option<u64>,
+    pub warnings: Vec<MergeWarning>,
+}
+
+impl DraftBuffers {
+    // The invoice an edit leaves once merged into the current one. A session seen for
+    // the first time, or switching invoices, starts from the current invoice.
+    pub fn merge(&mut self, session_id: &str, sequence: u64, current: &Value, incoming: &Value, now: u64) -> Value {
+        let invoice_id = current.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+        let buffer = self.buffers.entry(session_id.to_string())
+            .and_modify(|b| {
+                if b.invoice_id != invoice_id {
+                    b.invoice_id = invoice_id.clone();
+                    b.baseline = current.clone();
+                    b.sequences.clear();
+                    b.saved_sequence = None;
+                    b.warnings.clear();
+                }
+            })
+            .or_insert_with(|| Buffer {
+                invoice_id: invoice_id.clone(),
+                baseline: current.clone(),
+                sequences: HashMap::new(),
+                applied_sequence: 0,
+                saved_sequence: None,
+                warnings: Vec::new(),
+                touched_at: now,
+            });
+        buffer.touched_at = now;
+        buffer.applied_sequence = buffer.applied_sequence.max(sequence);
+
+        let (Some(incoming), Some(baseline)) = (incoming.as_object(), buffer.baseline.as_object().cloned()) else {
+            return current.clone();
+        };
+        let mut merged = current.clone();
+        for (key, value) in incoming {
+            if key == "line_items" {
+                let items = merge_items(
+                    buffer,
+                    sequence,
+                    now,
+                    baseline.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or(&[]),
+                    current.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or(&[]),
+                    value.as_array().map(Vec::as_slice).unwrap_or(&[]),
+                );
+                merged[key] = Value::Array(items);
+            } else if baseline.get(key) != Some(value) && buffer.claim(key, sequence) {
+                if baseline.get(key) != current.get(key) && current.get(key) != Some(value) {
+                    buffer.warn(now, key, format!("{} was also changed in another session; this edit replaced it", key));
+                }
+                merged[key] = value.clone();
+            }
+        }
+        merged
+    }
+
+    // Remembers the invoice sent back to a session, which its next edits build on
+    pub fn acknowledge(&mut self, session_id: &str, invoice: &Value) {
+        if let Some(buffer) = self.buffers.get_mut(session_id) {
+            buffer.baseline = invoice.clone();
+        }
+    }
+
+    // Marks every edit merged into the invoice so far as saved
+    pub fn saved(&mut self, invoice_id: &str) {
+        for buffer in self.buffers.values_mut().filter(|b| b.invoice_id == invoice_id) {
+            buffer.saved_sequence = Some(buffer.applied_sequence);
+        }
+    }
+
+    pub fn session_state(&self, session_id: &str, invoice_id: &str) -> Option<SessionState> {
+        self.buffers.get(session_id)
+            .filter(|b| b.invoice_id == invoice_id)
+            .map(|b| SessionState {
+                applied_sequence: b.applied_sequence,
+                saved_sequence: b.saved_sequence,
+                warnings: b.warnings.clone(),
+            })
+    }
+
+    pub fn prune(&mut self, now: u64) {
+        self.buffers.retain(|_, b| b.touched_at + BUFFER_TTL_SECS > now);
+    }
+}
+
+// Fields that differ between two versions of an invoice, as merges name them: top-level
+// fields, line_items/<item ID> for added, changed or removed items, and line_items/order
+pub fn changed_fields(before: &Value, after: &Value) -> Vec<String> {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let mut fields: Vec<String> = Vec::new();
+    for key in before.keys().chain(after.keys().filter(|k| !before.contains_key(*k))) {
+        if key == "updated_at" || before.get(key) == after.get(key) {
+            continue;
+        }
+        if key != "line_items" {
+            fields.push(key.clone());
+            continue;
+        }
+        let items = |v: Option<&Value>| v.and_then(Value::as_array).cloned().unwrap_or_default();
+        let (before_items, after_items) = (items(before.get(key)), items(after.get(key)));
+        let find = |list: &[Value], id: &str| list.iter().find(|i| item_id(i) == Some(id)).cloned();
+        for id in before_items.iter().chain(after_items.iter()).filter_map(item_id) {
+            let path = format!("line_items/{}", id);
+            if find(&before_items, id) != find(&after_items, id) && !fields.contains(&path) {
+                fields.push(path);
+            }
+        }
+        let order = |list: &[Value]| list.iter().filter_map(item_id).map(String::from).collect::<Vec<_>>();
+        if !fields.iter().any(|f| f.starts_with("line_items/")) && order(&before_items) != order(&after_items) {
+            fields.push(ORDER_PATH.to_string());
+        }
+    }
+    fields
+}
+
+fn item_id(item: &Value) -> Option<&str> {
+    item.get("id").and_then(Value::as_str)
+}
+
+// Line items merge one by one: items the session added, changed or removed since its
+// baseline are added, replaced or removed in the current list, and the session's order
+// is taken when it reordered them
+fn merge_items(buffer: &mut Buffer, sequence: u64, now: u64, baseline: &[Value], current: &[Value], incoming: &[Value]) -> Vec<Value> {
+    let find = |items: &[Value], id: &str| items.iter().find(|i| item_id(i) == Some(id)).cloned();
+    let mut merged: Vec<Value> = current.to_vec();
+
+    for item in incoming {
+        let Some(id) = item_id(item) else {
+            continue;
+        };
+        let base = find(baseline, id);
+        if base.as_ref() == Some(item) || !buffer.claim(&format!("line_items/{}", id), sequence) {
+            continue;
+        }
+        let path = format!("line_items/{}", id);
+        match merged.iter().position(|i| item_id(i) == Some(id)) {
+            Some(index) => {
+                if base.is_some() && base.as_ref() != Some(&merged[index]) && &merged[index] != item {
+                    buffer.warn(now, &path, "Line item was also changed in another session; this edit replaced it".to_string());
+                }
+                merged[index] = item.clone();
+            }
+            None if base.is_none() => merged.push(item.clone()),
+            None => buffer.warn(now, &path, "Line item was removed in another session; this edit was dropped".to_string()),
+        }
+    }
+    for item in baseline {
+        let Some(id) = item_id(item) else {
+            continue;
+        };
+        if find(incoming, id).is_none() && buffer.claim(&format!("line_items/{}", id), sequence) {
+            merged.retain(|i| item_id(i) != Some(id));
+        }
+    }
+
+    let ids = |items: &[Value]| items.iter().filter_map(item_id).map(String::from).collect::<Vec<_>>();
+    let (baseline_ids, incoming_ids) = (ids(baseline), ids(incoming));
+    let mut sorted_baseline = baseline_ids.clone();
+    let mut sorted_incoming = incoming_ids.clone();
+    sorted_baseline.sort();
+    sorted_incoming.sort();
+    let reordered = sorted_baseline == sorted_incoming && baseline_ids != incoming_ids;
+    if reordered && buffer.claim(ORDER_PATH, sequence) {
+        let position = |item: &Value| incoming_ids.iter()
+            .position(|id| Some(id.as_str()) == item_id(item))
+            .unwrap_or(usize::MAX);
+        merged.sort_by_key(position);
+    }
+    merged
+}
+
+// Separates the session ID and edit number an editor sends alongside the invoice
+pub fn take_session(body: &mut Map<String, Value>) -> Option<(String, u64)> {
+    let session_id = body.remove("session_id");
+    let sequence = body.remove("edit_sequence");
+    Some((session_id?.as_str()?.to_string(), sequence?.as_u64()?))
+}
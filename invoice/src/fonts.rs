@@ -0,0 +1,189 @@
+// Uploaded fonts, embedded in exports as @font-face rules with data URIs so
+// documents render the same in every browser and in PDFs printed from them
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum FontStyle {
+    #[default]
+    Normal,
+    Italic,
+}
+
+impl FontStyle {
+    fn css(&self) -> &'static str {
+        match self {
+            FontStyle::Normal => "normal",
+            FontStyle::Italic => "italic",
+        }
+    }
+}
+
+// One font file; a family is usually several files, e.g. regular, bold and italic
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct CustomFont {
+    pub id: String,
+    pub family: String, // Name used in CSS, e.g. "Noto Sans JP"
+    pub weight: u16, // 100 to 900; 400 is regular, 700 bold
+    #[serde(default)]
+    pub style: FontStyle,
+    pub file_name: String,
+    pub content_type: String, // font/woff2, font/woff, font/ttf or font/otf
+    pub path: String, // Path to the font in VFS
+    pub size: usize,
+    pub uploaded_at: u64,
+    #[serde(default)]
+    pub scripts: Vec<Script>, // Scripts this font is a fallback for, embedded when a document uses them
+}
+
+// Writing systems that need fonts beyond the usual Latin ones
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub enum Script {
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Hangul,
+    Cjk, // Chinese characters, kana and full-width forms
+    Emoji,
+}
+
+pub const ALL_SCRIPTS: [Script; 9] = [
+    Script::Greek,
+    Script::Cyrillic,
+    Script::Hebrew,
+    Script::Arabic,
+    Script::Devanagari,
+    Script::Thai,
+    Script::Hangul,
+    Script::Cjk,
+    Script::Emoji,
+];
+
+impl Script {
+    // Code point ranges, inclusive
+    fn ranges(&self) -> &'static [(u32, u32)] {
+        match self {
+            Script::Greek => &[(0x0370, 0x03FF), (0x1F00, 0x1FFF)],
+            Script::Cyrillic => &[(0x0400, 0x052F), (0x2DE0, 0x2DFF), (0xA640, 0xA69F)],
+            Script::Hebrew => &[(0x0590, 0x05FF), (0xFB1D, 0xFB4F)],
+            Script::Arabic => &[(0x0600, 0x06FF), (0x0750, 0x077F), (0x08A0, 0x08FF), (0xFB50, 0xFDFF), (0xFE70, 0xFEFF)],
+            Script::Devanagari => &[(0x0900, 0x097F), (0xA8E0, 0xA8FF)],
+            Script::Thai => &[(0x0E00, 0x0E7F)],
+            Script::Hangul => &[(0x1100, 0x11FF), (0x3130, 0x318F), (0xAC00, 0xD7AF)],
+            Script::Cjk => &[
+                (0x2E80, 0x2FDF), (0x3000, 0x30FF), (0x31F0, 0x31FF), (0x3400, 0x4DBF),
+                (0x4E00, 0x9FFF), (0xF900, 0xFAFF), (0xFF00, 0xFFEF), (0x20000, 0x2FA1F),
+            ],
+            Script::Emoji => &[
+                (0x2300, 0x23FF), (0x2600, 0x27BF), (0x2B00, 0x2BFF), (0x1F000, 0x1FAFF),
+            ],
+        }
+    }
+
+    // Fonts commonly installed on macOS, Windows, Linux and mobile that cover the script
+    fn system_fonts(&self) -> &'static [&'static str] {
+        match self {
+            Script::Greek | Script::Cyrillic => &["Noto Sans", "DejaVu Sans", "Segoe UI"],
+            Script::Hebrew => &["Noto Sans Hebrew", "Arial Hebrew", "Segoe UI"],
+            Script::Arabic => &["Noto Naskh Arabic", "Noto Sans Arabic", "Geeza Pro", "Segoe UI", "Tahoma"],
+            Script::Devanagari => &["Noto Sans Devanagari", "Kohinoor Devanagari", "Nirmala UI", "Mangal"],
+            Script::Thai => &["Noto Sans Thai", "Thonburi", "Leelawadee UI", "Tahoma"],
+            Script::Hangul => &["Noto Sans CJK KR", "Apple SD Gothic Neo", "Malgun Gothic"],
+            Script::Cjk => &[
+                "Noto Sans CJK JP", "Noto Sans CJK SC", "Hiragino Sans", "PingFang SC",
+                "Microsoft YaHei", "Yu Gothic", "Source Han Sans",
+            ],
+            Script::Emoji => &["Apple Color Emoji", "Segoe UI Emoji", "Noto Color Emoji", "Twemoji Mozilla"],
+        }
+    }
+
+    fn contains(&self, c: char) -> bool {
+        let code = c as u32;
+        self.ranges().iter().any(|(start, end)| (*start..=*end).contains(&code))
+    }
+}
+
+// Scripts used anywhere in the text, in ALL_SCRIPTS order
+pub fn detect_scripts(text: &str) -> Vec<Script> {
+    let mut found = [false; ALL_SCRIPTS.len()];
+    for c in text.chars().filter(|c| !c.is_ascii()) {
+        if let Some(index) = ALL_SCRIPTS.iter().position(|script| script.contains(c)) {
+            found[index] = true;
+        }
+    }
+    ALL_SCRIPTS.iter()
+        .zip(found)
+        .filter(|(_, found)| *found)
+        .map(|(script, _)| *script)
+        .collect()
+}
+
+pub fn default_weight() -> u16 {
+    400
+}
+
+// Family names end up inside CSS strings, so quotes, backslashes and the like are refused
+pub fn validate_family(family: &str) -> Result<(), String> {
+    let family = family.trim();
+    if family.is_empty() || family.chars().count() > 64 {
+        return Err("Font family must be between 1 and 64 characters".to_string());
+    }
+    if !family.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') {
+        return Err("Font family can only contain letters, digits, spaces, dashes and underscores".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_weight(weight: u16) -> Result<(), String> {
+    if !matches!(weight, 100 | 200 | 300 | 400 | 500 | 600 | 700 | 800 | 900) {
+        return Err("Font weight must be a multiple of 100 from 100 to 900".to_string());
+    }
+    Ok(())
+}
+
+fn css_format(content_type: &str) -> &'static str {
+    match content_type {
+        "font/woff2" => "woff2",
+        "font/woff" => "woff",
+        "font/otf" => "opentype",
+        _ => "truetype",
+    }
+}
+
+// @font-face rule for a font whose contents are given as a data URI
+pub fn font_face(font: &CustomFont, data_uri: &str) -> String {
+    format!(
+        "@font-face {{ font-family: \"{}\"; font-weight: {}; font-style: {}; src: url({}) format(\"{}\"); font-display: block; }}",
+        font.family, font.weight, font.style.css(), data_uri, css_format(&font.content_type)
+    )
+}
+
+// CSS font-family value: the chosen family, uploaded fallback families, system
+// fonts for the scripts in use, then the built-in fonts. Browsers pick a font per
+// character along this list, so each script gets the first font that covers it.
+// Emoji fonts go last so they don't take over digits and symbols.
+pub fn font_stack(family: Option<&str>, fallbacks: &[&str], scripts: &[Script]) -> String {
+    let mut families: Vec<&str> = family.into_iter().chain(fallbacks.iter().copied()).collect();
+    for script in scripts.iter().filter(|s| **s != Script::Emoji) {
+        families.extend(script.system_fonts());
+    }
+    families.extend(["Arial", "sans-serif"]);
+    if scripts.contains(&Script::Emoji) {
+        families.extend(Script::Emoji.system_fonts());
+    }
+    let mut stack: Vec<String> = Vec::new();
+    for family in families {
+        let css = match family {
+            "sans-serif" => family.to_string(),
+            family => format!("\"{}\"", family),
+        };
+        if !stack.contains(&css) {
+            stack.push(css);
+        }
+    }
+    stack.join(", ")
+}
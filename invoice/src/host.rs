@@ -0,0 +1,121 @@
+// What the process needs from the node it runs on besides storage: the time, its
+// own address and who sent the request being handled. Endpoints ask through here
+// instead of the system clock, our() and source(), so a fixed clock, identity and
+// caller can be installed and the handlers run off-node.
+
+use hyperware_process_lib::{hyperapp, our, Address};
+use std::cell::RefCell;
+#[cfg(test)]
+use std::sync::Mutex;
+
+pub trait Clock: Send {
+    fn now_millis(&self) -> u64;
+}
+
+pub trait Identity: Send {
+    fn address(&self) -> Address;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+struct NodeIdentity;
+
+impl Identity for NodeIdentity {
+    fn address(&self) -> Address {
+        our()
+    }
+}
+
+// Installed in place of the system clock, the node's identity and the sender. Each
+// thread has its own, so tests running side by side can't see each other's; the
+// process itself only ever has one thread.
+thread_local! {
+    static CLOCK: RefCell<Option<Box<dyn Clock>>> = const { RefCell::new(None) };
+    static IDENTITY: RefCell<Option<Box<dyn Identity>>> = const { RefCell::new(None) };
+    static CALLER: RefCell<Option<Address>> = const { RefCell::new(None) };
+}
+
+// None goes back to the system clock
+#[cfg(test)]
+pub fn install_clock(clock: Option<Box<dyn Clock>>) {
+    CLOCK.with(|installed| *installed.borrow_mut() = clock);
+}
+
+// None goes back to the node's own address
+#[cfg(test)]
+pub fn install_identity(identity: Option<Box<dyn Identity>>) {
+    IDENTITY.with(|installed| *installed.borrow_mut() = identity);
+}
+
+// None goes back to the sender of the current request
+#[cfg(test)]
+pub fn install_caller(caller: Option<Address>) {
+    CALLER.with(|installed| *installed.borrow_mut() = caller);
+}
+
+pub fn now_millis() -> u64 {
+    CLOCK.with(|installed| match installed.borrow().as_ref() {
+        Some(clock) => clock.now_millis(),
+        None => SystemClock.now_millis(),
+    })
+}
+
+// Unix seconds
+pub fn now() -> u64 {
+    now_millis() / 1000
+}
+
+pub fn address() -> Address {
+    IDENTITY.with(|installed| match installed.borrow().as_ref() {
+        Some(identity) => identity.address(),
+        None => NodeIdentity.address(),
+    })
+}
+
+pub fn source() -> Address {
+    CALLER.with(|installed| installed.borrow().clone()).unwrap_or_else(hyperapp::source)
+}
+
+// A clock that only moves when told to. Clones share the same time.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    millis: std::sync::Arc<Mutex<u64>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn at(secs: u64) -> Self {
+        ManualClock { millis: std::sync::Arc::new(Mutex::new(secs * 1000)) }
+    }
+
+    pub fn advance(&self, secs: u64) {
+        *self.millis.lock().unwrap() += secs * 1000;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        *self.millis.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+pub struct FixedIdentity(pub Address);
+
+#[cfg(test)]
+impl Identity for FixedIdentity {
+    fn address(&self) -> Address {
+        self.0.clone()
+    }
+}
@@ -0,0 +1,123 @@
+// Secondary indexes over the invoice list by client, status, month, project and
+// tag, kept up to date whenever a summary is stored or removed, so filters look up
+// matching invoice IDs instead of scanning every summary or loading every invoice.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// What an invoice is indexed under
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keys {
+    pub client: String, // Normalized client key
+    pub status: String, // e.g. "Sent"
+    pub date: String, // ISO date; indexed by its month
+    pub project: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct InvoiceIndex {
+    keys: HashMap<String, Keys>, // Key is invoice ID
+    by_client: HashMap<String, BTreeSet<String>>,
+    by_status: HashMap<String, BTreeSet<String>>,
+    by_month: BTreeMap<String, BTreeSet<String>>, // Ordered, e.g. "2025-03", for date ranges
+    by_project: HashMap<String, BTreeSet<String>>,
+    by_tag: HashMap<String, BTreeSet<String>>,
+}
+
+fn month(date: &str) -> String {
+    date.get(..7).unwrap_or(date).to_string()
+}
+
+fn add(index: &mut HashMap<String, BTreeSet<String>>, key: &str, id: &str) {
+    index.entry(key.to_string()).or_default().insert(id.to_string());
+}
+
+fn take(index: &mut HashMap<String, BTreeSet<String>>, key: &str, id: &str) {
+    if let Some(ids) = index.get_mut(key) {
+        ids.remove(id);
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl InvoiceIndex {
+    pub fn insert(&mut self, id: &str, keys: Keys) {
+        if self.keys.get(id) == Some(&keys) {
+            return;
+        }
+        self.remove(id);
+        add(&mut self.by_client, &keys.client, id);
+        add(&mut self.by_status, &keys.status, id);
+        self.by_month.entry(month(&keys.date)).or_default().insert(id.to_string());
+        if let Some(ref project) = keys.project {
+            add(&mut self.by_project, project, id);
+        }
+        for tag in &keys.tags {
+            add(&mut self.by_tag, tag, id);
+        }
+        self.keys.insert(id.to_string(), keys);
+    }
+
+    // Number of invoices indexed; should match the number of summaries
+    pub fn size(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(keys) = self.keys.remove(id) else {
+            return;
+        };
+        take(&mut self.by_client, &keys.client, id);
+        take(&mut self.by_status, &keys.status, id);
+        let month = month(&keys.date);
+        if let Some(ids) = self.by_month.get_mut(&month) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.by_month.remove(&month);
+            }
+        }
+        if let Some(ref project) = keys.project {
+            take(&mut self.by_project, project, id);
+        }
+        for tag in &keys.tags {
+            take(&mut self.by_tag, tag, id);
+        }
+    }
+
+    pub fn client(&self, client: &str) -> BTreeSet<String> {
+        self.by_client.get(client).cloned().unwrap_or_default()
+    }
+
+    pub fn project(&self, project: &str) -> BTreeSet<String> {
+        self.by_project.get(project).cloned().unwrap_or_default()
+    }
+
+    pub fn tag(&self, tag: &str) -> BTreeSet<String> {
+        self.by_tag.get(tag).cloned().unwrap_or_default()
+    }
+
+    // Invoices in any of the statuses
+    pub fn statuses(&self, statuses: &[String]) -> BTreeSet<String> {
+        statuses.iter()
+            .filter_map(|status| self.by_status.get(status))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    // Invoices dated between two ISO dates, inclusive; either end may be open
+    pub fn dated(&self, from: Option<&str>, to: Option<&str>) -> BTreeSet<String> {
+        let first = from.map(month).unwrap_or_default();
+        let last = to.map(month);
+        self.by_month.range(first..)
+            .take_while(|(m, _)| last.as_ref().map(|last| *m <= last).unwrap_or(true))
+            .flat_map(|(_, ids)| ids)
+            .filter(|id| {
+                let date = self.keys.get(*id).map(|k| k.date.as_str()).unwrap_or_default();
+                from.map(|from| date >= from).unwrap_or(true) && to.map(|to| date <= to).unwrap_or(true)
+            })
+            .cloned()
+            .collect()
+    }
+}
@@ -4,19 +4,73 @@
 use hyperprocess_macro::hyperprocess;
 
 use hyperware_process_lib::{
-    our,
+    Address, Request,
     homepage::add_to_homepage,
-    hyperapp::SaveOptions,
-    vfs::{self, create_drive, create_file, open_file, open_dir, remove_file},
+    eth,
+    hypermap,
+    hyperapp::{send, SaveOptions},
+    vfs::{create_drive, open_dir},
 };
 
+use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use base64::{Engine as _, engine::general_purpose};
+use sha2::{Digest, Sha256};
+
+mod access;
+mod api;
+mod barcode;
+mod boilerplate;
+mod commissions;
+mod currency;
+mod custom_fields;
+mod deltas;
+mod diagnostics;
+mod drafts;
+mod fonts;
+mod host;
+mod indexes;
+mod logos;
+mod margins;
+mod money;
+mod notifications;
+mod numbering;
+mod pagination;
+mod paste;
+mod pricing;
+mod qr;
+mod query;
+mod reminders;
+mod storage;
+mod store;
+mod subscriptions;
+mod tax_regimes;
+mod team;
+mod templates;
+mod words;
+
+use money::Money;
+
+// Records a diagnostics entry, e.g.
+// log!(Warn, "payments", "check_lightning_payments", Some(id.as_str()), "Lookup failed: {}", e)
+macro_rules! log {
+    ($level:ident, $module:expr, $operation:expr, $invoice_id:expr, $($arg:tt)*) => {
+        diagnostics::record(diagnostics::LogEntry {
+            at: host::now(),
+            level: diagnostics::Level::$level,
+            module: $module,
+            operation: $operation,
+            invoice_id: $invoice_id.map(|id: &str| id.to_string()),
+            request_id: None, // Filled in by record
+            message: format!($($arg)*),
+        })
+    };
+}
 
 const ICON: &str = include_str!("./icon");
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct InvoiceSettings {
     pub invoicer: ContactInfo,
     pub invoicee: ContactInfo,
@@ -24,9 +78,292 @@ pub struct InvoiceSettings {
     pub payment_image_path: Option<String>,
     pub invoice_number_prefix: String,
     pub next_invoice_number: u32,
+    #[serde(default)]
+    pub time_import_nodes: Vec<String>, // Other nodes allowed to push time entries
+    #[serde(default)]
+    pub zatca_qr_enabled: bool, // Embed the ZATCA (Saudi/GCC) e-invoice QR code on the default profile's invoices
+    #[serde(default)]
+    pub number_barcode: Option<barcode::NumberBarcode>, // Print the invoice number as a barcode for AP scanning
+    #[serde(default)]
+    pub epc_qr_enabled: bool, // Embed a SEPA (EPC069-12) QR code when payment info has an IBAN
+    #[serde(default)]
+    pub lightning: Option<LightningSettings>,
+    #[serde(default)]
+    pub stablecoins: Vec<StablecoinSettings>, // Tokens accepted via EIP-681 payment requests
+    #[serde(default)]
+    pub profiles: Vec<BusinessProfile>, // Additional entities invoices can be issued under
+    #[serde(default)]
+    pub email_inbox_address: Option<String>, // Only mail sent to this address is filed, if set
+    #[serde(default)]
+    pub email_ingest_processes: Vec<String>, // Mail processes allowed to push inbound email
+    #[serde(default)]
+    pub remittance_slip_enabled: bool, // Tear-off slip for clients who pay by cheque or transfer
+    #[serde(default)]
+    pub email_send_process: Option<String>, // Mail process used for outgoing email, e.g. share link codes
+    #[serde(default)]
+    pub approval_required: bool, // Invoices must be approved before they can be marked Sent
+    #[serde(default)]
+    pub approvers: Vec<String>, // Nodes allowed to approve or reject invoices
+    #[serde(default)]
+    pub utc_offset_minutes: i32, // Local standard time for "today", due dates and scheduling
+    #[serde(default)]
+    pub daylight_saving: Option<DaylightSaving>, // Adds an hour to the offset in summer
+    #[serde(default)]
+    pub payment_terms: Option<PaymentTerms>, // Given to new invoices, whose due date follows from them
+    #[serde(default)]
+    pub payment_terms_days: Option<u32>, // Net days, from before payment_terms; only read when that is unset
+    #[serde(default)]
+    pub roll_due_dates: bool, // Move computed due dates off weekends and holidays to the next business day
+    #[serde(default)]
+    pub holidays: Vec<String>, // ISO dates that are not business days
+    #[serde(default)]
+    pub totals_display: TotalsDisplay,
+    #[serde(default)]
+    pub rounding: Rounding, // Given to new invoices; invoices keep the rules they were created with
+    #[serde(default)]
+    pub language: words::Language, // Language amounts are written out in; invoices can override it
+    #[serde(default)]
+    pub tax: tax_regimes::TaxSettings, // Set in one step from a country preset with apply_tax_preset
+    #[serde(default)]
+    pub receipt_ocr_process: Option<String>, // Reads totals off receipts; entered expense amounts are checked against it when set
+    #[serde(default)]
+    pub font_family: Option<String>, // Uploaded font family the built-in layout uses
+    #[serde(default)]
+    pub logo_background: logos::LogoBackground, // How logos (ours and clients') sit on the page
+    #[serde(default)]
+    pub payment_method_fees: Vec<PaymentMethodFee>, // Alternative amounts shown on invoices, by how the client pays
+    #[serde(default)]
+    pub early_payment_terms: Option<EarlyPaymentTerms>, // Offered on new invoices
+    #[serde(default)]
+    pub late_fee: Option<LateFeePolicy>, // Charged on overdue invoices by check_late_fees
+    #[serde(default)]
+    pub default_unit: Option<String>, // Given to new line items that don't name a unit
+    #[serde(default)]
+    pub delivery_addresses_enabled: bool, // Invoices can have a Ship To address apart from the billing address
+    #[serde(default)]
+    pub client_purchase_orders: HashMap<String, String>, // Client ID -> PO number quoted on their new invoices
+    #[serde(default)]
+    pub default_text: boilerplate::DefaultText, // Notes, terms and thank-you text new invoices start with
+    #[serde(default)]
+    pub client_default_text: HashMap<String, boilerplate::DefaultText>, // Client ID -> text used instead, field by field
+    #[serde(default)]
+    pub custom_fields: Vec<custom_fields::FieldDefinition>, // Extra fields invoices and line items can carry
+    #[serde(default = "default_currency")]
+    pub default_currency: String, // ISO 4217 code new invoices are issued in; reports convert to it
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+// Daylight saving rules the local time follows, so the offset needn't be changed
+// twice a year
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum DaylightSaving {
+    Eu, // Last Sunday of March to last Sunday of October, switching at 01:00 UTC
+    NorthAmerica, // Second Sunday of March to first Sunday of November, switching at 02:00 local time
+}
+
+impl DaylightSaving {
+    // Whether summer time is in effect at a UTC timestamp, given the standard offset
+    fn in_effect(self, timestamp: u64, utc_offset_minutes: i32) -> bool {
+        let Ok(year) = date_from_timestamp(timestamp)[..4].parse::<i64>() else {
+            return false;
+        };
+        let offset = utc_offset_minutes as i64 * 60;
+        let (start, end) = match self {
+            DaylightSaving::Eu => (
+                last_sunday(year, 3) * 86400 + 3600,
+                last_sunday(year, 10) * 86400 + 3600,
+            ),
+            DaylightSaving::NorthAmerica => (
+                (first_sunday(year, 3) + 7) * 86400 + 2 * 3600 - offset,
+                first_sunday(year, 11) * 86400 + 2 * 3600 - offset - 3600,
+            ),
+        };
+        (start..end).contains(&(timestamp as i64))
+    }
+}
+
+// Days since the epoch of the first and last Sundays of a month
+fn first_sunday(year: i64, month: u32) -> i64 {
+    let first = days_from_date(&format!("{:04}-{:02}-01", year, month)).unwrap_or_default();
+    first + (7 - (first + 4).rem_euclid(7)) % 7
+}
+
+fn last_sunday(year: i64, month: u32) -> i64 {
+    let next_month = if month == 12 {
+        format!("{:04}-01-01", year + 1)
+    } else {
+        format!("{:04}-{:02}-01", year, month + 1)
+    };
+    let last = days_from_date(&next_month).unwrap_or_default() - 1;
+    last - (last + 4).rem_euclid(7)
+}
+
+// An entity invoices can be issued under, with its own letterhead and numbering.
+// The invoicer and numbering fields of InvoiceSettings act as the default profile.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct BusinessProfile {
+    pub id: String,
+    pub name: String,
+    pub invoicer: ContactInfo,
+    pub payment_info: Option<String>,
+    pub payment_image_path: Option<String>,
+    pub invoice_number_prefix: String,
+    pub next_invoice_number: u32,
+    #[serde(default)]
+    pub zatca_qr_enabled: bool, // Embed the ZATCA e-invoice QR code, for a profile registered for Saudi VAT
+}
+
+// Surcharge or discount for paying a certain way, e.g. 2% + 0.30 by card or -1% by bank transfer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct PaymentMethodFee {
+    pub id: String,
+    pub label: String, // e.g. "Credit card"
+    pub percent: f64, // Of the balance due; negative for a discount
+    #[serde(default)]
+    pub fixed: Money, // Added after the percentage, e.g. 0.30 per card payment
+}
+
+// Discount for paying within a number of days of the invoice date, e.g. 2% within
+// 10 days as in "2/10 net 30"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct EarlyPaymentTerms {
+    pub percent: f64,
+    pub days: u32,
+}
+
+impl EarlyPaymentTerms {
+    fn validate(&self) -> Result<(), String> {
+        if !self.percent.is_finite() || self.percent <= 0.0 || self.percent >= 100.0 {
+            return Err("Early payment discount must be between 0 and 100%".to_string());
+        }
+        if self.days == 0 {
+            return Err("Early payment window must be at least a day".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Fee for paying late, charged once for each period an invoice stays unpaid past
+// its due date, e.g. 25.00 every 30 days or 1.5% a month
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct LateFeePolicy {
+    pub charge: LateFeeCharge,
+    pub period_days: u32,
+    #[serde(default)]
+    pub grace_days: u32, // Days past the due date before the first fee
+    #[serde(default)]
+    pub max_periods: Option<u32>, // No more fees after this many; 1 charges a single fee
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum LateFeeCharge {
+    Flat(Money),
+    Percent(f64), // Of the amount that was due, each period; fees are never charged on fees
+}
+
+impl LateFeePolicy {
+    fn validate(&self) -> Result<(), String> {
+        match self.charge {
+            LateFeeCharge::Flat(amount) if amount <= Money::ZERO => {
+                return Err("Late fee must be more than zero".to_string());
+            }
+            LateFeeCharge::Percent(percent) if !percent.is_finite() || percent <= 0.0 || percent > 100.0 => {
+                return Err("Late fee must be between 0 and 100%".to_string());
+            }
+            _ => {}
+        }
+        if self.period_days == 0 {
+            return Err("Late fee period must be at least a day".to_string());
+        }
+        if self.max_periods == Some(0) {
+            return Err("Late fee limit must be at least one period".to_string());
+        }
+        Ok(())
+    }
+
+    // Fees owed after being a number of days overdue
+    fn periods(&self, days_overdue: i64) -> u32 {
+        let charged_days = days_overdue - self.grace_days as i64;
+        if charged_days <= 0 {
+            return 0;
+        }
+        let periods = 1 + ((charged_days - 1) / self.period_days as i64) as u32;
+        self.max_periods.map(|max| periods.min(max)).unwrap_or(periods)
+    }
+
+    // e.g. "1.5% of the amount due every 30 days overdue, after 5 days' grace"
+    fn describe(&self, currency: &str) -> String {
+        let charge = match self.charge {
+            LateFeeCharge::Flat(amount) => format_money(amount, currency),
+            LateFeeCharge::Percent(percent) => format!("{}% of the amount due", percent),
+        };
+        let mut text = if self.max_periods == Some(1) {
+            format!("{} once overdue", charge)
+        } else {
+            format!("{} every {} days overdue", charge, self.period_days)
+        };
+        if self.grace_days > 0 {
+            text.push_str(&format!(", after {} days' grace", self.grace_days));
+        }
+        text
+    }
+}
+
+// When an invoice is due, printed on it as e.g. "Net 30" or "Due on receipt"
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum PaymentTerms {
+    DueOnReceipt,
+    Net(u32), // Days after the invoice date, e.g. Net 15, 30 or 60
+}
+
+impl PaymentTerms {
+    fn days(self) -> u32 {
+        match self {
+            PaymentTerms::DueOnReceipt => 0,
+            PaymentTerms::Net(days) => days,
+        }
+    }
+
+    fn label(self) -> String {
+        match self {
+            PaymentTerms::DueOnReceipt => "Due on receipt".to_string(),
+            PaymentTerms::Net(days) => format!("Net {}", days),
+        }
+    }
+}
+
+// A payment recorded by hand with record_payment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct RecordedPayment {
+    pub method: Option<String>, // Label of the payment method fee applied, if any
+    pub amount: Money, // What the client paid, adjustment included
+    pub adjustment: Money, // Payment method surcharge (positive) or discount (negative) included in amount
+    #[serde(default)]
+    pub early_payment_discount: Money, // Taken off the balance due for paying within the early payment window
+    pub paid_on: String, // ISO date
+    pub reference: Option<String>, // e.g. a bank transfer or card transaction ID
+    pub recorded_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct LightningSettings {
+    pub process: String, // Address of the Lightning node process, e.g. "our@lightning:lnd:publisher.os"
+    pub sats_per_unit: f64, // Satoshis per unit of the currency below
+    #[serde(default = "default_currency")]
+    pub currency: String, // ISO 4217 code sats_per_unit is quoted in
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct StablecoinSettings {
+    pub symbol: String, // e.g. "USDC"
+    pub chain_id: u64,
+    pub token_address: String,
+    pub decimals: u8,
+    pub recipient: String, // Address payments are sent to
+    #[serde(default = "default_currency")]
+    pub currency: String, // ISO 4217 code one token is pegged to
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct ContactInfo {
     pub name: String,
     pub company: Option<String>,
@@ -34,9 +371,26 @@ pub struct ContactInfo {
     pub email: Option<String>,
     pub phone: Option<String>,
     pub logo_path: Option<String>,
+    #[serde(default)]
+    pub tax_id: Option<String>, // VAT/GST registration number
+    #[serde(default)]
+    pub state: Option<String>, // State or region, e.g. for India's GST place of supply
+    #[serde(default)]
+    pub node: Option<String>, // Hyperware node name, if the contact has one
+    #[serde(default)]
+    pub verified_identity: Option<VerifiedIdentity>, // Only set from a Hypermap lookup
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct VerifiedIdentity {
+    pub node: String,
+    pub owner: String, // Ethereum address owning the Hypermap entry
+    pub tba: String, // Token-bound account of the entry, usable as a payment address
+    pub name: Option<String>, // From the entry's ~name note, if set
+    pub verified_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct Invoice {
     pub id: String,
     pub number: String,
@@ -54,1144 +408,10841 @@ pub struct Invoice {
     pub status: InvoiceStatus,
     pub created_at: u64,
     pub updated_at: u64,
+    #[serde(default)]
+    pub kind: DocumentKind,
+    #[serde(default)]
+    pub signature: Option<Signature>, // Only set by sign_invoice
+    #[serde(default)]
+    pub terms: Option<TermsReference>, // Only set by attach_terms
+    #[serde(default)]
+    pub terms_acceptances: Vec<TermsAcceptance>, // Only set by accept_terms
+    #[serde(default)]
+    pub lightning_payment: Option<LightningPayment>, // Only set by create_lightning_invoice
+    #[serde(default)]
+    pub crypto_payment: Option<CryptoPayment>, // Only set by create_crypto_payment_request
+    #[serde(default)]
+    pub service_period_start: Option<String>, // ISO date
+    #[serde(default)]
+    pub service_period_end: Option<String>, // ISO date
+    #[serde(default)]
+    pub source_documents: Vec<SourceDocumentLink>, // Only set by consolidate_documents
+    #[serde(default)]
+    pub consolidated_into: Option<String>, // Only set by consolidate_documents
+    #[serde(default)]
+    pub profile_id: Option<String>, // Business profile issuing the invoice; None is the default
+    #[serde(default)]
+    pub reissued_from: Option<String>, // Only set by reissue_invoice
+    #[serde(default)]
+    pub reminders_sent: Vec<reminders::SentReminder>, // Only set by check_reminders
+    #[serde(default)]
+    pub approval: Option<Approval>, // Only set by the approval endpoints
+    #[serde(default)]
+    pub deposits_applied: Vec<DepositLink>, // Deposit invoices credited against this one; only set by apply_deposit
+    #[serde(default)]
+    pub applied_to: Vec<DepositLink>, // Invoices this deposit was credited to; only set by apply_deposit
+    #[serde(default)]
+    pub language: Option<words::Language>, // Overrides the language in settings
+    #[serde(default)]
+    pub place_of_supply: Option<String>, // State the supply is made to; defaults to the invoicee's state
+    #[serde(default)]
+    pub sales_taxes: Vec<tax_regimes::AppliedTax>, // Jurisdiction taxes making up tax_percent; only set by apply_jurisdictions
+    #[serde(default)]
+    pub tax_lines: Vec<TaxLine>, // Charged instead of tax_percent when given, each its own total row
+    #[serde(default)]
+    pub shipping: Option<ShippingCharge>,
+    #[serde(default)]
+    pub rounding: Rounding, // From settings when the invoice was created
+    #[serde(default)]
+    pub payment: Option<RecordedPayment>, // Only set by record_payment
+    #[serde(default)]
+    pub early_payment: Option<EarlyPaymentTerms>, // Discount offered for paying early
+    #[serde(default)]
+    pub payment_terms: Option<PaymentTerms>, // Printed on the invoice; the due date is computed from them on creation
+    #[serde(default)]
+    pub project: Option<String>, // Groups invoices in margin reports
+    #[serde(default)]
+    pub tags: Vec<String>, // Labels to find invoices by; never shown to the client
+    #[serde(default)]
+    pub commission: Option<Commission>, // Referral partner owed a share; never shown to the client
+    #[serde(default = "default_currency")]
+    pub currency: String, // ISO 4217 code all amounts on the invoice are in
+    #[serde(default)]
+    pub exchange_rate: Option<ExchangeRateSnapshot>, // Set when a foreign-currency invoice is sent, or by set_invoice_exchange_rate
+    #[serde(default)]
+    pub purchase_order: Option<String>, // Client's PO number, which many AP departments require
+    #[serde(default)]
+    pub reference: Option<String>, // Any other reference the client asked to be quoted
+    #[serde(default)]
+    pub delivery_address: Option<DeliveryAddress>, // Where goods go when not the invoicee's billing address
+    #[serde(default)]
+    pub custom_fields: custom_fields::Values, // Field name -> value, for the invoice fields defined in settings
+    #[serde(default)]
+    pub status_history: Vec<StatusChange>, // Oldest first; only set by status changes
+    #[serde(default)]
+    pub sent_at: Option<u64>, // When it was first marked Sent
+    #[serde(default)]
+    pub issued_documents: Vec<IssuedRecord>, // One per time it was marked Sent; the documents are in the store
+    #[serde(default)]
+    pub terms_text: Option<String>, // Printed under the notes; may hold the same tokens as default text
+    #[serde(default)]
+    pub thank_you: Option<String>, // Closing line of the document
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct LineItem {
-    pub id: String,
-    pub description: String,
-    pub quantity: f64,
-    pub rate: f64,
-    pub discount_percent: f64,
-    pub receipt_path: Option<String>, // Path to receipt file in VFS
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct IssuedRecord {
+    pub issued_at: u64,
+    pub sha256: String, // Of the HTML
 }
 
+// The document exactly as rendered when the invoice was marked Sent, which later
+// edits to the invoice never change
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct InvoiceSummary {
-    pub id: String,
+pub struct IssuedDocument {
+    pub invoice_id: String,
     pub number: String,
-    pub name: Option<String>,
-    pub date: String,
-    pub total: f64,
-    pub status: InvoiceStatus,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum InvoiceStatus {
-    Draft,
-    Sent,
-    Paid,
-    Overdue,
+    pub sequence: usize, // 0 for the first issue, 1 if it was sent again after a recall, ...
+    pub issued_at: u64,
+    pub sha256: String,
+    pub html: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct InvoiceSnapshot {
-    pub invoice: Invoice,
-    pub timestamp: u64,
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct StatusChange {
+    pub from: InvoiceStatus,
+    pub to: InvoiceStatus,
+    pub at: u64,
+    pub by: String, // Node that made the change
+    #[serde(default)]
+    pub metadata: HashMap<String, String>, // Given to set_invoice_status, e.g. how the invoice was sent
 }
 
-#[derive(Default, Serialize, Deserialize)]
-pub struct AppState {
-    pub settings: Option<InvoiceSettings>,
-    pub invoices: HashMap<String, InvoiceSummary>, // Key is invoice ID
-    pub current_invoice: Option<Invoice>,
-    pub undo_stack: Vec<InvoiceSnapshot>,
-    pub redo_stack: Vec<InvoiceSnapshot>,
-    pub last_save_time: u64,
-    pub has_unsaved_changes: bool,
+// Where goods are delivered, printed as "Ship To" beside the billing address
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct DeliveryAddress {
+    pub name: String, // Recipient
+    #[serde(default)]
+    pub company: Option<String>,
+    pub address: String, // Free-form, one line per line
+    #[serde(default)]
+    pub phone: Option<String>,
 }
 
-#[hyperprocess(
-    name = "Invoice",
-    ui = Some(HttpBindingConfig::default()),
-    endpoints = vec![
-        Binding::Http {
-            path: "/api",
-            config: HttpBindingConfig::default(),
+impl DeliveryAddress {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() && self.address.trim().is_empty() {
+            return Err("Delivery addresses need a recipient or an address".to_string());
         }
-    ],
-    save_config = SaveOptions::OnDiff,
-    wit_world = "invoice-nick-dot-hypr-v0"
-)]
-impl AppState {
-    // INITIALIZATION FUNCTION
-    // Runs once when your process starts
-    #[init]
-    async fn initialize(&mut self) {
-        // Add your app to the Hyperware homepage
-        add_to_homepage("Invoice", Some(ICON), Some("/"), None);
+        Ok(())
+    }
 
-        // Get our node identity
-        let _our_node = our().node.clone();
+    // Recipient, company, address and phone, escaped for HTML
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.name.clone()];
+        lines.extend(self.company.clone());
+        lines.extend(self.address.lines().map(|line| line.to_string()));
+        lines.extend(self.phone.clone());
+        lines.iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(escape_html)
+            .collect()
+    }
+}
 
-        // Create the invoice VFS drive
-        let package_id = our().package_id();
-        match create_drive(package_id, "invoice", Some(5)) {
-            Ok(drive_path) => {
-                // Load settings if they exist
-                let settings_path = format!("{}/settings.json", drive_path);
-                match open_file(&settings_path, false, Some(5)) {
-                    Ok(file) => {
-                        match file.read_to_string() {
-                            Ok(data) => {
-                                if let Ok(settings) = serde_json::from_str::<InvoiceSettings>(&data) {
-                                    self.settings = Some(settings);
-                                }
-                            }
-                            Err(_) => println!("No existing settings found"),
-                        }
-                    }
-                    Err(_) => println!("No settings file found"),
-                }
+// A named tax, e.g. GST 5% alongside PST 7%. A compound tax is charged on the
+// amount with the taxes listed before it included, as Quebec's QST once was.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TaxLine {
+    pub name: String,
+    pub percent: f64,
+    #[serde(default)]
+    pub compound: bool,
+}
 
-                // Load invoice summaries
-                self.load_invoice_summaries(&drive_path);
-            }
-            Err(e) => {
-                println!("Failed to create invoice drive: {:?}", e);
-            }
+impl TaxLine {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Tax lines need a name".to_string());
         }
+        if !self.percent.is_finite() || self.percent < 0.0 {
+            return Err(format!("Invalid rate for {}", self.name));
+        }
+        Ok(())
     }
+}
 
-    // Settings Management Endpoints
+// Shipping and handling, charged after the discount. It is taxed at the invoice's
+// rates only when marked taxable, since some places tax delivery and others don't.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ShippingCharge {
+    pub amount: Money,
+    #[serde(default)]
+    pub taxable: bool,
+}
 
-    #[http]
-    async fn get_settings(&self) -> Result<String, String> {
-        match &self.settings {
-            Some(settings) => serde_json::to_string(settings)
-                .map_err(|e| format!("Failed to serialize settings: {}", e)),
-            None => Ok("null".to_string()),
+impl ShippingCharge {
+    fn validate(&self) -> Result<(), String> {
+        if self.amount < Money::ZERO {
+            return Err("Shipping can't be negative".to_string());
         }
+        Ok(())
     }
+}
 
-    #[http]
-    async fn update_settings(&mut self, request_body: String) -> Result<String, String> {
-        let settings: InvoiceSettings = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid settings: {}", e))?;
+// Rate to the home currency taken when a foreign-currency invoice was sent, so it
+// is reported at what it was worth then
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ExchangeRateSnapshot {
+    pub home_currency: String,
+    pub rate: f64, // Units of the home currency per unit of the invoice's
+    pub home_total: Money, // Invoice total when the rate was taken, converted
+    pub taken_at: u64,
+}
 
-        self.settings = Some(settings.clone());
+// Partner credited with referring an invoice, and their share of it before tax
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Commission {
+    pub partner_id: String,
+    pub percent: f64,
+}
 
-        // Save settings to VFS
-        let package_id = our().package_id();
-        let drive_path = format!("/{}/invoice", package_id);
-        let settings_path = format!("{}/settings.json", drive_path);
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct LineItem {
+    pub id: String,
+    #[serde(default)]
+    pub kind: LineItemKind,
+    #[serde(default)]
+    pub item_code: Option<String>, // SKU or part number, printed in its own first column
+    pub description: String,
+    pub quantity: f64,
+    #[serde(default)]
+    pub unit: Option<String>, // What the quantity counts, e.g. "hours", "days", "pcs", "kg"
+    pub rate: f64, // Unit price, which may go below a cent; line amounts are rounded
+    pub discount_percent: f64,
+    pub receipt_path: Option<String>, // Path to receipt file in VFS
+    #[serde(default)]
+    pub section: Option<String>, // Heading the item is grouped under
+    #[serde(default)]
+    pub service_date_start: Option<String>, // ISO date the work was performed (or period start)
+    #[serde(default)]
+    pub service_date_end: Option<String>, // ISO date, for a period
+    #[serde(default)]
+    pub hsn_sac: Option<String>, // HSN (goods) or SAC (services) classification code
+    #[serde(default)]
+    pub catalog_item_id: Option<String>, // Catalog item the line was added from
+    #[serde(default)]
+    pub list_rate: Option<f64>, // Catalog price when a client or volume price lowered the rate
+    #[serde(default)]
+    pub cost: Option<f64>, // What each unit cost me, for margin reports; never shown to the client
+    #[serde(default)]
+    pub team_member_id: Option<String>, // Who did the work, for the team revenue report; never shown to the client
+    #[serde(default)]
+    pub custom_fields: custom_fields::Values, // Field name -> value, for the line item fields defined in settings
+    #[serde(default)]
+    pub time_entry_id: Option<String>, // Time entry the line bills, set by bill_time_entries
+}
 
-        match create_file(&settings_path, Some(5)) {
-            Ok(file) => {
-                let data = serde_json::to_vec(&settings)
-                    .map_err(|e| format!("Failed to serialize settings: {}", e))?;
-                file.write(&data)
-                    .map_err(|e| format!("Failed to write settings: {}", e))?;
-                Ok("Settings updated".to_string())
-            }
-            Err(e) => Err(format!("Failed to create settings file: {}", e)),
+// A text row is only its description, e.g. a heading or a note between items,
+// and never counts towards the totals
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum LineItemKind {
+    #[default]
+    Item,
+    Text,
+    LateFee, // Added and kept up to date by check_late_fees
+}
+
+impl LineItem {
+    // Quantity times rate and the line discount, each rounded to the minor unit
+    fn rounded_amount(&self, decimals: usize, mode: money::RoundingMode) -> Money {
+        if self.kind == LineItemKind::Text {
+            return Money::ZERO;
         }
+        let gross = Money::round(self.rate * self.quantity, decimals, mode);
+        gross - gross.percent_rounded(self.discount_percent, decimals, mode)
     }
 
-    #[http]
-    async fn upload_logo(&mut self, request_body: Vec<u8>) -> Result<String, String> {
-        let package_id = our().package_id();
-        let drive_path = format!("/{}/invoice", package_id);
-        let logo_path = format!("{}/logo.png", drive_path);
-
-        match create_file(&logo_path, Some(5)) {
-            Ok(file) => {
-                file.write(&request_body)
-                    .map_err(|e| format!("Failed to write logo: {}", e))?;
-                Ok(logo_path)
-            }
-            Err(e) => Err(format!("Failed to create logo file: {}", e)),
+    fn exact_amount(&self) -> f64 {
+        if self.kind == LineItemKind::Text {
+            return 0.0;
         }
+        self.rate * self.quantity * (1.0 - self.discount_percent / 100.0)
     }
 
-    #[http]
-    async fn upload_payment_image(&mut self, request_body: Vec<u8>) -> Result<String, String> {
-        let package_id = our().package_id();
-        let drive_path = format!("/{}/invoice", package_id);
-        let payment_path = format!("{}/payment.png", drive_path);
-
-        match create_file(&payment_path, Some(5)) {
-            Ok(file) => {
-                file.write(&request_body)
-                    .map_err(|e| format!("Failed to write payment image: {}", e))?;
-                Ok(payment_path)
-            }
-            Err(e) => Err(format!("Failed to create payment image file: {}", e)),
-        }
+    fn validate(&self) -> Result<(), String> {
+        validate_line_kind(self.kind, &self.description)?;
+        validate_service_dates(&self.service_date_start, &self.service_date_end)
     }
+}
 
-    // Invoice Management Endpoints
+fn validate_line_kind(kind: LineItemKind, description: &str) -> Result<(), String> {
+    if kind == LineItemKind::Text && description.trim().is_empty() {
+        return Err("Text rows need some text".to_string());
+    }
+    Ok(())
+}
 
-    #[http]
-    async fn list_invoices(&self) -> Result<String, String> {
-        let summaries: Vec<InvoiceSummary> = self.invoices.values().cloned().collect();
-        serde_json::to_string(&summaries)
-            .map_err(|e| format!("Failed to serialize invoices: {}", e))
+// A single service date, or a period whose end isn't before its start
+fn validate_service_dates(start: &Option<String>, end: &Option<String>) -> Result<(), String> {
+    for date in start.iter().chain(end) {
+        if !is_iso_date(date) {
+            return Err(format!("Invalid service date: {}", date));
+        }
+    }
+    match (start, end) {
+        (None, Some(_)) => Err("A service period needs a start date".to_string()),
+        (Some(start), Some(end)) if end < start => {
+            Err(format!("Service period ends ({}) before it starts ({})", end, start))
+        }
+        _ => Ok(()),
     }
+}
 
-    #[http]
-    async fn create_invoice(&mut self) -> Result<String, String> {
-        // Get current timestamp
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+// Line item as supplied to add_line_items and create_invoice; omitted fields take
+// the same defaults as add_line_item
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NewLineItem {
+    #[serde(default)]
+    pub kind: LineItemKind,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_quantity")]
+    pub quantity: f64,
+    #[serde(default)]
+    pub unit: Option<String>, // The default unit in settings when omitted
+    #[serde(default)]
+    pub rate: f64,
+    #[serde(default)]
+    pub discount_percent: f64,
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub item_code: Option<String>,
+    #[serde(default)]
+    pub service_date_start: Option<String>,
+    #[serde(default)]
+    pub service_date_end: Option<String>,
+    #[serde(default)]
+    pub hsn_sac: Option<String>,
+    #[serde(default)]
+    pub cost: Option<f64>,
+    #[serde(default)]
+    pub team_member_id: Option<String>,
+    #[serde(default)]
+    pub custom_fields: custom_fields::Values,
+}
 
-        // Generate invoice number
-        let invoice_number = if let Some(ref mut settings) = self.settings {
-            let number = format!("{}{:04}", settings.invoice_number_prefix, settings.next_invoice_number);
-            settings.next_invoice_number += 1;
+fn default_quantity() -> f64 {
+    1.0
+}
 
-            // Save updated settings to VFS
-            let package_id = our().package_id();
-            let drive_path = format!("/{}/invoice", package_id);
-            let settings_path = format!("{}/settings.json", drive_path);
+// Invoices and settings from before currencies were tracked were in dollars
+fn default_currency() -> String {
+    currency::DEFAULT_CODE.to_string()
+}
 
-            if let Ok(file) = create_file(&settings_path, Some(5)) {
-                if let Ok(data) = serde_json::to_vec(&settings) {
-                    let _ = file.write(&data);
-                }
-            }
+impl NewLineItem {
+    fn validate(&self) -> Result<(), String> {
+        if self.kind == LineItemKind::LateFee {
+            return Err("Late fees are only added for overdue invoices".to_string());
+        }
+        validate_line_kind(self.kind, &self.description)?;
+        validate_service_dates(&self.service_date_start, &self.service_date_end)
+    }
 
-            number
-        } else {
-            format!("INV-{:04}", self.invoices.len() + 1)
-        };
+    fn into_line_item(self, id: String, default_unit: &Option<String>) -> LineItem {
+        LineItem {
+            id,
+            kind: self.kind,
+            item_code: self.item_code.filter(|code| !code.trim().is_empty()),
+            description: self.description,
+            quantity: self.quantity,
+            unit: self.unit.or_else(|| default_unit.clone()),
+            rate: self.rate,
+            discount_percent: self.discount_percent,
+            section: self.section,
+            service_date_start: self.service_date_start,
+            service_date_end: self.service_date_end,
+            hsn_sac: self.hsn_sac,
+            cost: self.cost,
+            team_member_id: self.team_member_id,
+            custom_fields: self.custom_fields,
+            ..Default::default()
+        }
+    }
+}
 
-        // Generate unique ID
-        let id = format!("{}-{}", timestamp, invoice_number);
+// Optional payload for create_invoice; anything omitted comes from settings
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NewInvoice {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub due_date: Option<String>,
+    #[serde(default)]
+    pub invoicee: Option<ContactInfo>,
+    #[serde(default)]
+    pub kind: DocumentKind,
+    #[serde(default)]
+    pub line_items: Vec<NewLineItem>,
+    #[serde(default)]
+    pub discount_percent: f64,
+    #[serde(default)]
+    pub tax_percent: f64,
+    #[serde(default)]
+    pub tax_lines: Vec<TaxLine>, // Replace tax_percent when given
+    #[serde(default)]
+    pub shipping: Option<ShippingCharge>,
+    #[serde(default)]
+    pub notes: Option<String>, // Default notes from settings when omitted
+    #[serde(default)]
+    pub terms_text: Option<String>, // Default terms from settings when omitted
+    #[serde(default)]
+    pub thank_you: Option<String>, // Default thank-you text from settings when omitted
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    #[serde(default)]
+    pub payment_terms: Option<PaymentTerms>, // Overrides the terms in settings
+    #[serde(default)]
+    pub payment_terms_days: Option<u32>, // Same as Net terms of this many days
+    #[serde(default)]
+    pub early_payment: Option<EarlyPaymentTerms>, // Overrides the early payment terms in settings
+    #[serde(default)]
+    pub number: Option<String>, // Manual number instead of the next one in the sequence
+    #[serde(default)]
+    pub currency: Option<String>, // Overrides the default currency in settings
+    #[serde(default)]
+    pub purchase_order: Option<String>, // Overrides the client's PO number in settings
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub delivery_address: Option<DeliveryAddress>,
+    #[serde(default)]
+    pub custom_fields: custom_fields::Values,
+}
 
-        // Get current date
-        // Get current date - simple approximation for YYYY-MM-DD
-        let date = {
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            // Simple date calculation (not accurate for all cases, but works for demo)
-            let days_since_epoch = now / 86400;
-            let year = 1970 + (days_since_epoch / 365) as u32;
-            let month = ((days_since_epoch % 365) / 30) as u32 + 1;
-            let day = ((days_since_epoch % 365) % 30) as u32 + 1;
-            format!("{:04}-{:02}-{:02}", year, month, day)
-        };
+// Filters applied when rendering an export for a particular audience
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub redact_rates: bool, // Only descriptions, dates and quantities; no prices, totals or payment details
+    #[serde(default)]
+    pub hide_internal_fields: bool, // Drops receipts and links to source documents
+    #[serde(default)]
+    pub totals: Option<TotalsDisplay>, // Overrides the totals rows chosen in settings
+    #[serde(default)]
+    pub copy_label: Option<String>, // Marks the copy, e.g. "ORIGINAL FOR RECIPIENT"
+    #[serde(default)]
+    pub template_id: Option<String>, // Custom template to render with instead of the built-in layout
+    #[serde(default)]
+    pub page_layout: Option<pagination::PageLayout>, // Rows per printed page of the line item table; defaults apply when None
+}
 
-        // Create new invoice
-        let invoice = Invoice {
-            id: id.clone(),
-            number: invoice_number.clone(),
-            name: None,
-            date: date.clone(),
-            due_date: None,
-            invoicer: self.settings.as_ref().map(|s| s.invoicer.clone())
-                .unwrap_or(ContactInfo {
-                    name: String::new(),
-                    company: None,
-                    address: String::new(),
-                    email: None,
-                    phone: None,
-                    logo_path: None,
-                }),
-            invoicee: self.settings.as_ref().map(|s| s.invoicee.clone())
-                .unwrap_or(ContactInfo {
-                    name: String::new(),
-                    company: None,
-                    address: String::new(),
-                    email: None,
-                    phone: None,
-                    logo_path: None,
-                }),
-            line_items: vec![],
-            discount_percent: 0.0,
-            tax_percent: 0.0,
-            notes: None,
-            payment_info: self.settings.as_ref().and_then(|s| s.payment_info.clone()),
-            payment_image_path: self.settings.as_ref().and_then(|s| s.payment_image_path.clone()),
-            status: InvoiceStatus::Draft,
-            created_at: timestamp,
-            updated_at: timestamp,
-        };
+// Copy labels for the usual set of marked copies (e.g. India's GST invoice rules)
+const DEFAULT_COPY_LABELS: [&str; 3] = [
+    "ORIGINAL FOR RECIPIENT",
+    "DUPLICATE FOR TRANSPORTER",
+    "TRIPLICATE FOR SUPPLIER",
+];
+
+// Which rows the totals block of a rendered invoice shows
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TotalsDisplay {
+    #[serde(default = "default_true")]
+    pub show_subtotal: bool,
+    #[serde(default)]
+    pub hide_zero_discount: bool,
+    #[serde(default)]
+    pub hide_zero_tax: bool,
+    #[serde(default)]
+    pub show_balance_due: bool, // End with what is still owed (after deposits and payment) rather than the total
+    #[serde(default)]
+    pub show_amount_in_words: bool,
+    #[serde(default)]
+    pub show_section_subtotals: bool, // Repeat each section's subtotal above the subtotal row
+}
 
-        // Set as current invoice
-        self.current_invoice = Some(invoice.clone());
-        self.has_unsaved_changes = true;
+fn default_true() -> bool {
+    true
+}
 
-        // Add to summaries
-        let summary = InvoiceSummary {
-            id: invoice.id.clone(),
-            number: invoice.number.clone(),
-            name: invoice.name.clone(),
-            date: invoice.date.clone(),
-            total: 0.0,
-            status: invoice.status.clone(),
-        };
-        self.invoices.insert(invoice.id.clone(), summary);
+// When amounts are rounded to the cent. Accountants differ, and an invoice totalled
+// one way can be a cent off what the client's books make it.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Rounding {
+    #[serde(default)]
+    pub stage: RoundingStage,
+    #[serde(default)]
+    pub mode: money::RoundingMode,
+}
 
-        // Save invoice
-        self.save_current_invoice()?;
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum RoundingStage {
+    #[default]
+    PerLine, // Each line, the discount and each tax are rounded, and the rounded amounts added up
+    AtTotal, // Amounts are added up unrounded and only the total is rounded
+}
 
-        serde_json::to_string(&invoice)
-            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+impl Default for TotalsDisplay {
+    fn default() -> Self {
+        TotalsDisplay {
+            show_subtotal: true,
+            hide_zero_discount: false,
+            hide_zero_tax: false,
+            show_balance_due: false,
+            show_amount_in_words: false,
+            show_section_subtotals: false,
+        }
     }
+}
 
-    #[http]
-    async fn get_invoice(&mut self, request_body: String) -> Result<String, String> {
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct InvoiceSummary {
+    pub id: String,
+    pub number: String,
+    pub name: Option<String>,
+    pub date: String,
+    pub total: Money,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub home_total: Option<Money>, // At the invoice's exchange rate; None for invoices without one
+    pub status: InvoiceStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum InvoiceStatus {
+    Draft,
+    PendingApproval,
+    Approved,
+    Sent,
+    Paid,
+    Overdue,
+}
+
+// Statuses of invoices that have been issued, which reports count
+const ISSUED_STATUSES: &[InvoiceStatus] = &[InvoiceStatus::Sent, InvoiceStatus::Paid, InvoiceStatus::Overdue];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum DocumentKind {
+    #[default]
+    Invoice,
+    Estimate,
+    DeliveryNote,
+}
+
+impl DocumentKind {
+    fn label(&self) -> &'static str {
+        match self {
+            DocumentKind::Invoice => "Invoice",
+            DocumentKind::Estimate => "Estimate",
+            DocumentKind::DeliveryNote => "Delivery Note",
+        }
+    }
+}
+
+// Link from a consolidated invoice back to one of the documents it bills
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct SourceDocumentLink {
+    pub id: String,
+    pub number: String,
+    pub kind: DocumentKind,
+}
+
+// One side of a deposit credited against a final invoice
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct DepositLink {
+    pub invoice_id: String,
+    pub number: String,
+    pub amount: Money,
+}
+
+// Latest pass through the approval workflow; every step is also in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Approval {
+    pub submitted_at: u64,
+    pub document_hash: String, // Content the approver is asked to approve
+    pub approved: Option<bool>, // None while pending
+    pub decided_by: Option<String>, // Approver node
+    pub decided_at: Option<u64>,
+    pub rejection_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AuditAction {
+    SubmittedForApproval,
+    Approved,
+    Rejected,
+    Sent,
+    CopyGenerated, // Detail is the copy label
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub actor: String, // Node that took the action
+    pub action: AuditAction,
+    pub invoice_id: String,
+    pub invoice_number: String,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum DeliveryChannel {
+    ShareLink,
+    Email,
+    P2p,
+    Reminder, // By email
+}
+
+// An invoice, link or message about an invoice going out, whether or not it got there
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeliveryRecord {
+    pub at: u64,
+    pub invoice_id: String,
+    pub channel: DeliveryChannel,
+    pub destination: String, // Email address or node; for share links, who the link is for
+    pub error: Option<String>, // None when it went through
+    pub detail: Option<String>, // e.g. the subject of a reminder
+    #[serde(default)]
+    pub request_id: Option<String>, // Request that made the attempt, as quoted in diagnostics
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Signature {
+    pub signer_name: String,
+    pub signed_at: u64,
+    pub image_path: String, // Path to drawn signature image in VFS
+    pub document_hash: String, // SHA-256 of the document content at signing time
+    pub signature_hash: String, // SHA-256 binding the document hash to this signature
+    #[serde(default)]
+    pub signer_node: Option<String>, // Node that signed through a share link; None when signed in the app
+    #[serde(default)]
+    pub signer_email: Option<String>, // Address an email-restricted link proved the signer holds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TermsDocument {
+    pub id: String,
+    pub version: u32,
+    pub title: String,
+    pub file_name: String,
+    pub path: String, // Path to the document in VFS
+    pub hash: String, // SHA-256 of the document bytes
+    pub uploaded_at: u64,
+}
+
+// A saved custom template; its assets live in the drive under templates/<id>/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct CustomTemplate {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub source: String,
+    #[serde(default)]
+    pub assets: Vec<TemplateAsset>,
+    #[serde(default)]
+    pub imported: bool, // Came from a shared bundle
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TemplateAsset {
+    pub name: String, // Referred to as "asset:<name>" in the template
+    pub content_type: String,
+    pub path: String, // Path to the asset in VFS
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TermsReference {
+    pub terms_id: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TermsAcceptance {
+    pub terms_id: String,
+    pub version: u32,
+    pub accepted_by: String,
+    pub email: Option<String>,
+    pub accepted_at: u64,
+    #[serde(default)]
+    pub via_link_from: Option<String>, // Node that accepted through a share link
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct LightningPayment {
+    pub bolt11: String,
+    pub payment_hash: String,
+    pub amount_msat: u64,
+    pub created_at: u64,
+    pub settled_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct CryptoPayment {
+    pub symbol: String,
+    pub chain_id: u64,
+    pub token_address: String,
+    pub recipient: String,
+    pub amount: String, // Exact amount in token base units; sub-cent digits identify the invoice
+    pub uri: String, // EIP-681 payment request
+    pub from_block: u64, // Transfers before this block are ignored
+    pub created_at: u64,
+    pub tx_hash: Option<String>,
+    pub paid_at: Option<u64>,
+}
+
+// Requests understood by the Lightning node process
+#[derive(Debug, Serialize)]
+enum LightningRequest {
+    CreateInvoice { amount_msat: u64, memo: String },
+    LookupInvoice { payment_hash: String },
+}
+
+#[derive(Debug, Deserialize)]
+enum LightningResponse {
+    Invoice { bolt11: String, payment_hash: String },
+    Status { settled: bool },
+    Error(String),
+}
+
+// Requests understood by the receipt OCR process (an OCR engine or LLM wrapper)
+#[derive(Debug, Serialize)]
+enum OcrRequest {
+    DetectTotal { file_name: String, content_type: String, data: Vec<u8> },
+}
+
+#[derive(Debug, Deserialize)]
+enum OcrResponse {
+    Total { amount: Option<f64> },
+    Error(String),
+}
+
+// Requests understood by the outgoing mail process
+#[derive(Debug, Serialize)]
+enum MailRequest {
+    Send { to: String, subject: String, body: String },
+}
+
+#[derive(Debug, Deserialize)]
+enum MailResponse {
+    Sent,
+    Error(String),
+}
+
+// Requests understood by the Hyperware notifications process
+#[derive(Debug, Serialize)]
+enum PushRequest {
+    Push { title: String, body: String },
+}
+
+#[derive(Debug, Deserialize)]
+enum PushResponse {
+    Pushed,
+    Error(String),
+}
+
+// A link that lets someone outside the app view one invoice. Either requirement,
+// when set, must be met before the invoice is shown.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShareLink {
+    pub token: String,
+    pub invoice_id: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub revoked: bool,
+    pub passcode_hash: Option<String>, // SHA-256 of token and passcode; stripped from responses
+    pub requires_passcode: bool,
+    pub allowed_email: Option<String>, // Viewer must prove this address with a one-time code
+}
+
+impl ShareLink {
+    fn without_secrets(&self) -> ShareLink {
+        ShareLink {
+            passcode_hash: None,
+            ..self.clone()
+        }
+    }
+}
+
+// One-time code mailed to the allowed address of a share link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareCode {
+    pub code_hash: String,
+    pub expires_at: u64,
+    #[serde(default)]
+    pub misses: u32, // Wrong codes tried; the code is dropped after access::MAX_CODE_GUESSES
+}
+
+// What a share link asks for, sent with every request made through it
+#[derive(Debug, Clone, Deserialize)]
+struct ShareAccess {
+    token: String,
+    #[serde(default)]
+    passcode: Option<String>,
+    #[serde(default)]
+    code: Option<String>, // From request_share_code, for email-restricted links
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TimeEntry {
+    pub id: String,
+    pub source: String, // Address of the process that pushed the entry
+    pub external_id: String,
+    pub date: String,
+    pub description: String,
+    pub hours: f64,
+    pub rate: Option<f64>,
+    pub billed_invoice_id: Option<String>,
+    pub imported_at: u64,
+}
+
+// Payload time-tracking apps send to import_time_entries
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TimeEntryImport {
+    pub external_id: String,
+    pub date: String,
+    pub description: String,
+    pub hours: f64,
+    pub rate: Option<f64>,
+}
+
+impl TimeEntryImport {
+    fn validate(&self) -> Result<(), String> {
+        if !is_iso_date(&self.date) {
+            return Err(format!("Invalid date for time entry {}: {}", self.external_id, self.date));
+        }
+        if !self.hours.is_finite() || self.hours < 0.0 {
+            return Err(format!("Invalid hours for time entry {}", self.external_id));
+        }
+        if self.rate.is_some_and(|rate| !rate.is_finite() || rate < 0.0) {
+            return Err(format!("Invalid rate for time entry {}", self.external_id));
+        }
+        Ok(())
+    }
+}
+
+// A vendor invoice or other expense received from outside
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Expense {
+    pub id: String,
+    pub vendor: String,
+    pub vendor_email: Option<String>,
+    pub description: String,
+    pub date: String,
+    pub amount: Option<f64>,
+    pub category: Option<String>,
+    pub attachments: Vec<String>, // VFS paths of the filed documents
+    pub message_id: Option<String>, // Set for emailed invoices; used to skip duplicates
+    pub received_at: u64,
+    #[serde(default)]
+    pub amount_check: Option<AmountCheck>, // Only set by receipt verification
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum AmountCheckStatus {
+    Matched,
+    Mismatch, // Flagged for review
+    Unreadable, // No total could be read off the receipt
+    Reviewed, // Mismatch accepted by the user
+}
+
+// Entered expense amount compared with the total read off its receipt
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct AmountCheck {
+    pub entered: f64,
+    pub detected: Option<f64>,
+    pub receipt_path: String,
+    pub status: AmountCheckStatus,
+    pub checked_at: u64,
+    pub error: Option<String>, // Why the receipt could not be read
+}
+
+// Payload mail processes (IMAP pollers, inbound webhook bridges) send to ingest_email
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InboundEmail {
+    pub message_id: String,
+    pub from: String,
+    #[serde(default)]
+    pub to: Vec<String>,
+    pub subject: String,
+    #[serde(default)]
+    pub attachments: Vec<EmailAttachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EmailAttachment {
+    pub file_name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+// A receipt uploaded without a line item, waiting to be filed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct UnfiledReceipt {
+    pub id: String,
+    pub file_name: String,
+    pub path: String,
+    pub uploaded_at: u64,
+    #[serde(default)]
+    pub category: Option<String>, // Suggested by category rules; carried over when filed to an expense
+}
+
+// Assigns a category to expenses and receipts. Every condition that is set must
+// match; rules are tried in order and the first match wins.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct CategoryRule {
+    #[serde(default)]
+    pub id: String,
+    pub category: String,
+    #[serde(default)]
+    pub vendor_contains: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>, // Any one of these in the description or file names
+    #[serde(default)]
+    pub min_amount: Option<f64>,
+    #[serde(default)]
+    pub max_amount: Option<f64>,
+}
+
+// A reminder to chase an invoice, e.g. "call accounting on Friday"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct FollowUp {
+    pub id: String,
+    pub invoice_id: String,
+    pub note: String,
+    pub due_date: String, // ISO date; moved forward by snooze_followup
+    pub done: bool,
+    pub created_at: u64,
+    pub completed_at: Option<u64>,
+    #[serde(default)]
+    pub notified: bool, // A FollowUpDue notification was queued for the current due date
+}
+
+// An invoice queued to go out at a later time
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledSend {
+    pub id: String,
+    pub invoice_id: String,
+    pub send_at: u64,
+    pub email: bool, // Email the invoicee a summary
+    pub p2p: bool, // Deliver to the invoicee's node
+    pub created_at: u64,
+    pub last_error: Option<String>, // Set when a delivery attempt failed; retried on the next check
+    #[serde(default)]
+    pub request_id: Option<String>, // Request that scheduled it, as quoted in diagnostics
+}
+
+// A retainer billed monthly in advance; check_subscriptions invoices each period
+// once it starts
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Subscription {
+    pub id: String,
+    pub name: String, // Line item description, e.g. "Monthly retainer"
+    pub invoicee: ContactInfo,
+    pub plan: subscriptions::Plan,
+    #[serde(default)]
+    pub profile_id: Option<String>, // Business profile the invoices are issued under
+    pub next_period_start: String, // First day not yet invoiced
+    #[serde(default)]
+    pub billed: Vec<subscriptions::BilledPeriod>, // Oldest first
+    pub created_at: u64,
+}
+
+// Someone who refers clients in exchange for a share of what they are billed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Partner {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    pub default_percent: f64, // Suggested for invoices they refer
+    pub created_at: u64,
+}
+
+// Commission paid out to a partner, recorded with record_partner_payout
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct PartnerPayout {
+    pub id: String,
+    pub partner_id: String,
+    pub amount: Money,
+    pub paid_on: String, // ISO date
+    pub reference: Option<String>,
+    pub recorded_at: u64,
+}
+
+// Someone on the team line items can be credited to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TeamMember {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    pub created_at: u64,
+}
+
+// Current rate of a foreign currency, taken onto invoices in it when they are sent
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub home_currency: String, // Rates for another home currency are ignored
+    pub rate: f64, // Units of the home currency per unit of this one
+    pub updated_at: u64,
+}
+
+// An invoice another node delivered to us
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReceivedInvoice {
+    pub id: String,
+    pub from: String, // Sending node
+    pub number: String,
+    pub total: Money,
+    pub due_date: Option<String>,
+    pub html: String,
+    pub received_at: u64,
+}
+
+// An invoice that couldn't be read at startup and was moved out of the way
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuarantinedInvoice {
+    pub key: String, // Where it was stored: file path in the VFS, invoice ID in SQLite
+    pub moved_to: Option<String>, // None when it couldn't be moved and is still in place
+    pub error: String,
+    pub at: u64,
+    pub invoice_id: Option<String>, // When a summary identified it
+    pub recovered_from: Option<String>, // Which copy was put back in its place, if any
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceSnapshot {
+    pub invoice: Invoice,
+    pub timestamp: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct AppState {
+    pub settings: Option<InvoiceSettings>,
+    pub invoices: HashMap<String, InvoiceSummary>, // Key is invoice ID
+    pub current_invoice: Option<Invoice>,
+    pub undo_stack: Vec<InvoiceSnapshot>,
+    pub redo_stack: Vec<InvoiceSnapshot>,
+    pub last_save_time: u64,
+    pub has_unsaved_changes: bool,
+    #[serde(default)]
+    pub time_entries: HashMap<String, TimeEntry>, // Key is time entry ID
+    #[serde(default)]
+    pub terms_documents: Vec<TermsDocument>, // Every uploaded version
+    #[serde(default)]
+    pub custom_templates: Vec<CustomTemplate>,
+    #[serde(default)]
+    pub fonts: Vec<fonts::CustomFont>,
+    #[serde(default)]
+    pub catalog: Vec<pricing::CatalogItem>,
+    #[serde(default)]
+    pub client_prices: HashMap<String, HashMap<String, pricing::ClientPrice>>, // Client ID -> catalog item ID -> price
+    #[serde(default)]
+    pub client_logos: HashMap<String, String>, // Client ID -> logo in the client's folder; only set by upload_client_logo
+    #[serde(default)]
+    pub pending_lightning: HashMap<String, String>, // Invoice ID -> unsettled payment hash
+    #[serde(default)]
+    pub pending_crypto: HashMap<String, CryptoPayment>, // Invoice ID -> unpaid request
+    #[serde(default)]
+    pub expenses: HashMap<String, Expense>, // Key is expense ID
+    #[serde(default)]
+    pub unfiled_receipts: Vec<UnfiledReceipt>, // Uploaded but not yet attached to anything
+    #[serde(default)]
+    pub category_rules: Vec<CategoryRule>, // In priority order
+    #[serde(default)]
+    pub receipt_hashes: HashMap<String, Vec<String>>, // SHA-256 of receipt contents -> stored paths
+    #[serde(default)]
+    pub access_guard: access::AccessGuard, // Limits and logs calls from other nodes and processes
+    #[serde(default)]
+    pub encryption: storage::EncryptionConfig, // How invoices and settings are encrypted, in the VFS or SQLite
+    #[serde(default)]
+    pub storage_backend: store::Backend, // Where invoices are kept; changed with set_storage_backend
+    #[serde(default)]
+    pub share_links: HashMap<String, ShareLink>, // Key is the link token
+    #[serde(default)]
+    pub share_codes: HashMap<String, ShareCode>, // Link token -> outstanding one-time code
+    #[serde(default)]
+    pub notification_preferences: notifications::NotificationPreferences,
+    #[serde(default)]
+    pub notifications: Vec<notifications::Notification>, // Undelivered ones wait for check_notifications
+    #[serde(default)]
+    pub last_digest_at: u64,
+    #[serde(default)]
+    pub default_reminder_policy: Option<reminders::ReminderPolicy>, // None sends no reminders unless a client has a policy
+    #[serde(default)]
+    pub reminder_policies: HashMap<String, reminders::ReminderPolicy>, // Keyed by client ID
+    #[serde(default)]
+    pub client_jurisdictions: HashMap<String, Vec<String>>, // Tax jurisdiction IDs of each client's address, keyed by client ID
+    #[serde(default)]
+    pub followups: HashMap<String, FollowUp>, // Key is follow-up ID
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>, // Oldest first; never trimmed
+    #[serde(default)]
+    pub delivery_log: Vec<DeliveryRecord>, // Oldest first; never trimmed
+    #[serde(default)]
+    pub scheduled_sends: Vec<ScheduledSend>, // Pending only; removed once sent or cancelled
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+    #[serde(default)]
+    pub received_invoices: Vec<ReceivedInvoice>,
+    #[serde(default)]
+    pub partners: Vec<Partner>,
+    #[serde(default)]
+    pub partner_payouts: Vec<PartnerPayout>, // Oldest first
+    #[serde(default)]
+    pub exchange_rates: Vec<ExchangeRate>,
+    #[serde(default)]
+    pub team_members: Vec<TeamMember>,
+    #[serde(skip)]
+    pub draft_buffers: drafts::DraftBuffers, // Per editing session; only live in memory
+    #[serde(default)]
+    pub summary_log: deltas::SummaryLog, // Versions of the summaries, for get_summary_deltas
+    #[serde(skip)]
+    pub invoice_index: indexes::InvoiceIndex, // Built as summaries are loaded at startup
+    #[serde(default)]
+    pub deleted_numbers: Vec<String>, // Numbers of deleted invoices, never reused
+    #[serde(default)]
+    pub quarantined: Vec<QuarantinedInvoice>, // Oldest first; shown by get_diagnostics
+}
+
+#[hyperprocess(
+    name = "Invoice",
+    ui = Some(HttpBindingConfig::default()),
+    endpoints = vec![
+        Binding::Http {
+            path: "/api",
+            config: HttpBindingConfig::default(),
+        },
+        Binding::Http {
+            path: "/api/v1",
+            config: HttpBindingConfig::default(),
+        }
+    ],
+    save_config = SaveOptions::OnDiff,
+    wit_world = "invoice-nick-dot-hypr-v0"
+)]
+impl AppState {
+    // INITIALIZATION FUNCTION
+    // Runs once when your process starts
+    #[init]
+    async fn initialize(&mut self) {
+        // Add your app to the Hyperware homepage
+        add_to_homepage("Invoice", Some(ICON), Some("/"), None);
+
+        // Get our node identity
+        let _our_node = host::address().node.clone();
+
+        // Passphrase-encrypted storage stays locked until unlock_storage is called
+        storage::set_key(&self.encryption.mode, self.encryption.startup_key());
+
+        // Create the invoice VFS drive
+        let package_id = host::address().package_id();
+        match create_drive(package_id, "invoice", Some(5)) {
+            Ok(_) => {
+                if self.storage_backend == store::Backend::Sqlite {
+                    match sqlite_store() {
+                        Ok(db) => store::install(Some(Box::new(db))),
+                        Err(e) => log!(Error, "store", "init", None, "Could not open the invoice database, reading the VFS instead: {}", e),
+                    }
+                }
+
+                // Load settings if they exist
+                match with_store(|store| store.load_settings()) {
+                    Ok(Some(data)) => {
+                        if let Ok(settings) = serde_json::from_value::<InvoiceSettings>(data) {
+                            self.settings = Some(settings);
+                        }
+                    }
+                    Ok(None) => log!(Info, "store", "init", None, "No settings file found"),
+                    Err(e) => log!(Error, "store", "init", None, "Could not read settings: {}", e),
+                }
+
+                // Load invoice summaries
+                self.load_invoice_summaries();
+            }
+            Err(e) => {
+                log!(Error, "store", "init", None, "Failed to create invoice drive: {:?}", e);
+            }
+        }
+    }
+
+    // Settings Management Endpoints
+
+    #[http]
+    async fn get_settings(&self) -> Result<String, String> {
+        let _trace = trace("get_settings");
+        match &self.settings {
+            Some(settings) => serde_json::to_string(settings)
+                .map_err(|e| format!("Failed to serialize settings: {}", e)),
+            None => Ok("null".to_string()),
+        }
+    }
+
+    #[http]
+    async fn update_settings(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_settings");
+        let mut settings: InvoiceSettings = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid settings: {}", e))?;
+
+        // Verified identities can only come from a Hypermap lookup
+        let existing_identity = self.settings.as_ref()
+            .and_then(|s| s.invoicee.verified_identity.clone());
+        settings.invoicee.verified_identity = existing_identity
+            .filter(|v| settings.invoicee.node.as_ref() == Some(&v.node));
+        settings.invoicer.verified_identity = None;
+        if !(-720..=840).contains(&settings.utc_offset_minutes) {
+            return Err("UTC offset must be between -12:00 and +14:00".to_string());
+        }
+        if let Some(holiday) = settings.holidays.iter().find(|h| !is_iso_date(h)) {
+            return Err(format!("Invalid holiday date: {}", holiday));
+        }
+        settings.holidays.sort();
+        settings.holidays.dedup();
+        settings.tax.validate()?;
+        let payment_currencies = settings.lightning.iter().map(|l| &l.currency)
+            .chain(settings.stablecoins.iter().map(|c| &c.currency));
+        for code in std::iter::once(&settings.default_currency).chain(payment_currencies) {
+            if !currency::is_valid_code(code) {
+                return Err(format!("Invalid currency code: {}", code));
+            }
+        }
+        if let Some(ref family) = settings.font_family {
+            if !self.fonts.iter().any(|f| &f.family == family) {
+                return Err(format!("No font of family {} has been uploaded", family));
+            }
+        }
+        if let Some(ref terms) = settings.early_payment_terms {
+            terms.validate()?;
+        }
+        if let Some(ref policy) = settings.late_fee {
+            policy.validate()?;
+        }
+        settings.logo_background.validate()?;
+        for (index, fee) in settings.payment_method_fees.iter().enumerate() {
+            if fee.id.trim().is_empty() || fee.label.trim().is_empty() {
+                return Err("Payment methods need an ID and a label".to_string());
+            }
+            if settings.payment_method_fees[..index].iter().any(|f| f.id == fee.id) {
+                return Err(format!("Two payment methods have the ID {}", fee.id));
+            }
+            if !fee.percent.is_finite() || fee.percent <= -100.0 {
+                return Err(format!("Invalid fee for {}", fee.label));
+            }
+        }
+
+        custom_fields::validate_definitions(&settings.custom_fields)?;
+        settings.default_text.validate()?;
+        for text in settings.client_default_text.values() {
+            text.validate()?;
+        }
+
+        save_settings(&settings)?;
+        self.settings = Some(settings);
+        Ok("Settings updated".to_string())
+    }
+
+    // Every country preset with the tax settings it applies
+    #[http]
+    async fn list_tax_presets(&self) -> Result<String, String> {
+        let _trace = trace("list_tax_presets");
+        let presets: Vec<tax_regimes::TaxSettings> = tax_regimes::ALL_REGIMES.iter()
+            .map(|regime| regime.preset())
+            .collect();
+        serde_json::to_string(&presets)
+            .map_err(|e| format!("Failed to serialize tax presets: {}", e))
+    }
+
+    // Replaces the tax settings with a country preset. Existing invoices keep their rates.
+    #[http]
+    async fn apply_tax_preset(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("apply_tax_preset");
+        let regime: tax_regimes::TaxRegime = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid tax regime: {}", e))?;
+        let settings = self.settings.as_mut().ok_or("Settings have not been set up yet")?;
+        // Jurisdictions are configured per business, not per regime
+        let jurisdictions = std::mem::take(&mut settings.tax.jurisdictions);
+        settings.tax = regime.preset();
+        settings.tax.jurisdictions = jurisdictions;
+        save_settings(settings)?;
+        serde_json::to_string(&settings.tax)
+            .map_err(|e| format!("Failed to serialize tax settings: {}", e))
+    }
+
+    // Tax jurisdiction IDs of each client, keyed by client ID
+    #[http]
+    async fn get_client_jurisdictions(&self) -> Result<String, String> {
+        let _trace = trace("get_client_jurisdictions");
+        serde_json::to_string(&self.client_jurisdictions)
+            .map_err(|e| format!("Failed to serialize client jurisdictions: {}", e))
+    }
+
+    // Sets the jurisdictions a client's address falls in; an empty list removes them
+    #[http]
+    async fn set_client_jurisdictions(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_client_jurisdictions");
+        #[derive(Deserialize)]
+        struct SetClientJurisdictionsRequest {
+            client_id: String,
+            jurisdiction_ids: Vec<String>,
+        }
+
+        let request: SetClientJurisdictionsRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let client_id = request.client_id.trim().to_lowercase();
+        if client_id.is_empty() {
+            return Err("Client ID is required".to_string());
+        }
+        self.applied_taxes(&request.jurisdiction_ids)?;
+
+        if request.jurisdiction_ids.is_empty() {
+            self.client_jurisdictions.remove(&client_id);
+        } else {
+            self.client_jurisdictions.insert(client_id, request.jurisdiction_ids);
+        }
+        Ok("Client jurisdictions saved".to_string())
+    }
+
+    // Applies jurisdiction taxes to an invoice, setting its tax rate to their sum.
+    // Without IDs, the jurisdictions of the invoice's client are used, so a different
+    // shipping address can be taxed by passing its jurisdictions. An empty list removes them.
+    #[http]
+    async fn apply_jurisdictions(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("apply_jurisdictions");
+        #[derive(Deserialize)]
+        struct ApplyJurisdictionsRequest {
+            invoice_id: String,
+            #[serde(default)]
+            jurisdiction_ids: Option<Vec<String>>,
+        }
+
+        let request: ApplyJurisdictionsRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let (mut invoice, is_current) = self.invoice_for_update(&request.invoice_id)?;
+        let ids = match request.jurisdiction_ids {
+            Some(ids) => ids,
+            None => self.client_jurisdictions.get(&client_key(&invoice.invoicee))
+                .cloned()
+                .ok_or("No jurisdictions are set for this client")?,
+        };
+
+        invoice.sales_taxes = self.applied_taxes(&ids)?;
+        if !invoice.sales_taxes.is_empty() {
+            invoice.tax_percent = invoice.sales_taxes.iter().map(|t| t.percent).sum();
+            invoice.tax_lines = vec![];
+        }
+        invoice.updated_at = host::now();
+        self.store_managed_change(invoice.clone(), is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Taxable sales and tax per jurisdiction for a filing period (ISO dates, inclusive).
+    // Covers issued invoices; tax on invoices marked Paid is reported as collected.
+    // Amounts are in the home currency; foreign-currency invoices without an exchange
+    // rate are left out and counted.
+    #[http]
+    async fn get_jurisdiction_report(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_jurisdiction_report");
+        #[derive(Deserialize, Default)]
+        struct JurisdictionReportRequest {
+            #[serde(default)]
+            from: Option<String>,
+            #[serde(default)]
+            to: Option<String>,
+        }
+
+        let request: JurisdictionReportRequest = if request_body.trim().is_empty() {
+            JurisdictionReportRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let home_currency = self.home_currency();
+        let ids = self.indexed_ids(ISSUED_STATUSES, request.from.as_deref(), request.to.as_deref());
+        let mut invoices = Vec::new();
+        let mut unconverted = 0;
+        for id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            if invoice.kind != DocumentKind::Invoice || invoice.sales_taxes.is_empty() {
+                continue;
+            }
+            match in_currency(&invoice, tax_base(&invoice), &home_currency) {
+                Some(base) => invoices.push((invoice, base)),
+                None => unconverted += 1,
+            }
+        }
+        let rows: Vec<(&[tax_regimes::AppliedTax], f64, bool)> = invoices.iter()
+            .map(|(i, base)| (i.sales_taxes.as_slice(), base.to_f64(), i.status == InvoiceStatus::Paid))
+            .collect();
+
+        let response = serde_json::json!({
+            "from": request.from,
+            "to": request.to,
+            "currency": home_currency,
+            "jurisdictions": tax_regimes::jurisdiction_report(&rows),
+            "unconverted": unconverted,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize jurisdiction report: {}", e))
+    }
+
+    // Revenue, cost and margin of issued invoices in a period (ISO dates, inclusive),
+    // per invoice and totalled per client and per project, in the home currency.
+    // Foreign-currency invoices without an exchange rate are left out and counted.
+    #[http]
+    async fn get_margin_report(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_margin_report");
+        #[derive(Deserialize, Default)]
+        struct MarginReportRequest {
+            #[serde(default)]
+            from: Option<String>,
+            #[serde(default)]
+            to: Option<String>,
+        }
+
+        let request: MarginReportRequest = if request_body.trim().is_empty() {
+            MarginReportRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let home_currency = self.home_currency();
+        let ids = self.indexed_ids(ISSUED_STATUSES, request.from.as_deref(), request.to.as_deref());
+        let mut margins = Vec::new();
+        let mut unconverted = 0;
+        for id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            if invoice.kind != DocumentKind::Invoice {
+                continue;
+            }
+            match invoice_margin(&invoice, &home_currency) {
+                Some(margin) => margins.push(margin),
+                None => unconverted += 1,
+            }
+        }
+        margins.sort_by(|a, b| a.date.cmp(&b.date));
+
+        let response = serde_json::json!({
+            "from": request.from,
+            "to": request.to,
+            "invoices": margins.iter()
+                .map(|m| {
+                    let mut row = serde_json::json!(m);
+                    row["margin"] = serde_json::json!(m.margin());
+                    row["margin_percent"] = serde_json::json!(m.margin_percent());
+                    row
+                })
+                .collect::<Vec<_>>(),
+            "clients": margins::totals_by(&margins, |m| Some(m.client.clone())),
+            "projects": margins::totals_by(&margins, |m| m.project.clone()),
+            "total": margins::totals_by(&margins, |_| Some("Total".to_string())).pop(),
+            "currency": home_currency,
+            "unconverted": unconverted,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize margin report: {}", e))
+    }
+
+    #[http]
+    async fn list_partners(&self) -> Result<String, String> {
+        let _trace = trace("list_partners");
+        serde_json::to_string(&self.partners)
+            .map_err(|e| format!("Failed to serialize partners: {}", e))
+    }
+
+    // Creates a referral partner, or updates one. Changing the default percentage
+    // leaves the commission on invoices already referred as it was.
+    #[http]
+    async fn save_partner(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("save_partner");
+        #[derive(Deserialize)]
+        struct SavePartnerRequest {
+            #[serde(default)]
+            id: Option<String>,
+            name: String,
+            #[serde(default)]
+            email: Option<String>,
+            default_percent: f64,
+        }
+
+        let request: SavePartnerRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid partner: {}", e))?;
+        if request.name.trim().is_empty() {
+            return Err("Partners need a name".to_string());
+        }
+        if !(0.0..=100.0).contains(&request.default_percent) {
+            return Err("Commission must be between 0 and 100%".to_string());
+        }
+        let email = request.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+
+        let partner = match request.id {
+            Some(id) => {
+                let partner = self.partners.iter_mut().find(|p| p.id == id)
+                    .ok_or("Partner not found")?;
+                partner.name = request.name.trim().to_string();
+                partner.email = email;
+                partner.default_percent = request.default_percent;
+                partner.clone()
+            }
+            None => {
+                let timestamp = host::now();
+                let partner = Partner {
+                    id: format!("partner-{}-{}", timestamp, self.partners.len()),
+                    name: request.name.trim().to_string(),
+                    email,
+                    default_percent: request.default_percent,
+                    created_at: timestamp,
+                };
+                self.partners.push(partner.clone());
+                partner
+            }
+        };
+
+        serde_json::to_string(&partner)
+            .map_err(|e| format!("Failed to serialize partner: {}", e))
+    }
+
+    // Partners with payouts recorded are kept so the payouts still add up
+    #[http]
+    async fn delete_partner(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_partner");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid partner ID: {}", e))?;
+
+        let index = self.partners.iter().position(|p| p.id == id)
+            .ok_or("Partner not found")?;
+        if self.partner_payouts.iter().any(|p| p.partner_id == id) {
+            return Err("Partner has payouts recorded and can't be deleted".to_string());
+        }
+        self.partners.remove(index);
+
+        Ok("Partner deleted".to_string())
+    }
+
+    #[http]
+    async fn record_partner_payout(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("record_partner_payout");
+        #[derive(Deserialize)]
+        struct RecordPayoutRequest {
+            partner_id: String,
+            amount: Money,
+            #[serde(default)]
+            paid_on: Option<String>, // Defaults to today
+            #[serde(default)]
+            reference: Option<String>,
+        }
+
+        let request: RecordPayoutRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if !self.partners.iter().any(|p| p.id == request.partner_id) {
+            return Err("Partner not found".to_string());
+        }
+        if request.amount <= Money::ZERO {
+            return Err("Payout must be greater than zero".to_string());
+        }
+        let timestamp = host::now();
+        let paid_on = request.paid_on.unwrap_or_else(|| date_from_timestamp(self.local_time(timestamp)));
+        if !is_iso_date(&paid_on) {
+            return Err(format!("Invalid date: {}", paid_on));
+        }
+
+        let payout = PartnerPayout {
+            id: format!("payout-{}-{}", timestamp, self.partner_payouts.len()),
+            partner_id: request.partner_id,
+            amount: request.amount,
+            paid_on,
+            reference: request.reference.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+            recorded_at: timestamp,
+        };
+        self.partner_payouts.push(payout.clone());
+
+        serde_json::to_string(&payout)
+            .map_err(|e| format!("Failed to serialize payout: {}", e))
+    }
+
+    #[http]
+    async fn delete_partner_payout(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_partner_payout");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid payout ID: {}", e))?;
+
+        let index = self.partner_payouts.iter().position(|p| p.id == id)
+            .ok_or("Payout not found")?;
+        self.partner_payouts.remove(index);
+
+        Ok("Payout deleted".to_string())
+    }
+
+    // Commission per partner on the issued invoices they referred and the payouts
+    // made to them in a period (ISO dates, inclusive; all time when left out).
+    // Commission is earned once an invoice is paid. Amounts are in the home currency;
+    // foreign-currency invoices without an exchange rate are left out and counted.
+    #[http]
+    async fn get_commission_report(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_commission_report");
+        #[derive(Deserialize, Default)]
+        struct CommissionReportRequest {
+            #[serde(default)]
+            from: Option<String>,
+            #[serde(default)]
+            to: Option<String>,
+        }
+
+        let request: CommissionReportRequest = if request_body.trim().is_empty() {
+            CommissionReportRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+        let in_period = |date: &str| {
+            request.from.as_ref().map(|from| date >= from.as_str()).unwrap_or(true)
+                && request.to.as_ref().map(|to| date <= to.as_str()).unwrap_or(true)
+        };
+
+        let home_currency = self.home_currency();
+        let ids = self.indexed_ids(ISSUED_STATUSES, request.from.as_deref(), request.to.as_deref());
+        let mut lines = Vec::new();
+        let mut unconverted = 0;
+        for id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            let Some(ref commission) = invoice.commission else {
+                continue;
+            };
+            if invoice.kind != DocumentKind::Invoice {
+                continue;
+            }
+            let base = taxable_amount(&invoice);
+            let amount = base.percent(commission.percent, currency_decimals(&invoice));
+            let (Some(base), Some(amount)) = (
+                in_currency(&invoice, base, &home_currency),
+                in_currency(&invoice, amount, &home_currency),
+            ) else {
+                unconverted += 1;
+                continue;
+            };
+            lines.push((commission.partner_id.clone(), commissions::CommissionLine {
+                invoice_id: invoice.id.clone(),
+                number: invoice.number.clone(),
+                date: invoice.date.clone(),
+                client: invoice.invoicee.company.clone()
+                    .filter(|c| !c.is_empty())
+                    .unwrap_or_else(|| invoice.invoicee.name.clone()),
+                base: base.to_f64(),
+                percent: commission.percent,
+                amount: amount.to_f64(),
+                paid: invoice.status == InvoiceStatus::Paid,
+            }));
+        }
+        let partners: Vec<(String, String)> = self.partners.iter()
+            .map(|p| (p.id.clone(), p.name.clone()))
+            .collect();
+        let payouts: Vec<(String, f64)> = self.partner_payouts.iter()
+            .filter(|p| in_period(&p.paid_on))
+            .map(|p| (p.partner_id.clone(), p.amount.to_f64()))
+            .collect();
+
+        let response = serde_json::json!({
+            "from": request.from,
+            "to": request.to,
+            "currency": home_currency,
+            "partners": commissions::statements(&partners, lines, &payouts),
+            "unconverted": unconverted,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize commission report: {}", e))
+    }
+
+    #[http]
+    async fn list_team_members(&self) -> Result<String, String> {
+        let _trace = trace("list_team_members");
+        serde_json::to_string(&self.team_members)
+            .map_err(|e| format!("Failed to serialize team members: {}", e))
+    }
+
+    // Adds someone to the team, or updates them
+    #[http]
+    async fn save_team_member(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("save_team_member");
+        #[derive(Deserialize)]
+        struct SaveTeamMemberRequest {
+            #[serde(default)]
+            id: Option<String>,
+            name: String,
+            #[serde(default)]
+            email: Option<String>,
+        }
+
+        let request: SaveTeamMemberRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid team member: {}", e))?;
+        if request.name.trim().is_empty() {
+            return Err("Team members need a name".to_string());
+        }
+        let email = request.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+
+        let member = match request.id {
+            Some(id) => {
+                let member = self.team_members.iter_mut().find(|m| m.id == id)
+                    .ok_or("Team member not found")?;
+                member.name = request.name.trim().to_string();
+                member.email = email;
+                member.clone()
+            }
+            None => {
+                let timestamp = host::now();
+                let member = TeamMember {
+                    id: format!("member-{}-{}", timestamp, self.team_members.len()),
+                    name: request.name.trim().to_string(),
+                    email,
+                    created_at: timestamp,
+                };
+                self.team_members.push(member.clone());
+                member
+            }
+        };
+
+        serde_json::to_string(&member)
+            .map_err(|e| format!("Failed to serialize team member: {}", e))
+    }
+
+    // Members credited with line items are kept so the revenue report still adds up
+    #[http]
+    async fn delete_team_member(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_team_member");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid team member ID: {}", e))?;
+
+        let index = self.team_members.iter().position(|m| m.id == id)
+            .ok_or("Team member not found")?;
+        let ids: Vec<String> = self.invoices.keys().cloned().collect();
+        for invoice_id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == invoice_id => current.clone(),
+                _ => self.load_invoice(invoice_id)?,
+            };
+            if invoice.line_items.iter().any(|item| item.team_member_id.as_ref() == Some(&id)) {
+                return Err(format!("Team member is credited on invoice {} and can't be deleted", invoice.number));
+            }
+        }
+        self.team_members.remove(index);
+
+        Ok("Team member deleted".to_string())
+    }
+
+    // Revenue billed per team member and month, quarter or year, over issued invoices
+    // dated in a period (ISO dates, inclusive). Line amounts are after discounts and
+    // before tax, in the home currency; foreign-currency invoices without an exchange
+    // rate are left out and counted.
+    #[http]
+    async fn get_team_report(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_team_report");
+        #[derive(Deserialize, Default)]
+        struct TeamReportRequest {
+            #[serde(default)]
+            from: Option<String>,
+            #[serde(default)]
+            to: Option<String>,
+            #[serde(default)]
+            period: team::Period,
+        }
+
+        let request: TeamReportRequest = if request_body.trim().is_empty() {
+            TeamReportRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let home_currency = self.home_currency();
+        let ids = self.indexed_ids(ISSUED_STATUSES, request.from.as_deref(), request.to.as_deref());
+        let mut lines = Vec::new();
+        let mut unattributed = Money::ZERO;
+        let mut unconverted = 0;
+        for id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            if invoice.kind != DocumentKind::Invoice {
+                continue;
+            }
+            if invoice.currency != home_currency
+                && !invoice.exchange_rate.as_ref().map(|r| r.home_currency == home_currency).unwrap_or(false)
+            {
+                unconverted += 1;
+                continue;
+            }
+            for (item, amount) in invoice.line_items.iter().zip(line_taxable_amounts(&invoice)) {
+                let amount = if invoice.currency == home_currency {
+                    amount
+                } else {
+                    to_home_currency(&invoice, amount).unwrap_or(amount)
+                };
+                match item.team_member_id {
+                    Some(ref member_id) => lines.push(team::BilledLine {
+                        member_id: member_id.clone(),
+                        invoice_id: invoice.id.clone(),
+                        date: invoice.date.clone(),
+                        amount: amount.to_f64(),
+                    }),
+                    None => unattributed += amount,
+                }
+            }
+        }
+        let members: Vec<(String, String)> = self.team_members.iter()
+            .map(|m| (m.id.clone(), m.name.clone()))
+            .collect();
+
+        let response = serde_json::json!({
+            "from": request.from,
+            "to": request.to,
+            "period": request.period,
+            "currency": home_currency,
+            "members": team::revenue_by_member(&members, &lines, request.period),
+            "unattributed": unattributed,
+            "unconverted": unconverted,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize team report: {}", e))
+    }
+
+    #[http]
+    async fn list_exchange_rates(&self) -> Result<String, String> {
+        let _trace = trace("list_exchange_rates");
+        serde_json::to_string(&self.exchange_rates)
+            .map_err(|e| format!("Failed to serialize exchange rates: {}", e))
+    }
+
+    // Sets the rate of a currency into the home currency. Invoices already sent
+    // keep the rate they were sent at.
+    #[http]
+    async fn set_exchange_rate(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_exchange_rate");
+        #[derive(Deserialize)]
+        struct SetExchangeRateRequest {
+            currency: String,
+            rate: f64, // Units of the home currency per unit of this one
+        }
+
+        let request: SetExchangeRateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let code = request.currency.trim().to_uppercase();
+        if !currency::is_valid_code(&code) {
+            return Err(format!("Invalid currency code: {}", request.currency));
+        }
+        let home_currency = self.home_currency();
+        if code == home_currency {
+            return Err(format!("{} is the home currency", code));
+        }
+        if !request.rate.is_finite() || request.rate <= 0.0 {
+            return Err("Exchange rate must be greater than zero".to_string());
+        }
+        let timestamp = host::now();
+
+        let rate = ExchangeRate {
+            currency: code,
+            home_currency,
+            rate: request.rate,
+            updated_at: timestamp,
+        };
+        self.exchange_rates.retain(|r| r.currency != rate.currency);
+        self.exchange_rates.push(rate.clone());
+        self.exchange_rates.sort_by(|a, b| a.currency.cmp(&b.currency));
+
+        serde_json::to_string(&rate)
+            .map_err(|e| format!("Failed to serialize exchange rate: {}", e))
+    }
+
+    #[http]
+    async fn delete_exchange_rate(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_exchange_rate");
+        let code: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid currency code: {}", e))?;
+
+        let count = self.exchange_rates.len();
+        self.exchange_rates.retain(|r| r.currency != code);
+        if self.exchange_rates.len() == count {
+            return Err("Exchange rate not found".to_string());
+        }
+
+        Ok("Exchange rate deleted".to_string())
+    }
+
+    // Sets the rate an invoice is reported at, e.g. the one the bank converted at,
+    // or for one sent before a rate was entered. No rate clears it.
+    #[http]
+    async fn set_invoice_exchange_rate(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_invoice_exchange_rate");
+        #[derive(Deserialize)]
+        struct SetInvoiceExchangeRateRequest {
+            invoice_id: String,
+            #[serde(default)]
+            rate: Option<f64>,
+        }
+
+        let request: SetInvoiceExchangeRateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let (mut invoice, is_current) = self.invoice_for_update(&request.invoice_id)?;
+        let home_currency = self.home_currency();
+        let timestamp = host::now();
+
+        invoice.exchange_rate = match request.rate {
+            Some(rate) => {
+                if invoice.currency == home_currency {
+                    return Err(format!("Invoice is already in {}", home_currency));
+                }
+                if !rate.is_finite() || rate <= 0.0 {
+                    return Err("Exchange rate must be greater than zero".to_string());
+                }
+                Some(ExchangeRateSnapshot {
+                    home_total: convert(calculate_invoice_total(&invoice), rate, &home_currency),
+                    home_currency,
+                    rate,
+                    taken_at: timestamp,
+                })
+            }
+            None => None,
+        };
+        invoice.updated_at = timestamp;
+        self.store_managed_change(invoice.clone(), is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // What clients still owe on sent invoices, in each currency and in total in the
+    // home currency. Foreign-currency invoices without a rate into the current home
+    // currency are listed but left out of the total.
+    #[http]
+    async fn get_outstanding_report(&self) -> Result<String, String> {
+        let _trace = trace("get_outstanding_report");
+        let home_currency = self.home_currency();
+        let ids = self.indexed_ids(&[InvoiceStatus::Sent, InvoiceStatus::Overdue], None, None);
+
+        let mut rows = Vec::new();
+        let mut by_currency: Vec<(String, Money)> = Vec::new();
+        let mut total = Money::ZERO;
+        let mut unconverted = 0;
+        for id in &ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            let due = amount_due(&invoice);
+            let home_amount = if invoice.currency == home_currency {
+                Some(due)
+            } else {
+                to_home_currency(&invoice, due)
+                    .filter(|_| invoice.exchange_rate.as_ref().map(|r| r.home_currency == home_currency).unwrap_or(false))
+            };
+            match home_amount {
+                Some(amount) => total += amount,
+                None => unconverted += 1,
+            }
+            match by_currency.iter_mut().find(|(code, _)| *code == invoice.currency) {
+                Some(entry) => entry.1 += due,
+                None => by_currency.push((invoice.currency.clone(), due)),
+            }
+            rows.push((invoice.due_date.clone(), serde_json::json!({
+                "invoice_id": invoice.id,
+                "number": invoice.number,
+                "client": invoice.invoicee.company.clone()
+                    .filter(|c| !c.is_empty())
+                    .unwrap_or_else(|| invoice.invoicee.name.clone()),
+                "due_date": invoice.due_date,
+                "status": invoice.status,
+                "currency": invoice.currency,
+                "amount_due": due,
+                "exchange_rate": invoice.exchange_rate.as_ref().map(|r| r.rate),
+                "home_amount": home_amount,
+            })));
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        by_currency.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let response = serde_json::json!({
+            "home_currency": home_currency,
+            "total": total,
+            "unconverted": unconverted,
+            "currencies": by_currency.into_iter()
+                .map(|(currency, amount)| serde_json::json!({ "currency": currency, "amount_due": amount }))
+                .collect::<Vec<_>>(),
+            "invoices": rows.into_iter().map(|(_, row)| row).collect::<Vec<_>>(),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize outstanding report: {}", e))
+    }
+
+    // PNG, JPEG, WebP or SVG; SVG is stored sanitized
+    #[http]
+    async fn upload_logo(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_logo");
+        let (format, data) = logo_upload(request_body)?;
+        let package_id = host::address().package_id();
+        let drive_path = format!("/{}/invoice", package_id);
+        let logo_path = format!("{}/logo.{}", drive_path, format.extension());
+
+        save_blob(&logo_path, &data)
+            .map_err(|e| format!("Failed to write logo: {}", e))?;
+        Ok(logo_path)
+    }
+
+    // A client's own logo, printed beside their address on new invoices for them
+    // and on the pages they open from share links
+    #[http]
+    async fn upload_client_logo(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_client_logo");
+        #[derive(Deserialize)]
+        struct ClientLogoUpload {
+            client_id: String,
+            file_data: Vec<u8>, // PNG, JPEG, WebP or SVG
+        }
+
+        let request: ClientLogoUpload = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let client_id = request.client_id.trim().to_lowercase();
+        if client_id.is_empty() {
+            return Err("Client ID is required".to_string());
+        }
+        let (format, data) = logo_upload(request.file_data)?;
+
+        let logo_path = format!("{}/logo.{}", client_dir_path(&client_id), format.extension());
+        save_blob(&logo_path, &data)
+            .map_err(|e| format!("Failed to write logo: {}", e))?;
+
+        self.client_logos.insert(client_id, logo_path.clone());
+        Ok(logo_path)
+    }
+
+    // Client ID -> logo path, for every client with a logo
+    #[http]
+    async fn get_client_logos(&self) -> Result<String, String> {
+        let _trace = trace("get_client_logos");
+        serde_json::to_string(&self.client_logos)
+            .map_err(|e| format!("Failed to serialize client logos: {}", e))
+    }
+
+    // Stops new invoices for the client getting a logo; ones that have it keep it
+    #[http]
+    async fn remove_client_logo(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("remove_client_logo");
+        let client_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid client ID: {}", e))?;
+
+        self.client_logos.remove(&client_id.trim().to_lowercase())
+            .ok_or("Client has no logo")?;
+        Ok("Client logo removed".to_string())
+    }
+
+    #[http]
+    async fn upload_payment_image(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_payment_image");
+        let package_id = host::address().package_id();
+        let drive_path = format!("/{}/invoice", package_id);
+        let payment_path = format!("{}/payment.png", drive_path);
+
+        save_blob(&payment_path, &request_body)
+            .map_err(|e| format!("Failed to write payment image: {}", e))?;
+        Ok(payment_path)
+    }
+
+    // Invoice Management Endpoints
+
+    // The number create_invoice would assign next in a profile's series (or the default one)
+    #[http]
+    async fn peek_next_number(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("peek_next_number");
+        let profile_id: Option<String> = if request_body.trim().is_empty() {
+            None
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid profile ID: {}", e))?
+        };
+
+        let number = self.peek_number(profile_id.as_deref())?;
+        serde_json::to_string(&number)
+            .map_err(|e| format!("Failed to serialize number: {}", e))
+    }
+
+    // Gaps, voided numbers and out-of-order dates in each numbering series
+    #[http]
+    async fn get_sequence_report(&self) -> Result<String, String> {
+        let _trace = trace("get_sequence_report");
+        let settings = self.settings.as_ref().ok_or("Settings not configured")?;
+        let series: Vec<numbering::Series> = std::iter::once(numbering::Series {
+            name: "Default".to_string(),
+            prefix: settings.invoice_number_prefix.clone(),
+            next_number: settings.next_invoice_number,
+        })
+        .chain(settings.profiles.iter().map(|p| numbering::Series {
+            name: p.name.clone(),
+            prefix: p.invoice_number_prefix.clone(),
+            next_number: p.next_invoice_number,
+        }))
+        .collect();
+        let invoices: Vec<(String, String)> = self.invoices.values()
+            .map(|s| (s.number.clone(), s.date.clone()))
+            .collect();
+
+        serde_json::to_string(&numbering::report(&series, &invoices, &self.deleted_numbers))
+            .map_err(|e| format!("Failed to serialize sequence report: {}", e))
+    }
+
+    // Due date for an invoice date and payment terms, rolled to a business day if
+    // that's enabled in settings. Terms default to the ones in settings.
+    #[http]
+    async fn calculate_due_date(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("calculate_due_date");
+        #[derive(Deserialize)]
+        struct CalculateDueDateRequest {
+            date: String,
+            #[serde(default)]
+            payment_terms_days: Option<u32>,
+        }
+
+        let request: CalculateDueDateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if !is_iso_date(&request.date) {
+            return Err(format!("Invalid date: {}", request.date));
+        }
+        let days = request.payment_terms_days
+            .or_else(|| self.default_payment_terms().map(PaymentTerms::days))
+            .ok_or("No payment terms given or set in settings")?;
+
+        let due_date = self.due_date_from_terms(&request.date, days)
+            .ok_or("Could not compute a due date")?;
+        serde_json::to_string(&due_date)
+            .map_err(|e| format!("Failed to serialize due date: {}", e))
+    }
+
+    #[http]
+    async fn list_invoices(&self) -> Result<String, String> {
+        let _trace = trace("list_invoices");
+        let summaries: Vec<InvoiceSummary> = self.invoices.values().cloned().collect();
+        serde_json::to_string(&summaries)
+            .map_err(|e| format!("Failed to serialize invoices: {}", e))
+    }
+
+    // Summaries of invoices matching every filter given, oldest first, looked up in
+    // the invoice index rather than by loading invoices
+    #[http]
+    async fn find_invoices(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("find_invoices");
+        #[derive(Deserialize, Default)]
+        struct FindInvoicesRequest {
+            #[serde(default)]
+            client: Option<String>, // Client name or company, matched like client records
+            #[serde(default)]
+            statuses: Vec<InvoiceStatus>, // Any of these; all statuses when empty
+            #[serde(default)]
+            from: Option<String>,
+            #[serde(default)]
+            to: Option<String>,
+            #[serde(default)]
+            project: Option<String>,
+            #[serde(default)]
+            tags: Vec<String>, // All of these
+        }
+
+        let request: FindInvoicesRequest = if request_body.trim().is_empty() {
+            FindInvoicesRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let mut matches: Vec<std::collections::BTreeSet<String>> = Vec::new();
+        if let Some(ref client) = request.client {
+            matches.push(self.invoice_index.client(&client.trim().to_lowercase()));
+        }
+        if !request.statuses.is_empty() {
+            matches.push(self.indexed_ids(&request.statuses, None, None).into_iter().collect());
+        }
+        if request.from.is_some() || request.to.is_some() {
+            matches.push(self.invoice_index.dated(request.from.as_deref(), request.to.as_deref()));
+        }
+        if let Some(ref project) = request.project {
+            matches.push(self.invoice_index.project(project));
+        }
+        for tag in &request.tags {
+            matches.push(self.invoice_index.tag(tag.trim()));
+        }
+
+        let mut summaries: Vec<&InvoiceSummary> = match matches.split_first() {
+            Some((first, rest)) => first.iter()
+                .filter(|id| rest.iter().all(|ids| ids.contains(*id)))
+                .filter_map(|id| self.invoices.get(id))
+                .collect(),
+            None => self.invoices.values().collect(),
+        };
+        summaries.sort_by(|a, b| a.date.cmp(&b.date));
+        serde_json::to_string(&summaries)
+            .map_err(|e| format!("Failed to serialize invoices: {}", e))
+    }
+
+    // Summaries created, updated or deleted since a version of the list, so a long list
+    // can be kept current without fetching it all again. Version 0 returns every summary.
+    #[http]
+    async fn get_summary_deltas(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_summary_deltas");
+        let since_version: u64 = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid version: {}", e))?;
+
+        let delta = self.summary_log.since(since_version, self.invoices.keys());
+        let summaries = |ids: &[String]| ids.iter()
+            .filter_map(|id| self.invoices.get(id).cloned())
+            .collect::<Vec<InvoiceSummary>>();
+        let response = serde_json::json!({
+            "version": delta.version,
+            "reset": delta.reset,
+            "created": summaries(&delta.created),
+            "updated": summaries(&delta.updated),
+            "deleted": delta.deleted,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize summary deltas: {}", e))
+    }
+
+    #[http]
+    async fn create_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("create_invoice");
+        // The payload is optional; without one an empty draft is created
+        let payload: Option<NewInvoice> = if request_body.trim().is_empty() {
+            None
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid invoice data: {}", e))?
+        };
+
+        if let Some(ref payload) = payload {
+            for date in [&payload.date, &payload.due_date].into_iter().flatten() {
+                if !is_iso_date(date) {
+                    return Err(format!("Invalid date: {}", date));
+                }
+            }
+            if let Some(ref code) = payload.currency {
+                if !currency::is_valid_code(code) {
+                    return Err(format!("Invalid currency code: {}", code));
+                }
+            }
+        }
+
+        let profile_id = payload.as_ref().and_then(|p| p.profile_id.clone());
+        let number = payload.as_ref()
+            .and_then(|p| p.number.as_ref())
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty());
+        if let Some(ref number) = number {
+            if number.contains(['/', '\\']) {
+                return Err("Invoice numbers cannot contain slashes".to_string());
+            }
+            if self.number_in_use(number) {
+                return Err(format!("Invoice number {} is already in use", number));
+            }
+        }
+        let millis = host::now_millis();
+        let mut invoice = self.new_invoice(profile_id.as_deref(), number, millis / 1000)?;
+        if let Some(payload) = payload {
+            invoice.name = payload.name;
+            if let Some(date) = payload.date {
+                invoice.date = date;
+            }
+            if let Some(terms) = payload.payment_terms.or(payload.payment_terms_days.map(PaymentTerms::Net)) {
+                invoice.payment_terms = Some(terms);
+            }
+            invoice.due_date = payload.due_date.or_else(|| invoice.payment_terms
+                .and_then(|terms| self.due_date_from_terms(&invoice.date, terms.days())));
+            if let Some(early) = payload.early_payment {
+                early.validate()?;
+                invoice.early_payment = Some(early);
+            }
+            if let Some(invoicee) = payload.invoicee {
+                invoice.invoicee = invoicee;
+                invoice.invoicee.verified_identity = None;
+            }
+            invoice.kind = payload.kind;
+            if let Some(code) = payload.currency {
+                invoice.currency = code;
+            }
+            invoice.discount_percent = payload.discount_percent;
+            invoice.tax_percent = payload.tax_percent;
+            if !payload.tax_lines.is_empty() {
+                for line in &payload.tax_lines {
+                    line.validate()?;
+                }
+                invoice.tax_percent = payload.tax_lines.iter().map(|t| t.percent).sum();
+                invoice.tax_lines = payload.tax_lines;
+            }
+            if let Some(ref shipping) = payload.shipping {
+                shipping.validate()?;
+            }
+            let definitions = self.custom_field_definitions();
+            custom_fields::validate(definitions, custom_fields::Scope::Invoice, &payload.custom_fields)?;
+            for item in &payload.line_items {
+                item.validate()?;
+                custom_fields::validate(definitions, custom_fields::Scope::LineItem, &item.custom_fields)?;
+            }
+            invoice.custom_fields = payload.custom_fields;
+            invoice.shipping = payload.shipping;
+            invoice.notes = payload.notes;
+            invoice.terms_text = payload.terms_text;
+            invoice.thank_you = payload.thank_you;
+            invoice.purchase_order = payload.purchase_order.filter(|po| !po.trim().is_empty());
+            invoice.reference = payload.reference.filter(|r| !r.trim().is_empty());
+            if let Some(ref delivery) = payload.delivery_address {
+                delivery.validate()?;
+            }
+            invoice.delivery_address = payload.delivery_address;
+            let default_unit = self.settings.as_ref().and_then(|s| s.default_unit.clone());
+            invoice.line_items = payload.line_items.into_iter()
+                .enumerate()
+                .map(|(index, item)| item.into_line_item(format!("item-{}-{}", millis, index), &default_unit))
+                .collect();
+            group_sections(&mut invoice.line_items);
+        }
+        if invoice.purchase_order.is_none() {
+            invoice.purchase_order = self.settings.as_ref()
+                .and_then(|s| s.client_purchase_orders.get(&client_key(&invoice.invoicee)).cloned());
+        }
+        if invoice.invoicee.logo_path.is_none() {
+            invoice.invoicee.logo_path = self.client_logos.get(&client_key(&invoice.invoicee)).cloned();
+        }
+        if let Some(ref settings) = self.settings {
+            let defaults = settings.default_text
+                .with_overrides(settings.client_default_text.get(&client_key(&invoice.invoicee)));
+            invoice.notes = invoice.notes.take().or(defaults.notes);
+            invoice.terms_text = invoice.terms_text.take().or(defaults.terms);
+            invoice.thank_you = invoice.thank_you.take().or(defaults.thank_you);
+        }
+
+        // Set as current invoice
+        self.current_invoice = Some(invoice.clone());
+        self.has_unsaved_changes = true;
+
+        // Add to summaries
+        self.load_summary(&invoice);
+
+        // Save invoice
+        self.save_current_invoice()?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    #[http]
+    async fn get_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_invoice");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+
+        // Check if it's already the current invoice
+        if let Some(ref current) = self.current_invoice {
+            if current.id == id {
+                return serde_json::to_string(current)
+                    .map_err(|e| format!("Failed to serialize invoice: {}", e));
+            }
+        }
+
+        let invoice = self.load_invoice(&id)?;
+        self.current_invoice = Some(invoice.clone());
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Editors may send a session_id and an increasing edit_sequence alongside the
+    // invoice; edits to the open invoice are then merged through the session's draft
+    // buffer rather than replacing it outright
+    #[http]
+    async fn update_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_invoice");
+        let mut body: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid invoice data: {}", e))?;
+        let session = drafts::take_session(&mut body);
+
+        // Update timestamp
+        let timestamp = host::now();
+
+        let body = serde_json::Value::Object(body);
+        let current = self.current_invoice.as_ref()
+            .filter(|c| body.get("id").and_then(|id| id.as_str()) == Some(c.id.as_str()));
+        let body = match (&session, current) {
+            (Some((session_id, sequence)), Some(current)) => {
+                let current = serde_json::to_value(current)
+                    .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+                self.draft_buffers.merge(session_id, *sequence, &current, &body, timestamp)
+            }
+            _ => body,
+        };
+        let updates: Invoice = serde_json::from_value(body)
+            .map_err(|e| format!("Invalid invoice data: {}", e))?;
+        for item in &updates.line_items {
+            item.validate()?;
+        }
+
+        // Update invoice
+        let mut updated_invoice = updates;
+        updated_invoice.updated_at = timestamp;
+
+        let existing = match self.current_invoice {
+            Some(ref current) if current.id == updated_invoice.id => Some(current.clone()),
+            _ => self.load_invoice(&updated_invoice.id).ok(),
+        };
+        let previous_status = existing.as_ref()
+            .map(|e| e.status.clone())
+            .unwrap_or(InvoiceStatus::Draft);
+        preserve_managed_fields(&mut updated_invoice, existing);
+        group_sections(&mut updated_invoice.line_items);
+        updated_invoice.tags = updated_invoice.tags.iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        updated_invoice.tags.sort();
+        updated_invoice.tags.dedup();
+        if let Some(ref terms) = updated_invoice.early_payment {
+            terms.validate()?;
+        }
+        if !updated_invoice.tax_lines.is_empty() {
+            if !updated_invoice.sales_taxes.is_empty() {
+                return Err("Remove the jurisdiction taxes before adding tax lines".to_string());
+            }
+            for line in &updated_invoice.tax_lines {
+                line.validate()?;
+            }
+            updated_invoice.tax_percent = updated_invoice.tax_lines.iter().map(|t| t.percent).sum();
+        }
+        if let Some(ref shipping) = updated_invoice.shipping {
+            shipping.validate()?;
+        }
+        if let Some(ref delivery) = updated_invoice.delivery_address {
+            delivery.validate()?;
+        }
+        self.check_custom_fields(&updated_invoice)?;
+        if !currency::is_valid_code(&updated_invoice.currency) {
+            return Err(format!("Invalid currency code: {}", updated_invoice.currency));
+        }
+        for member_id in updated_invoice.line_items.iter().filter_map(|item| item.team_member_id.as_ref()) {
+            if !self.team_members.iter().any(|m| &m.id == member_id) {
+                return Err(format!("Team member not found: {}", member_id));
+            }
+        }
+        if let Some(ref commission) = updated_invoice.commission {
+            if !self.partners.iter().any(|p| p.id == commission.partner_id) {
+                return Err("Referral partner not found".to_string());
+            }
+            if !(0.0..=100.0).contains(&commission.percent) {
+                return Err("Commission must be between 0 and 100%".to_string());
+            }
+        }
+        self.apply_status_change(&mut updated_invoice, previous_status, HashMap::new(), timestamp)?;
+
+        // The edit is accepted, so the current state goes on the undo stack
+        if let Some(ref current) = self.current_invoice {
+            if current.id == updated_invoice.id {
+                let snapshot = InvoiceSnapshot {
+                    invoice: current.clone(),
+                    timestamp: current.updated_at,
+                };
+                self.undo_stack.push(snapshot);
+
+                // Limit undo stack size
+                if self.undo_stack.len() > 50 {
+                    self.undo_stack.remove(0);
+                }
+
+                // Clear redo stack on new change
+                self.redo_stack.clear();
+            }
+        }
+
+        sync_time_entries(&mut self.time_entries, &updated_invoice);
+        self.current_invoice = Some(updated_invoice.clone());
+        self.has_unsaved_changes = true;
+
+        // Update summary
+        self.load_summary(&updated_invoice);
+
+        // Auto-save after 1 second
+        self.last_save_time = timestamp;
+        self.save_current_invoice()?;
+
+        if let Some((session_id, _)) = session {
+            let sent = serde_json::to_value(&updated_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+            self.draft_buffers.acknowledge(&session_id, &sent);
+        }
+        serde_json::to_string(&updated_invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Moves an invoice to a new status, with the same rules as update_invoice, and
+    // records who did it along with any metadata given (how it was sent, a reason).
+    // Setting the status it already has changes nothing.
+    #[http]
+    async fn set_invoice_status(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_invoice_status");
+        #[derive(Deserialize)]
+        struct SetInvoiceStatusRequest {
+            id: String,
+            status: InvoiceStatus,
+            #[serde(default)]
+            metadata: HashMap<String, String>,
+        }
+
+        let request: SetInvoiceStatusRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let (mut invoice, is_current) = self.invoice_for_update(&request.id)?;
+        let previous_status = invoice.status.clone();
+        if request.status == previous_status {
+            return serde_json::to_string(&invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e));
+        }
+        invoice.status = request.status;
+        let timestamp = host::now();
+        invoice.updated_at = timestamp;
+        self.apply_status_change(&mut invoice, previous_status, request.metadata, timestamp)?;
+        self.store_managed_change(invoice.clone(), is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // A document exactly as it was issued, whatever has happened to the invoice
+    // since: the latest issue unless a sequence number is given
+    #[http]
+    async fn get_issued_document(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_issued_document");
+        #[derive(Deserialize)]
+        struct GetIssuedDocumentRequest {
+            id: String,
+            #[serde(default)]
+            sequence: Option<usize>,
+        }
+
+        let request: GetIssuedDocumentRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let (invoice, _) = self.invoice_for_update(&request.id)?;
+        let sequence = match request.sequence {
+            Some(sequence) if sequence < invoice.issued_documents.len() => sequence,
+            Some(sequence) => return Err(format!("Invoice {} has no issue {}", invoice.number, sequence)),
+            None => invoice.issued_documents.len().checked_sub(1)
+                .ok_or_else(|| format!("Invoice {} hasn't been issued", invoice.number))?,
+        };
+        let data = with_store(|store| store.load_issued(&invoice.id, sequence))
+            .map_err(diagnostics::tagged)?;
+        let document: IssuedDocument = serde_json::from_value(data)
+            .map_err(|e| format!("Failed to parse issued document: {}", e))?;
+        if hex::encode(Sha256::digest(document.html.as_bytes())) != invoice.issued_documents[sequence].sha256 {
+            log!(Error, "store", "get_issued_document", Some(invoice.id.as_str()), "Issue {} doesn't match its hash", sequence);
+            return Err(diagnostics::tagged("Issued document doesn't match the hash recorded when it was sent".to_string()));
+        }
+        serde_json::to_string(&document)
+            .map_err(|e| format!("Failed to serialize issued document: {}", e))
+    }
+
+    // Changes only header fields of an invoice, so editing a due date doesn't mean
+    // sending back every line item. Fields left out stay as they are and null clears
+    // the optional ones. Goes through update_invoice, so the same checks (status
+    // changes included) apply and the invoice becomes the open one.
+    #[http]
+    async fn update_invoice_meta(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_invoice_meta");
+        const FIELDS: [&str; 5] = ["name", "date", "due_date", "status", "notes"];
+        #[derive(Deserialize)]
+        struct UpdateInvoiceMetaRequest {
+            id: String,
+            meta: serde_json::Map<String, serde_json::Value>, // Any of FIELDS
+        }
+
+        let request: UpdateInvoiceMetaRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if let Some(field) = request.meta.keys().find(|field| !FIELDS.contains(&field.as_str())) {
+            return Err(format!("{} can't be changed with update_invoice_meta", field));
+        }
+        for field in ["date", "due_date"] {
+            if let Some(date) = request.meta.get(field).and_then(|date| date.as_str()) {
+                if !is_iso_date(date) {
+                    return Err(format!("Invalid date: {}", date));
+                }
+            }
+        }
+
+        let invoice = match self.current_invoice {
+            Some(ref current) if current.id == request.id => current.clone(),
+            _ => {
+                // The open invoice is about to be replaced
+                self.save_current_invoice()?;
+                self.load_invoice(&request.id)?
+            }
+        };
+        let previous_key = with_store(|store| store.key(&invoice_location(&invoice)));
+        let mut body = serde_json::to_value(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+        if let Some(fields) = body.as_object_mut() {
+            fields.extend(request.meta);
+        }
+        let response = self.update_invoice(body.to_string()).await?;
+
+        // A new name or date files the invoice elsewhere in the VFS
+        if let Some(ref updated) = self.current_invoice {
+            let location = invoice_location(updated);
+            if with_store(|store| store.key(&location)) != previous_key {
+                let previous = invoice_location(&invoice);
+                with_store(|store| store.remove(&previous))
+                    .unwrap_or_else(|e| log!(Warn, "store", "update_invoice_meta", Some(invoice.id.as_str()), "Old copy left behind: {}", e));
+            }
+        }
+        Ok(response)
+    }
+
+    #[http]
+    async fn delete_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_invoice");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+
+        // Remove from summaries
+        if let Some(summary) = self.summary_log.remove(&mut self.invoices, &id) {
+            self.invoice_index.remove(&id);
+            self.deleted_numbers.push(summary.number.clone());
+
+            let _ = with_store(|store| store.remove(&summary_location(&summary)));
+
+            // Clear current invoice if it's the deleted one
+            if let Some(ref current) = self.current_invoice {
+                if current.id == id {
+                    self.current_invoice = None;
+                }
+            }
+            self.followups.retain(|_, f| f.invoice_id != id);
+            self.scheduled_sends.retain(|s| s.invoice_id != id);
+            for entry in self.time_entries.values_mut() {
+                if entry.billed_invoice_id.as_ref() == Some(&id) {
+                    entry.billed_invoice_id = None;
+                }
+            }
+
+            Ok("Invoice deleted".to_string())
+        } else {
+            Err("Invoice not found".to_string())
+        }
+    }
+
+    // Re-issues an invoice under another business profile: a new number from
+    // that profile's sequence and its letterhead, with the same line items
+    #[http]
+    async fn reissue_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("reissue_invoice");
+        #[derive(Deserialize)]
+        struct ReissueInvoiceRequest {
+            invoice_id: String,
+            profile_id: Option<String>, // None re-issues under the default profile
+        }
+
+        let request: ReissueInvoiceRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let source = match self.current_invoice {
+            Some(ref current) if current.id == request.invoice_id => current.clone(),
+            _ => self.load_invoice(&request.invoice_id)?,
+        };
+        if source.profile_id == request.profile_id {
+            return Err("Invoice is already issued under this profile".to_string());
+        }
+
+        let millis = host::now_millis();
+        let mut invoice = self.new_invoice(request.profile_id.as_deref(), None, millis / 1000)?;
+        invoice.name = source.name.clone();
+        invoice.due_date = source.due_date.clone();
+        invoice.invoicee = source.invoicee.clone();
+        invoice.kind = source.kind.clone();
+        invoice.discount_percent = source.discount_percent;
+        invoice.tax_percent = source.tax_percent;
+        invoice.tax_lines = source.tax_lines.clone();
+        invoice.shipping = source.shipping;
+        invoice.rounding = source.rounding;
+        invoice.purchase_order = source.purchase_order.clone();
+        invoice.reference = source.reference.clone();
+        invoice.delivery_address = source.delivery_address.clone();
+        invoice.custom_fields = source.custom_fields.clone();
+        invoice.notes = source.notes.clone();
+        invoice.terms_text = source.terms_text.clone();
+        invoice.thank_you = source.thank_you.clone();
+        invoice.service_period_start = source.service_period_start.clone();
+        invoice.service_period_end = source.service_period_end.clone();
+        invoice.reissued_from = Some(source.id.clone());
+
+        for (index, item) in source.line_items.iter().enumerate() {
+            let mut item = item.clone();
+            item.id = format!("item-{}-{}", millis, index);
+            if let Some(ref receipt_path) = item.receipt_path {
+                item.receipt_path = Some(copy_receipt(receipt_path, &invoice)?);
+            }
+            invoice.line_items.push(item);
+        }
+
+        // Set as current invoice
+        self.current_invoice = Some(invoice.clone());
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.has_unsaved_changes = true;
+
+        // Add to summaries
+        self.load_summary(&invoice);
+
+        // Save invoice
+        self.save_current_invoice()?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Bills several estimates or delivery notes in one new invoice, with each
+    // source document's items grouped under its own section
+    #[http]
+    async fn consolidate_documents(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("consolidate_documents");
+        let source_ids: Vec<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid document IDs: {}", e))?;
+
+        if source_ids.is_empty() {
+            return Err("No documents to consolidate".to_string());
+        }
+
+        let mut sources: Vec<Invoice> = Vec::new();
+        for id in &source_ids {
+            let source = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            if source.kind == DocumentKind::Invoice {
+                return Err(format!("{} is an invoice; only estimates and delivery notes can be consolidated", source.number));
+            }
+            if source.consolidated_into.is_some() {
+                return Err(format!("{} {} has already been invoiced", source.kind.label(), source.number));
+            }
+            if let Some(first) = sources.first() {
+                if first.invoicee.name != source.invoicee.name {
+                    return Err("All documents must be for the same client".to_string());
+                }
+            }
+            sources.push(source);
+        }
+
+        let millis = host::now_millis();
+        let mut invoice = self.new_invoice(sources[0].profile_id.as_deref(), None, millis / 1000)?;
+        invoice.invoicee = sources[0].invoicee.clone();
+        invoice.tax_percent = sources[0].tax_percent;
+        invoice.tax_lines = sources[0].tax_lines.clone();
+
+        for source in &sources {
+            let section = match source.name {
+                Some(ref name) if !name.is_empty() => format!("{} {}: {}", source.kind.label(), source.number, name),
+                _ => format!("{} {}", source.kind.label(), source.number),
+            };
+            for item in &source.line_items {
+                let mut item = item.clone();
+                item.id = format!("item-{}-{}", millis, invoice.line_items.len());
+                item.section = Some(section.clone());
+                invoice.line_items.push(item);
+            }
+            invoice.source_documents.push(SourceDocumentLink {
+                id: source.id.clone(),
+                number: source.number.clone(),
+                kind: source.kind.clone(),
+            });
+        }
+
+        // Link the sources forward to the new invoice
+        for mut source in sources {
+            source.consolidated_into = Some(invoice.id.clone());
+            source.updated_at = invoice.created_at;
+            save_invoice(&source)?;
+        }
+
+        // Set as current invoice
+        self.current_invoice = Some(invoice.clone());
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.has_unsaved_changes = true;
+
+        // Add to summaries
+        self.load_summary(&invoice);
+
+        // Save invoice
+        self.save_current_invoice()?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Line Item Operations
+
+    #[http]
+    async fn add_line_item(&mut self) -> Result<String, String> {
+        let _trace = trace("add_line_item");
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Create new line item
+            let millis = host::now_millis();
+            let new_item = LineItem {
+                id: format!("item-{}", millis),
+                description: String::new(),
+                quantity: 1.0,
+                unit: self.settings.as_ref().and_then(|s| s.default_unit.clone()),
+                ..Default::default()
+            };
+
+            invoice.line_items.push(new_item);
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    #[http]
+    async fn add_line_items(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("add_line_items");
+        let items: Vec<NewLineItem> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid line items: {}", e))?;
+        for item in &items {
+            item.validate()?;
+            custom_fields::validate(self.custom_field_definitions(), custom_fields::Scope::LineItem, &item.custom_fields)?;
+        }
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            let millis = host::now_millis();
+            let default_unit = self.settings.as_ref().and_then(|s| s.default_unit.clone());
+            for (index, item) in items.into_iter().enumerate() {
+                invoice.line_items.push(item.into_line_item(format!("item-{}-{}", millis, index), &default_unit));
+            }
+            group_sections(&mut invoice.line_items);
+
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Parses a table pasted from a spreadsheet or document into line items for
+    // review; the confirmed items are added with add_line_items
+    #[http]
+    async fn preview_pasted_line_items(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("preview_pasted_line_items");
+        let text: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid pasted text: {}", e))?;
+
+        let table = paste::parse_table(&text)?;
+        serde_json::to_string(&table)
+            .map_err(|e| format!("Failed to serialize pasted line items: {}", e))
+    }
+
+    // Catalog
+
+    // The whole catalog, or with an item code given, the items whose code starts
+    // with it (ignoring case)
+    #[http]
+    async fn list_catalog(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("list_catalog");
+        let code: Option<String> = if request_body.trim().is_empty() {
+            None
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid item code: {}", e))?
+        };
+
+        let code = code.map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty());
+        let items: Vec<&pricing::CatalogItem> = self.catalog.iter()
+            .filter(|item| match code {
+                Some(ref code) => item.item_code.as_ref()
+                    .map(|c| c.to_lowercase().starts_with(code.as_str()))
+                    .unwrap_or(false),
+                None => true,
+            })
+            .collect();
+        serde_json::to_string(&items)
+            .map_err(|e| format!("Failed to serialize catalog: {}", e))
+    }
+
+    // Adds a catalog item, or replaces the one with the same ID. Lines already on
+    // invoices keep the rate they were added at.
+    #[http]
+    async fn save_catalog_item(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("save_catalog_item");
+        let mut item: pricing::CatalogItem = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid catalog item: {}", e))?;
+        item.validate()?;
+        item.tiers.sort_by(|a, b| a.min_quantity.total_cmp(&b.min_quantity));
+        item.item_code = item.item_code.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+
+        if item.id.trim().is_empty() {
+            let timestamp = host::now();
+            item.id = format!("catalog-{}-{}", timestamp, self.catalog.len());
+        }
+        match self.catalog.iter_mut().find(|c| c.id == item.id) {
+            Some(existing) => *existing = item.clone(),
+            None => self.catalog.push(item.clone()),
+        }
+
+        serde_json::to_string(&item)
+            .map_err(|e| format!("Failed to serialize catalog item: {}", e))
+    }
+
+    #[http]
+    async fn delete_catalog_item(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_catalog_item");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid catalog item ID: {}", e))?;
+
+        let index = self.catalog.iter()
+            .position(|c| c.id == id)
+            .ok_or("Catalog item not found")?;
+        self.catalog.remove(index);
+        for prices in self.client_prices.values_mut() {
+            prices.remove(&id);
+        }
+        self.client_prices.retain(|_, prices| !prices.is_empty());
+
+        Ok("Catalog item deleted".to_string())
+    }
+
+    // Catalog item ID -> price, for one client
+    #[http]
+    async fn get_client_prices(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_client_prices");
+        let client_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid client ID: {}", e))?;
+
+        let prices = self.client_prices.get(&client_id.trim().to_lowercase()).cloned().unwrap_or_default();
+        serde_json::to_string(&prices)
+            .map_err(|e| format!("Failed to serialize client prices: {}", e))
+    }
+
+    // Sets a client's price for a catalog item; a null price removes it
+    #[http]
+    async fn set_client_price(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_client_price");
+        #[derive(Deserialize)]
+        struct SetClientPriceRequest {
+            client_id: String,
+            item_id: String,
+            price: Option<pricing::ClientPrice>,
+        }
+
+        let request: SetClientPriceRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let client_id = request.client_id.trim().to_lowercase();
+        if client_id.is_empty() {
+            return Err("Client ID is required".to_string());
+        }
+        if !self.catalog.iter().any(|c| c.id == request.item_id) {
+            return Err("Catalog item not found".to_string());
+        }
+
+        match request.price {
+            Some(mut price) => {
+                price.validate()?;
+                price.tiers.sort_by(|a, b| a.min_quantity.total_cmp(&b.min_quantity));
+                self.client_prices.entry(client_id).or_default().insert(request.item_id, price);
+            }
+            None => {
+                if let Some(prices) = self.client_prices.get_mut(&client_id) {
+                    prices.remove(&request.item_id);
+                    if prices.is_empty() {
+                        self.client_prices.remove(&client_id);
+                    }
+                }
+            }
+        }
+        Ok("Client price saved".to_string())
+    }
+
+    // The rate a client would be charged for a quantity, and where it comes from
+    #[http]
+    async fn quote_catalog_item(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("quote_catalog_item");
+        #[derive(Deserialize)]
+        struct QuoteRequest {
+            item_id: String,
+            #[serde(default)]
+            client_id: Option<String>,
+            #[serde(default = "default_quantity")]
+            quantity: f64,
+        }
+
+        let request: QuoteRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let item = self.catalog.iter()
+            .find(|c| c.id == request.item_id)
+            .ok_or("Catalog item not found")?;
+        let client_price = request.client_id
+            .and_then(|id| self.client_prices.get(&id.trim().to_lowercase()))
+            .and_then(|prices| prices.get(&item.id));
+
+        serde_json::to_string(&pricing::quote(item, client_price, request.quantity))
+            .map_err(|e| format!("Failed to serialize quote: {}", e))
+    }
+
+    // A price list of catalog items (all of them unless IDs are given) at a client's
+    // prices, rendered with the built-in price list template or a custom one and
+    // saved to the drive for sharing
+    #[http]
+    async fn generate_price_list(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("generate_price_list");
+        #[derive(Deserialize)]
+        struct PriceListRequest {
+            #[serde(default)]
+            item_ids: Vec<String>,
+            #[serde(default)]
+            client_id: Option<String>,
+            #[serde(default)]
+            client_name: Option<String>, // Shown as who the prices are for; the client ID otherwise
+            #[serde(default)]
+            title: Option<String>,
+            #[serde(default)]
+            valid_from: Option<String>,
+            #[serde(default)]
+            valid_until: Option<String>,
+            #[serde(default)]
+            notes: Option<String>,
+            #[serde(default)]
+            template_id: Option<String>,
+        }
+
+        let request: PriceListRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid price list request: {}", e))?;
+        for date in request.valid_from.iter().chain(request.valid_until.iter()) {
+            if !is_iso_date(date) {
+                return Err(format!("Invalid date: {}", date));
+            }
+        }
+        if let (Some(from), Some(until)) = (&request.valid_from, &request.valid_until) {
+            if until < from {
+                return Err("Price list can't expire before it starts".to_string());
+            }
+        }
+
+        let items: Vec<&pricing::CatalogItem> = if request.item_ids.is_empty() {
+            self.catalog.iter().collect()
+        } else {
+            request.item_ids.iter()
+                .map(|id| self.catalog.iter().find(|c| &c.id == id).ok_or_else(|| format!("Catalog item not found: {}", id)))
+                .collect::<Result<_, _>>()?
+        };
+        if items.is_empty() {
+            return Err("The catalog is empty".to_string());
+        }
+
+        let client_id = request.client_id.as_ref().map(|id| id.trim().to_lowercase());
+        let client_prices = client_id.as_ref().and_then(|id| self.client_prices.get(id));
+        let settings = self.settings.clone().ok_or("Settings not configured")?;
+        let now = host::now();
+        let date = date_from_timestamp(self.local_time(now));
+
+        let context = templates::PriceListContext {
+            title: request.title.clone().filter(|t| !t.trim().is_empty()).unwrap_or_else(|| "Price List".to_string()),
+            date: date.clone(),
+            valid_from: request.valid_from,
+            valid_until: request.valid_until,
+            issuer: templates::PartyContext {
+                name: settings.invoicer.name.clone(),
+                company: settings.invoicer.company.clone(),
+                address: settings.invoicer.address.clone(),
+                email: settings.invoicer.email.clone(),
+                phone: settings.invoicer.phone.clone(),
+                tax_id: settings.invoicer.tax_id.clone(),
+                tax_id_label: settings.tax.tax_id_label.clone(),
+            },
+            client: request.client_name.filter(|n| !n.trim().is_empty()).or(request.client_id),
+            items: items.iter()
+                .map(|item| {
+                    let client_price = client_prices.and_then(|prices| prices.get(&item.id));
+                    let quote = pricing::quote(item, client_price, 1.0);
+                    let tiers = match client_price {
+                        Some(price) if !price.tiers.is_empty() => &price.tiers,
+                        _ => &item.tiers,
+                    };
+                    templates::PriceListItemContext {
+                        name: item.name.clone(),
+                        description: item.description.clone().filter(|d| !d.trim().is_empty()),
+                        unit: item.unit.clone(),
+                        rate: quote.rate,
+                        list_rate: Some(quote.list_rate).filter(|list| *list > quote.rate),
+                        tiers: tiers.iter()
+                            .filter(|t| t.rate < quote.rate)
+                            .map(|t| templates::TierContext { min_quantity: t.min_quantity, rate: t.rate })
+                            .collect(),
+                    }
+                })
+                .collect(),
+            notes: request.notes,
+            currency: currency_context(&settings.default_currency),
+        };
+        let context = serde_json::to_value(&context)
+            .map_err(|e| format!("Failed to serialize price list: {}", e))?;
+
+        let (html, errors) = match request.template_id {
+            Some(ref id) => {
+                let custom = self.custom_template(id)?;
+                let template = templates::check_source(&custom.source)
+                    .map_err(|errors| describe_template_errors(&errors))?;
+                self.render_custom(&template, &custom.assets, &context)
+            }
+            None => {
+                let template = templates::Template::parse(templates::PRICE_LIST_TEMPLATE)
+                    .map_err(|e| format!("Price list template: {}", e.message))?;
+                let (body, errors) = template.render(&context);
+                (html_document(&body, &self.default_layout_head(&body), DOCUMENT_CONTENT_SECURITY_POLICY), errors)
+            }
+        };
+
+        let name = match context["client"].as_str() {
+            Some(client) => format!("price-list-{}-{}", copy_label_slug(client), date),
+            None => format!("price-list-{}", date),
+        };
+        let package_id = host::address().package_id();
+        let path = format!("/{}/invoice/price-lists/{}.html", package_id, name);
+        save_blob(&path, html.as_bytes())
+            .map_err(|e| format!("Failed to write price list: {}", e))?;
+
+        let response = serde_json::json!({
+            "path": path,
+            "html": html,
+            "filename": format!("{}.html", name),
+            "errors": errors,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Adds catalog items to the current invoice at the invoicee's prices, with
+    // volume tiers applied to each quantity
+    #[http]
+    async fn add_catalog_items(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("add_catalog_items");
+        #[derive(Deserialize)]
+        struct CatalogLine {
+            item_id: String,
+            #[serde(default = "default_quantity")]
+            quantity: f64,
+            #[serde(default)]
+            section: Option<String>,
+        }
+
+        let lines: Vec<CatalogLine> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid catalog items: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            let client_id = client_key(&invoice.invoicee);
+            let mut new_items = Vec::new();
+            let millis = host::now_millis();
+            for (index, line) in lines.into_iter().enumerate() {
+                let catalog_item = self.catalog.iter()
+                    .find(|c| c.id == line.item_id)
+                    .ok_or_else(|| format!("Catalog item not found: {}", line.item_id))?;
+                let client_price = self.client_prices.get(&client_id)
+                    .and_then(|prices| prices.get(&catalog_item.id));
+                let quote = pricing::quote(catalog_item, client_price, line.quantity);
+                new_items.push(LineItem {
+                    id: format!("item-{}-{}", millis, index),
+                    item_code: catalog_item.item_code.clone(),
+                    description: catalog_item.line_description(),
+                    quantity: line.quantity,
+                    unit: catalog_item.unit.clone()
+                        .or_else(|| self.settings.as_ref().and_then(|s| s.default_unit.clone())),
+                    rate: quote.rate,
+                    section: line.section,
+                    hsn_sac: catalog_item.hsn_sac.clone(),
+                    catalog_item_id: Some(catalog_item.id.clone()),
+                    list_rate: Some(quote.list_rate).filter(|list| *list > quote.rate),
+                    ..Default::default()
+                });
+            }
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            invoice.line_items.extend(new_items);
+            group_sections(&mut invoice.line_items);
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    #[http]
+    async fn update_line_item(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_line_item");
+        #[derive(Deserialize)]
+        struct UpdateLineItemRequest {
+            item_id: String,
+            updates: LineItem,
+        }
+
+        let req: UpdateLineItemRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        req.updates.validate()?;
+        custom_fields::validate(self.custom_field_definitions(), custom_fields::Scope::LineItem, &req.updates.custom_fields)?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Find and update line item. Catalog items are repriced when the quantity
+            // changes, so volume tiers follow it, unless the rate was edited too.
+            let client_id = client_key(&invoice.invoicee);
+            if let Some(item) = invoice.line_items.iter_mut().find(|i| i.id == req.item_id) {
+                let mut updates = req.updates;
+                let catalog_item = updates.catalog_item_id.as_ref()
+                    .and_then(|id| self.catalog.iter().find(|c| &c.id == id));
+                if let Some(catalog_item) = catalog_item {
+                    if updates.quantity != item.quantity && updates.rate == item.rate {
+                        let client_price = self.client_prices.get(&client_id)
+                            .and_then(|prices| prices.get(&catalog_item.id));
+                        let quote = pricing::quote(catalog_item, client_price, updates.quantity);
+                        updates.rate = quote.rate;
+                        updates.list_rate = Some(quote.list_rate).filter(|list| *list > quote.rate);
+                    }
+                }
+                *item = updates;
+            } else {
+                return Err("Line item not found".to_string());
+            }
+            group_sections(&mut invoice.line_items);
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    #[http]
+    async fn delete_line_item(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_line_item");
+        let item_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid item ID: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Remove line item
+            invoice.line_items.retain(|item| item.id != item_id);
+            sync_time_entries(&mut self.time_entries, invoice);
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    #[http]
+    async fn reorder_line_items(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("reorder_line_items");
+        let item_ids: Vec<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid item IDs: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Reorder line items. Items dropped outside their section are pulled back
+            // to the end of it, so sections stay together.
+            let mut new_items = Vec::new();
+            for id in item_ids {
+                if let Some(item) = invoice.line_items.iter().find(|i| i.id == id) {
+                    new_items.push(item.clone());
+                }
+            }
+            group_sections(&mut new_items);
+            invoice.line_items = new_items;
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+
+            serde_json::to_string(invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Moves whole sections, given their names in the new order. Sections left out
+    // follow in their current order, and items in no section stay at the end.
+    #[http]
+    async fn reorder_sections(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("reorder_sections");
+        let names: Vec<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid section names: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            if let Some(name) = names.iter().find(|name| !invoice.line_items.iter().any(|i| i.section.as_ref() == Some(*name))) {
+                return Err(format!("Section not found: {}", name));
+            }
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            let rank = |item: &LineItem| match item.section {
+                Some(ref section) => names.iter().position(|name| name == section).unwrap_or(names.len()),
+                None => names.len() + 1,
+            };
+            invoice.line_items.sort_by_key(rank);
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+
+            serde_json::to_string(invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    #[http]
+    async fn duplicate_line_item(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("duplicate_line_item");
+        let item_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid item ID: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            let index = invoice.line_items.iter().position(|item| item.id == item_id)
+                .ok_or("Line item not found")?;
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Insert the copy directly below the original
+            let millis = host::now_millis();
+            let mut copy = invoice.line_items[index].clone();
+            copy.id = format!("item-{}", millis);
+            copy.time_entry_id = None; // The entry stays billed by the original
+            invoice.line_items.insert(index + 1, copy);
+
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Moves (or copies) line items from the current invoice to another one,
+    // copying their receipts into the target invoice's directory. Undo restores
+    // the current invoice; the target invoice is saved directly.
+    #[http]
+    async fn transfer_line_items(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("transfer_line_items");
+        #[derive(Deserialize)]
+        struct TransferLineItemsRequest {
+            item_ids: Vec<String>,
+            target_invoice_id: String,
+            copy: bool,
+        }
+
+        let request: TransferLineItemsRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let current_id = self.current_invoice.as_ref()
+            .map(|i| i.id.clone())
+            .ok_or("No invoice currently loaded")?;
+        if current_id == request.target_invoice_id {
+            return Err("Target invoice must be different from the current invoice".to_string());
+        }
+
+        let mut target = self.load_invoice(&request.target_invoice_id)?;
+        if target.signature.is_some() {
+            return Err("Cannot add line items to a signed document".to_string());
+        }
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            let items: Vec<LineItem> = invoice.line_items.iter()
+                .filter(|item| request.item_ids.contains(&item.id))
+                .cloned()
+                .collect();
+            if items.len() != request.item_ids.len() {
+                return Err("Line item not found".to_string());
+            }
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            let millis = host::now_millis();
+            for (index, mut item) in items.into_iter().enumerate() {
+                item.id = format!("item-{}-{}", millis, index);
+                if request.copy {
+                    item.time_entry_id = None; // The entry stays billed by the original
+                }
+                if let Some(ref receipt_path) = item.receipt_path {
+                    item.receipt_path = Some(copy_receipt(receipt_path, &target)?);
+                }
+                target.line_items.push(item);
+            }
+            target.updated_at = millis / 1000;
+            save_invoice(&target)?;
+
+            if !request.copy {
+                invoice.line_items.retain(|item| !request.item_ids.contains(&item.id));
+                sync_time_entries(&mut self.time_entries, invoice);
+                sync_time_entries(&mut self.time_entries, &target);
+            }
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summaries
+            self.load_summary(&target);
+            self.load_current_summary();
+
+            self.save_current_invoice()?;
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Receipt Upload
+
+    #[http]
+    async fn upload_receipt(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_receipt");
+        #[derive(Deserialize)]
+        struct ReceiptUploadRequest {
+            item_id: String,
+            file_name: String,
+            file_data: Vec<u8>,
+        }
+
+        let request: ReceiptUploadRequest = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let file_name = safe_file_name(&request.file_name)?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Find the line item
+            let item_index = invoice.line_items.iter().position(|item| item.id == request.item_id)
+                .ok_or("Line item not found")?;
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            // Save the receipt file, in a receipts directory for this invoice
+            let receipt_path = format!("{}/receipts/{}", invoice_dir_path(invoice), file_name);
+            save_blob(&receipt_path, &request.file_data)
+                .map_err(|e| format!("Failed to write receipt: {}", e))?;
+
+            // Update the line item with the receipt path
+            invoice.line_items[item_index].receipt_path = Some(receipt_path.clone());
+            index_receipt(&mut self.receipt_hashes, &request.file_data, &receipt_path);
+
+            // If the line item description is empty or default, use the filename without extension
+            if invoice.line_items[item_index].description.is_empty() ||
+               invoice.line_items[item_index].description == "Click to add description" {
+                invoice.line_items[item_index].description = file_stem(&file_name);
+            }
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+            self.save_current_invoice()?;
+
+            // Return the path
+            Ok(receipt_path)
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Unfiled Receipts
+
+    #[http]
+    async fn upload_unfiled_receipt(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_unfiled_receipt");
+        #[derive(Deserialize)]
+        struct UnfiledReceiptUploadRequest {
+            file_name: String,
+            file_data: Vec<u8>,
+        }
+
+        let request: UnfiledReceiptUploadRequest = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let file_name = safe_file_name(&request.file_name)?;
+
+        let timestamp = host::now();
+        let id = format!("receipt-{}-{}", timestamp, self.unfiled_receipts.len());
+
+        // Each receipt gets its own directory so the original file name survives filing
+        let package_id = host::address().package_id();
+        let path = format!("/{}/invoice/unfiled/{}/{}", package_id, id, file_name);
+        save_blob(&path, &request.file_data)
+            .map_err(|e| format!("Failed to write receipt: {}", e))?;
+        index_receipt(&mut self.receipt_hashes, &request.file_data, &path);
+
+        let category = categorize(&self.category_rules, "", &file_name, None);
+        let receipt = UnfiledReceipt {
+            id,
+            file_name,
+            path,
+            uploaded_at: timestamp,
+            category,
+        };
+        self.unfiled_receipts.push(receipt.clone());
+
+        serde_json::to_string(&receipt)
+            .map_err(|e| format!("Failed to serialize receipt: {}", e))
+    }
+
+    #[http]
+    async fn list_unfiled_receipts(&self) -> Result<String, String> {
+        let _trace = trace("list_unfiled_receipts");
+        serde_json::to_string(&self.unfiled_receipts)
+            .map_err(|e| format!("Failed to serialize receipts: {}", e))
+    }
+
+    // Attaches an unfiled receipt to a line item of the current invoice or to an expense
+    #[http]
+    async fn file_receipt(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("file_receipt");
+        #[derive(Deserialize)]
+        struct FileReceiptRequest {
+            receipt_id: String,
+            item_id: Option<String>,
+            expense_id: Option<String>,
+        }
+
+        let request: FileReceiptRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let index = self.unfiled_receipts.iter().position(|r| r.id == request.receipt_id)
+            .ok_or("Receipt not found")?;
+        let receipt = self.unfiled_receipts[index].clone();
+
+        let response = match (request.item_id, request.expense_id) {
+            (Some(item_id), None) => {
+                let invoice = self.current_invoice.as_mut()
+                    .ok_or("No invoice currently loaded")?;
+                let item_index = invoice.line_items.iter().position(|item| item.id == item_id)
+                    .ok_or("Line item not found")?;
+
+                // Save current state for undo
+                let snapshot = InvoiceSnapshot {
+                    invoice: invoice.clone(),
+                    timestamp: invoice.updated_at,
+                };
+                self.undo_stack.push(snapshot);
+                if self.undo_stack.len() > 50 {
+                    self.undo_stack.remove(0);
+                }
+                self.redo_stack.clear();
+
+                let receipt_path = copy_receipt(&receipt.path, invoice)?;
+                rename_indexed_receipt(&mut self.receipt_hashes, &receipt.path, &receipt_path);
+                let item = &mut invoice.line_items[item_index];
+                item.receipt_path = Some(receipt_path);
+                if item.description.is_empty() || item.description == "Click to add description" {
+                    item.description = file_stem(&receipt.file_name);
+                }
+
+                invoice.updated_at = host::now();
+
+                self.has_unsaved_changes = true;
+                self.save_current_invoice()?;
+
+                serde_json::to_string(&self.current_invoice)
+                    .map_err(|e| format!("Failed to serialize invoice: {}", e))?
+            }
+            (None, Some(expense_id)) => {
+                let expense = self.expenses.get_mut(&expense_id)
+                    .ok_or("Expense not found")?;
+
+                let data = load_blob(&receipt.path)
+                    .map_err(|e| format!("Failed to read receipt: {}", e))?;
+                let package_id = host::address().package_id();
+                let path = format!("/{}/invoice/expenses/{}/{}", package_id, expense.id, receipt.file_name);
+                save_blob(&path, &data)
+                    .map_err(|e| format!("Failed to write receipt: {}", e))?;
+                rename_indexed_receipt(&mut self.receipt_hashes, &receipt.path, &path);
+                expense.attachments.push(path);
+                if expense.category.is_none() {
+                    expense.category = receipt.category.clone();
+                }
+
+                serde_json::to_string(&expense)
+                    .map_err(|e| format!("Failed to serialize expense: {}", e))?
+            }
+            _ => return Err("Specify exactly one of item_id or expense_id".to_string()),
+        };
+
+        self.unfiled_receipts.remove(index);
+        remove_unfiled_receipt(&receipt);
+
+        Ok(response)
+    }
+
+    #[http]
+    async fn delete_unfiled_receipt(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_unfiled_receipt");
+        let receipt_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid receipt ID: {}", e))?;
+
+        let index = self.unfiled_receipts.iter().position(|r| r.id == receipt_id)
+            .ok_or("Receipt not found")?;
+        let receipt = self.unfiled_receipts.remove(index);
+        remove_unfiled_receipt(&receipt);
+        for paths in self.receipt_hashes.values_mut() {
+            paths.retain(|p| p != &receipt.path);
+        }
+        self.receipt_hashes.retain(|_, paths| !paths.is_empty());
+
+        Ok("Receipt deleted".to_string())
+    }
+
+    #[http]
+    async fn get_receipt(&self, request_body: String) -> Result<Vec<u8>, String> {
+        let _trace = trace("get_receipt");
+        let receipt_path: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid receipt path: {}", e))?;
+
+        load_blob(&receipt_path)
+            .map_err(|e| format!("Receipt not found: {}", e))
+    }
+
+    // Duplicate Detection
+
+    // Looks for invoices to the same client with a similar total and date, and for
+    // receipts with identical contents. Pass either a saved invoice's ID, the
+    // client/total/date of one about to be created, or a receipt's bytes.
+    #[http]
+    async fn find_possible_duplicates(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("find_possible_duplicates");
+        #[derive(Deserialize)]
+        struct FindDuplicatesRequest {
+            #[serde(default)]
+            invoice_id: Option<String>,
+            #[serde(default)]
+            client: Option<String>,
+            #[serde(default)]
+            total: Option<f64>,
+            #[serde(default)]
+            date: Option<String>,
+            #[serde(default)]
+            file_data: Option<Vec<u8>>,
+        }
+
+        let request: FindDuplicatesRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let (client, total, date) = match request.invoice_id {
+            Some(ref invoice_id) => {
+                let invoice = match self.current_invoice {
+                    Some(ref current) if &current.id == invoice_id => current.clone(),
+                    _ => self.load_invoice(invoice_id)?,
+                };
+                let client = client_key(&invoice.invoicee);
+                (Some(client), Some(calculate_invoice_total(&invoice)), Some(invoice.date))
+            }
+            None => (request.client.map(|c| c.trim().to_lowercase()), request.total.map(Money::from_f64), request.date),
+        };
+
+        let mut invoices = Vec::new();
+        if let (Some(client), Some(total), Some(date)) = (client, total, date) {
+            let day = days_from_date(&date).ok_or("Date must be YYYY-MM-DD")?;
+            for summary in self.invoices.values() {
+                if request.invoice_id.as_ref() == Some(&summary.id) {
+                    continue;
+                }
+                // Within 1% (at least one cent) and a week of each other
+                let close_total = (summary.total - total).abs() <= total.abs().percent(1.0, money::DECIMALS).max(Money::from_cents(1));
+                let close_date = days_from_date(&summary.date)
+                    .map(|d| (d - day).abs() <= 7)
+                    .unwrap_or(false);
+                if !close_total || !close_date {
+                    continue;
+                }
+                let candidate = match self.current_invoice {
+                    Some(ref current) if current.id == summary.id => current.clone(),
+                    _ => match self.load_invoice(&summary.id) {
+                        Ok(invoice) => invoice,
+                        Err(_) => continue,
+                    },
+                };
+                if !client.is_empty() && client_key(&candidate.invoicee) == client {
+                    invoices.push(summary.clone());
+                }
+            }
+        }
+
+        let receipts = match request.file_data {
+            Some(ref data) => {
+                let hash = hex::encode(Sha256::digest(data));
+                self.receipt_hashes.get(&hash).cloned().unwrap_or_default()
+            }
+            None => Vec::new(),
+        };
+
+        let response = serde_json::json!({
+            "invoices": invoices,
+            "receipts": receipts,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // E-Signatures
+
+    // Signs in the app, e.g. for a recipient signing on the owner's device; recipients
+    // elsewhere sign through their share link with sign_shared_invoice
+    #[http]
+    async fn sign_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("sign_invoice");
+        #[derive(Deserialize)]
+        struct SignInvoiceRequest {
+            invoice_id: String,
+            signer_name: String,
+            signature_image: Vec<u8>, // PNG of the drawn signature
+        }
+
+        let request: SignInvoiceRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let invoice = self.record_signature(&request.invoice_id, request.signer_name, &request.signature_image, None, None)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // The recipient's signature, made through the share link the invoice was sent
+    // with. The signer's node, and the link's address when it is restricted to
+    // one, are recorded with it.
+    #[local]
+    #[remote]
+    async fn sign_shared_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("sign_shared_invoice");
+        #[derive(Deserialize)]
+        struct SignSharedInvoiceRequest {
+            #[serde(flatten)]
+            access: ShareAccess,
+            signer_name: String,
+            signature_image: Vec<u8>, // PNG of the drawn signature
+        }
+
+        let timestamp = host::now();
+        let source = host::source().node;
+        let request: SignSharedInvoiceRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let link = self.admit_share_link(&source, "sign_shared_invoice", &request.access, timestamp)?;
+
+        let invoice = self.record_signature(
+            &link.invoice_id,
+            request.signer_name,
+            &request.signature_image,
+            Some(source),
+            link.allowed_email.clone(),
+        )?;
+        // Codes are single use
+        self.share_codes.remove(&link.token);
+
+        serde_json::to_string(&invoice.signature)
+            .map_err(|e| format!("Failed to serialize signature: {}", e))
+    }
+
+    // Terms and Conditions
+
+    #[http]
+    async fn upload_terms(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_terms");
+        #[derive(Deserialize)]
+        struct TermsUploadRequest {
+            terms_id: Option<String>, // Set to upload a new version of existing terms
+            title: String,
+            file_name: String,
+            file_data: Vec<u8>,
+        }
+
+        let request: TermsUploadRequest = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let file_name = safe_file_name(&request.file_name)?;
+
+        let timestamp = host::now();
+
+        let (id, version) = match request.terms_id {
+            Some(id) => {
+                let latest = self.terms_documents.iter()
+                    .filter(|d| d.id == id)
+                    .map(|d| d.version)
+                    .max()
+                    .ok_or("Terms not found")?;
+                (id, latest + 1)
+            }
+            None => (format!("terms-{}", timestamp), 1),
+        };
+
+        let package_id = host::address().package_id();
+        let drive_path = format!("/{}/invoice", package_id);
+        let path = format!("{}/terms/{}-v{}-{}", drive_path, id, version, file_name);
+        save_blob(&path, &request.file_data)
+            .map_err(|e| format!("Failed to write terms: {}", e))?;
+
+        let document = TermsDocument {
+            id,
+            version,
+            title: request.title,
+            file_name,
+            path,
+            hash: hex::encode(Sha256::digest(&request.file_data)),
+            uploaded_at: timestamp,
+        };
+        self.terms_documents.push(document.clone());
+
+        serde_json::to_string(&document)
+            .map_err(|e| format!("Failed to serialize terms: {}", e))
+    }
+
+    #[http]
+    async fn list_terms(&self) -> Result<String, String> {
+        let _trace = trace("list_terms");
+        serde_json::to_string(&self.terms_documents)
+            .map_err(|e| format!("Failed to serialize terms: {}", e))
+    }
+
+    #[http]
+    async fn get_terms_document(&self, request_body: String) -> Result<Vec<u8>, String> {
+        let _trace = trace("get_terms_document");
+        let reference: TermsReference = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid terms reference: {}", e))?;
+
+        let document = self.terms_documents.iter()
+            .find(|d| d.id == reference.terms_id && d.version == reference.version)
+            .ok_or("Terms not found")?;
+
+        load_blob(&document.path)
+            .map_err(|e| format!("Terms not found: {}", e))
+    }
+
+    // Attaches the latest version of a terms document to the current invoice,
+    // or detaches terms when called with null
+    #[http]
+    async fn attach_terms(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("attach_terms");
+        let terms_id: Option<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid terms ID: {}", e))?;
+
+        let terms = match terms_id {
+            Some(id) => {
+                let version = self.terms_documents.iter()
+                    .filter(|d| d.id == id)
+                    .map(|d| d.version)
+                    .max()
+                    .ok_or("Terms not found")?;
+                Some(TermsReference { terms_id: id, version })
+            }
+            None => None,
+        };
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            if invoice.signature.is_some() {
+                return Err("Cannot change the terms of a signed document".to_string());
+            }
+
+            invoice.terms = terms;
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+            self.save_current_invoice()?;
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Records that the recipient acknowledged the terms attached to an invoice
+    #[http]
+    async fn accept_terms(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("accept_terms");
+        #[derive(Deserialize)]
+        struct AcceptTermsRequest {
+            invoice_id: String,
+            accepted_by: String,
+            email: Option<String>,
+        }
+
+        let request: AcceptTermsRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let acceptance = self.record_terms_acceptance(&request.invoice_id, request.accepted_by, request.email, None)?;
+
+        serde_json::to_string(&acceptance)
+            .map_err(|e| format!("Failed to serialize acceptance: {}", e))
+    }
+
+    // Lightning Payments
+
+    #[http]
+    async fn create_lightning_invoice(&mut self) -> Result<String, String> {
+        let _trace = trace("create_lightning_invoice");
+        let lightning = self.settings.as_ref()
+            .and_then(|s| s.lightning.clone())
+            .ok_or("Lightning is not configured in settings")?;
+        let invoice = self.current_invoice.clone()
+            .ok_or("No invoice currently loaded")?;
+
+        if invoice.status == InvoiceStatus::Paid {
+            return Err("Invoice is already paid".to_string());
+        }
+        let total = amount_due_in(&invoice, &lightning.currency)?;
+        let amount_msat = (total.to_f64() * lightning.sats_per_unit * 1000.0).round() as u64;
+        if amount_msat == 0 {
+            return Err("Invoice total must be greater than zero".to_string());
+        }
+
+        let response = send_lightning_request(&lightning, &LightningRequest::CreateInvoice {
+            amount_msat,
+            memo: format!("Invoice {}", invoice.number),
+        }).await?;
+        let (bolt11, payment_hash) = match response {
+            LightningResponse::Invoice { bolt11, payment_hash } => (bolt11, payment_hash),
+            LightningResponse::Error(e) => return Err(format!("Lightning node error: {}", e)),
+            _ => return Err("Unexpected response from Lightning node".to_string()),
+        };
+
+        let timestamp = host::now();
+
+        if let Some(ref mut current) = self.current_invoice {
+            current.lightning_payment = Some(LightningPayment {
+                bolt11,
+                payment_hash: payment_hash.clone(),
+                amount_msat,
+                created_at: timestamp,
+                settled_at: None,
+            });
+            current.updated_at = timestamp;
+            self.pending_lightning.insert(current.id.clone(), payment_hash);
+            self.has_unsaved_changes = true;
+        }
+        self.save_current_invoice()?;
+
+        serde_json::to_string(&self.current_invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Polled by the UI; marks invoices Paid once their Lightning invoice settles
+    #[http]
+    async fn check_lightning_payments(&mut self) -> Result<String, String> {
+        let _trace = trace("check_lightning_payments");
+        let Some(lightning) = self.settings.as_ref().and_then(|s| s.lightning.clone()) else {
+            return Ok("[]".to_string());
+        };
+
+        let mut settled = Vec::new();
+        for (invoice_id, payment_hash) in self.pending_lightning.clone() {
+            let response = send_lightning_request(&lightning, &LightningRequest::LookupInvoice {
+                payment_hash,
+            }).await;
+            match response {
+                Ok(LightningResponse::Status { settled: true }) => settled.push(invoice_id),
+                Ok(_) => {}
+                Err(e) => log!(Warn, "payments", "check_lightning_payments", Some(invoice_id.as_str()), "Failed to check Lightning payment: {}", e),
+            }
+        }
+
+        let timestamp = host::now();
+
+        for invoice_id in &settled {
+            self.pending_lightning.remove(invoice_id);
+
+            let (mut invoice, is_current) = match self.invoice_for_update(invoice_id) {
+                Ok(found) => found,
+                Err(e) => {
+                    log!(Error, "store", "check_lightning_payments", Some(invoice_id.as_str()), "Failed to load settled invoice: {}", e);
+                    continue;
+                }
+            };
+
+            if let Some(ref mut payment) = invoice.lightning_payment {
+                payment.settled_at = Some(timestamp);
+            }
+            let previous_status = std::mem::replace(&mut invoice.status, InvoiceStatus::Paid);
+            if previous_status != InvoiceStatus::Paid {
+                self.record_status_change(&mut invoice, previous_status, HashMap::new(), timestamp);
+            }
+            invoice.updated_at = timestamp;
+            self.notify(
+                notifications::NotificationKind::PaymentReceived,
+                format!("Invoice {} paid", invoice.number),
+                "Payment received over Lightning".to_string(),
+                Some(invoice.id.clone()),
+            );
+
+            // Update summary
+            self.load_summary(&invoice);
+
+            if is_current {
+                self.current_invoice = Some(invoice);
+                self.has_unsaved_changes = true;
+                self.save_current_invoice()?;
+            } else {
+                save_invoice(&invoice)?;
+            }
+        }
+
+        serde_json::to_string(&settled)
+            .map_err(|e| format!("Failed to serialize settled invoices: {}", e))
+    }
+
+    // Stablecoin Payments
+
+    #[http]
+    async fn create_crypto_payment_request(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("create_crypto_payment_request");
+        let symbol: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid token symbol: {}", e))?;
+
+        let token = self.settings.as_ref()
+            .and_then(|s| s.stablecoins.iter().find(|c| c.symbol == symbol).cloned())
+            .ok_or_else(|| format!("{} is not configured in settings", symbol))?;
+        let invoice = self.current_invoice.clone()
+            .ok_or("No invoice currently loaded")?;
+
+        if invoice.status == InvoiceStatus::Paid {
+            return Err("Invoice is already paid".to_string());
+        }
+        let amount = stablecoin_amount(amount_due_in(&invoice, &token.currency)?, token.decimals, &invoice.id)?;
+
+        let provider = eth::Provider::new(token.chain_id, 30);
+        let from_block = provider.get_block_number()
+            .map_err(|e| format!("Failed to get block number: {}", e))?;
+
+        let timestamp = host::now();
+
+        let payment = CryptoPayment {
+            symbol: token.symbol,
+            chain_id: token.chain_id,
+            uri: format!(
+                "ethereum:{}@{}/transfer?address={}&uint256={}",
+                token.token_address, token.chain_id, token.recipient, amount
+            ),
+            token_address: token.token_address,
+            recipient: token.recipient,
+            amount: amount.to_string(),
+            from_block,
+            created_at: timestamp,
+            tx_hash: None,
+            paid_at: None,
+        };
+
+        if let Some(ref mut current) = self.current_invoice {
+            current.crypto_payment = Some(payment.clone());
+            current.updated_at = timestamp;
+            self.pending_crypto.insert(current.id.clone(), payment);
+            self.has_unsaved_changes = true;
+        }
+        self.save_current_invoice()?;
+
+        serde_json::to_string(&self.current_invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Records a payment made outside the app and marks the invoice Paid. The amount
+    // must match the balance due with the fee of the payment method used, if any.
+    #[http]
+    async fn record_payment(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("record_payment");
+        #[derive(Deserialize)]
+        struct RecordPaymentRequest {
+            invoice_id: String,
+            #[serde(default)]
+            method_id: Option<String>, // A payment method fee from settings
+            amount: Money,
+            #[serde(default)]
+            paid_on: Option<String>, // Defaults to today
+            #[serde(default)]
+            reference: Option<String>,
+        }
+
+        let request: RecordPaymentRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let timestamp = host::now();
+        let paid_on = request.paid_on.unwrap_or_else(|| date_from_timestamp(self.local_time(timestamp)));
+        if !is_iso_date(&paid_on) {
+            return Err(format!("Invalid date: {}", paid_on));
+        }
+        let fee = match request.method_id {
+            Some(ref id) => Some(
+                self.settings.as_ref()
+                    .and_then(|s| s.payment_method_fees.iter().find(|f| &f.id == id))
+                    .cloned()
+                    .ok_or("Payment method not found")?
+            ),
+            None => None,
+        };
+
+        let is_current = self.current_invoice.as_ref()
+            .map(|i| i.id == request.invoice_id)
+            .unwrap_or(false);
+        let mut invoice = match self.current_invoice {
+            Some(ref current) if is_current => current.clone(),
+            _ => self.load_invoice(&request.invoice_id)?,
+        };
+        if invoice.status == InvoiceStatus::Paid {
+            return Err("Invoice is already paid".to_string());
+        }
+
+        // Within the early payment window the discounted amount is accepted too,
+        // and the discount recorded with the payment
+        let due = amount_due(&invoice);
+        let discount = match self.early_payment_offer(&invoice) {
+            Some((pay_by, discount)) if paid_on <= pay_by => discount,
+            _ => Money::ZERO,
+        };
+        let with_fee = |amount: Money| fee.as_ref().map(|f| amount_with_fee(amount, f, currency_decimals(&invoice))).unwrap_or(amount);
+        let early_payment_discount = if discount > Money::ZERO && request.amount == with_fee(due - discount) {
+            discount
+        } else if request.amount == with_fee(due) {
+            Money::ZERO
+        } else {
+            let early = if discount > Money::ZERO {
+                format!(" ({} with the early payment discount)", format_money(with_fee(due - discount), &invoice.currency))
+            } else {
+                String::new()
+            };
+            let method = fee.as_ref().map(|f| format!(" when paying by {}", f.label)).unwrap_or_default();
+            return Err(format!(
+                "{}{} is due{}, not {}",
+                format_money(with_fee(due), &invoice.currency),
+                early,
+                method,
+                format_money(request.amount, &invoice.currency),
+            ));
+        };
+        let discounted = due - early_payment_discount;
+        let expected = with_fee(discounted);
+
+        let previous_status = invoice.status.clone();
+        invoice.status = InvoiceStatus::Paid;
+        check_status_change(&previous_status, &invoice, self.settings.as_ref())?;
+        invoice.payment = Some(RecordedPayment {
+            method: fee.as_ref().map(|f| f.label.clone()),
+            amount: expected,
+            adjustment: expected - discounted,
+            early_payment_discount,
+            paid_on,
+            reference: request.reference.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+            recorded_at: timestamp,
+        });
+        if previous_status != InvoiceStatus::Paid {
+            self.record_status_change(&mut invoice, previous_status, HashMap::new(), timestamp);
+        }
+        invoice.updated_at = timestamp;
+        self.notify(
+            notifications::NotificationKind::PaymentReceived,
+            format!("Invoice {} paid", invoice.number),
+            match fee {
+                Some(ref fee) => format!("{} received by {}", format_money(expected, &invoice.currency), fee.label),
+                None => format!("{} received", format_money(expected, &invoice.currency)),
+            },
+            Some(invoice.id.clone()),
+        );
+
+        self.load_summary(&invoice);
+
+        if is_current {
+            self.current_invoice = Some(invoice.clone());
+            self.has_unsaved_changes = true;
+            self.save_current_invoice()?;
+        } else {
+            save_invoice(&invoice)?;
+        }
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Polled by the UI; marks invoices Paid once a transfer of the exact
+    // requested amount reaches the recipient
+    #[http]
+    async fn check_crypto_payments(&mut self) -> Result<String, String> {
+        let _trace = trace("check_crypto_payments");
+        let mut paid = Vec::new();
+        for (invoice_id, payment) in self.pending_crypto.clone() {
+            match find_stablecoin_transfer(&payment) {
+                Ok(Some(tx_hash)) => paid.push((invoice_id, tx_hash)),
+                Ok(None) => {}
+                Err(e) => log!(Warn, "payments", "check_crypto_payments", Some(invoice_id.as_str()), "Failed to check {} payment: {}", payment.symbol, e),
+            }
+        }
+
+        let timestamp = host::now();
+
+        for (invoice_id, tx_hash) in &paid {
+            self.pending_crypto.remove(invoice_id);
+
+            let (mut invoice, is_current) = match self.invoice_for_update(invoice_id) {
+                Ok(found) => found,
+                Err(e) => {
+                    log!(Error, "store", "check_crypto_payments", Some(invoice_id.as_str()), "Failed to load paid invoice: {}", e);
+                    continue;
+                }
+            };
+
+            let mut symbol = String::new();
+            if let Some(ref mut payment) = invoice.crypto_payment {
+                payment.tx_hash = Some(tx_hash.clone());
+                payment.paid_at = Some(timestamp);
+                symbol = payment.symbol.clone();
+            }
+            let previous_status = std::mem::replace(&mut invoice.status, InvoiceStatus::Paid);
+            if previous_status != InvoiceStatus::Paid {
+                self.record_status_change(&mut invoice, previous_status, HashMap::new(), timestamp);
+            }
+            invoice.updated_at = timestamp;
+            self.notify(
+                notifications::NotificationKind::PaymentReceived,
+                format!("Invoice {} paid", invoice.number),
+                format!("Payment received in {}", symbol),
+                Some(invoice.id.clone()),
+            );
+
+            // Update summary
+            self.load_summary(&invoice);
+
+            if is_current {
+                self.current_invoice = Some(invoice);
+                self.has_unsaved_changes = true;
+                self.save_current_invoice()?;
+            } else {
+                save_invoice(&invoice)?;
+            }
+        }
+
+        let paid_ids: Vec<&String> = paid.iter().map(|(id, _)| id).collect();
+        serde_json::to_string(&paid_ids)
+            .map_err(|e| format!("Failed to serialize paid invoices: {}", e))
+    }
+
+    // Hypermap Identity
+
+    #[http]
+    async fn lookup_hypermap_identity(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("lookup_hypermap_identity");
+        let node: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid node name: {}", e))?;
+
+        let identity = lookup_identity(&node)?;
+        serde_json::to_string(&identity)
+            .map_err(|e| format!("Failed to serialize identity: {}", e))
+    }
+
+    // Resolves a node on Hypermap and fills in the current invoice's invoicee
+    #[http]
+    async fn verify_invoicee(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("verify_invoicee");
+        let node: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid node name: {}", e))?;
+
+        let identity = lookup_identity(&node)?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            if invoice.invoicee.name.is_empty() {
+                invoice.invoicee.name = identity.name.clone().unwrap_or_else(|| node.clone());
+            }
+            invoice.invoicee.node = Some(node);
+            invoice.invoicee.verified_identity = Some(identity);
+
+            invoice.updated_at = host::now();
+
+            self.has_unsaved_changes = true;
+            self.save_current_invoice()?;
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Time Entry Import
+
+    // Called by time-tracking apps on this node or on nodes listed in settings
+    #[local]
+    #[remote]
+    async fn import_time_entries(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("import_time_entries");
+        let timestamp = host::now();
+
+        let source = host::source();
+        self.access_guard.admit(&source.node, "import_time_entries", timestamp)?;
+        let allowed = source.node == host::address().node
+            || self.settings.as_ref()
+                .map(|s| s.time_import_nodes.contains(&source.node))
+                .unwrap_or(false);
+        if !allowed {
+            self.access_guard.deny(&source.node, "import_time_entries", timestamp, "node not allowed");
+            return Err(format!("Node {} is not allowed to import time entries", source.node));
+        }
+        self.access_guard.allow(&source.node, "import_time_entries", timestamp);
+        let source = source.to_string();
+
+        let entries: Vec<TimeEntryImport> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid time entries: {}", e))?;
+        // Nothing is imported unless every entry is valid
+        for entry in &entries {
+            entry.validate()?;
+        }
+
+        let mut imported = 0;
+        let mut updated = 0;
+        let mut skipped = 0;
+        for entry in entries {
+            let existing = self.time_entries.values_mut()
+                .find(|e| e.source == source && e.external_id == entry.external_id);
+            match existing {
+                // Entries already on an invoice are never changed by a re-import
+                Some(existing) if existing.billed_invoice_id.is_some() => skipped += 1,
+                Some(existing) => {
+                    existing.date = entry.date;
+                    existing.description = entry.description;
+                    existing.hours = entry.hours;
+                    existing.rate = entry.rate;
+                    updated += 1;
+                }
+                None => {
+                    let id = format!("time-{}-{}", timestamp, self.time_entries.len());
+                    self.time_entries.insert(id.clone(), TimeEntry {
+                        id,
+                        source: source.clone(),
+                        external_id: entry.external_id,
+                        date: entry.date,
+                        description: entry.description,
+                        hours: entry.hours,
+                        rate: entry.rate,
+                        billed_invoice_id: None,
+                        imported_at: timestamp,
+                    });
+                    imported += 1;
+                }
+            }
+        }
+
+        let response = serde_json::json!({
+            "imported": imported,
+            "updated": updated,
+            "skipped": skipped,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    #[http]
+    async fn list_time_entries(&self) -> Result<String, String> {
+        let _trace = trace("list_time_entries");
+        let mut entries: Vec<&TimeEntry> = self.time_entries.values().collect();
+        entries.sort_by(|a, b| a.date.cmp(&b.date));
+        serde_json::to_string(&entries)
+            .map_err(|e| format!("Failed to serialize time entries: {}", e))
+    }
+
+    #[http]
+    async fn bill_time_entries(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("bill_time_entries");
+        let entry_ids: Vec<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid time entry IDs: {}", e))?;
+
+        if let Some(ref mut invoice) = self.current_invoice {
+            for (index, id) in entry_ids.iter().enumerate() {
+                if entry_ids[..index].contains(id) {
+                    return Err(format!("Time entry {} is listed more than once", id));
+                }
+                match self.time_entries.get(id) {
+                    Some(entry) if entry.billed_invoice_id.is_some() => {
+                        return Err(format!("Time entry {} is already billed", id));
+                    }
+                    Some(_) => {}
+                    None => return Err(format!("Time entry {} not found", id)),
+                }
+            }
+
+            // Save current state for undo
+            let snapshot = InvoiceSnapshot {
+                invoice: invoice.clone(),
+                timestamp: invoice.updated_at,
+            };
+            self.undo_stack.push(snapshot);
+            if self.undo_stack.len() > 50 {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+
+            let millis = host::now_millis();
+            for (index, id) in entry_ids.iter().enumerate() {
+                if let Some(entry) = self.time_entries.get_mut(id) {
+                    invoice.line_items.push(LineItem {
+                        id: format!("item-{}-{}", millis, index),
+                        description: entry.description.clone(),
+                        quantity: entry.hours,
+                        unit: Some("hours".to_string()),
+                        rate: entry.rate.unwrap_or(0.0),
+                        service_date_start: Some(entry.date.clone()),
+                        time_entry_id: Some(entry.id.clone()),
+                        ..Default::default()
+                    });
+                    entry.billed_invoice_id = Some(invoice.id.clone());
+                }
+            }
+
+            invoice.updated_at = millis / 1000;
+
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_current_summary();
+
+            self.save_current_invoice()?;
+
+            serde_json::to_string(&self.current_invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Expenses
+
+    // Called by mail processes listed in settings with each batch of new messages
+    #[local]
+    #[remote]
+    async fn ingest_email(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("ingest_email");
+        let timestamp = host::now();
+
+        let source = host::source().to_string();
+        self.access_guard.admit(&source, "ingest_email", timestamp)?;
+        let settings = self.settings.clone().ok_or("Settings not configured")?;
+        if !settings.email_ingest_processes.contains(&source) {
+            self.access_guard.deny(&source, "ingest_email", timestamp, "process not allowed");
+            return Err(format!("Process {} is not allowed to ingest email", source));
+        }
+        self.access_guard.allow(&source, "ingest_email", timestamp);
+
+        let emails: Vec<InboundEmail> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid emails: {}", e))?;
+        let package_id = host::address().package_id();
+        let drive_path = format!("/{}/invoice", package_id);
+
+        let mut filed = Vec::new();
+        let mut skipped = 0;
+        for email in emails {
+            let addressed_to_inbox = match settings.email_inbox_address {
+                Some(ref inbox) => email.to.iter().any(|to| to.to_lowercase().contains(&inbox.to_lowercase())),
+                None => true,
+            };
+            let duplicate = self.expenses.values()
+                .any(|e| e.message_id.as_ref() == Some(&email.message_id));
+            let documents: Vec<&EmailAttachment> = email.attachments.iter()
+                .filter(|a| is_document_attachment(a))
+                .collect();
+            if !addressed_to_inbox || duplicate || documents.is_empty() {
+                skipped += 1;
+                continue;
+            }
+
+            let id = format!("expense-{}-{}", timestamp, self.expenses.len());
+            let expense_dir = format!("{}/expenses/{}", drive_path, id);
+
+            let mut attachments = Vec::new();
+            for attachment in documents {
+                let file_name = safe_file_name(&attachment.file_name).unwrap_or_else(|_| "attachment".to_string());
+                let path = format!("{}/{}", expense_dir, file_name);
+                save_blob(&path, &attachment.data)
+                    .map_err(|e| format!("Failed to write attachment: {}", e))?;
+                index_receipt(&mut self.receipt_hashes, &attachment.data, &path);
+                attachments.push(path);
+            }
+
+            let (vendor, vendor_email) = parse_sender(&email.from);
+            let mut expense = Expense {
+                id: id.clone(),
+                vendor,
+                vendor_email,
+                description: email.subject,
+                date: date_from_timestamp(self.local_time(timestamp)),
+                amount: None,
+                category: None,
+                attachments,
+                message_id: Some(email.message_id),
+                received_at: timestamp,
+                amount_check: None,
+            };
+            expense.category = categorize_expense(&self.category_rules, &expense);
+            self.expenses.insert(id.clone(), expense);
+            filed.push(id);
+        }
+        if !filed.is_empty() {
+            self.notify(
+                notifications::NotificationKind::ExpenseReceived,
+                "Vendor invoices received".to_string(),
+                format!("{} emailed expense(s) filed", filed.len()),
+                None,
+            );
+        }
+
+        let response = serde_json::json!({
+            "filed": filed,
+            "skipped": skipped,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    #[http]
+    async fn list_expenses(&self) -> Result<String, String> {
+        let _trace = trace("list_expenses");
+        let mut expenses: Vec<&Expense> = self.expenses.values().collect();
+        expenses.sort_by(|a, b| b.date.cmp(&a.date));
+        serde_json::to_string(&expenses)
+            .map_err(|e| format!("Failed to serialize expenses: {}", e))
+    }
+
+    #[http]
+    async fn update_expense(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_expense");
+        let updated: Expense = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid expense: {}", e))?;
+
+        let expense = self.expenses.get_mut(&updated.id)
+            .ok_or("Expense not found")?;
+
+        // Attachments and the source message stay as they were filed
+        let amount_changed = expense.amount != updated.amount;
+        expense.vendor = updated.vendor;
+        expense.vendor_email = updated.vendor_email;
+        expense.description = updated.description;
+        expense.date = updated.date;
+        expense.amount = updated.amount;
+        expense.category = updated.category;
+        if amount_changed {
+            expense.amount_check = None;
+        }
+
+        let mut expense = expense.clone();
+        let ocr_process = self.settings.as_ref()
+            .and_then(|s| s.receipt_ocr_process.clone())
+            .filter(|_| amount_changed);
+        if let Some(process) = ocr_process {
+            // An unreadable receipt is recorded on the check; it doesn't fail the update
+            expense.amount_check = check_expense_amount(&process, &expense).await;
+            if let Some(stored) = self.expenses.get_mut(&expense.id) {
+                stored.amount_check = expense.amount_check.clone();
+            }
+        }
+
+        serde_json::to_string(&expense)
+            .map_err(|e| format!("Failed to serialize expense: {}", e))
+    }
+
+    // Checks an expense's amount against its receipt again, e.g. after an unreadable result
+    #[http]
+    async fn verify_expense_amount(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("verify_expense_amount");
+        let expense_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid expense ID: {}", e))?;
+        let process = self.settings.as_ref()
+            .and_then(|s| s.receipt_ocr_process.clone())
+            .ok_or("No receipt OCR process is configured")?;
+        let expense = self.expenses.get(&expense_id).cloned().ok_or("Expense not found")?;
+
+        let check = check_expense_amount(&process, &expense).await
+            .ok_or("Expense needs an amount and a receipt to verify")?;
+        let expense = self.expenses.get_mut(&expense_id).ok_or("Expense not found")?;
+        expense.amount_check = Some(check);
+        serde_json::to_string(&expense)
+            .map_err(|e| format!("Failed to serialize expense: {}", e))
+    }
+
+    // Accepts the entered amount of an expense flagged as not matching its receipt
+    #[http]
+    async fn review_expense_amount(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("review_expense_amount");
+        let expense_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid expense ID: {}", e))?;
+        let expense = self.expenses.get_mut(&expense_id).ok_or("Expense not found")?;
+        match expense.amount_check {
+            Some(ref mut check) if check.status == AmountCheckStatus::Mismatch => {
+                check.status = AmountCheckStatus::Reviewed;
+            }
+            _ => return Err("Expense is not flagged for review".to_string()),
+        }
+        serde_json::to_string(&expense)
+            .map_err(|e| format!("Failed to serialize expense: {}", e))
+    }
+
+    // Expense Category Rules
+
+    #[http]
+    async fn list_category_rules(&self) -> Result<String, String> {
+        let _trace = trace("list_category_rules");
+        serde_json::to_string(&self.category_rules)
+            .map_err(|e| format!("Failed to serialize rules: {}", e))
+    }
+
+    #[http]
+    async fn add_category_rule(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("add_category_rule");
+        let mut rule: CategoryRule = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid rule: {}", e))?;
+        validate_category_rule(&rule)?;
+
+        let timestamp = host::now();
+        rule.id = format!("rule-{}-{}", timestamp, self.category_rules.len());
+        self.category_rules.push(rule.clone());
+
+        serde_json::to_string(&rule)
+            .map_err(|e| format!("Failed to serialize rule: {}", e))
+    }
+
+    #[http]
+    async fn update_category_rule(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_category_rule");
+        let rule: CategoryRule = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid rule: {}", e))?;
+        validate_category_rule(&rule)?;
+
+        let existing = self.category_rules.iter_mut().find(|r| r.id == rule.id)
+            .ok_or("Rule not found")?;
+        *existing = rule.clone();
+
+        serde_json::to_string(&rule)
+            .map_err(|e| format!("Failed to serialize rule: {}", e))
+    }
+
+    #[http]
+    async fn delete_category_rule(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_category_rule");
+        let rule_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid rule ID: {}", e))?;
+
+        let index = self.category_rules.iter().position(|r| r.id == rule_id)
+            .ok_or("Rule not found")?;
+        self.category_rules.remove(index);
+
+        Ok("Rule deleted".to_string())
+    }
+
+    #[http]
+    async fn reorder_category_rules(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("reorder_category_rules");
+        let rule_ids: Vec<String> = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid rule IDs: {}", e))?;
+
+        let mut reordered = Vec::new();
+        for rule_id in &rule_ids {
+            let rule = self.category_rules.iter().find(|r| &r.id == rule_id)
+                .ok_or_else(|| format!("Rule {} not found", rule_id))?;
+            reordered.push(rule.clone());
+        }
+        if reordered.len() != self.category_rules.len() {
+            return Err("Every rule must appear exactly once".to_string());
+        }
+        self.category_rules = reordered;
+
+        serde_json::to_string(&self.category_rules)
+            .map_err(|e| format!("Failed to serialize rules: {}", e))
+    }
+
+    // Re-runs the rules over existing expenses and unfiled receipts. Records that
+    // already have a category keep it unless overwrite is set.
+    #[http]
+    async fn apply_category_rules(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("apply_category_rules");
+        #[derive(Deserialize)]
+        struct ApplyRulesRequest {
+            #[serde(default)]
+            overwrite: bool,
+        }
+
+        let request: ApplyRulesRequest = if request_body.trim().is_empty() {
+            ApplyRulesRequest { overwrite: false }
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let mut categorized = 0;
+        for expense in self.expenses.values_mut() {
+            if expense.category.is_some() && !request.overwrite {
+                continue;
+            }
+            if let Some(category) = categorize_expense(&self.category_rules, expense) {
+                if expense.category.as_ref() != Some(&category) {
+                    expense.category = Some(category);
+                    categorized += 1;
+                }
+            }
+        }
+        for receipt in &mut self.unfiled_receipts {
+            if receipt.category.is_some() && !request.overwrite {
+                continue;
+            }
+            if let Some(category) = categorize(&self.category_rules, "", &receipt.file_name, None) {
+                if receipt.category.as_ref() != Some(&category) {
+                    receipt.category = Some(category);
+                    categorized += 1;
+                }
+            }
+        }
+
+        let response = serde_json::json!({ "categorized": categorized });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Reporting Queries
+
+    // Runs a field-selection and filter document over invoices, clients or payments
+    // so reporting tools can fetch exactly what they need in one call
+    #[http]
+    async fn query(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("query");
+        let document: query::QueryDocument = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid query: {}", e))?;
+
+        let mut invoices = Vec::new();
+        for id in self.invoices.keys() {
+            match self.current_invoice {
+                Some(ref current) if &current.id == id => invoices.push(current.clone()),
+                _ => match self.load_invoice(id) {
+                    Ok(invoice) => invoices.push(invoice),
+                    Err(e) => log!(Error, "store", "query", Some(id.as_str()), "Skipping invoice in query: {}", e),
+                },
+            }
+        }
+
+        let records = match document.collection {
+            query::Collection::Invoices => invoices.iter()
+                .map(|invoice| {
+                    let mut record = serde_json::to_value(invoice)
+                        .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+                    record["total"] = serde_json::json!(calculate_invoice_total(invoice));
+                    Ok(record)
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            query::Collection::Clients => client_records(&invoices),
+            query::Collection::Payments => payment_records(&invoices),
+        };
+
+        serde_json::to_string(&query::execute(records, &document))
+            .map_err(|e| format!("Failed to serialize query results: {}", e))
+    }
+
+    // Most recent calls to endpoints other nodes and processes can reach, newest first
+    #[http]
+    async fn get_access_log(&self) -> Result<String, String> {
+        let _trace = trace("get_access_log");
+        let entries: Vec<&access::AccessLogEntry> = self.access_guard.log.iter().rev().collect();
+        serde_json::to_string(&entries)
+            .map_err(|e| format!("Failed to serialize access log: {}", e))
+    }
+
+    // Share Links
+
+    #[http]
+    async fn create_share_link(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("create_share_link");
+        #[derive(Deserialize)]
+        struct CreateShareLinkRequest {
+            invoice_id: String,
+            #[serde(default)]
+            passcode: Option<String>,
+            #[serde(default)]
+            allowed_email: Option<String>,
+            #[serde(default)]
+            expires_in_days: Option<u64>,
+        }
+
+        let request: CreateShareLinkRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if !self.invoices.contains_key(&request.invoice_id) {
+            return Err("Invoice not found".to_string());
+        }
+        let passcode = request.passcode.filter(|p| !p.is_empty());
+        if passcode.as_ref().map(|p| p.chars().count() < 6).unwrap_or(false) {
+            return Err("Passcode must be at least 6 characters".to_string());
+        }
+        let allowed_email = request.allowed_email
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty());
+        if let Some(ref email) = allowed_email {
+            if !email.contains('@') {
+                return Err("Invalid email address".to_string());
+            }
+            let mail_configured = self.settings.as_ref()
+                .map(|s| s.email_send_process.is_some())
+                .unwrap_or(false);
+            if !mail_configured {
+                return Err("Set a mail process in settings before restricting links to an email".to_string());
+            }
+        }
+
+        let timestamp = host::now();
+        let token = access::random_token();
+        let link = ShareLink {
+            token: token.clone(),
+            invoice_id: request.invoice_id,
+            created_at: timestamp,
+            expires_at: request.expires_in_days.map(|days| timestamp + days * 86400),
+            revoked: false,
+            passcode_hash: passcode.as_ref().map(|p| share_secret_hash(&token, p)),
+            requires_passcode: passcode.is_some(),
+            allowed_email,
+        };
+        self.share_links.insert(token, link.clone());
+        let shared_with = link.allowed_email.clone().unwrap_or_else(|| "Anyone with the link".to_string());
+        let detail = link.requires_passcode.then(|| "Passcode required".to_string());
+        self.log_delivery(&link.invoice_id, DeliveryChannel::ShareLink, &shared_with, &Ok(()), detail);
+
+        serde_json::to_string(&link.without_secrets())
+            .map_err(|e| format!("Failed to serialize share link: {}", e))
+    }
+
+    #[http]
+    async fn list_share_links(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("list_share_links");
+        let invoice_id: Option<String> = if request_body.trim().is_empty() {
+            None
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid invoice ID: {}", e))?
+        };
+
+        let mut links: Vec<ShareLink> = self.share_links.values()
+            .filter(|l| invoice_id.as_ref().map(|id| &l.invoice_id == id).unwrap_or(true))
+            .map(|l| l.without_secrets())
+            .collect();
+        links.sort_by_key(|l| l.created_at);
+        serde_json::to_string(&links)
+            .map_err(|e| format!("Failed to serialize share links: {}", e))
+    }
+
+    #[http]
+    async fn revoke_share_link(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("revoke_share_link");
+        let token: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid token: {}", e))?;
+
+        let link = self.share_links.get_mut(&token).ok_or("Share link not found")?;
+        link.revoked = true;
+        self.share_codes.remove(&token);
+
+        Ok("Share link revoked".to_string())
+    }
+
+    // Mails a one-time code to the address an email-restricted link is bound to
+    #[local]
+    #[remote]
+    async fn request_share_code(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("request_share_code");
+        #[derive(Deserialize)]
+        struct ShareCodeRequest {
+            token: String,
+            email: String,
+        }
+
+        let timestamp = host::now();
+        let source = host::source().node;
+        let request: ShareCodeRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        self.access_guard.admit_link(&source, "request_share_code", &request.token, timestamp)?;
+
+        let link = match self.share_links.get(&request.token) {
+            Some(link) if share_link_active(link, timestamp) => link.clone(),
+            _ => {
+                self.access_guard.deny(&source, "request_share_code", timestamp, "unknown or expired link");
+                return Err("Share link not found".to_string());
+            }
+        };
+        let allowed_email = link.allowed_email.as_ref().ok_or("This link is not restricted to an email")?;
+        if request.email.trim().to_lowercase() != *allowed_email {
+            if self.access_guard.deny_link(&source, "request_share_code", &link.token, timestamp, "email does not match") {
+                self.share_codes.remove(&link.token);
+            }
+            return Err("This link is not shared with that email".to_string());
+        }
+
+        let code = format!("{:06}", u32::from_str_radix(&access::random_token()[..8], 16).unwrap_or(0) % 1_000_000);
+        self.share_codes.insert(link.token.clone(), ShareCode {
+            code_hash: share_secret_hash(&link.token, &code),
+            expires_at: timestamp + 600,
+            misses: 0,
+        });
+        self.access_guard.allow(&source, "request_share_code", timestamp);
+
+        let process = self.settings.as_ref()
+            .and_then(|s| s.email_send_process.clone())
+            .ok_or("No mail process configured")?;
+        let result = send_email(
+            &process,
+            allowed_email,
+            "Your invoice access code",
+            &format!("Your code to view the shared invoice is {}. It expires in 10 minutes.", code),
+        ).await;
+        self.log_delivery(&link.invoice_id, DeliveryChannel::Email, allowed_email, &result, Some("Share link access code".to_string()));
+        result?;
+
+        Ok("Code sent".to_string())
+    }
+
+    // Renders a shared invoice once the link's passcode and email requirements are
+    // met, and any terms attached to it have been accepted; the viewer can accept
+    // them with the same request
+    #[local]
+    #[remote]
+    async fn open_share_link(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("open_share_link");
+        #[derive(Deserialize)]
+        struct TermsAcceptanceRequest {
+            accepted_by: String,
+            #[serde(default)]
+            email: Option<String>,
+        }
+        #[derive(Deserialize)]
+        struct OpenShareLinkRequest {
+            #[serde(flatten)]
+            access: ShareAccess,
+            #[serde(default)]
+            accept_terms: Option<TermsAcceptanceRequest>, // Accepts the attached terms before viewing
+        }
+
+        let timestamp = host::now();
+        let source = host::source().node;
+        let request: OpenShareLinkRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let link = self.admit_share_link(&source, "open_share_link", &request.access, timestamp)?;
+
+        let (invoice, _) = self.invoice_for_update(&link.invoice_id)?;
+        let invoice = if terms_accepted(&invoice) {
+            invoice
+        } else {
+            let acceptance = request.accept_terms
+                .ok_or("Accept the terms attached to this invoice to view it; get_shared_terms has them")?;
+            self.record_terms_acceptance(&invoice.id, acceptance.accepted_by, acceptance.email, Some(source.clone()))?;
+            self.invoice_for_update(&link.invoice_id)?.0
+        };
+
+        // Codes are single use
+        self.share_codes.remove(&link.token);
+        self.notify(
+            notifications::NotificationKind::ShareLinkOpened,
+            "Shared invoice viewed".to_string(),
+            format!("{} opened a share link", source),
+            Some(link.invoice_id.clone()),
+        );
+
+        let options = ExportOptions {
+            redact_rates: false,
+            hide_internal_fields: true,
+            totals: None,
+            copy_label: None,
+            template_id: None,
+            page_layout: None,
+        };
+        Ok(self.generate_invoice_html(&invoice, &options))
+    }
+
+    // The terms attached to a shared invoice, for the viewer to read before
+    // accepting them with open_share_link
+    #[local]
+    #[remote]
+    async fn get_shared_terms(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_shared_terms");
+        let access: ShareAccess = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let link = self.admit_share_link(&host::source().node, "get_shared_terms", &access, host::now())?;
+
+        let (invoice, _) = self.invoice_for_update(&link.invoice_id)?;
+        let reference = invoice.terms.ok_or("Invoice has no terms attached")?;
+        let document = self.terms_documents.iter()
+            .find(|d| d.id == reference.terms_id && d.version == reference.version)
+            .ok_or("Terms not found")?;
+        let file_data = load_blob(&document.path)
+            .map_err(|e| format!("Failed to read terms: {}", e))?;
+
+        let response = serde_json::json!({
+            "title": document.title,
+            "version": document.version,
+            "file_name": document.file_name,
+            "file_data": file_data,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize terms: {}", e))
+    }
+
+    // Personal Data (GDPR)
+
+    // Everything stored about one client, as listed by the clients query
+    #[http]
+    async fn export_client_data(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("export_client_data");
+        let client_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid client ID: {}", e))?;
+
+        let invoices = self.client_invoices(&client_id)?;
+        if invoices.is_empty() {
+            return Err("Client not found".to_string());
+        }
+
+        let mut contacts: Vec<ContactInfo> = Vec::new();
+        for invoice in &invoices {
+            if !contacts.contains(&invoice.invoicee) {
+                contacts.push(invoice.invoicee.clone());
+            }
+        }
+        if let Some(ref settings) = self.settings {
+            if client_key(&settings.invoicee) == client_id && !contacts.contains(&settings.invoicee) {
+                contacts.push(settings.invoicee.clone());
+            }
+        }
+
+        let invoice_ids: Vec<&String> = invoices.iter().map(|i| &i.id).collect();
+        let time_entries: Vec<&TimeEntry> = self.time_entries.values()
+            .filter(|e| e.billed_invoice_id.as_ref().map(|id| invoice_ids.contains(&id)).unwrap_or(false))
+            .collect();
+        let nodes: Vec<&String> = contacts.iter().filter_map(|c| c.node.as_ref()).collect();
+        let access_log: Vec<&access::AccessLogEntry> = self.access_guard.log.iter()
+            .filter(|entry| nodes.iter().any(|node| entry.source == **node || entry.source.starts_with(&format!("{}@", node))))
+            .collect();
+        let attachments: Vec<&String> = invoices.iter()
+            .flat_map(|invoice| {
+                invoice.line_items.iter()
+                    .filter_map(|item| item.receipt_path.as_ref())
+                    .chain(invoice.signature.as_ref().map(|s| &s.image_path))
+            })
+            .chain(self.client_logos.get(&client_id))
+            .collect();
+
+        let response = serde_json::json!({
+            "client_id": client_id,
+            "contacts": contacts,
+            "invoices": invoices,
+            "time_entries": time_entries,
+            "access_log": access_log,
+            "attachments": attachments,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize client data: {}", e))
+    }
+
+    // Redacts a client's personal data everywhere it is stored. Invoices themselves
+    // are kept for bookkeeping, with the client anonymized and amounts untouched.
+    // Documents already issued are kept exactly as sent, since they are the legal
+    // record and their hashes prove it; the report lists them as retained.
+    #[http]
+    async fn forget_client(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("forget_client");
+        let client_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid client ID: {}", e))?;
+
+        let invoices = self.client_invoices(&client_id)?;
+        if invoices.is_empty() {
+            return Err("Client not found".to_string());
+        }
+
+        // Stable placeholder so the anonymized invoices still group together
+        let placeholder = format!("Redacted client {}", &hex::encode(Sha256::digest(client_id.as_bytes()))[..8]);
+        let nodes: Vec<String> = invoices.iter().filter_map(|i| i.invoicee.node.clone()).collect();
+
+        let mut anonymized = Vec::new();
+        let mut signatures_redacted = 0;
+        let mut acceptances_redacted = 0;
+        let mut files_deleted = Vec::new();
+        let mut issued_retained = Vec::new();
+        for mut invoice in invoices {
+            if !invoice.issued_documents.is_empty() {
+                issued_retained.push(serde_json::json!({
+                    "number": invoice.number,
+                    "issues": invoice.issued_documents.len(),
+                }));
+            }
+            anonymize_contact(&mut invoice.invoicee, &placeholder);
+            if let Some(ref mut signature) = invoice.signature {
+                signature.signer_name = "Redacted".to_string();
+                signature.signer_email = None;
+                if remove_blob(&signature.image_path).is_ok() {
+                    files_deleted.push(signature.image_path.clone());
+                }
+                signatures_redacted += 1;
+            }
+            for acceptance in &mut invoice.terms_acceptances {
+                acceptance.accepted_by = "Redacted".to_string();
+                acceptance.email = None;
+                acceptances_redacted += 1;
+            }
+
+            save_invoice(&invoice)?;
+            self.load_summary(&invoice);
+            if self.current_invoice.as_ref().map(|c| c.id == invoice.id).unwrap_or(false) {
+                self.current_invoice = Some(invoice.clone());
+            }
+            // Undo history would otherwise keep the old contact details
+            self.undo_stack.retain(|s| s.invoice.id != invoice.id);
+            self.redo_stack.retain(|s| s.invoice.id != invoice.id);
+            anonymized.push(invoice.number.clone());
+        }
+
+        if let Some(logo_path) = self.client_logos.remove(&client_id) {
+            if remove_blob(&logo_path).is_ok() {
+                files_deleted.push(logo_path);
+            }
+        }
+
+        let mut default_contact_cleared = false;
+        if let Some(ref mut settings) = self.settings {
+            if client_key(&settings.invoicee) == client_id {
+                settings.invoicee = ContactInfo::default();
+                save_settings(settings)?;
+                default_contact_cleared = true;
+            }
+        }
+
+        let log_before = self.access_guard.log.len();
+        self.access_guard.log.retain(|entry| {
+            !nodes.iter().any(|node| entry.source == *node || entry.source.starts_with(&format!("{}@", node)))
+        });
+
+        let response = serde_json::json!({
+            "client_id": client_id,
+            "replaced_with": placeholder,
+            "invoices_anonymized": anonymized,
+            "signatures_redacted": signatures_redacted,
+            "terms_acceptances_redacted": acceptances_redacted,
+            "files_deleted": files_deleted,
+            "issued_documents_retained": issued_retained, // Still name the client as sent
+            "default_contact_cleared": default_contact_cleared,
+            "access_log_entries_removed": log_before - self.access_guard.log.len(),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize report: {}", e))
+    }
+
+    // Storage Encryption
+
+    #[http]
+    async fn get_encryption_status(&self) -> Result<String, String> {
+        let _trace = trace("get_encryption_status");
+        let response = serde_json::json!({
+            "mode": self.encryption.mode,
+            "locked": storage::is_locked(),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Switches encryption mode (or passphrase) and rewrites settings, every
+    // invoice and every issued document under the new key
+    #[http]
+    async fn configure_encryption(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("configure_encryption");
+        #[derive(Deserialize)]
+        struct ConfigureEncryptionRequest {
+            mode: storage::EncryptionMode,
+            passphrase: Option<String>,
+        }
+
+        let request: ConfigureEncryptionRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if storage::is_locked() {
+            return Err("Unlock storage before changing encryption".to_string());
+        }
+
+        // Read everything with the old key before switching
+        let mut invoices = Vec::new();
+        for id in self.invoices.keys() {
+            match self.current_invoice {
+                Some(ref current) if &current.id == id => invoices.push(current.clone()),
+                _ => invoices.push(self.load_invoice(id)?),
+            }
+        }
+        let mut issued = Vec::new();
+        for invoice in &invoices {
+            for sequence in 0..invoice.issued_documents.len() {
+                let document = with_store(|store| store.load_issued(&invoice.id, sequence))
+                    .map_err(diagnostics::tagged)?;
+                issued.push((invoice.id.clone(), sequence, document));
+            }
+        }
+
+        let (config, key) = match request.mode {
+            storage::EncryptionMode::Off => (storage::EncryptionConfig::default(), None),
+            storage::EncryptionMode::NodeKey => {
+                let (config, key) = storage::EncryptionConfig::node_key();
+                (config, Some(key))
+            }
+            storage::EncryptionMode::Passphrase => {
+                let passphrase = request.passphrase.ok_or("A passphrase is required")?;
+                let (config, key) = storage::EncryptionConfig::passphrase(&passphrase)?;
+                (config, Some(key))
+            }
+        };
+        storage::set_key(&config.mode, key);
+        self.encryption = config;
+
+        if let Some(ref settings) = self.settings {
+            save_settings(settings)?;
+        }
+        for invoice in &invoices {
+            save_invoice(invoice)?;
+        }
+        for (invoice_id, sequence, document) in &issued {
+            with_store(|store| store.save_issued(invoice_id, *sequence, document))
+                .map_err(diagnostics::tagged)?;
+        }
+
+        let response = serde_json::json!({
+            "mode": self.encryption.mode,
+            "rewritten": invoices.len(),
+            "issued_documents_rewritten": issued.len(),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    #[http]
+    async fn unlock_storage(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("unlock_storage");
+        let passphrase: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid passphrase: {}", e))?;
+
+        let key = self.encryption.passphrase_key(&passphrase)?;
+        storage::set_key(&self.encryption.mode, Some(key));
+
+        // Pick up anything written while the process was restarting
+        self.load_invoice_summaries();
+
+        Ok("Storage unlocked".to_string())
+    }
+
+    // Storage Backend
+
+    #[http]
+    async fn get_storage_backend(&self) -> Result<String, String> {
+        let _trace = trace("get_storage_backend");
+        serde_json::to_string(&self.storage_backend)
+            .map_err(|e| format!("Failed to serialize storage backend: {}", e))
+    }
+
+    // Moves invoices and settings between the VFS and SQLite by copying them into
+    // the new store. The old copies are left in place but no longer read or written;
+    // attachments stay in the VFS.
+    #[http]
+    async fn set_storage_backend(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_storage_backend");
+        let backend: store::Backend = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid storage backend: {}", e))?;
+        if backend == self.storage_backend {
+            return Err("Invoices are already kept there".to_string());
+        }
+        if storage::is_locked() {
+            return Err("Unlock storage before moving invoices".to_string());
+        }
+        self.save_current_invoice()?;
+
+        let target: Box<dyn store::InvoiceStore + Send> = match backend {
+            store::Backend::Sqlite => Box::new(sqlite_store()?),
+            store::Backend::Vfs => Box::new(vfs_store()),
+        };
+        let mut copied = 0;
+        let mut skipped = Vec::new();
+        for stored in with_store(|store| store.load_all()).map_err(diagnostics::tagged)? {
+            let invoice = stored.contents.clone().and_then(|data| serde_json::from_value::<Invoice>(data)
+                .map_err(|e| format!("Failed to parse invoice: {}", e)));
+            match (stored.contents, invoice) {
+                (Ok(data), Ok(invoice)) => {
+                    target.save(&invoice_location(&invoice), &data)?;
+                    for sequence in 0..invoice.issued_documents.len() {
+                        let document = with_store(|store| store.load_issued(&invoice.id, sequence))?;
+                        target.save_issued(&invoice.id, sequence, &document)?;
+                    }
+                    copied += 1;
+                }
+                // Left where it is; the next startup quarantines it
+                (_, Err(e)) | (Err(e), _) => {
+                    log!(Warn, "store", "set_storage_backend", None, "Not copying {}: {}", stored.key, e);
+                    skipped.push(stored.key);
+                }
+            }
+        }
+        if let Some(ref settings) = self.settings {
+            let data = serde_json::to_value(settings)
+                .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+            target.save_settings(&data)?;
+        }
+        // The VFS is what's used when nothing is installed
+        store::install(match backend {
+            store::Backend::Sqlite => Some(target),
+            store::Backend::Vfs => None,
+        });
+        self.storage_backend = backend;
+
+        let response = serde_json::json!({
+            "backend": self.storage_backend,
+            "copied": copied,
+            "skipped": skipped,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Diagnostics
+
+    // What's needed to troubleshoot a reported persistence failure: whether the
+    // drive can be read, where invoices are kept, how many are known, and the
+    // latest errors and log entries (newest first)
+    #[http]
+    async fn get_diagnostics(&self) -> Result<String, String> {
+        let _trace = trace("get_diagnostics");
+        let drive_path = format!("/{}/invoice", host::address().package_id());
+        let drive_error = open_dir(&drive_path, false, Some(5))
+            .and_then(|dir| dir.read())
+            .err()
+            .map(|e| e.to_string());
+        let response = serde_json::json!({
+            "drive": {
+                "path": drive_path,
+                "ok": drive_error.is_none(),
+                "error": drive_error,
+            },
+            "storage_backend": self.storage_backend,
+            "encryption": {
+                "mode": self.encryption.mode,
+                "locked": storage::is_locked(),
+            },
+            "invoice_count": self.invoices.len(),
+            "index_size": self.invoice_index.size(),
+            "quarantined": self.quarantined,
+            "last_errors": diagnostics::last_errors(),
+            "recent": diagnostics::recent(diagnostics::Level::Info),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize diagnostics: {}", e))
+    }
+
+    // Cheap enough to poll: "degraded" while storage is locked or after a storage
+    // error in the last 15 minutes, so monitoring can alert without reading logs
+    #[http]
+    async fn health(&self) -> Result<String, String> {
+        let _trace = trace("health");
+        let recent_since = host::now().saturating_sub(15 * 60);
+        let storage_failing = diagnostics::last_errors().iter()
+            .any(|e| e.module == "store" && e.at >= recent_since);
+        let locked = storage::is_locked();
+        let response = serde_json::json!({
+            "status": if locked || storage_failing { "degraded" } else { "ok" },
+            "storage_locked": locked,
+            "storage_failing": storage_failing,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize health: {}", e))
+    }
+
+    // Counters for monitoring. Error counts are since the process last started.
+    #[http]
+    async fn get_metrics(&self) -> Result<String, String> {
+        let _trace = trace("get_metrics");
+        let undelivered = self.notifications.iter().filter(|n| n.delivered_at.is_none()).count();
+        let response = serde_json::json!({
+            "invoices": self.invoices.len(),
+            "pending_jobs": {
+                "scheduled_sends": self.scheduled_sends.len(),
+                "notifications": undelivered,
+                "lightning_payments": self.pending_lightning.len(),
+                "crypto_payments": self.pending_crypto.len(),
+            },
+            "failed_deliveries": self.scheduled_sends.iter().filter(|s| s.last_error.is_some()).count(),
+            "storage_errors": diagnostics::error_count("store"),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize metrics: {}", e))
+    }
+
+    // Versioned API
+
+    // Runs a v1 operation (see api.rs) through the endpoint that implements it. The
+    // reply is always a v1 envelope; failures are reported in it rather than as errors.
+    #[http]
+    async fn api_v1(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("api_v1");
+        let response = match serde_json::from_str::<api::Request>(&request_body) {
+            Ok(request) => {
+                let params = request.params.to_string();
+                let output = match request.method.as_str() {
+                    "get_settings" => Some(self.get_settings().await),
+                    "update_settings" => Some(self.update_settings(params).await),
+                    "list_invoices" => Some(self.list_invoices().await),
+                    "find_invoices" => Some(self.find_invoices(params).await),
+                    "get_invoice" => Some(self.get_invoice(params).await),
+                    "create_invoice" => Some(self.create_invoice(params).await),
+                    "update_invoice" => Some(self.update_invoice(params).await),
+                    "delete_invoice" => Some(self.delete_invoice(params).await),
+                    "add_line_items" => Some(self.add_line_items(params).await),
+                    "record_payment" => Some(self.record_payment(params).await),
+                    "set_invoice_status" => Some(self.set_invoice_status(params).await),
+                    "import_time_entries" => Some(self.import_time_entries(params).await),
+                    "get_outstanding_report" => Some(self.get_outstanding_report().await),
+                    "health" => Some(self.health().await),
+                    _ => None,
+                };
+                match output {
+                    Some(Ok(output)) => api::Response::ok(api::result(&output)),
+                    Some(Err(e)) => api::Response::error(api::ErrorCode::Failed, e),
+                    None => api::Response::error(
+                        api::ErrorCode::UnknownMethod,
+                        format!("No v{} operation named {}", api::VERSION, request.method),
+                    ),
+                }
+            }
+            Err(e) => api::Response::error(api::ErrorCode::InvalidRequest, format!("Invalid request: {}", e)),
+        };
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // The v1 operations with what each takes and returns, and the JSON Schema of the
+    // types they name
+    #[http]
+    async fn get_api_contract(&self) -> Result<String, String> {
+        let _trace = trace("get_api_contract");
+        let response = serde_json::json!({
+            "version": api::VERSION,
+            "operations": api::OPERATIONS,
+            "types": {
+                "Invoice": schema_for!(Invoice),
+                "InvoiceSettings": schema_for!(InvoiceSettings),
+                "InvoiceSummary": schema_for!(InvoiceSummary),
+                "NewInvoice": schema_for!(NewInvoice),
+                "NewLineItem": schema_for!(NewLineItem),
+                "TimeEntryImport": schema_for!(TimeEntryImport),
+            },
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize API contract: {}", e))
+    }
+
+    // Schemas
+
+    // JSON Schema for the core types and for the body of each endpoint that takes
+    // one of them, so external systems can validate what they send
+    #[http]
+    async fn get_json_schemas(&self) -> Result<String, String> {
+        let _trace = trace("get_json_schemas");
+        let response = serde_json::json!({
+            "types": {
+                "Invoice": schema_for!(Invoice),
+                "LineItem": schema_for!(LineItem),
+                "ContactInfo": schema_for!(ContactInfo),
+                "InvoiceSettings": schema_for!(InvoiceSettings),
+                "InvoiceSummary": schema_for!(InvoiceSummary),
+                "Expense": schema_for!(Expense),
+                "TimeEntry": schema_for!(TimeEntry),
+            },
+            "payloads": {
+                "create_invoice": schema_for!(NewInvoice),
+                "update_invoice": schema_for!(Invoice),
+                "add_line_items": schema_for!(Vec<NewLineItem>),
+                "update_settings": schema_for!(InvoiceSettings),
+                "import_time_entries": schema_for!(Vec<TimeEntryImport>),
+                "ingest_email": schema_for!(Vec<InboundEmail>),
+                "update_expense": schema_for!(Expense),
+                "add_category_rule": schema_for!(CategoryRule),
+                "update_category_rule": schema_for!(CategoryRule),
+                "generate_pdf": schema_for!(ExportOptions),
+            },
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize schemas: {}", e))
+    }
+
+    // Everything a custom template can use: the JSON Schema of the render context,
+    // the context of a sample invoice, and each variable path with its sample value
+    #[http]
+    async fn get_template_variables(&self) -> Result<String, String> {
+        let _trace = trace("get_template_variables");
+        let context = self.template_context(&sample_invoice(), &ExportOptions::default());
+        let sample = serde_json::to_value(&context)
+            .map_err(|e| format!("Failed to serialize sample context: {}", e))?;
+        let variables: Vec<serde_json::Value> = templates::variables(&sample).into_iter()
+            .map(|(path, value)| serde_json::json!({ "path": path, "sample": value }))
+            .collect();
+
+        let response = serde_json::json!({
+            "schema": schema_for!(templates::RenderContext),
+            "sample": sample,
+            "variables": variables,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize template variables: {}", e))
+    }
+
+    // Undo/Redo Operations
+
+    #[http]
+    async fn undo(&mut self) -> Result<String, String> {
+        let _trace = trace("undo");
+        if let Some(mut snapshot) = self.undo_stack.pop() {
+            // Save current state to redo stack
+            if let Some(ref current) = self.current_invoice {
+                let redo_snapshot = InvoiceSnapshot {
+                    invoice: current.clone(),
+                    timestamp: current.updated_at,
+                };
+                self.redo_stack.push(redo_snapshot);
+
+                // Acceptances record what the recipient did, not an edit to undo
+                if current.id == snapshot.invoice.id {
+                    snapshot.invoice.terms_acceptances = current.terms_acceptances.clone();
+                }
+            }
+
+            // Restore from undo stack
+            sync_time_entries(&mut self.time_entries, &snapshot.invoice);
+            self.current_invoice = Some(snapshot.invoice.clone());
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_summary(&snapshot.invoice);
+
+            serde_json::to_string(&snapshot.invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("Nothing to undo".to_string())
+        }
+    }
+
+    #[http]
+    async fn redo(&mut self) -> Result<String, String> {
+        let _trace = trace("redo");
+        if let Some(mut snapshot) = self.redo_stack.pop() {
+            // Save current state to undo stack
+            if let Some(ref current) = self.current_invoice {
+                let undo_snapshot = InvoiceSnapshot {
+                    invoice: current.clone(),
+                    timestamp: current.updated_at,
+                };
+                self.undo_stack.push(undo_snapshot);
+
+                // Acceptances record what the recipient did, not an edit to undo
+                if current.id == snapshot.invoice.id {
+                    snapshot.invoice.terms_acceptances = current.terms_acceptances.clone();
+                }
+            }
+
+            // Restore from redo stack
+            sync_time_entries(&mut self.time_entries, &snapshot.invoice);
+            self.current_invoice = Some(snapshot.invoice.clone());
+            self.has_unsaved_changes = true;
+
+            // Update summary
+            self.load_summary(&snapshot.invoice);
+
+            serde_json::to_string(&snapshot.invoice)
+                .map_err(|e| format!("Failed to serialize invoice: {}", e))
+        } else {
+            Err("Nothing to redo".to_string())
+        }
+    }
+
+    #[http]
+    async fn can_undo(&self) -> Result<bool, String> {
+        let _trace = trace("can_undo");
+        Ok(!self.undo_stack.is_empty())
+    }
+
+    #[http]
+    async fn can_redo(&self) -> Result<bool, String> {
+        let _trace = trace("can_redo");
+        Ok(!self.redo_stack.is_empty())
+    }
+
+    // PDF Generation
+
+    #[http]
+    async fn generate_pdf(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("generate_pdf");
+        let options: ExportOptions = if request_body.trim().is_empty() {
+            ExportOptions::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Failed to parse export options: {}", e))?
+        };
+        if let Some(ref layout) = options.page_layout {
+            layout.validate()?;
+        }
+
+        if let Some(invoice) = self.current_invoice.clone() {
+            // Generate HTML for the invoice
+            let html = self.generate_invoice_html(&invoice, &options);
+
+            // Save the HTML to VFS
+            let package_id = host::address().package_id();
+            let drive_path = format!("/{}/invoice", package_id);
+
+            let invoice_dir = if let Some(ref name) = invoice.name {
+                name.clone()
+            } else {
+                invoice.number.clone()
+            };
+
+            // Filtered exports and marked copies get their own file so the full copy is never overwritten
+            let filtered = ExportOptions { copy_label: None, template_id: None, page_layout: None, ..options.clone() } != ExportOptions::default();
+            let mut suffix = if filtered { "-redacted".to_string() } else { String::new() };
+            if let Some(ref label) = options.copy_label {
+                suffix.push('-');
+                suffix.push_str(&copy_label_slug(label));
+            }
+            let html_path = format!("{}/{}/{}/invoice{}.html", drive_path, invoice.date, invoice_dir, suffix);
+            save_blob(&html_path, html.as_bytes())
+                .map_err(|e| format!("Failed to write HTML: {}", e))?;
+
+            if let Some(ref label) = options.copy_label {
+                self.audit(host::now(), &host::address().node, AuditAction::CopyGenerated, &invoice, Some(label.clone()));
+            }
+
+            // Return both the path and the HTML content as JSON
+            let response = serde_json::json!({
+                "path": html_path,
+                "html": html,
+                "filename": format!("invoice_{}{}.html", invoice.number, suffix)
+            });
+            serde_json::to_string(&response)
+                .map_err(|e| format!("Failed to serialize response: {}", e))
+        } else {
+            Err("No invoice currently loaded".to_string())
+        }
+    }
+
+    // Renders an invoice straight from the request for a live print preview;
+    // nothing is saved and the current invoice is left untouched
+    #[http]
+    async fn preview_invoice(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("preview_invoice");
+        #[derive(Deserialize)]
+        struct PreviewRequest {
+            invoice: Invoice,
+            #[serde(default)]
+            options: ExportOptions,
+        }
+
+        let request: PreviewRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid preview request: {}", e))?;
+
+        let html = self.generate_invoice_html(&request.invoice, &request.options);
+        let response = serde_json::json!({ "html": html });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // The current invoice once per copy label, each copy on its own page; the
+    // usual Original/Duplicate/Triplicate set unless labels are given
+    #[http]
+    async fn generate_copies(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("generate_copies");
+        #[derive(Deserialize)]
+        struct GenerateCopiesRequest {
+            #[serde(default)]
+            labels: Vec<String>,
+            #[serde(default)]
+            options: ExportOptions,
+        }
+
+        let request: GenerateCopiesRequest = if request_body.trim().is_empty() {
+            GenerateCopiesRequest { labels: Vec::new(), options: ExportOptions::default() }
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid copies request: {}", e))?
+        };
+        let labels: Vec<String> = if request.labels.is_empty() {
+            DEFAULT_COPY_LABELS.iter().map(|label| label.to_string()).collect()
+        } else {
+            request.labels.iter().map(|label| label.trim().to_string()).collect()
+        };
+        if labels.iter().any(|label| label.is_empty()) {
+            return Err("Copy labels cannot be empty".to_string());
+        }
+        let invoice = self.current_invoice.clone().ok_or("No invoice currently loaded")?;
+
+        let mut pages = Vec::new();
+        for (index, label) in labels.iter().enumerate() {
+            let options = ExportOptions {
+                copy_label: Some(label.clone()),
+                ..request.options.clone()
+            };
+            let body = self.invoice_body_html(&invoice, &options, &format!("{}-", index));
+            pages.push(format!("<div class=\"batch-page\">\n{}\n</div>", body));
+        }
+        for label in &labels {
+            self.audit(host::now(), &host::address().node, AuditAction::CopyGenerated, &invoice, Some(label.clone()));
+        }
+
+        let response = serde_json::json!({
+            "html": html_document(&pages.join("\n"), &self.default_layout_head(&pages.join("\n")), DOCUMENT_CONTENT_SECURITY_POLICY),
+            "filename": format!("invoice_{}_copies.html", invoice.number),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Renders a custom template against the sample invoice; nothing is saved. A template
+    // that doesn't parse or could load outside resources returns no HTML; otherwise
+    // unknown variables are listed as errors. template_id lends a saved template's assets.
+    #[http]
+    async fn preview_template(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("preview_template");
+        #[derive(Deserialize)]
+        struct PreviewTemplateRequest {
+            template_source: String,
+            #[serde(default)]
+            template_id: Option<String>,
+            #[serde(default)]
+            options: ExportOptions,
+        }
+
+        let request: PreviewTemplateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid preview request: {}", e))?;
+
+        let assets = match request.template_id {
+            Some(ref id) => self.custom_template(id)?.assets.clone(),
+            None => Vec::new(),
+        };
+        let response = match templates::check_source(&request.template_source) {
+            Ok(template) => {
+                let (html, errors) = self.render_template(&template, &assets, &sample_invoice(), &request.options)?;
+                serde_json::json!({ "html": html, "errors": errors })
+            }
+            Err(errors) => serde_json::json!({ "html": null, "errors": errors }),
+        };
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    #[http]
+    async fn list_templates(&self) -> Result<String, String> {
+        let _trace = trace("list_templates");
+        serde_json::to_string(&self.custom_templates)
+            .map_err(|e| format!("Failed to serialize templates: {}", e))
+    }
+
+    // Creates a template, or updates one when an ID is given. Templates that don't
+    // parse or could load outside resources are refused with every problem listed.
+    #[http]
+    async fn save_template(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("save_template");
+        #[derive(Deserialize)]
+        struct SaveTemplateRequest {
+            id: Option<String>,
+            name: String,
+            #[serde(default)]
+            description: Option<String>,
+            source: String,
+        }
+
+        let request: SaveTemplateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid template: {}", e))?;
+        if request.name.trim().is_empty() {
+            return Err("Template name cannot be empty".to_string());
+        }
+        templates::check_source(&request.source).map_err(|errors| describe_template_errors(&errors))?;
+
+        let timestamp = host::now();
+
+        let template = match request.id {
+            Some(id) => {
+                let template = self.custom_templates.iter_mut()
+                    .find(|t| t.id == id)
+                    .ok_or("Template not found")?;
+                template.name = request.name;
+                template.description = request.description;
+                template.source = request.source;
+                template.updated_at = timestamp;
+                template.clone()
+            }
+            None => {
+                let template = CustomTemplate {
+                    id: format!("template-{}-{}", timestamp, self.custom_templates.len()),
+                    name: request.name,
+                    description: request.description,
+                    source: request.source,
+                    assets: Vec::new(),
+                    imported: false,
+                    created_at: timestamp,
+                    updated_at: timestamp,
+                };
+                self.custom_templates.push(template.clone());
+                template
+            }
+        };
+
+        serde_json::to_string(&template)
+            .map_err(|e| format!("Failed to serialize template: {}", e))
+    }
+
+    #[http]
+    async fn delete_template(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_template");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid template ID: {}", e))?;
+
+        let index = self.custom_templates.iter()
+            .position(|t| t.id == id)
+            .ok_or("Template not found")?;
+        let template = self.custom_templates.remove(index);
+        for asset in &template.assets {
+            let _ = remove_blob(&asset.path);
+        }
+        let _ = with_store(|store| store.remove_blob_dir(&template_dir_path(&template.id)));
+
+        Ok("Template deleted".to_string())
+    }
+
+    // Adds an image or font to a template, replacing any asset of the same name
+    #[http]
+    async fn upload_template_asset(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_template_asset");
+        #[derive(Deserialize)]
+        struct TemplateAssetUpload {
+            template_id: String,
+            name: String,
+            file_data: Vec<u8>,
+        }
+
+        let request: TemplateAssetUpload = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let template = self.custom_templates.iter_mut()
+            .find(|t| t.id == request.template_id)
+            .ok_or("Template not found")?;
+        let replacing = template.assets.iter().any(|a| a.name == request.name);
+        if !replacing && template.assets.len() >= templates::MAX_ASSETS {
+            return Err(format!("A template can have at most {} assets", templates::MAX_ASSETS));
+        }
+
+        let content_type = templates::validate_asset(&request.name, request.file_data.len())?;
+        if !asset_matches_content_type(&request.file_data, content_type) {
+            return Err(format!("{} is not a valid {} file", request.name, content_type));
+        }
+        let asset = write_template_asset(&template.id, &request.name, &request.file_data)?;
+        template.assets.retain(|a| a.name != asset.name);
+        template.assets.push(asset.clone());
+        template.updated_at = host::now();
+
+        serde_json::to_string(&asset)
+            .map_err(|e| format!("Failed to serialize asset: {}", e))
+    }
+
+    #[http]
+    async fn delete_template_asset(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_template_asset");
+        #[derive(Deserialize)]
+        struct DeleteAssetRequest {
+            template_id: String,
+            name: String,
+        }
+
+        let request: DeleteAssetRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let template = self.custom_templates.iter_mut()
+            .find(|t| t.id == request.template_id)
+            .ok_or("Template not found")?;
+        let index = template.assets.iter()
+            .position(|a| a.name == request.name)
+            .ok_or("Asset not found")?;
+        let asset = template.assets.remove(index);
+        let _ = remove_blob(&asset.path);
+
+        Ok("Asset deleted".to_string())
+    }
+
+    // A template and its assets as one JSON file that can be shared and imported elsewhere
+    #[http]
+    async fn export_template(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("export_template");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid template ID: {}", e))?;
+
+        let template = self.custom_template(&id)?;
+        let mut assets = Vec::new();
+        for asset in &template.assets {
+            let data = load_blob(&asset.path)
+                .map_err(|e| format!("Asset {} not found: {}", asset.name, e))?;
+            assets.push(templates::BundleAsset {
+                name: asset.name.clone(),
+                content_type: asset.content_type.clone(),
+                data: general_purpose::STANDARD.encode(&data),
+            });
+        }
+        let bundle = templates::TemplateBundle {
+            format: templates::BUNDLE_FORMAT.to_string(),
+            version: templates::BUNDLE_VERSION,
+            name: template.name.clone(),
+            description: template.description.clone(),
+            source: template.source.clone(),
+            assets,
+        };
+
+        let response = serde_json::json!({
+            "bundle": bundle,
+            "filename": format!("{}.invoice-template.json", copy_label_slug(&template.name)),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Saves a shared bundle as a new template. Bundles are checked the same way as
+    // saved templates, and assets must be images or fonts whose contents match their type.
+    #[http]
+    async fn import_template(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("import_template");
+        let bundle: templates::TemplateBundle = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid template bundle: {}", e))?;
+        bundle.validate()?;
+        templates::check_source(&bundle.source).map_err(|errors| describe_template_errors(&errors))?;
+        let missing: Vec<String> = templates::asset_references(&bundle.source).into_iter()
+            .filter(|name| !bundle.assets.iter().any(|a| &a.name == name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("Bundle is missing assets: {}", missing.join(", ")));
+        }
+
+        let mut files = Vec::new();
+        for asset in &bundle.assets {
+            let data = general_purpose::STANDARD.decode(&asset.data)
+                .map_err(|e| format!("Asset {} is not valid base64: {}", asset.name, e))?;
+            templates::validate_asset(&asset.name, data.len())?;
+            if !asset_matches_content_type(&data, &asset.content_type) {
+                return Err(format!("{} is not a valid {} file", asset.name, asset.content_type));
+            }
+            files.push((asset.name.clone(), data));
+        }
+
+        let timestamp = host::now();
+        let id = format!("template-{}-{}", timestamp, self.custom_templates.len());
+        let mut assets = Vec::new();
+        for (name, data) in files {
+            assets.push(write_template_asset(&id, &name, &data)?);
+        }
+
+        let template = CustomTemplate {
+            id,
+            name: bundle.name,
+            description: bundle.description,
+            source: bundle.source,
+            assets,
+            imported: true,
+            created_at: timestamp,
+            updated_at: timestamp,
+        };
+        self.custom_templates.push(template.clone());
+
+        serde_json::to_string(&template)
+            .map_err(|e| format!("Failed to serialize template: {}", e))
+    }
+
+    // Fonts
+
+    #[http]
+    async fn list_fonts(&self) -> Result<String, String> {
+        let _trace = trace("list_fonts");
+        serde_json::to_string(&self.fonts)
+            .map_err(|e| format!("Failed to serialize fonts: {}", e))
+    }
+
+    // Stores a WOFF2, WOFF, TTF or OTF file as one weight and style of a family.
+    // Uploading the same weight and style again replaces the file.
+    #[http]
+    async fn upload_font(&mut self, request_body: Vec<u8>) -> Result<String, String> {
+        let _trace = trace("upload_font");
+        #[derive(Deserialize)]
+        struct FontUpload {
+            family: String,
+            #[serde(default = "fonts::default_weight")]
+            weight: u16,
+            #[serde(default)]
+            style: fonts::FontStyle,
+            #[serde(default)]
+            scripts: Vec<fonts::Script>, // Scripts to use it as a fallback for
+            file_name: String,
+            file_data: Vec<u8>,
+        }
+
+        let request: FontUpload = serde_json::from_slice(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let family = request.family.trim().to_string();
+        fonts::validate_family(&family)?;
+        fonts::validate_weight(request.weight)?;
+        let content_type = templates::asset_content_type(&request.file_name)
+            .filter(|t| t.starts_with("font/"))
+            .ok_or("Fonts must be WOFF2, WOFF, TTF or OTF files")?;
+        if !asset_matches_content_type(&request.file_data, content_type) {
+            return Err(format!("{} is not a valid {} file", request.file_name, content_type));
+        }
+        if request.file_data.len() > MAX_FONT_BYTES {
+            return Err(format!("Fonts can be at most {} MB", MAX_FONT_BYTES / (1024 * 1024)));
+        }
+
+        let timestamp = host::now();
+
+        if let Some(index) = self.fonts.iter()
+            .position(|f| f.family == family && f.weight == request.weight && f.style == request.style)
+        {
+            let replaced = self.fonts.remove(index);
+            let _ = remove_blob(&replaced.path);
+        }
+
+        let id = format!("font-{}-{}", timestamp, self.fonts.len());
+        let extension = content_type.trim_start_matches("font/");
+        let package_id = host::address().package_id();
+        let path = format!("/{}/invoice/fonts/{}.{}", package_id, id, extension);
+        save_blob(&path, &request.file_data)
+            .map_err(|e| format!("Failed to write font: {}", e))?;
+
+        let font = fonts::CustomFont {
+            id,
+            family,
+            weight: request.weight,
+            style: request.style,
+            file_name: request.file_name,
+            content_type: content_type.to_string(),
+            path,
+            size: request.file_data.len(),
+            uploaded_at: timestamp,
+            scripts: request.scripts,
+        };
+        self.fonts.push(font.clone());
+
+        serde_json::to_string(&font)
+            .map_err(|e| format!("Failed to serialize font: {}", e))
+    }
+
+    // Documents using the family fall back to the built-in fonts once none are left
+    #[http]
+    async fn delete_font(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_font");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid font ID: {}", e))?;
+
+        let index = self.fonts.iter()
+            .position(|f| f.id == id)
+            .ok_or("Font not found")?;
+        let font = self.fonts.remove(index);
+        let _ = remove_blob(&font.path);
+
+        Ok("Font deleted".to_string())
+    }
+
+    // Several invoices in one printable document, each starting on a new page
+    #[http]
+    async fn render_batch(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("render_batch");
+        #[derive(Deserialize)]
+        struct RenderBatchRequest {
+            ids: Vec<String>,
+            #[serde(default)]
+            options: ExportOptions,
+        }
+
+        let request: RenderBatchRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid batch request: {}", e))?;
+        if request.ids.is_empty() {
+            return Err("No invoices selected".to_string());
+        }
+
+        let mut pages = Vec::new();
+        for (index, id) in request.ids.iter().enumerate() {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            let body = self.invoice_body_html(&invoice, &request.options, &format!("{}-", index));
+            pages.push(format!("<div class=\"batch-page\">\n{}\n</div>", body));
+        }
+
+        let timestamp = host::now();
+        let response = serde_json::json!({
+            "html": html_document(&pages.join("\n"), &self.default_layout_head(&pages.join("\n")), DOCUMENT_CONTENT_SECURITY_POLICY),
+            "filename": format!("invoices_{}.html", date_from_timestamp(self.local_time(timestamp))),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Mailing labels or window-envelope cover pages for the invoicees of a set of invoices
+    #[http]
+    async fn render_address_labels(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("render_address_labels");
+        #[derive(Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        enum AddressLayout {
+            Labels,
+            WindowEnvelope,
+        }
+
+        #[derive(Deserialize)]
+        struct AddressLabelsRequest {
+            ids: Vec<String>,
+            layout: AddressLayout,
+        }
+
+        let request: AddressLabelsRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid label request: {}", e))?;
+        if request.ids.is_empty() {
+            return Err("No invoices selected".to_string());
+        }
+
+        let mut invoices = Vec::new();
+        for id in &request.ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if &current.id == id => current.clone(),
+                _ => self.load_invoice(id)?,
+            };
+            invoices.push(invoice);
+        }
+
+        let html = match request.layout {
+            AddressLayout::Labels => {
+                // One label per distinct address, so a client with several invoices gets one envelope
+                let mut addresses: Vec<Vec<String>> = Vec::new();
+                for invoice in &invoices {
+                    let lines = address_lines(&invoice.invoicee);
+                    if !addresses.contains(&lines) {
+                        addresses.push(lines);
+                    }
+                }
+                let labels = addresses.iter()
+                    .map(|lines| format!("<div class=\"label\">{}</div>", lines.join("<br>")))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <style>
+        /* 30 labels per US Letter sheet (2.625" x 1"), as on Avery 5160 */
+        @page {{ size: letter; margin: 0.5in 0.19in; }}
+        body {{ margin: 0; font-family: Arial, sans-serif; font-size: 10pt; }}
+        .sheet {{ display: grid; grid-template-columns: repeat(3, 2.625in); grid-auto-rows: 1in; column-gap: 0.125in; }}
+        .label {{ padding: 0.1in 0.15in; overflow: hidden; box-sizing: border-box; }}
+    </style>
+</head>
+<body>
+<div class="sheet">
+{}
+</div>
+</body>
+</html>"#, TEMPLATE_CONTENT_SECURITY_POLICY, labels)
+            }
+            AddressLayout::WindowEnvelope => {
+                let pages = invoices.iter()
+                    .map(|invoice| {
+                        let return_address = address_lines(&invoice.invoicer).join(" &middot; ");
+                        format!(
+                            r#"<div class="cover">
+    <div class="window">
+        <div class="return-address">{}</div>
+        <div class="recipient">{}</div>
+    </div>
+    <div class="reference">{} {} &middot; {}</div>
+</div>"#,
+                            return_address,
+                            address_lines(&invoice.invoicee).join("<br>"),
+                            invoice.kind.label(),
+                            escape_html(&invoice.number),
+                            invoice.date
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <style>
+        /* A4 cover page with the address in the DIN 5008 (form B) window of a DL envelope */
+        @page {{ size: A4; margin: 0; }}
+        body {{ margin: 0; font-family: Arial, sans-serif; font-size: 11pt; }}
+        .cover {{ position: relative; width: 210mm; height: 297mm; break-after: page; page-break-after: always; }}
+        .cover:last-child {{ break-after: auto; page-break-after: auto; }}
+        .window {{ position: absolute; left: 20mm; top: 45mm; width: 85mm; height: 45mm; }}
+        .return-address {{ font-size: 7pt; border-bottom: 0.5pt solid #000; margin-bottom: 3mm; white-space: nowrap; overflow: hidden; }}
+        .reference {{ position: absolute; left: 25mm; top: 105mm; }}
+    </style>
+</head>
+<body>
+{}
+</body>
+</html>"#, TEMPLATE_CONTENT_SECURITY_POLICY, pages)
+            }
+        };
+
+        let timestamp = host::now();
+        let response = serde_json::json!({
+            "html": html,
+            "filename": format!("addresses_{}.html", date_from_timestamp(self.local_time(timestamp))),
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize response: {}", e))
+    }
+
+    // Follow-ups
+
+    #[http]
+    async fn add_followup(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("add_followup");
+        #[derive(Deserialize)]
+        struct AddFollowUpRequest {
+            invoice_id: String,
+            note: String,
+            due_date: String,
+        }
+
+        let request: AddFollowUpRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if !self.invoices.contains_key(&request.invoice_id) {
+            return Err("Invoice not found".to_string());
+        }
+        if request.note.trim().is_empty() {
+            return Err("Note is required".to_string());
+        }
+        if !is_iso_date(&request.due_date) {
+            return Err("Due date must be YYYY-MM-DD".to_string());
+        }
+
+        let timestamp = host::now();
+        let followup = FollowUp {
+            id: format!("followup-{}-{}", timestamp, self.followups.len()),
+            invoice_id: request.invoice_id,
+            note: request.note.trim().to_string(),
+            due_date: request.due_date,
+            done: false,
+            created_at: timestamp,
+            completed_at: None,
+            notified: false,
+        };
+        self.followups.insert(followup.id.clone(), followup.clone());
+
+        serde_json::to_string(&followup)
+            .map_err(|e| format!("Failed to serialize follow-up: {}", e))
+    }
+
+    #[http]
+    async fn update_followup(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_followup");
+        #[derive(Deserialize)]
+        struct UpdateFollowUpRequest {
+            id: String,
+            note: Option<String>,
+            due_date: Option<String>,
+            done: Option<bool>,
+        }
+
+        let request: UpdateFollowUpRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let timestamp = host::now();
+
+        let followup = self.followups.get_mut(&request.id).ok_or("Follow-up not found")?;
+        if let Some(note) = request.note {
+            if note.trim().is_empty() {
+                return Err("Note is required".to_string());
+            }
+            followup.note = note.trim().to_string();
+        }
+        if let Some(due_date) = request.due_date {
+            if !is_iso_date(&due_date) {
+                return Err("Due date must be YYYY-MM-DD".to_string());
+            }
+            if due_date != followup.due_date {
+                followup.due_date = due_date;
+                followup.notified = false;
+            }
+        }
+        if let Some(done) = request.done {
+            followup.completed_at = if done { Some(timestamp) } else { None };
+            followup.done = done;
+        }
+
+        serde_json::to_string(&followup)
+            .map_err(|e| format!("Failed to serialize follow-up: {}", e))
+    }
+
+    // Pushes a follow-up back by a number of days, or to a given date
+    #[http]
+    async fn snooze_followup(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("snooze_followup");
+        #[derive(Deserialize)]
+        struct SnoozeFollowUpRequest {
+            id: String,
+            days: Option<u32>,
+            until: Option<String>,
+        }
+
+        let request: SnoozeFollowUpRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let local_now = self.local_time(host::now());
+
+        let followup = self.followups.get_mut(&request.id).ok_or("Follow-up not found")?;
+        if followup.done {
+            return Err("Follow-up is already done".to_string());
+        }
+        let due_date = match (request.until, request.days) {
+            (Some(until), _) if is_iso_date(&until) => until,
+            (Some(_), _) => return Err("Snooze date must be YYYY-MM-DD".to_string()),
+            (None, Some(days)) => {
+                // Snoozing an overdue follow-up counts from today, not its old due date
+                let from = days_from_date(&followup.due_date)
+                    .unwrap_or(0)
+                    .max((local_now / 86400) as i64);
+                date_from_timestamp(((from + days as i64) * 86400) as u64)
+            }
+            (None, None) => return Err("Give a number of days or a date to snooze until".to_string()),
+        };
+        followup.due_date = due_date;
+        followup.notified = false;
+
+        serde_json::to_string(&followup)
+            .map_err(|e| format!("Failed to serialize follow-up: {}", e))
+    }
+
+    #[http]
+    async fn delete_followup(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_followup");
         let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid follow-up ID: {}", e))?;
+
+        self.followups.remove(&id).ok_or("Follow-up not found")?;
+        Ok("Follow-up deleted".to_string())
+    }
+
+    // Open follow-ups, soonest first; optionally for one invoice or including done ones
+    #[http]
+    async fn get_followups(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_followups");
+        #[derive(Deserialize, Default)]
+        struct GetFollowUpsRequest {
+            #[serde(default)]
+            invoice_id: Option<String>,
+            #[serde(default)]
+            include_done: bool,
+        }
+
+        let request: GetFollowUpsRequest = if request_body.trim().is_empty() {
+            GetFollowUpsRequest::default()
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid request: {}", e))?
+        };
+
+        let mut followups: Vec<&FollowUp> = self.followups.values()
+            .filter(|f| request.include_done || !f.done)
+            .filter(|f| request.invoice_id.as_ref().map(|id| &f.invoice_id == id).unwrap_or(true))
+            .collect();
+        followups.sort_by(|a, b| a.due_date.cmp(&b.due_date).then(a.created_at.cmp(&b.created_at)));
+        serde_json::to_string(&followups)
+            .map_err(|e| format!("Failed to serialize follow-ups: {}", e))
+    }
+
+    // Deposits
+
+    // Credits a deposit invoice against a final invoice, linking the two. The
+    // amount defaults to whatever of the deposit hasn't been credited elsewhere.
+    #[http]
+    async fn apply_deposit(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("apply_deposit");
+        #[derive(Deserialize)]
+        struct ApplyDepositRequest {
+            invoice_id: String,
+            deposit_invoice_id: String,
+            #[serde(default)]
+            amount: Option<Money>,
+        }
+
+        let request: ApplyDepositRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if request.invoice_id == request.deposit_invoice_id {
+            return Err("An invoice cannot be its own deposit".to_string());
+        }
+
+        let (mut invoice, invoice_is_current) = self.invoice_for_update(&request.invoice_id)?;
+        let (mut deposit, deposit_is_current) = self.invoice_for_update(&request.deposit_invoice_id)?;
+        if invoice.deposits_applied.iter().any(|d| d.invoice_id == deposit.id) {
+            return Err(format!("Deposit {} is already applied", deposit.number));
+        }
+        if !deposit.deposits_applied.is_empty() || !invoice.applied_to.is_empty() {
+            return Err("Deposits cannot be chained through other deposits or final invoices".to_string());
+        }
+        if deposit.currency != invoice.currency {
+            return Err(format!("Deposit {} is in {}, not {}", deposit.number, deposit.currency, invoice.currency));
+        }
+
+        let remaining = calculate_invoice_total(&deposit) - deposit.applied_to.iter().map(|d| d.amount).sum::<Money>();
+        let amount = request.amount.unwrap_or(remaining);
+        if amount <= Money::ZERO {
+            return Err(format!("Nothing left of deposit {} to apply", deposit.number));
+        }
+        if amount > remaining {
+            return Err(format!("Only {} of deposit {} is left to apply", format_money(remaining, &deposit.currency), deposit.number));
+        }
+        if amount > amount_due(&invoice) {
+            return Err("Deposit is more than the amount due".to_string());
+        }
+
+        let timestamp = host::now();
+        invoice.deposits_applied.push(DepositLink {
+            invoice_id: deposit.id.clone(),
+            number: deposit.number.clone(),
+            amount,
+        });
+        invoice.updated_at = timestamp;
+        deposit.applied_to.push(DepositLink {
+            invoice_id: invoice.id.clone(),
+            number: invoice.number.clone(),
+            amount,
+        });
+        deposit.updated_at = timestamp;
+
+        self.store_managed_change(deposit, deposit_is_current)?;
+        self.store_managed_change(invoice.clone(), invoice_is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    #[http]
+    async fn remove_deposit(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("remove_deposit");
+        #[derive(Deserialize)]
+        struct RemoveDepositRequest {
+            invoice_id: String,
+            deposit_invoice_id: String,
+        }
+
+        let request: RemoveDepositRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+
+        let (mut invoice, invoice_is_current) = self.invoice_for_update(&request.invoice_id)?;
+        let before = invoice.deposits_applied.len();
+        invoice.deposits_applied.retain(|d| d.invoice_id != request.deposit_invoice_id);
+        if invoice.deposits_applied.len() == before {
+            return Err("Deposit is not applied to this invoice".to_string());
+        }
+
+        // The deposit may have been deleted since; the link on the final invoice still goes
+        if let Ok((mut deposit, deposit_is_current)) = self.invoice_for_update(&request.deposit_invoice_id) {
+            deposit.applied_to.retain(|d| d.invoice_id != invoice.id);
+            self.store_managed_change(deposit, deposit_is_current)?;
+        }
+        self.store_managed_change(invoice.clone(), invoice_is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Approvals
+
+    #[http]
+    async fn submit_for_approval(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("submit_for_approval");
+        let invoice_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+
+        let approvers_configured = self.settings.as_ref()
+            .map(|s| !s.approvers.is_empty())
+            .unwrap_or(false);
+        if !approvers_configured {
+            return Err("Add approvers in settings before submitting invoices for approval".to_string());
+        }
+
+        let (mut invoice, is_current) = self.invoice_for_update(&invoice_id)?;
+        if invoice.status != InvoiceStatus::Draft && invoice.status != InvoiceStatus::Approved {
+            return Err("Only drafts can be submitted for approval".to_string());
+        }
+
+        let timestamp = host::now();
+        invoice.approval = Some(Approval {
+            submitted_at: timestamp,
+            document_hash: document_hash(&invoice),
+            approved: None,
+            decided_by: None,
+            decided_at: None,
+            rejection_reason: None,
+        });
+        invoice.status = InvoiceStatus::PendingApproval;
+        invoice.updated_at = timestamp;
+        self.audit(timestamp, &host::address().node, AuditAction::SubmittedForApproval, &invoice, None);
+        self.store_managed_change(invoice.clone(), is_current)?;
+
+        serde_json::to_string(&invoice)
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+    }
+
+    // Invoices waiting on an approver, rendered so the approver sees exactly what
+    // they are approving. Only callable by nodes listed as approvers.
+    #[local]
+    #[remote]
+    async fn list_pending_approvals(&mut self) -> Result<String, String> {
+        let _trace = trace("list_pending_approvals");
+        let timestamp = host::now();
+        let approver = host::source().node;
+        self.check_approver(&approver, "list_pending_approvals", timestamp)?;
+
+        let pending_ids = self.indexed_ids(&[InvoiceStatus::PendingApproval], None, None);
+        let options = ExportOptions {
+            redact_rates: false,
+            hide_internal_fields: true,
+            totals: None,
+            copy_label: None,
+            template_id: None,
+            page_layout: None,
+        };
+
+        let mut pending = Vec::new();
+        for invoice_id in pending_ids {
+            let invoice = match self.current_invoice {
+                Some(ref current) if current.id == invoice_id => current.clone(),
+                _ => self.load_invoice(&invoice_id)?,
+            };
+            let Some(ref approval) = invoice.approval else {
+                continue;
+            };
+            pending.push(serde_json::json!({
+                "invoice_id": invoice.id,
+                "number": invoice.number,
+                "invoicee": invoice.invoicee.name,
+                "total": calculate_invoice_total(&invoice),
+                "submitted_at": approval.submitted_at,
+                "document_hash": approval.document_hash,
+                "html": self.generate_invoice_html(&invoice, &options),
+            }));
+        }
+
+        serde_json::to_string(&pending)
+            .map_err(|e| format!("Failed to serialize pending approvals: {}", e))
+    }
+
+    // Approves or rejects a pending invoice. The approver passes back the document
+    // hash from list_pending_approvals so an invoice edited since cannot be approved.
+    #[local]
+    #[remote]
+    async fn decide_approval(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("decide_approval");
+        #[derive(Deserialize)]
+        struct DecideApprovalRequest {
+            invoice_id: String,
+            document_hash: String,
+            approve: bool,
+            #[serde(default)]
+            reason: Option<String>,
+        }
+
+        let timestamp = host::now();
+        let approver = host::source().node;
+        self.check_approver(&approver, "decide_approval", timestamp)?;
+
+        let request: DecideApprovalRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let reason = request.reason
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty());
+        if !request.approve && reason.is_none() {
+            return Err("A reason is required to reject an invoice".to_string());
+        }
+
+        let (mut invoice, is_current) = self.invoice_for_update(&request.invoice_id)?;
+        if invoice.status != InvoiceStatus::PendingApproval {
+            return Err("Invoice is not pending approval".to_string());
+        }
+        let current_hash = document_hash(&invoice);
+        let approval = invoice.approval.as_mut().ok_or("Invoice is not pending approval")?;
+        if request.document_hash != approval.document_hash || current_hash != approval.document_hash {
+            return Err("Invoice changed since it was submitted; reload it before deciding".to_string());
+        }
+
+        approval.approved = Some(request.approve);
+        approval.decided_by = Some(approver.clone());
+        approval.decided_at = Some(timestamp);
+        approval.rejection_reason = reason.clone();
+        invoice.status = if request.approve {
+            InvoiceStatus::Approved
+        } else {
+            InvoiceStatus::Draft
+        };
+        invoice.updated_at = timestamp;
+
+        let action = if request.approve { AuditAction::Approved } else { AuditAction::Rejected };
+        self.audit(timestamp, &approver, action, &invoice, reason.clone());
+        self.notify(
+            notifications::NotificationKind::ApprovalDecided,
+            format!("Invoice {} {}", invoice.number, if request.approve { "approved" } else { "rejected" }),
+            match reason {
+                Some(reason) => format!("{}: {}", approver, reason),
+                None => format!("By {}", approver),
+            },
+            Some(invoice.id.clone()),
+        );
+        self.store_managed_change(invoice.clone(), is_current)?;
+
+        serde_json::to_string(&invoice.approval)
+            .map_err(|e| format!("Failed to serialize approval: {}", e))
+    }
+
+    // Every approval step and send, newest first
+    #[http]
+    async fn get_audit_log(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_audit_log");
+        let invoice_id: Option<String> = if request_body.trim().is_empty() {
+            None
+        } else {
+            serde_json::from_str(&request_body)
+                .map_err(|e| format!("Invalid invoice ID: {}", e))?
+        };
+
+        let entries: Vec<&AuditEntry> = self.audit_log.iter()
+            .rev()
+            .filter(|e| invoice_id.as_ref().map(|id| &e.invoice_id == id).unwrap_or(true))
+            .collect();
+        serde_json::to_string(&entries)
+            .map_err(|e| format!("Failed to serialize audit log: {}", e))
+    }
+
+    // Every share link, email, P2P send and reminder for an invoice, newest first
+    #[http]
+    async fn get_delivery_log(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_delivery_log");
+        let invoice_id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+
+        let records: Vec<&DeliveryRecord> = self.delivery_log.iter()
+            .rev()
+            .filter(|r| r.invoice_id == invoice_id)
+            .collect();
+        serde_json::to_string(&records)
+            .map_err(|e| format!("Failed to serialize delivery log: {}", e))
+    }
+
+    // What has happened to an invoice, newest first: status changes, approvals and
+    // deliveries, each as a line to show in a feed
+    #[http]
+    async fn get_activity(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_activity");
+        let invoice_id: String = serde_json::from_str(&request_body)
             .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+        let (invoice, _) = self.invoice_for_update(&invoice_id)?;
+
+        let mut activity: Vec<(u64, String)> = invoice.status_history.iter()
+            .map(|change| (change.at, format!("{:?} to {:?} by {}", change.from, change.to, change.by)))
+            .collect();
+        activity.extend(self.audit_log.iter()
+            .filter(|entry| entry.invoice_id == invoice_id && entry.action != AuditAction::Sent)
+            .map(|entry| (entry.timestamp, match entry.detail {
+                Some(ref detail) => format!("{:?} by {}: {}", entry.action, entry.actor, detail),
+                None => format!("{:?} by {}", entry.action, entry.actor),
+            })));
+        activity.extend(self.delivery_log.iter()
+            .filter(|record| record.invoice_id == invoice_id)
+            .map(|record| {
+                let what = match record.channel {
+                    DeliveryChannel::ShareLink => "Share link created for",
+                    DeliveryChannel::Email => "Emailed",
+                    DeliveryChannel::P2p => "Delivered to",
+                    DeliveryChannel::Reminder => "Reminder emailed to",
+                };
+                let line = match record.error {
+                    Some(ref e) => format!("Failed: {} {} ({})", what.to_lowercase(), record.destination, e),
+                    None => format!("{} {}", what, record.destination),
+                };
+                (record.at, line)
+            }));
+        // Stable, so entries at the same second keep the order they were added in
+        activity.sort_by_key(|(at, _)| std::cmp::Reverse(*at));
+
+        let feed: Vec<serde_json::Value> = activity.into_iter()
+            .map(|(at, text)| serde_json::json!({ "at": at, "text": text }))
+            .collect();
+        serde_json::to_string(&feed)
+            .map_err(|e| format!("Failed to serialize activity: {}", e))
+    }
+
+    // Scheduled Sending
+
+    // Queues an invoice to be marked Sent and delivered at a later time
+    #[http]
+    async fn schedule_send(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("schedule_send");
+        #[derive(Deserialize)]
+        struct ScheduleSendRequest {
+            invoice_id: String,
+            send_at: String, // YYYY-MM-DD or an ISO 8601 date-time; local time unless it ends in Z
+            #[serde(default)]
+            email: bool,
+            #[serde(default)]
+            p2p: bool,
+        }
+
+        let request: ScheduleSendRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        // The offset is looked up at the send time read as UTC, which is near enough
+        // except within hours of a daylight saving change
+        let send_at = timestamp_from_datetime(&request.send_at, 0)
+            .and_then(|utc| timestamp_from_datetime(&request.send_at, self.utc_offset_minutes(utc)))
+            .ok_or("Send time must be YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS][Z]")?;
+        let timestamp = host::now();
+        if send_at <= timestamp {
+            return Err("Send time must be in the future".to_string());
+        }
+
+        let invoice = match self.current_invoice {
+            Some(ref current) if current.id == request.invoice_id => current.clone(),
+            _ => self.load_invoice(&request.invoice_id)?,
+        };
+        if invoice.status != InvoiceStatus::Draft && invoice.status != InvoiceStatus::Approved {
+            return Err("Only unsent invoices can be scheduled".to_string());
+        }
+        if request.email && invoice.invoicee.email.as_ref().map(|e| e.trim().is_empty()).unwrap_or(true) {
+            return Err("Invoicee has no email address".to_string());
+        }
+        if request.email && self.settings.as_ref().and_then(|s| s.email_send_process.as_ref()).is_none() {
+            return Err("Set a mail process in settings before scheduling email delivery".to_string());
+        }
+        if request.p2p && invoice.invoicee.node.is_none() {
+            return Err("Invoicee has no node to deliver to".to_string());
+        }
+        if self.scheduled_sends.iter().any(|s| s.invoice_id == invoice.id) {
+            return Err("Invoice is already scheduled; cancel it first".to_string());
+        }
+
+        let scheduled = ScheduledSend {
+            id: format!("send-{}-{}", timestamp, self.scheduled_sends.len()),
+            invoice_id: invoice.id,
+            send_at,
+            email: request.email,
+            p2p: request.p2p,
+            created_at: timestamp,
+            last_error: None,
+            request_id: diagnostics::request_id(),
+        };
+        self.scheduled_sends.push(scheduled.clone());
+
+        serde_json::to_string(&scheduled)
+            .map_err(|e| format!("Failed to serialize scheduled send: {}", e))
+    }
+
+    #[http]
+    async fn list_scheduled_sends(&self) -> Result<String, String> {
+        let _trace = trace("list_scheduled_sends");
+        let mut sends = self.scheduled_sends.clone();
+        sends.sort_by_key(|s| s.send_at);
+        serde_json::to_string(&sends)
+            .map_err(|e| format!("Failed to serialize scheduled sends: {}", e))
+    }
+
+    #[http]
+    async fn cancel_scheduled_send(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("cancel_scheduled_send");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid scheduled send ID: {}", e))?;
+
+        let index = self.scheduled_sends.iter().position(|s| s.id == id)
+            .ok_or("Scheduled send not found")?;
+        self.scheduled_sends.remove(index);
+
+        Ok("Scheduled send cancelled".to_string())
+    }
+
+    // Scheduled send timer method; delivers and marks Sent every invoice whose
+    // time has come. Failed deliveries stay queued with the error recorded.
+    #[http]
+    async fn check_scheduled_sends(&mut self) -> Result<String, String> {
+        let _trace = trace("check_scheduled_sends");
+        let timestamp = host::now();
+        let due: Vec<ScheduledSend> = self.scheduled_sends.iter()
+            .filter(|s| s.send_at <= timestamp)
+            .cloned()
+            .collect();
+        let settings = self.settings.clone();
+        let mail_process = self.settings.as_ref().and_then(|s| s.email_send_process.clone());
+
+        let mut sent = Vec::new();
+        for scheduled in due {
+            let is_current = self.current_invoice.as_ref()
+                .map(|i| i.id == scheduled.invoice_id)
+                .unwrap_or(false);
+            let result = match self.current_invoice {
+                Some(ref current) if is_current => Ok(current.clone()),
+                _ => self.load_invoice(&scheduled.invoice_id),
+            };
+            let result = match result {
+                Ok(mut invoice) => {
+                    let previous_status = invoice.status.clone();
+                    invoice.status = InvoiceStatus::Sent;
+                    match check_status_change(&previous_status, &invoice, settings.as_ref()) {
+                        Ok(()) => self.deliver_invoice(&invoice, &scheduled, mail_process.as_deref()).await
+                            .map(|_| (invoice, previous_status)),
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            let (mut invoice, scheduled_from) = match result {
+                Ok(sent) => sent,
+                Err(e) => {
+                    log!(Warn, "send", "check_scheduled_sends", Some(scheduled.invoice_id.as_str()), "Delivery failed: {}", e);
+                    if let Some(s) = self.scheduled_sends.iter_mut().find(|s| s.id == scheduled.id) {
+                        s.last_error = Some(e);
+                    }
+                    continue;
+                }
+            };
+            invoice.updated_at = timestamp;
+            let mut metadata = HashMap::new();
+            metadata.insert("scheduled_send".to_string(), scheduled.id.clone());
+            self.record_status_change(&mut invoice, scheduled_from, metadata, timestamp);
+            // Already delivered, so a snapshot that couldn't be kept doesn't undo the send
+            let _ = self.issue_document(&mut invoice, timestamp);
+            self.scheduled_sends.retain(|s| s.id != scheduled.id);
+            self.audit(timestamp, &host::address().node, AuditAction::Sent, &invoice, Some("Scheduled send".to_string()));
+
+            self.load_summary(&invoice);
+            if is_current {
+                self.current_invoice = Some(invoice);
+                self.has_unsaved_changes = true;
+                self.save_current_invoice()?;
+            } else {
+                save_invoice(&invoice)?;
+            }
+            sent.push(scheduled.invoice_id);
+        }
+
+        serde_json::to_string(&sent)
+            .map_err(|e| format!("Failed to serialize sent invoices: {}", e))
+    }
+
+    #[http]
+    async fn list_subscriptions(&self) -> Result<String, String> {
+        let _trace = trace("list_subscriptions");
+        serde_json::to_string(&self.subscriptions)
+            .map_err(|e| format!("Failed to serialize subscriptions: {}", e))
+    }
+
+    // Creates a subscription, or updates one. The amount, billing day and start
+    // date can't change once a period has been invoiced; use change_subscription_plan
+    // and end_subscription instead.
+    #[http]
+    async fn save_subscription(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("save_subscription");
+        #[derive(Deserialize)]
+        struct SaveSubscriptionRequest {
+            #[serde(default)]
+            id: Option<String>,
+            name: String,
+            invoicee: ContactInfo,
+            amount: f64,
+            billing_day: u32,
+            start_date: String,
+            #[serde(default)]
+            end_date: Option<String>,
+            #[serde(default)]
+            profile_id: Option<String>,
+        }
+
+        let request: SaveSubscriptionRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid subscription: {}", e))?;
+        if request.name.trim().is_empty() {
+            return Err("Subscriptions need a name".to_string());
+        }
+        if let Some(ref profile_id) = request.profile_id {
+            let exists = self.settings.as_ref()
+                .map(|s| s.profiles.iter().any(|p| &p.id == profile_id))
+                .unwrap_or(false);
+            if !exists {
+                return Err("Business profile not found".to_string());
+            }
+        }
+        let timestamp = host::now();
+
+        let subscription = match request.id {
+            Some(id) => {
+                let subscription = self.subscriptions.iter_mut().find(|s| s.id == id)
+                    .ok_or("Subscription not found")?;
+                let mut plan = subscription.plan.clone();
+                if !subscription.billed.is_empty()
+                    && (plan.amount != request.amount
+                        || plan.billing_day != request.billing_day
+                        || plan.start_date != request.start_date)
+                {
+                    return Err("Subscription has been invoiced; change its plan or end it instead".to_string());
+                }
+                plan.amount = request.amount;
+                plan.billing_day = request.billing_day;
+                plan.start_date = request.start_date.clone();
+                plan.end_date = request.end_date;
+                plan.validate()?;
+                if subscription.billed.is_empty() {
+                    subscription.next_period_start = request.start_date;
+                }
+                subscription.plan = plan;
+                subscription.name = request.name.trim().to_string();
+                subscription.invoicee = request.invoicee;
+                subscription.profile_id = request.profile_id;
+                subscription.clone()
+            }
+            None => {
+                let plan = subscriptions::Plan {
+                    amount: request.amount,
+                    billing_day: request.billing_day,
+                    start_date: request.start_date.clone(),
+                    end_date: request.end_date,
+                    changes: Vec::new(),
+                };
+                plan.validate()?;
+                let subscription = Subscription {
+                    id: format!("subscription-{}-{}", timestamp, self.subscriptions.len()),
+                    name: request.name.trim().to_string(),
+                    invoicee: request.invoicee,
+                    plan,
+                    profile_id: request.profile_id,
+                    next_period_start: request.start_date,
+                    billed: Vec::new(),
+                    created_at: timestamp,
+                };
+                self.subscriptions.push(subscription.clone());
+                subscription
+            }
+        };
+
+        serde_json::to_string(&subscription)
+            .map_err(|e| format!("Failed to serialize subscription: {}", e))
+    }
+
+    // Changes the monthly amount from a date on. Days already invoiced at the old
+    // amount are credited or charged on the next invoice.
+    #[http]
+    async fn change_subscription_plan(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("change_subscription_plan");
+        #[derive(Deserialize)]
+        struct ChangePlanRequest {
+            id: String,
+            amount: f64,
+            effective_date: String,
+        }
+
+        let request: ChangePlanRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let subscription = self.subscriptions.iter_mut().find(|s| s.id == request.id)
+            .ok_or("Subscription not found")?;
+        if !subscriptions::on_or_before(&subscription.plan.start_date, &request.effective_date) {
+            return Err("Plan changes can't take effect before the subscription starts".to_string());
+        }
+
+        let mut plan = subscription.plan.clone();
+        plan.changes.retain(|c| c.effective_date != request.effective_date);
+        plan.changes.push(subscriptions::PlanChange {
+            effective_date: request.effective_date,
+            amount: request.amount,
+        });
+        plan.changes.sort_by(|a, b| a.effective_date.cmp(&b.effective_date));
+        plan.validate()?;
+        subscription.plan = plan;
+
+        serde_json::to_string(&*subscription)
+            .map_err(|e| format!("Failed to serialize subscription: {}", e))
+    }
+
+    // Sets the last day of service; the final period is prorated, and an already
+    // invoiced one is credited on a closing invoice
+    #[http]
+    async fn end_subscription(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("end_subscription");
+        #[derive(Deserialize)]
+        struct EndSubscriptionRequest {
+            id: String,
+            end_date: String,
+        }
+
+        let request: EndSubscriptionRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let subscription = self.subscriptions.iter_mut().find(|s| s.id == request.id)
+            .ok_or("Subscription not found")?;
+
+        let mut plan = subscription.plan.clone();
+        plan.end_date = Some(request.end_date);
+        plan.validate()?;
+        subscription.plan = plan;
+
+        serde_json::to_string(&*subscription)
+            .map_err(|e| format!("Failed to serialize subscription: {}", e))
+    }
+
+    #[http]
+    async fn delete_subscription(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("delete_subscription");
+        let id: String = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid subscription ID: {}", e))?;
+
+        let index = self.subscriptions.iter().position(|s| s.id == id)
+            .ok_or("Subscription not found")?;
+        self.subscriptions.remove(index);
+
+        Ok("Subscription deleted".to_string())
+    }
+
+    // Subscription timer method; drafts an invoice for every period that has
+    // started, plus a closing one when an ended subscription is owed a credit.
+    // Returns the new invoice IDs.
+    #[http]
+    async fn check_subscriptions(&mut self) -> Result<String, String> {
+        let _trace = trace("check_subscriptions");
+        // One instant for the whole run, so invoices created either side of midnight
+        // all get the date the periods were checked against
+        let millis = host::now_millis();
+        let timestamp = millis / 1000;
+        let today = date_from_timestamp(self.local_time(timestamp));
+
+        let mut created = Vec::new();
+        for index in 0..self.subscriptions.len() {
+            loop {
+                let subscription = self.subscriptions[index].clone();
+                let period = subscription.plan.period(&subscription.next_period_start)
+                    .filter(|(start, _)| subscriptions::on_or_before(start, &today));
+                // Periods billed before a plan change or an earlier end date
+                let adjustments: Vec<(usize, f64)> = subscription.billed.iter()
+                    .enumerate()
+                    .map(|(i, b)| (i, subscription.plan.period_amount(&b.start, &b.end)))
+                    .filter(|(i, amount)| (amount - subscription.billed[*i].amount).abs() >= 0.005)
+                    .collect();
+                let ended = subscription.plan.period(&subscription.next_period_start).is_none();
+                if period.is_none() && (!ended || adjustments.is_empty()) {
+                    break;
+                }
+
+                let mut invoice = self.new_invoice(subscription.profile_id.as_deref(), None, timestamp)?;
+                invoice.name = Some(subscription.name.clone());
+                invoice.invoicee = subscription.invoicee.clone();
+
+                for (i, amount) in &adjustments {
+                    let billed = &subscription.billed[*i];
+                    invoice.line_items.push(LineItem {
+                        id: format!("item-{}-{}", millis, invoice.line_items.len()),
+                        description: format!("{} plan adjustment", subscription.name),
+                        quantity: 1.0,
+                        rate: ((amount - billed.amount) * 100.0).round() / 100.0,
+                        service_date_start: Some(billed.start.clone()),
+                        service_date_end: Some(billed.end.clone()),
+                        ..Default::default()
+                    });
+                }
+                let mut billed_period = None;
+                if let Some((start, end)) = period {
+                    for charge in subscription.plan.charges(&start, &end) {
+                        let description = if charge.prorated() {
+                            format!("{} ({} of {} days)", subscription.name, charge.days, charge.cycle_days)
+                        } else {
+                            subscription.name.clone()
+                        };
+                        invoice.line_items.push(LineItem {
+                            id: format!("item-{}-{}", millis, invoice.line_items.len()),
+                            description,
+                            quantity: 1.0,
+                            rate: charge.amount,
+                            service_date_start: Some(charge.start),
+                            service_date_end: Some(charge.end),
+                            ..Default::default()
+                        });
+                    }
+                    invoice.service_period_start = Some(start.clone());
+                    invoice.service_period_end = Some(end.clone());
+                    billed_period = Some(subscriptions::BilledPeriod {
+                        amount: subscription.plan.period_amount(&start, &end),
+                        start,
+                        end,
+                        invoice_id: invoice.id.clone(),
+                    });
+                }
+
+                save_invoice(&invoice)?;
+                self.load_summary(&invoice);
+                created.push(invoice.id.clone());
+
+                let subscription = &mut self.subscriptions[index];
+                for (i, amount) in adjustments {
+                    subscription.billed[i].amount = amount;
+                }
+                match billed_period {
+                    Some(billed) => {
+                        subscription.next_period_start = subscriptions::add_days(&billed.end, 1);
+                        subscription.billed.push(billed);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        serde_json::to_string(&created)
+            .map_err(|e| format!("Failed to serialize invoice IDs: {}", e))
+    }
+
+    // Accepts an invoice delivered by another node's invoice app
+    #[remote]
+    async fn receive_invoice(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("receive_invoice");
+        #[derive(Deserialize)]
+        struct DeliveredInvoice {
+            number: String,
+            total: Money,
+            due_date: Option<String>,
+            html: String,
+        }
+
+        let timestamp = host::now();
+        let from = host::source().node;
+        self.access_guard.admit(&from, "receive_invoice", timestamp)?;
+
+        let delivered: DeliveredInvoice = match serde_json::from_str(&request_body) {
+            Ok(delivered) => delivered,
+            Err(e) => {
+                self.access_guard.deny(&from, "receive_invoice", timestamp, "malformed invoice");
+                return Err(format!("Invalid invoice: {}", e));
+            }
+        };
+        self.access_guard.allow(&from, "receive_invoice", timestamp);
+
+        self.received_invoices.push(ReceivedInvoice {
+            id: format!("received-{}-{}", timestamp, self.received_invoices.len()),
+            from,
+            number: delivered.number,
+            total: delivered.total,
+            due_date: delivered.due_date,
+            html: delivered.html,
+            received_at: timestamp,
+        });
+
+        Ok("Invoice received".to_string())
+    }
+
+    #[http]
+    async fn list_received_invoices(&self) -> Result<String, String> {
+        let _trace = trace("list_received_invoices");
+        let invoices: Vec<&ReceivedInvoice> = self.received_invoices.iter().rev().collect();
+        serde_json::to_string(&invoices)
+            .map_err(|e| format!("Failed to serialize received invoices: {}", e))
+    }
+
+    // Payment Reminders
+
+    #[http]
+    async fn get_reminder_policies(&self) -> Result<String, String> {
+        let _trace = trace("get_reminder_policies");
+        let response = serde_json::json!({
+            "default": self.default_reminder_policy,
+            "clients": self.reminder_policies,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize reminder policies: {}", e))
+    }
+
+    // Sets the policy for one client, or the default policy when no client is given.
+    // A null policy removes it; clients then fall back to the default.
+    #[http]
+    async fn set_reminder_policy(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("set_reminder_policy");
+        #[derive(Deserialize)]
+        struct SetReminderPolicyRequest {
+            client_id: Option<String>,
+            policy: Option<reminders::ReminderPolicy>,
+        }
+
+        let request: SetReminderPolicyRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if let Some(ref policy) = request.policy {
+            policy.validate()?;
+        }
+
+        match request.client_id {
+            Some(client_id) => {
+                let client_id = client_id.trim().to_lowercase();
+                if client_id.is_empty() {
+                    return Err("Client ID is required".to_string());
+                }
+                match request.policy {
+                    Some(policy) => {
+                        self.reminder_policies.insert(client_id, policy);
+                    }
+                    None => {
+                        self.reminder_policies.remove(&client_id);
+                    }
+                }
+            }
+            None => self.default_reminder_policy = request.policy,
+        }
+
+        Ok("Reminder policy saved".to_string())
+    }
+
+    // Reminder timer method; emails the next escalation step for overdue invoices.
+    // Paid invoices and drafts are never reminded, so reminders stop once payment is recorded.
+    #[http]
+    async fn check_reminders(&mut self) -> Result<String, String> {
+        let _trace = trace("check_reminders");
+        let Some(mail_process) = self.settings.as_ref().and_then(|s| s.email_send_process.clone()) else {
+            return Ok("[]".to_string());
+        };
+
+        let timestamp = host::now();
+        let today = (self.local_time(timestamp) / 86400) as i64;
+
+        let candidates = self.indexed_ids(&[InvoiceStatus::Sent, InvoiceStatus::Overdue], None, None);
+
+        let mut reminded = Vec::new();
+        for invoice_id in candidates {
+            let (mut invoice, is_current) = match self.invoice_for_update(&invoice_id) {
+                Ok(found) => found,
+                Err(e) => {
+                    log!(Error, "store", "check_reminders", Some(invoice_id.as_str()), "Failed to load invoice for reminders: {}", e);
+                    continue;
+                }
+            };
+            if invoice.kind != DocumentKind::Invoice {
+                continue;
+            }
+
+            let Some(due) = invoice.due_date.as_deref().and_then(days_from_date) else {
+                continue;
+            };
+            let Some(email) = invoice.invoicee.email.clone().filter(|e| !e.trim().is_empty()) else {
+                continue;
+            };
+            let Some(policy) = self.reminder_policies.get(&client_key(&invoice.invoicee))
+                .or(self.default_reminder_policy.as_ref())
+                .cloned()
+            else {
+                continue;
+            };
+            let days_overdue = today - due;
+            let Some((step_index, step)) = policy.next_step(&invoice.reminders_sent, days_overdue) else {
+                continue;
+            };
+
+            let fields = [
+                ("number", invoice.number.clone()),
+                ("client", invoice.invoicee.name.clone()),
+                ("total", format_money(amount_due(&invoice), &invoice.currency)),
+                ("due_date", invoice.due_date.clone().unwrap_or_default()),
+                ("days_overdue", days_overdue.max(0).to_string()),
+            ];
+            let subject = reminders::render(&step.subject, &fields);
+            let body = reminders::render(&step.body, &fields);
+            let result = send_email(&mail_process, &email, &subject, &body).await;
+            self.log_delivery(&invoice.id, DeliveryChannel::Reminder, &email, &result, Some(subject.clone()));
+            if let Err(e) = result {
+                log!(Warn, "reminders", "check_reminders", Some(invoice.id.as_str()), "Failed to send reminder for invoice {}: {}", invoice.number, e);
+                continue;
+            }
+
+            invoice.reminders_sent.push(reminders::SentReminder {
+                step: step_index,
+                tone: step.tone.clone(),
+                to: email,
+                sent_at: timestamp,
+            });
+            invoice.updated_at = timestamp;
+            if is_current {
+                self.current_invoice = Some(invoice);
+                self.has_unsaved_changes = true;
+                self.save_current_invoice()?;
+            } else {
+                save_invoice(&invoice)?;
+            }
+            reminded.push(invoice_id);
+        }
+
+        serde_json::to_string(&reminded)
+            .map_err(|e| format!("Failed to serialize reminded invoices: {}", e))
+    }
+
+    // Late fee timer method; brings the late fee line of each overdue invoice up to
+    // the number of periods it has been overdue under the policy in settings
+    #[http]
+    async fn check_late_fees(&mut self) -> Result<String, String> {
+        let _trace = trace("check_late_fees");
+        let Some(policy) = self.settings.as_ref().and_then(|s| s.late_fee.clone()) else {
+            return Ok("[]".to_string());
+        };
+
+        let timestamp = host::now();
+        let today = (self.local_time(timestamp) / 86400) as i64;
+
+        let mut charged = Vec::new();
+        for invoice_id in self.indexed_ids(&[InvoiceStatus::Sent, InvoiceStatus::Overdue], None, None) {
+            let (mut invoice, is_current) = match self.invoice_for_update(&invoice_id) {
+                Ok(found) => found,
+                Err(e) => {
+                    log!(Error, "store", "check_late_fees", Some(invoice_id.as_str()), "Failed to load invoice for late fees: {}", e);
+                    continue;
+                }
+            };
+            if invoice.kind != DocumentKind::Invoice {
+                continue;
+            }
+            let Some(due) = invoice.due_date.as_deref().and_then(days_from_date) else {
+                continue;
+            };
+            if !apply_late_fee(&mut invoice, &policy, today - due) {
+                continue;
+            }
+
+            invoice.updated_at = timestamp;
+            if let Err(e) = self.store_managed_change(invoice, is_current) {
+                log!(Error, "store", "check_late_fees", Some(invoice_id.as_str()), "Failed to save late fee: {}", e);
+                continue;
+            }
+            charged.push(invoice_id);
+        }
+
+        serde_json::to_string(&charged)
+            .map_err(|e| format!("Failed to serialize invoices charged late fees: {}", e))
+    }
+
+    // Notifications
+
+    #[http]
+    async fn get_notification_preferences(&self) -> Result<String, String> {
+        let _trace = trace("get_notification_preferences");
+        serde_json::to_string(&self.notification_preferences)
+            .map_err(|e| format!("Failed to serialize notification preferences: {}", e))
+    }
+
+    #[http]
+    async fn update_notification_preferences(&mut self, request_body: String) -> Result<String, String> {
+        let _trace = trace("update_notification_preferences");
+        let mut preferences: notifications::NotificationPreferences = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid notification preferences: {}", e))?;
+        preferences.email = preferences.email
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty());
+        preferences.push_process = preferences.push_process
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty());
+        preferences.validate()?;
+
+        if let Some(ref process) = preferences.push_process {
+            process.parse::<Address>()
+                .map_err(|e| format!("Invalid notification process address: {}", e))?;
+        }
+        if preferences.email.is_some() {
+            let mail_configured = self.settings.as_ref()
+                .map(|s| s.email_send_process.is_some())
+                .unwrap_or(false);
+            if !mail_configured {
+                return Err("Set a mail process in settings before emailing notifications".to_string());
+            }
+        }
+
+        self.notification_preferences = preferences;
+        serde_json::to_string(&self.notification_preferences)
+            .map_err(|e| format!("Failed to serialize notification preferences: {}", e))
+    }
+
+    // Newest first, including ones still waiting for quiet hours or the digest
+    #[http]
+    async fn list_notifications(&self) -> Result<String, String> {
+        let _trace = trace("list_notifications");
+        let notifications: Vec<&notifications::Notification> = self.notifications.iter().rev().collect();
+        serde_json::to_string(&notifications)
+            .map_err(|e| format!("Failed to serialize notifications: {}", e))
+    }
+
+    // Notification timer method; sends queued notifications once quiet hours
+    // are over or the daily digest is due
+    #[http]
+    async fn check_notifications(&mut self) -> Result<String, String> {
+        let _trace = trace("check_notifications");
+        let timestamp = host::now();
+
+        // Follow-ups falling due are queued like any other event
+        let today = date_from_timestamp(self.local_time(timestamp));
+        let mut due_followups: Vec<FollowUp> = self.followups.values()
+            .filter(|f| !f.done && !f.notified && f.due_date <= today)
+            .cloned()
+            .collect();
+        due_followups.sort_by(|a, b| a.due_date.cmp(&b.due_date));
+        for followup in due_followups {
+            let number = self.invoices.get(&followup.invoice_id)
+                .map(|s| s.number.clone())
+                .unwrap_or_default();
+            self.notify(
+                notifications::NotificationKind::FollowUpDue,
+                format!("Follow up on invoice {}", number),
+                followup.note.clone(),
+                Some(followup.invoice_id.clone()),
+            );
+            if let Some(f) = self.followups.get_mut(&followup.id) {
+                f.notified = true;
+            }
+        }
+
+        let preferences = self.notification_preferences.clone();
+        let pending: Vec<notifications::Notification> = self.notifications.iter()
+            .filter(|n| n.delivered_at.is_none())
+            .cloned()
+            .collect();
+        let due = preferences.due(self.local_time(timestamp), self.local_time(self.last_digest_at));
+        if pending.is_empty() || !due {
+            return Ok("[]".to_string());
+        }
+
+        // Each message lists the notifications it covers
+        let messages: Vec<(Vec<String>, (String, String))> = match preferences.delivery {
+            notifications::Delivery::Immediate => pending.iter()
+                .map(|n| (vec![n.id.clone()], (n.title.clone(), n.body.clone())))
+                .collect(),
+            notifications::Delivery::DailyDigest => vec![(
+                pending.iter().map(|n| n.id.clone()).collect(),
+                notifications::digest(&pending),
+            )],
+        };
+        let mail_process = self.settings.as_ref().and_then(|s| s.email_send_process.clone());
+
+        let mut delivered = Vec::new();
+        for (ids, (title, body)) in messages {
+            let mut result = Ok(());
+            if let Some(ref process) = preferences.push_process {
+                result = push_notification(process, &title, &body).await;
+            }
+            if let (Ok(()), Some(email), Some(process)) = (&result, &preferences.email, &mail_process) {
+                result = send_email(process, email, &title, &body).await;
+            }
+            if let Err(e) = result {
+                // Left queued and retried on the next check
+                log!(Warn, "notifications", "check_notifications", None, "Failed to send notification: {}", e);
+                break;
+            }
+            delivered.extend(ids);
+        }
+
+        for notification in self.notifications.iter_mut() {
+            if delivered.contains(&notification.id) {
+                notification.delivered_at = Some(timestamp);
+            }
+        }
+        if preferences.delivery == notifications::Delivery::DailyDigest && !delivered.is_empty() {
+            self.last_digest_at = timestamp;
+        }
+        notifications::trim_history(&mut self.notifications);
+
+        serde_json::to_string(&delivered)
+            .map_err(|e| format!("Failed to serialize delivered notifications: {}", e))
+    }
+
+    // Where edits to an invoice stand, for the editor's saving/saved/conflict indicator:
+    // when the stored copy was saved, the fields changed since, and for the calling
+    // session its latest edit merged and saved, with any merge warnings
+    #[http]
+    async fn get_edit_state(&self, request_body: String) -> Result<String, String> {
+        let _trace = trace("get_edit_state");
+        #[derive(Deserialize)]
+        struct EditStateRequest {
+            invoice_id: String,
+            #[serde(default)]
+            session_id: Option<String>,
+        }
+
+        let request: EditStateRequest = serde_json::from_str(&request_body)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let current = self.current_invoice.as_ref().filter(|c| c.id == request.invoice_id);
+        let saved = self.load_invoice(&request.invoice_id).ok();
+        if current.is_none() && saved.is_none() {
+            return Err("Invoice not found".to_string());
+        }
+
+        let unsaved = current.is_some() && self.has_unsaved_changes;
+        let dirty_fields = match (current, &saved) {
+            (Some(current), Some(saved)) if unsaved => {
+                let current = serde_json::to_value(current)
+                    .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+                let saved = serde_json::to_value(saved)
+                    .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+                drafts::changed_fields(&saved, &current)
+            }
+            _ => Vec::new(),
+        };
+        let session = request.session_id.as_ref()
+            .and_then(|id| self.draft_buffers.session_state(id, &request.invoice_id));
+
+        let response = serde_json::json!({
+            "invoice_id": request.invoice_id,
+            "saved_at": saved.map(|s| s.updated_at), // None until first saved
+            "unsaved": unsaved,
+            "dirty_fields": dirty_fields,
+            "session": session,
+        });
+        serde_json::to_string(&response)
+            .map_err(|e| format!("Failed to serialize edit state: {}", e))
+    }
+
+    // Auto-save timer method
+    #[http]
+    async fn check_autosave(&mut self) -> Result<String, String> {
+        let _trace = trace("check_autosave");
+        if self.has_unsaved_changes {
+            let current_time = host::now();
+
+            if current_time - self.last_save_time >= 1 {
+                self.draft_buffers.prune(current_time);
+                self.save_current_invoice()?;
+                self.last_save_time = current_time;
+                Ok("saved".to_string())
+            } else {
+                Ok("waiting".to_string())
+            }
+        } else {
+            Ok("no_changes".to_string())
+        }
+    }
+}
+
+// Points each time entry at the invoice whose lines bill it. An entry whose line
+// was deleted, undone or moved away can be billed again, and one whose line comes
+// back on a redo is taken again.
+fn sync_time_entries(entries: &mut HashMap<String, TimeEntry>, invoice: &Invoice) {
+    let billed: std::collections::HashSet<&String> = invoice.line_items.iter()
+        .filter_map(|item| item.time_entry_id.as_ref())
+        .collect();
+    for entry in entries.values_mut() {
+        match entry.billed_invoice_id {
+            Some(ref id) if id == &invoice.id && !billed.contains(&entry.id) => entry.billed_invoice_id = None,
+            None if billed.contains(&entry.id) => entry.billed_invoice_id = Some(invoice.id.clone()),
+            _ => {}
+        }
+    }
+}
+
+// Keeps the items of each section together, in the order the sections first
+// appear. An item whose section already appears above joins the end of it.
+fn group_sections(items: &mut Vec<LineItem>) {
+    let mut grouped: Vec<LineItem> = Vec::with_capacity(items.len());
+    for mut item in items.drain(..) {
+        item.section = item.section.filter(|section| !section.trim().is_empty());
+        let end_of_section = item.section.as_ref()
+            .and_then(|section| grouped.iter().rposition(|other| other.section.as_ref() == Some(section)));
+        match end_of_section {
+            Some(last) => grouped.insert(last + 1, item),
+            None => grouped.push(item),
+        }
+    }
+    *items = grouped;
+}
+
+// Adds, updates or removes the late fee line so it charges one fee per period
+// overdue; false when the line was already right
+fn apply_late_fee(invoice: &mut Invoice, policy: &LateFeePolicy, days_overdue: i64) -> bool {
+    let existing = invoice.line_items.iter().position(|item| item.kind == LineItemKind::LateFee);
+    let mut without_fee = invoice.clone();
+    without_fee.line_items.retain(|item| item.kind != LineItemKind::LateFee);
+    let base = amount_due(&without_fee);
+    let periods = if base > Money::ZERO { policy.periods(days_overdue) } else { 0 };
+
+    if periods == 0 {
+        return match existing {
+            Some(index) => {
+                invoice.line_items.remove(index);
+                true
+            }
+            None => false,
+        };
+    }
+    let rate = match policy.charge {
+        LateFeeCharge::Flat(amount) => amount,
+        LateFeeCharge::Percent(percent) => base.percent_rounded(percent, currency_decimals(invoice), invoice.rounding.mode),
+    };
+    let description = format!("Late fee: {}", policy.describe(&invoice.currency));
+    match existing {
+        Some(index) => {
+            let item = &mut invoice.line_items[index];
+            if item.quantity == periods as f64 && item.rate == rate.to_f64() && item.description == description {
+                return false;
+            }
+            item.quantity = periods as f64;
+            item.rate = rate.to_f64();
+            item.description = description;
+        }
+        None => invoice.line_items.push(LineItem {
+            id: format!("late-fee-{}", invoice.id),
+            kind: LineItemKind::LateFee,
+            description,
+            quantity: periods as f64,
+            rate: rate.to_f64(),
+            ..Default::default()
+        }),
+    }
+    true
+}
+
+// Subtotal of each section's lines, in table order
+fn section_subtotals(invoice: &Invoice) -> Vec<(String, Money)> {
+    let mut subtotals: Vec<(String, Money)> = Vec::new();
+    for (item, amount) in invoice.line_items.iter().zip(line_amounts(invoice)) {
+        let Some(ref section) = item.section else { continue };
+        match subtotals.iter_mut().find(|(name, _)| name == section) {
+            Some((_, subtotal)) => *subtotal += amount,
+            None => subtotals.push((section.clone(), amount)),
+        }
+    }
+    subtotals
+}
+
+// Decimal places the invoice's amounts are rounded to, e.g. 0 for yen
+fn currency_decimals(invoice: &Invoice) -> usize {
+    currency::decimals(&invoice.currency)
+}
+
+// Each line's amount as the invoice shows it
+fn line_amounts(invoice: &Invoice) -> Vec<Money> {
+    let (decimals, mode) = (currency_decimals(invoice), invoice.rounding.mode);
+    invoice.line_items.iter()
+        .map(|item| match invoice.rounding.stage {
+            RoundingStage::PerLine => item.rounded_amount(decimals, mode),
+            RoundingStage::AtTotal => Money::round(item.exact_amount(), decimals, mode),
+        })
+        .collect()
+}
+
+fn subtotal(invoice: &Invoice) -> Money {
+    match invoice.rounding.stage {
+        RoundingStage::PerLine => line_amounts(invoice).into_iter().sum(),
+        RoundingStage::AtTotal => Money::round(exact_subtotal(invoice), currency_decimals(invoice), invoice.rounding.mode),
+    }
+}
+
+fn exact_subtotal(invoice: &Invoice) -> f64 {
+    invoice.line_items.iter().map(LineItem::exact_amount).sum()
+}
+
+fn exact_taxable_amount(invoice: &Invoice) -> f64 {
+    exact_subtotal(invoice) * (1.0 - invoice.discount_percent / 100.0)
+}
+
+// Line items after line and invoice discounts, before tax
+fn taxable_amount(invoice: &Invoice) -> Money {
+    let (decimals, mode) = (currency_decimals(invoice), invoice.rounding.mode);
+    match invoice.rounding.stage {
+        RoundingStage::PerLine => {
+            let subtotal = subtotal(invoice);
+            subtotal - subtotal.percent_rounded(invoice.discount_percent, decimals, mode)
+        }
+        RoundingStage::AtTotal => Money::round(exact_taxable_amount(invoice), decimals, mode),
+    }
+}
+
+// Each line's share of the taxable amount: its amount as shown less its part of
+// the invoice discount (and of any rounding at the total), split so the shares
+// add up to taxable_amount
+fn line_taxable_amounts(invoice: &Invoice) -> Vec<Money> {
+    let amounts = line_amounts(invoice);
+    let shown: Money = amounts.iter().sum();
+    let weights: Vec<f64> = amounts.iter().map(|a| a.to_f64()).collect();
+    let discounts = (shown - taxable_amount(invoice)).allocate(&weights, currency_decimals(invoice));
+    amounts.into_iter().zip(discounts).map(|(amount, discount)| amount - discount).collect()
+}
+
+fn shipping_amount(invoice: &Invoice) -> Money {
+    invoice.shipping.map(|s| s.amount).unwrap_or(Money::ZERO)
+}
+
+// What tax is charged on: the taxable amount, plus shipping when it is taxed
+fn tax_base(invoice: &Invoice) -> Money {
+    match invoice.shipping {
+        Some(shipping) if shipping.taxable => taxable_amount(invoice) + shipping.amount,
+        _ => taxable_amount(invoice),
+    }
+}
+
+// What the invoice list shows for an invoice
+fn summary(invoice: &Invoice) -> InvoiceSummary {
+    InvoiceSummary {
+        id: invoice.id.clone(),
+        number: invoice.number.clone(),
+        name: invoice.name.clone(),
+        date: invoice.date.clone(),
+        total: calculate_invoice_total(invoice),
+        currency: invoice.currency.clone(),
+        home_total: home_total(invoice),
+        status: invoice.status.clone(),
+    }
+}
+
+// Standalone helper function for calculating invoice total
+fn calculate_invoice_total(invoice: &Invoice) -> Money {
+    let (decimals, mode) = (currency_decimals(invoice), invoice.rounding.mode);
+    if invoice.rounding.stage == RoundingStage::AtTotal {
+        let shipping = shipping_amount(invoice).to_f64();
+        let taxable = exact_taxable_amount(invoice);
+        let base = match invoice.shipping {
+            Some(ref s) if s.taxable => taxable + shipping,
+            _ => taxable,
+        };
+        let tax: f64 = if invoice.tax_lines.is_empty() {
+            base * invoice.tax_percent / 100.0
+        } else {
+            exact_tax_line_amounts(base, &invoice.tax_lines).into_iter().sum()
+        };
+        return Money::round(taxable + shipping + tax, decimals, mode);
+    }
+    let base = tax_base(invoice);
+    let tax = if invoice.tax_lines.is_empty() {
+        base.percent_rounded(invoice.tax_percent, decimals, mode)
+    } else {
+        tax_line_amounts(base, &invoice.tax_lines, decimals, mode).into_iter().sum()
+    };
+    taxable_amount(invoice) + shipping_amount(invoice) + tax
+}
+
+// Each tax line's tax on the taxable amount, compound ones on the amount with the
+// taxes before them added
+fn tax_line_amounts(taxable: Money, tax_lines: &[TaxLine], decimals: usize, mode: money::RoundingMode) -> Vec<Money> {
+    let mut charged = Money::ZERO;
+    tax_lines.iter()
+        .map(|line| {
+            let base = if line.compound { taxable + charged } else { taxable };
+            let amount = base.percent_rounded(line.percent, decimals, mode);
+            charged += amount;
+            amount
+        })
+        .collect()
+}
+
+// The same, unrounded
+fn exact_tax_line_amounts(taxable: f64, tax_lines: &[TaxLine]) -> Vec<f64> {
+    let mut charged = 0.0;
+    tax_lines.iter()
+        .map(|line| {
+            let base = if line.compound { taxable + charged } else { taxable };
+            let amount = base * line.percent / 100.0;
+            charged += amount;
+            amount
+        })
+        .collect()
+}
+
+// Revenue after line and invoice discounts, against the cost of the lines that have one
+fn invoice_margin(invoice: &Invoice, home_currency: &str) -> Option<margins::InvoiceMargin> {
+    let mut revenue = Money::ZERO;
+    let mut cost = Money::ZERO;
+    let mut uncosted_revenue = Money::ZERO;
+    for (item, amount) in invoice.line_items.iter().zip(line_taxable_amounts(invoice)) {
+        revenue += amount;
+        match item.cost {
+            Some(unit_cost) => cost += Money::times(unit_cost, item.quantity, currency_decimals(invoice)),
+            None => uncosted_revenue += amount,
+        }
+    }
+    Some(margins::InvoiceMargin {
+        invoice_id: invoice.id.clone(),
+        number: invoice.number.clone(),
+        date: invoice.date.clone(),
+        client: invoice.invoicee.company.clone()
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| invoice.invoicee.name.clone()),
+        project: invoice.project.clone().filter(|p| !p.trim().is_empty()),
+        revenue: in_currency(invoice, revenue, home_currency)?.to_f64(),
+        cost: in_currency(invoice, cost, home_currency)?.to_f64(),
+        uncosted_revenue: in_currency(invoice, uncosted_revenue, home_currency)?.to_f64(),
+    })
+}
+
+// What is left to pay once deposits are credited
+fn amount_due(invoice: &Invoice) -> Money {
+    calculate_invoice_total(invoice) - invoice.deposits_applied.iter().map(|d| d.amount).sum::<Money>()
+}
+
+// An amount on the invoice in the home currency, at the rate taken when it was sent
+fn to_home_currency(invoice: &Invoice, amount: Money) -> Option<Money> {
+    invoice.exchange_rate.as_ref().map(|r| convert(amount, r.rate, &r.home_currency))
+}
+
+fn home_total(invoice: &Invoice) -> Option<Money> {
+    to_home_currency(invoice, calculate_invoice_total(invoice))
+}
+
+// An amount on the invoice in another currency: as is when the invoice is in it,
+// or converted at the rate taken when it was sent when that rate is into it.
+// None when the invoice has no rate into the currency.
+fn in_currency(invoice: &Invoice, amount: Money, code: &str) -> Option<Money> {
+    if invoice.currency == code {
+        return Some(amount);
+    }
+    invoice.exchange_rate.as_ref()
+        .filter(|rate| rate.home_currency == code)
+        .map(|rate| convert(amount, rate.rate, code))
+}
+
+// The balance due in a payment method's currency
+fn amount_due_in(invoice: &Invoice, code: &str) -> Result<Money, String> {
+    in_currency(invoice, amount_due(invoice), code)
+        .ok_or_else(|| format!("Invoice is in {} and this payment method takes {}", invoice.currency, code))
+}
+
+// An amount at an exchange rate, rounded to the other currency's minor unit
+fn convert(amount: Money, rate: f64, currency: &str) -> Money {
+    Money::round(amount.to_f64() * rate, currency::decimals(currency), money::RoundingMode::HalfUp)
+}
+
+// Amount payable by a payment method: the balance due with the method's fee applied
+fn amount_with_fee(due: Money, fee: &PaymentMethodFee, decimals: usize) -> Money {
+    due + due.percent(fee.percent, decimals) + fee.fixed
+}
+
+// Terms printed on the document: e.g. "Net 30", or "2/10 net 30" when paying early
+// earns a discount
+fn terms_label(invoice: &Invoice) -> Option<String> {
+    match invoice.early_payment {
+        Some(ref early) => Some(early_payment_label(early, invoice)),
+        None => invoice.payment_terms.map(PaymentTerms::label),
+    }
+}
+
+// e.g. "2/10 net 30", or "2/10" without a due date
+fn early_payment_label(terms: &EarlyPaymentTerms, invoice: &Invoice) -> String {
+    let net = invoice.due_date.as_deref()
+        .and_then(days_from_date)
+        .zip(days_from_date(&invoice.date))
+        .map(|(due, date)| format!(" net {}", due - date))
+        .unwrap_or_default();
+    format!("{}/{}{}", terms.percent, terms.days, net)
+}
+
+// e.g. "+2% + $0.30" or "-1%"
+fn fee_terms(fee: &PaymentMethodFee, currency: &str) -> String {
+    let mut terms = format!("{:+}%", fee.percent);
+    if !fee.fixed.is_zero() {
+        terms.push_str(&format!(" {} {}", if fee.fixed > Money::ZERO { "+" } else { "-" }, format_money(fee.fixed.abs(), currency)));
+    }
+    terms
+}
+
+// An amount with the currency's symbol and decimal places, e.g. "€1234.50"
+fn format_money(amount: Money, currency: &str) -> String {
+    currency::format_amount(amount.to_f64(), currency)
+}
+
+// How the money filter of custom templates shows amounts; codes without a
+// known symbol follow the amount, as in "1234.50 NGN"
+fn currency_context(code: &str) -> templates::CurrencyContext {
+    match currency::find(code) {
+        Some(known) => templates::CurrencyContext {
+            code: known.code.to_string(),
+            symbol: known.symbol.to_string(),
+            decimals: known.decimals,
+            symbol_after: known.symbol_after,
+        },
+        None => templates::CurrencyContext {
+            code: code.to_string(),
+            symbol: code.to_string(),
+            decimals: currency::decimals(code),
+            symbol_after: true,
+        },
+    }
+}
+
+// SHA-256 of an invoice's content, ignoring fields that change without
+// altering what was agreed to (status, timestamps, the signature itself)
+fn document_hash(invoice: &Invoice) -> String {
+    let mut content = invoice.clone();
+    content.signature = None;
+    content.approval = None;
+    content.payment = None;
+    content.exchange_rate = None;
+    content.status = InvoiceStatus::Draft;
+    content.status_history = vec![];
+    content.sent_at = None;
+    content.issued_documents = vec![];
+    content.updated_at = 0;
+    let data = serde_json::to_vec(&content).unwrap_or_default();
+    hex::encode(Sha256::digest(&data))
+}
+
+// PDFs and images are filed; signatures, calendar invites and the like are not
+fn is_document_attachment(attachment: &EmailAttachment) -> bool {
+    let content_type = attachment.content_type.to_lowercase();
+    let file_name = attachment.file_name.to_lowercase();
+    content_type == "application/pdf"
+        || file_name.ends_with(".pdf")
+        || content_type.starts_with("image/")
+}
+
+// Split a From header like "ACME Billing <billing@acme.com>" into name and address
+fn parse_sender(from: &str) -> (String, Option<String>) {
+    match (from.find('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => {
+            let email = from[start + 1..end].trim().to_string();
+            let name = from[..start].trim().trim_matches('"').trim().to_string();
+            if name.is_empty() {
+                (email.clone(), Some(email))
+            } else {
+                (name, Some(email))
+            }
+        }
+        _ => {
+            let from = from.trim().to_string();
+            let email = if from.contains('@') { Some(from.clone()) } else { None };
+            (from, email)
+        }
+    }
+}
+
+// Wraps rendered invoice bodies in the shared styles and receipt viewer
+// head is extra markup for the end of <head>, e.g. embedded fonts; policy is the
+// document's Content-Security-Policy
+fn html_document(body: &str, head: &str, policy: &str) -> String {
+    format!(r#"
+<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta http-equiv="Content-Security-Policy" content="{}">
+    <style>
+        /* Light theme (default) */
+        :root {{
+            --primary-color: #4a6fa5;
+            --background: #f5f5dc;
+            --surface: #fffef9;
+            --text-primary: #333333;
+            --text-secondary: #666666;
+            --border-color: rgba(0, 0, 0, 0.15);
+            --table-header-bg: #e8e8d5;
+        }}
+
+        /* Dark theme */
+        @media (prefers-color-scheme: dark) {{
+            :root {{
+                --primary-color: #6b8bc4;
+                --background: #1a1a1a;
+                --surface: #2a2a2a;
+                --text-primary: #e0e0e0;
+                --text-secondary: #b0b0b0;
+                --border-color: rgba(255, 255, 255, 0.15);
+                --table-header-bg: #333333;
+            }}
+        }}
+
+        body {{
+            font-family: Arial, sans-serif;
+            margin: 40px;
+            background-color: var(--background);
+            color: var(--text-primary);
+        }}
+        .header {{ display: flex; justify-content: space-between; margin-bottom: 40px; }}
+        .invoice-details {{ text-align: right; }}
+        .contact-info {{ margin-bottom: 30px; }}
+        /* Right-to-left text such as Arabic or Hebrew lays out by its own direction */
+        p, td, h2 {{ unicode-bidi: plaintext; }}
+        table {{
+            width: 100%;
+            border-collapse: collapse;
+            margin: 20px 0;
+            background-color: var(--surface);
+        }}
+        th, td {{
+            padding: 10px;
+            text-align: left;
+            border-bottom: 1px solid var(--border-color);
+        }}
+        th {{ background-color: var(--table-header-bg); }}
+        .totals {{ text-align: right; margin-top: 20px; }}
+        .total-row {{ display: flex; justify-content: flex-end; margin: 5px 0; }}
+        .total-label {{ width: 150px; }}
+        .total-value {{ width: 100px; text-align: right; }}
+        .receipt-link {{
+            color: var(--primary-color);
+            text-decoration: underline;
+            cursor: pointer;
+            font-size: 0.9em;
+        }}
+        .receipt-link:hover {{ opacity: 0.8; }}
+        .verified-badge {{ color: var(--primary-color); font-size: 0.85em; margin-left: 0.5em; }}
+        .remittance-slip {{ margin-top: 40px; padding-top: 15px; border-top: 2px dashed var(--border-color); break-inside: avoid; }}
+        .remittance-slip table {{ width: auto; }}
+        .remittance-slip td {{ border: none; padding: 4px 20px 4px 0; }}
+        .amount-enclosed {{ display: inline-block; width: 150px; border-bottom: 1px solid var(--text-primary); }}
+        .modal {{
+            display: none;
+            position: fixed;
+            z-index: 1000;
+            left: 0;
+            top: 0;
+            width: 100%;
+            height: 100%;
+            background-color: rgba(0,0,0,0.9);
+        }}
+        .modal-content {{
+            margin: 2% auto;
+            display: block;
+            max-width: 90%;
+            max-height: 90%;
+        }}
+        .close {{
+            position: absolute;
+            top: 15px;
+            right: 35px;
+            color: #f1f1f1;
+            font-size: 40px;
+            font-weight: bold;
+            cursor: pointer;
+        }}
+        .close:hover {{ color: #bbb; }}
+        .copy-label {{ font-weight: bold; letter-spacing: 0.05em; }}
+        .client-reference {{ font-size: 1.1em; }}
+        .addressees {{ display: flex; gap: 40px; }}
+        .addressees > div {{ flex: 1; }}
+        .hsn-summary {{ margin-top: 30px; font-size: 0.9em; }}
+        .thank-you {{ margin-top: 30px; text-align: center; font-style: italic; }}
+        .late-fee td {{ border-top: 2px solid #c0392b; color: #c0392b; font-weight: bold; }}
+        .number-barcode {{ margin-top: 8px; }}
+        .number-barcode svg {{ max-height: 80px; width: auto; }}
+        .list-rate {{ color: var(--text-secondary); }}
+        .section-subtotal td {{ text-align: right; font-weight: bold; }}
+        .text-row td {{ white-space: pre-line; }}
+        .running-total td {{ text-align: right; font-style: italic; color: var(--text-secondary); }}
+        .page-header {{ display: flex; justify-content: space-between; margin-bottom: 10px; font-size: 0.9em; }}
+        .line-item-page + .line-item-page {{
+            break-before: page;
+            page-break-before: always;
+        }}
+        .batch-page + .batch-page {{
+            break-before: page;
+            page-break-before: always;
+        }}
+        @media print {{
+            .receipt-link {{ display: none; }}
+            body {{ background-color: white; color: black; }}
+            table {{ background-color: white; }}
+            th {{ background-color: #f5f5f5; }}
+        }}
+    </style>
+{}</head>
+<body>
+{}
 
-        // Check if it's already the current invoice
-        if let Some(ref current) = self.current_invoice {
-            if current.id == id {
-                return serde_json::to_string(current)
-                    .map_err(|e| format!("Failed to serialize invoice: {}", e));
+    <!-- Receipt viewer modal -->
+    <div id="receiptModal" class="modal">
+        <span class="close" onclick="closeModal()">&times;</span>
+        <iframe id="receiptFrame" class="modal-content" style="width: 90%; height: 90%; border: none;"></iframe>
+    </div>
+
+    <script>
+        function showReceipt(index) {{
+            const receiptDiv = document.getElementById('receipt-' + index);
+            if (!receiptDiv) return;
+
+            const base64Data = receiptDiv.textContent;
+            const mimeType = receiptDiv.getAttribute('data-mime');
+            const dataUri = 'data:' + mimeType + ';base64,' + base64Data;
+
+            const modal = document.getElementById('receiptModal');
+            const frame = document.getElementById('receiptFrame');
+            frame.src = dataUri;
+            modal.style.display = 'block';
+        }}
+
+        function closeModal() {{
+            const modal = document.getElementById('receiptModal');
+            const frame = document.getElementById('receiptFrame');
+            modal.style.display = 'none';
+            frame.src = '';
+        }}
+
+        window.onclick = function(event) {{
+            const modal = document.getElementById('receiptModal');
+            if (event.target === modal) {{
+                closeModal();
+            }}
+        }}
+    </script>
+</body>
+</html>
+        "#,
+        policy, head, body
+    )
+}
+
+// Days since 1970-01-01 for a YYYY-MM-DD date
+fn days_from_date(date: &str) -> Option<i64> {
+    if !is_iso_date(date) {
+        return None;
+    }
+    let year: i64 = date[0..4].parse().ok()?;
+    let month: i64 = date[5..7].parse().ok()?;
+    let day: i64 = date[8..10].parse().ok()?;
+
+    // Days-from-civil, the inverse of date_from_timestamp
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+// Format a UNIX timestamp as YYYY-MM-DD (UTC)
+fn date_from_timestamp(timestamp: u64) -> String {
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Format a UNIX timestamp as an ISO 8601 UTC date-time
+fn datetime_from_timestamp(timestamp: u64) -> String {
+    let seconds_of_day = timestamp % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        date_from_timestamp(timestamp),
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60
+    )
+}
+
+// Parse YYYY-MM-DD (midnight) or YYYY-MM-DDTHH:MM[:SS][Z] as a timestamp. Times
+// ending in Z are UTC; anything else is local time at the given offset.
+fn timestamp_from_datetime(datetime: &str, utc_offset_minutes: i32) -> Option<u64> {
+    let (date, time, offset) = match datetime.split_once('T') {
+        Some((date, time)) if time.ends_with('Z') => (date, time.trim_end_matches('Z'), 0),
+        Some((date, time)) => (date, time, utc_offset_minutes as i64 * 60),
+        None => (datetime, "00:00", utc_offset_minutes as i64 * 60),
+    };
+    let days = days_from_date(date)?;
+    let parts: Vec<u64> = time.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [h, m] => (h, m, 0),
+        [h, m, s] => (h, m, s),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    let local = days * 86400 + (hours * 3600 + minutes * 60 + seconds) as i64;
+    u64::try_from(local - offset).ok()
+}
+
+// Days since the epoch (a Thursday) falling on a weekday that isn't a holiday
+fn is_business_day(days: i64, holidays: &[String]) -> bool {
+    let weekday = (days + 4).rem_euclid(7); // 0 is Sunday
+    weekday != 0 && weekday != 6 && !holidays.contains(&date_from_timestamp((days * 86400) as u64))
+}
+
+// Whether a string is a YYYY-MM-DD date (dates are also used as directory names)
+fn is_iso_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return false;
+    }
+    match (parts[0].parse::<u32>(), parts[1].parse::<u32>(), parts[2].parse::<u32>()) {
+        (Ok(_), Ok(month), Ok(day)) => (1..=12).contains(&month) && (1..=31).contains(&day),
+        _ => false,
+    }
+}
+
+// Format an optional date or date range, e.g. "2024-03-01 – 2024-03-31"
+// Invoice used to show template variables with realistic values
+fn sample_invoice() -> Invoice {
+    serde_json::from_value(serde_json::json!({
+        "id": "sample",
+        "number": "INV-0042",
+        "name": null,
+        "date": "2025-03-01",
+        "due_date": "2025-03-31",
+        "invoicer": {
+            "name": "Jane Smith",
+            "company": "Acme Design Ltd",
+            "address": "1 High Street, London",
+            "email": "billing@acme.example",
+            "phone": "+44 20 7946 0000",
+            "logo_path": null,
+            "tax_id": "GB123456789",
+        },
+        "invoicee": {
+            "name": "John Doe",
+            "company": "Globex Corporation",
+            "address": "500 Market Street, San Francisco",
+            "email": "ap@globex.example",
+            "phone": null,
+            "logo_path": null,
+        },
+        "line_items": [
+            {
+                "id": "item-1",
+                "description": "Website redesign",
+                "quantity": 1.0,
+                "rate": 2400.0,
+                "discount_percent": 0.0,
+                "receipt_path": null,
+                "section": "Design",
+                "service_date_start": "2025-02-01",
+                "service_date_end": "2025-02-28",
+            },
+            {
+                "id": "item-2",
+                "description": "Hosting (monthly)",
+                "quantity": 3.0,
+                "rate": 40.0,
+                "discount_percent": 10.0,
+                "receipt_path": null,
+            },
+        ],
+        "discount_percent": 5.0,
+        "tax_percent": 20.0,
+        "notes": "Thank you for your business.",
+        "purchase_order": "PO-77120",
+        "payment_info": "Bank transfer to account 12345678",
+        "payment_image_path": null,
+        "status": "Sent",
+        "created_at": 1740787200,
+        "updated_at": 1740787200,
+        "deposits_applied": [
+            { "invoice_id": "sample-deposit", "number": "INV-0041", "amount": 500.0 },
+        ],
+    }))
+    .unwrap()
+}
+
+// State the supply is made to, for taxes split by place of supply
+fn place_of_supply(invoice: &Invoice) -> Option<&String> {
+    invoice.place_of_supply.as_ref().or(invoice.invoicee.state.as_ref())
+}
+
+// Tax components with their rates: the jurisdiction taxes applied to the invoice,
+// else its tax lines, else the split (e.g. CGST and SGST) within the seller's state, or the interstate
+// tax (e.g. IGST) when the place of supply is another state
+fn tax_components(invoice: &Invoice, tax_settings: &tax_regimes::TaxSettings) -> Vec<(String, f64)> {
+    if !invoice.sales_taxes.is_empty() {
+        return invoice.sales_taxes.iter().map(|t| (t.name.clone(), t.percent)).collect();
+    }
+    if !invoice.tax_lines.is_empty() {
+        return invoice.tax_lines.iter().map(|t| (t.name.clone(), t.percent)).collect();
+    }
+    let interstate = match (&tax_settings.interstate_name, &invoice.invoicer.state, place_of_supply(invoice)) {
+        (Some(name), Some(seller), Some(place)) if !seller.trim().eq_ignore_ascii_case(place.trim()) => Some(name.clone()),
+        _ => None,
+    };
+    let names = match interstate {
+        Some(name) => vec![name],
+        None if tax_settings.split_names.is_empty() => vec![tax_settings.tax_name.clone()],
+        None => tax_settings.split_names.clone(),
+    };
+    // A split tax is shown as equal parts
+    let parts = names.len() as f64;
+    names.into_iter().map(|name| (name, invoice.tax_percent / parts)).collect()
+}
+
+// Tax per component, to the cent, adding up to the invoice's tax
+fn tax_amounts(invoice: &Invoice, tax_components: &[(String, f64)]) -> Vec<Money> {
+    let by_tax_line = invoice.sales_taxes.is_empty() && !invoice.tax_lines.is_empty();
+    if by_tax_line && invoice.rounding.stage == RoundingStage::PerLine {
+        return tax_line_amounts(tax_base(invoice), &invoice.tax_lines, currency_decimals(invoice), invoice.rounding.mode);
+    }
+    // Rounded at the total, the tax is whatever the total leaves, split in
+    // proportion to what each component charges
+    let tax = calculate_invoice_total(invoice) - taxable_amount(invoice) - shipping_amount(invoice);
+    let weights: Vec<f64> = if by_tax_line {
+        exact_tax_line_amounts(100.0, &invoice.tax_lines)
+    } else {
+        tax_components.iter().map(|(_, percent)| *percent).collect()
+    };
+    tax.allocate(&weights, currency_decimals(invoice))
+}
+
+// HSN/SAC-wise summary of taxable value and tax, as prescribed for GST invoices in India
+fn hsn_summary_html(invoice: &Invoice, tax_components: &[(String, f64)]) -> String {
+    // Taxable value per code, after line and invoice discounts, in order of first use
+    let mut groups: Vec<(String, Money)> = Vec::new();
+    for (item, taxable) in invoice.line_items.iter().zip(line_taxable_amounts(invoice)) {
+        if item.kind == LineItemKind::Text {
+            continue;
+        }
+        let code = item.hsn_sac.clone().unwrap_or_default();
+        match groups.iter_mut().find(|(existing, _)| *existing == code) {
+            Some(group) => group.1 += taxable,
+            None => groups.push((code, taxable)),
+        }
+    }
+
+    let component_headers: String = tax_components.iter()
+        .map(|(name, _)| format!("<th>{0} Rate</th><th>{0} Amount</th>", escape_html(name)))
+        .collect();
+
+    // Each component is rounded per code, and the totals add up the rounded amounts
+    let money = |amount: Money| format_money(amount, &invoice.currency);
+    let mut rows = String::new();
+    let mut total_taxable = Money::ZERO;
+    let mut component_totals = vec![Money::ZERO; tax_components.len()];
+    for (code, taxable) in &groups {
+        let mut cells = String::new();
+        let mut tax = Money::ZERO;
+        for ((_, percent), component_total) in tax_components.iter().zip(component_totals.iter_mut()) {
+            let amount = taxable.percent_rounded(*percent, currency_decimals(invoice), invoice.rounding.mode);
+            cells.push_str(&format!("<td>{}%</td><td>{}</td>", percent, money(amount)));
+            tax += amount;
+            *component_total += amount;
+        }
+        total_taxable += *taxable;
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td>{}<td>{}</td></tr>",
+            escape_html(code), money(*taxable), cells, money(tax)
+        ));
+    }
+    let total_cells: String = component_totals.iter()
+        .map(|amount| format!("<td></td><td>{}</td>", money(*amount)))
+        .collect();
+    rows.push_str(&format!(
+        "<tr><td><strong>Total</strong></td><td>{}</td>{}<td>{}</td></tr>",
+        money(total_taxable), total_cells, money(component_totals.iter().sum())
+    ));
+
+    format!(
+        "<table class=\"hsn-summary\"><thead><tr><th>HSN/SAC</th><th>Taxable Value</th>{}<th>Total Tax</th></tr></thead><tbody>{}</tbody></table>",
+        component_headers, rows
+    )
+}
+
+fn format_period(start: &Option<String>, end: &Option<String>) -> String {
+    match (start, end) {
+        (Some(start), Some(end)) if start != end => format!("{} &ndash; {}", start, end),
+        (Some(date), _) | (None, Some(date)) => date.clone(),
+        (None, None) => String::new(),
+    }
+}
+
+// Quantity followed by its unit, e.g. "7.5 hours", escaped for HTML
+fn format_quantity(item: &LineItem) -> String {
+    match item.unit.as_deref().map(str::trim) {
+        Some(unit) if !unit.is_empty() => format!("{} {}", item.quantity, escape_html(unit)),
+        _ => item.quantity.to_string(),
+    }
+}
+
+// Name, company and each line of the free-form address, escaped for HTML
+fn address_lines(contact: &ContactInfo) -> Vec<String> {
+    let mut lines = vec![contact.name.clone()];
+    if let Some(ref company) = contact.company {
+        lines.push(company.clone());
+    }
+    lines.extend(contact.address.lines().map(|line| line.to_string()));
+    lines.iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(escape_html)
+        .collect()
+}
+
+// Escape text supplied by people other than the invoicer before embedding it in HTML
+// File name suffix for a copy label, e.g. "duplicate-for-transporter"
+fn copy_label_slug(label: &str) -> String {
+    label.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// One problem per line, e.g. "Line 3: <script> is not allowed in templates"
+fn describe_template_errors(errors: &[templates::TemplateError]) -> String {
+    errors.iter()
+        .map(|e| format!("Line {}: {}", e.line, e.message))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn template_dir_path(template_id: &str) -> String {
+    format!("/{}/invoice/templates/{}", host::address().package_id(), template_id)
+}
+
+// Writes an asset to the template's directory; the name is validated first so it
+// can't escape the directory
+fn write_template_asset(template_id: &str, name: &str, data: &[u8]) -> Result<TemplateAsset, String> {
+    let content_type = templates::validate_asset(name, data.len())?;
+    let path = format!("{}/{}", template_dir_path(template_id), name);
+    save_blob(&path, data)
+        .map_err(|e| format!("Failed to write asset: {}", e))?;
+
+    Ok(TemplateAsset {
+        name: name.to_string(),
+        content_type: content_type.to_string(),
+        path,
+        size: data.len(),
+    })
+}
+
+// Whether a file starts with the signature of its claimed type, so an imported
+// bundle can't pass off other content as an image or font
+fn asset_matches_content_type(data: &[u8], content_type: &str) -> bool {
+    match content_type {
+        "image/png" => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => data.starts_with(&[0xFF, 0xD8, 0xFF]),
+        "image/gif" => data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a"),
+        "image/webp" => data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP",
+        "font/woff2" => data.starts_with(b"wOF2"),
+        "font/woff" => data.starts_with(b"wOFF"),
+        "font/ttf" => data.starts_with(&[0x00, 0x01, 0x00, 0x00]) || data.starts_with(b"true"),
+        "font/otf" => data.starts_with(b"OTTO"),
+        _ => false,
+    }
+}
+
+// (name, data URI) of each asset that can still be read
+fn template_asset_uris(assets: &[TemplateAsset]) -> Vec<(String, String)> {
+    assets.iter()
+        .filter_map(|asset| {
+            let data = load_blob(&asset.path).ok()?;
+            Some((asset.name.clone(), format!("data:{};base64,{}", asset.content_type, general_purpose::STANDARD.encode(&data))))
+        })
+        .collect()
+}
+
+// Fonts with full CJK coverage run to several megabytes
+const MAX_FONT_BYTES: usize = 16 * 1024 * 1024;
+
+// Restricts a document to inline styles and embedded images and fonts; custom
+// templates get it in case anything slipped past the sandbox check
+const TEMPLATE_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src data:; font-src data:";
+
+// The built-in layouts also run the receipt viewer, which shows receipts in a frame
+const DOCUMENT_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src data:; font-src data:; script-src 'unsafe-inline'; frame-src data:";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Hand an email to the configured mail process for delivery
+async fn send_email(process: &str, to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let address = process.parse::<Address>()
+        .map_err(|e| format!("Invalid mail process address: {}", e))?;
+    let request = MailRequest::Send {
+        to: to.to_string(),
+        subject: subject.to_string(),
+        body: body.to_string(),
+    };
+    let body = serde_json::to_vec(&request)
+        .map_err(|e| format!("Failed to serialize mail request: {}", e))?;
+    match send::<MailResponse>(Request::to(address).body(body).expects_response(30)).await {
+        Ok(MailResponse::Sent) => Ok(()),
+        Ok(MailResponse::Error(e)) => Err(format!("Mail process error: {}", e)),
+        Err(e) => Err(format!("Failed to reach mail process: {}", e)),
+    }
+}
+
+async fn push_notification(process: &str, title: &str, body: &str) -> Result<(), String> {
+    let address = process.parse::<Address>()
+        .map_err(|e| format!("Invalid notification process address: {}", e))?;
+    let request = PushRequest::Push {
+        title: title.to_string(),
+        body: body.to_string(),
+    };
+    let body = serde_json::to_vec(&request)
+        .map_err(|e| format!("Failed to serialize notification: {}", e))?;
+    match send::<PushResponse>(Request::to(address).body(body).expects_response(30)).await {
+        Ok(PushResponse::Pushed) => Ok(()),
+        Ok(PushResponse::Error(e)) => Err(format!("Notification process error: {}", e)),
+        Err(e) => Err(format!("Failed to reach notification process: {}", e)),
+    }
+}
+
+fn share_link_active(link: &ShareLink, now: u64) -> bool {
+    !link.revoked && link.expires_at.map(|expires| now < expires).unwrap_or(true)
+}
+
+// Passcodes and one-time codes are only stored hashed, salted with the link token
+fn share_secret_hash(token: &str, secret: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}:{}", token, secret.trim()).as_bytes()))
+}
+
+// Compares an expense's amount with the total the OCR process reads off its first
+// receipt. None when there is no amount or receipt to compare.
+async fn check_expense_amount(process: &str, expense: &Expense) -> Option<AmountCheck> {
+    let entered = expense.amount?;
+    let receipt_path = expense.attachments.first()?.clone();
+    let timestamp = host::now();
+
+    let detected = detect_receipt_total(process, &receipt_path).await;
+    let (detected, status, error) = match detected {
+        Ok(Some(amount)) if (amount - entered).abs() < 0.005 => (Some(amount), AmountCheckStatus::Matched, None),
+        Ok(Some(amount)) => (Some(amount), AmountCheckStatus::Mismatch, None),
+        Ok(None) => (None, AmountCheckStatus::Unreadable, None),
+        Err(e) => (None, AmountCheckStatus::Unreadable, Some(e)),
+    };
+    Some(AmountCheck {
+        entered,
+        detected,
+        receipt_path,
+        status,
+        checked_at: timestamp,
+        error,
+    })
+}
+
+async fn detect_receipt_total(process: &str, receipt_path: &str) -> Result<Option<f64>, String> {
+    let address = process.parse::<Address>()
+        .map_err(|e| format!("Invalid OCR process address: {}", e))?;
+    let data = load_blob(receipt_path)
+        .map_err(|e| format!("Failed to read receipt: {}", e))?;
+    let content_type = if receipt_path.ends_with(".pdf") {
+        "application/pdf"
+    } else if receipt_path.ends_with(".jpg") || receipt_path.ends_with(".jpeg") {
+        "image/jpeg"
+    } else if receipt_path.ends_with(".png") {
+        "image/png"
+    } else {
+        "application/octet-stream"
+    };
+    let request = OcrRequest::DetectTotal {
+        file_name: receipt_path.rsplit('/').next().unwrap_or("receipt").to_string(),
+        content_type: content_type.to_string(),
+        data,
+    };
+    let body = serde_json::to_vec(&request)
+        .map_err(|e| format!("Failed to serialize OCR request: {}", e))?;
+    match send::<OcrResponse>(Request::to(address).body(body).expects_response(60)).await {
+        Ok(OcrResponse::Total { amount }) => Ok(amount),
+        Ok(OcrResponse::Error(e)) => Err(format!("OCR process error: {}", e)),
+        Err(e) => Err(format!("Failed to reach OCR process: {}", e)),
+    }
+}
+
+// Send a request to the configured Lightning node process
+async fn send_lightning_request(
+    lightning: &LightningSettings,
+    request: &LightningRequest,
+) -> Result<LightningResponse, String> {
+    let address = lightning.process.parse::<Address>()
+        .map_err(|e| format!("Invalid Lightning process address: {}", e))?;
+    let body = serde_json::to_vec(request)
+        .map_err(|e| format!("Failed to serialize Lightning request: {}", e))?;
+    send::<LightningResponse>(Request::to(address).body(body).expects_response(30))
+        .await
+        .map_err(|e| format!("Failed to reach Lightning node: {}", e))
+}
+
+// Resolve a node's Hypermap entry and its optional ~name note
+fn lookup_identity(node: &str) -> Result<VerifiedIdentity, String> {
+    let hypermap = hypermap::Hypermap::default(30);
+    let (tba, owner, _) = hypermap.get(node)
+        .map_err(|e| format!("{} not found on Hypermap: {}", node, e))?;
+
+    let name = hypermap.get(&format!("~name.{}", node))
+        .ok()
+        .and_then(|(_, _, note)| note)
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+        .filter(|name| !name.is_empty());
+
+    Ok(VerifiedIdentity {
+        node: node.to_string(),
+        owner: owner.to_string(),
+        tba: tba.to_string(),
+        name,
+        verified_at: host::now(),
+    })
+}
+
+// Token amount in base units for an invoice total. ERC-20 transfers carry no
+// memo, so the sub-cent digits are derived from the invoice ID to tell apart
+// payments of the same total.
+fn stablecoin_amount(total: Money, decimals: u8, invoice_id: &str) -> Result<u128, String> {
+    if !(2..=30).contains(&decimals) {
+        return Err("Token must have between 2 and 30 decimals".to_string());
+    }
+    if total <= Money::ZERO {
+        return Err("Invoice total must be greater than zero".to_string());
+    }
+
+    let sub_cent_units = 10u128.pow(decimals as u32 - 2);
+    let cents = total.minor_units(2) as u128;
+    let reference = if sub_cent_units > 1 {
+        let digest = Sha256::digest(invoice_id.as_bytes());
+        let seed = u64::from_be_bytes(digest[..8].try_into().unwrap()) as u128;
+        1 + seed % (sub_cent_units.min(10_000) - 1)
+    } else {
+        0
+    };
+    Ok(cents * sub_cent_units + reference)
+}
+
+// Look for an ERC-20 Transfer of exactly the requested amount to the recipient
+fn find_stablecoin_transfer(payment: &CryptoPayment) -> Result<Option<String>, String> {
+    let token = payment.token_address.parse::<eth::Address>()
+        .map_err(|e| format!("Invalid token address: {}", e))?;
+    let recipient = payment.recipient.parse::<eth::Address>()
+        .map_err(|e| format!("Invalid recipient address: {}", e))?;
+
+    let filter = eth::Filter::new()
+        .address(token)
+        .from_block(payment.from_block)
+        .event("Transfer(address,address,uint256)")
+        .topic2(recipient.into_word());
+
+    let provider = eth::Provider::new(payment.chain_id, 30);
+    let logs = provider.get_logs(&filter)
+        .map_err(|e| format!("Failed to get logs: {}", e))?;
+
+    Ok(logs.iter()
+        .find(|log| {
+            let data = &log.data().data;
+            data.len() >= 32 && eth::U256::from_be_slice(&data[..32]).to_string() == payment.amount
+        })
+        .and_then(|log| log.transaction_hash)
+        .map(|hash| hash.to_string()))
+}
+
+// Strips whatever the export options say this audience should not see
+fn apply_export_filters(invoice: &Invoice, options: &ExportOptions) -> Invoice {
+    let mut filtered = invoice.clone();
+    // Costs, commissions and team credits are internal to their reports and never leave the app
+    for item in &mut filtered.line_items {
+        item.cost = None;
+        item.team_member_id = None;
+    }
+    filtered.commission = None;
+    if options.redact_rates {
+        filtered.payment_info = None;
+        filtered.payment_image_path = None;
+        filtered.lightning_payment = None;
+        filtered.crypto_payment = None;
+    }
+    if options.hide_internal_fields {
+        for item in &mut filtered.line_items {
+            item.receipt_path = None;
+        }
+        filtered.source_documents.clear();
+    }
+    filtered
+}
+
+// Fields that are only ever set by their own endpoints are carried over from
+// the stored invoice, so a full-invoice update can never set or clear them
+fn preserve_managed_fields(invoice: &mut Invoice, existing: Option<Invoice>) {
+    match existing {
+        Some(existing) => {
+            // The number names the invoice's directory and is checked for uniqueness at creation
+            invoice.number = existing.number;
+            invoice.signature = existing.signature;
+            invoice.terms = existing.terms;
+            invoice.terms_acceptances = existing.terms_acceptances;
+            invoice.lightning_payment = existing.lightning_payment;
+            invoice.crypto_payment = existing.crypto_payment;
+            invoice.source_documents = existing.source_documents;
+            invoice.consolidated_into = existing.consolidated_into;
+            invoice.profile_id = existing.profile_id;
+            invoice.reissued_from = existing.reissued_from;
+            invoice.reminders_sent = existing.reminders_sent;
+            invoice.approval = existing.approval;
+            invoice.deposits_applied = existing.deposits_applied;
+            invoice.applied_to = existing.applied_to;
+            invoice.sales_taxes = existing.sales_taxes;
+            invoice.payment = existing.payment;
+            invoice.status_history = existing.status_history;
+            invoice.sent_at = existing.sent_at;
+            invoice.issued_documents = existing.issued_documents;
+            // A rate into another currency means nothing once the invoice's changes
+            invoice.exchange_rate = existing.exchange_rate.filter(|_| invoice.currency == existing.currency);
+            if !invoice.sales_taxes.is_empty() {
+                invoice.tax_percent = invoice.sales_taxes.iter().map(|t| t.percent).sum();
+            }
+            invoice.invoicee.verified_identity = existing.invoicee.verified_identity
+                .filter(|v| invoice.invoicee.node.as_ref() == Some(&v.node));
+        }
+        None => {
+            invoice.signature = None;
+            invoice.terms = None;
+            invoice.terms_acceptances = vec![];
+            invoice.lightning_payment = None;
+            invoice.crypto_payment = None;
+            invoice.source_documents = vec![];
+            invoice.consolidated_into = None;
+            invoice.profile_id = None;
+            invoice.reissued_from = None;
+            invoice.reminders_sent = vec![];
+            invoice.approval = None;
+            invoice.deposits_applied = vec![];
+            invoice.applied_to = vec![];
+            invoice.sales_taxes = vec![];
+            invoice.payment = None;
+            invoice.status_history = vec![];
+            invoice.sent_at = None;
+            invoice.issued_documents = vec![];
+            invoice.exchange_rate = None;
+            invoice.invoicee.verified_identity = None;
+        }
+    }
+}
+
+// Moves a person can make by hand. Paying happens through the payment endpoints,
+// which may go straight from Draft, and a paid invoice can only be reopened as
+// Sent or Overdue (e.g. when a payment bounces), never made a draft again.
+fn check_transition(from: &InvoiceStatus, to: &InvoiceStatus) -> Result<(), String> {
+    use InvoiceStatus::*;
+    let allowed = from == to || match from {
+        Draft => matches!(to, Sent | PendingApproval | Approved),
+        PendingApproval | Approved => matches!(to, Draft | Sent | PendingApproval | Approved),
+        Sent | Overdue => matches!(to, Draft | Sent | Paid | Overdue),
+        Paid => matches!(to, Sent | Overdue),
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(format!("An invoice can't go from {:?} to {:?}", from, to))
+    }
+}
+
+// Statuses set through update_invoice. PendingApproval and Approved only come
+// from the approval endpoints, and with approvals required an invoice must be
+// Approved, unchanged since, before it can go out.
+fn check_status_change(previous: &InvoiceStatus, invoice: &Invoice, settings: Option<&InvoiceSettings>) -> Result<(), String> {
+    if invoice.status == *previous {
+        return Ok(());
+    }
+    let approval_required = settings.map(|s| s.approval_required).unwrap_or(false);
+    if invoice.status == InvoiceStatus::Sent && invoice.kind == DocumentKind::Invoice {
+        let required = settings.map(|s| s.tax.required_fields.as_slice()).unwrap_or(&[]);
+        let missing = missing_required_fields(invoice, required);
+        if !missing.is_empty() {
+            return Err(format!("Add {} before sending this invoice", missing.join(", ")));
+        }
+    }
+    match invoice.status {
+        InvoiceStatus::PendingApproval | InvoiceStatus::Approved => {
+            Err("Use submit_for_approval to request approval".to_string())
+        }
+        InvoiceStatus::Sent | InvoiceStatus::Paid | InvoiceStatus::Overdue if approval_required => {
+            match previous {
+                InvoiceStatus::Draft | InvoiceStatus::PendingApproval => {
+                    Err("Invoice must be approved before it is sent".to_string())
+                }
+                InvoiceStatus::Approved => {
+                    let unchanged = invoice.approval.as_ref()
+                        .map(|a| a.document_hash == document_hash(invoice))
+                        .unwrap_or(false);
+                    if unchanged {
+                        Ok(())
+                    } else {
+                        Err("Invoice changed since it was approved; submit it for approval again".to_string())
+                    }
+                }
+                _ => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+// Labels of the fields the tax regime requires that the invoice leaves empty
+fn missing_required_fields(invoice: &Invoice, required: &[tax_regimes::RequiredField]) -> Vec<&'static str> {
+    use tax_regimes::RequiredField;
+    let filled = |value: &Option<String>| value.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false);
+    required.iter()
+        .filter(|field| !match field {
+            RequiredField::InvoicerTaxId => filled(&invoice.invoicer.tax_id),
+            RequiredField::InvoiceeTaxId => filled(&invoice.invoicee.tax_id),
+            RequiredField::InvoiceeAddress => !invoice.invoicee.address.trim().is_empty(),
+            RequiredField::SupplyDate => invoice.service_period_start.is_some()
+                || (!invoice.line_items.is_empty()
+                    && invoice.line_items.iter().all(|item| item.service_date_start.is_some())),
+            RequiredField::DueDate => filled(&invoice.due_date),
+        })
+        .map(|field| field.label())
+        .collect()
+}
+
+// Whether the terms attached to an invoice (if any) have been accepted
+fn terms_accepted(invoice: &Invoice) -> bool {
+    match invoice.terms {
+        Some(ref terms) => invoice.terms_acceptances.iter()
+            .any(|a| a.terms_id == terms.terms_id && a.version == terms.version),
+        None => true,
+    }
+}
+
+// The last part of an uploaded file's name, so writing it can't reach outside the
+// directory it goes in
+fn safe_file_name(name: &str) -> Result<String, String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if base.is_empty() || base == "." || base == ".." {
+        return Err(format!("Invalid file name: {}", name));
+    }
+    Ok(base.to_string())
+}
+
+// Copy a receipt into another invoice's receipts directory, returning the new path
+fn copy_receipt(receipt_path: &str, target: &Invoice) -> Result<String, String> {
+    let data = load_blob(receipt_path)
+        .map_err(|e| format!("Failed to read receipt: {}", e))?;
+
+    let file_name = receipt_path.rsplit('/').next().unwrap_or("receipt");
+    let new_path = format!("{}/receipts/{}", invoice_dir_path(target), file_name);
+    if new_path != receipt_path {
+        save_blob(&new_path, &data)
+            .map_err(|e| format!("Failed to write receipt: {}", e))?;
+    }
+    Ok(new_path)
+}
+
+fn validate_category_rule(rule: &CategoryRule) -> Result<(), String> {
+    if rule.category.trim().is_empty() {
+        return Err("Rule needs a category".to_string());
+    }
+    if rule.vendor_contains.as_ref().map(|v| v.trim().is_empty()).unwrap_or(true)
+        && rule.keywords.iter().all(|k| k.trim().is_empty())
+        && rule.min_amount.is_none()
+        && rule.max_amount.is_none()
+    {
+        return Err("Rule needs at least one condition".to_string());
+    }
+    if let (Some(min), Some(max)) = (rule.min_amount, rule.max_amount) {
+        if min > max {
+            return Err("Minimum amount is greater than maximum amount".to_string());
+        }
+    }
+    Ok(())
+}
+
+// Category of the first rule matching the vendor, free text and amount
+fn categorize(rules: &[CategoryRule], vendor: &str, text: &str, amount: Option<f64>) -> Option<String> {
+    let vendor = vendor.to_lowercase();
+    let text = text.to_lowercase();
+    rules.iter()
+        .find(|rule| {
+            let vendor_matches = match rule.vendor_contains {
+                Some(ref needle) if !needle.trim().is_empty() => vendor.contains(&needle.trim().to_lowercase()),
+                _ => true,
+            };
+            let keywords: Vec<String> = rule.keywords.iter()
+                .map(|k| k.trim().to_lowercase())
+                .filter(|k| !k.is_empty())
+                .collect();
+            let keywords_match = keywords.is_empty() || keywords.iter().any(|k| text.contains(k));
+            let amount_matches = match (rule.min_amount, rule.max_amount) {
+                (None, None) => true,
+                (min, max) => amount
+                    .map(|a| min.map(|m| a >= m).unwrap_or(true) && max.map(|m| a <= m).unwrap_or(true))
+                    .unwrap_or(false),
+            };
+            vendor_matches && keywords_match && amount_matches
+        })
+        .map(|rule| rule.category.clone())
+}
+
+fn categorize_expense(rules: &[CategoryRule], expense: &Expense) -> Option<String> {
+    let vendor = format!("{} {}", expense.vendor, expense.vendor_email.as_deref().unwrap_or(""));
+    let file_names = expense.attachments.iter()
+        .filter_map(|path| path.rsplit('/').next())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let text = format!("{} {}", expense.description, file_names);
+    categorize(rules, &vendor, &text, expense.amount)
+}
+
+// "lunch.receipt.pdf" -> "lunch.receipt"; names without an extension are kept as is
+fn file_stem(file_name: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+        _ => file_name.to_string(),
+    }
+}
+
+// What an invoice is found under in the invoice index
+fn index_keys(invoice: &Invoice) -> indexes::Keys {
+    indexes::Keys {
+        client: client_key(&invoice.invoicee),
+        status: format!("{:?}", invoice.status),
+        date: invoice.date.clone(),
+        project: invoice.project.clone(),
+        tags: invoice.tags.clone(),
+    }
+}
+
+// Company if set, otherwise the contact name, normalized for comparison
+fn client_key(contact: &ContactInfo) -> String {
+    contact.company.as_ref()
+        .filter(|c| !c.trim().is_empty())
+        .unwrap_or(&contact.name)
+        .trim()
+        .to_lowercase()
+}
+
+// One record per client across all invoices, keyed like duplicate detection.
+// The id is what export_client_data and forget_client take.
+fn client_records(invoices: &[Invoice]) -> Vec<serde_json::Value> {
+    let mut clients: Vec<(String, serde_json::Value)> = Vec::new();
+    for invoice in invoices {
+        let key = client_key(&invoice.invoicee);
+        if key.is_empty() {
+            continue;
+        }
+        let total = calculate_invoice_total(invoice);
+        let paid = if invoice.status == InvoiceStatus::Paid { total } else { Money::ZERO };
+        match clients.iter_mut().find(|(k, _)| k == &key) {
+            Some((_, record)) => {
+                record["invoice_count"] = serde_json::json!(record["invoice_count"].as_u64().unwrap_or(0) + 1);
+                let billed = Money::from_f64(record["total_billed"].as_f64().unwrap_or(0.0));
+                let paid_before = Money::from_f64(record["total_paid"].as_f64().unwrap_or(0.0));
+                record["total_billed"] = serde_json::json!(billed + total);
+                record["total_paid"] = serde_json::json!(paid_before + paid);
+                if record["last_invoice_date"].as_str().map(|d| d < invoice.date.as_str()).unwrap_or(true) {
+                    record["last_invoice_date"] = serde_json::json!(invoice.date);
+                    record["contact"] = serde_json::json!(invoice.invoicee);
+                }
+            }
+            None => clients.push((key.clone(), serde_json::json!({
+                "id": key,
+                "contact": invoice.invoicee,
+                "invoice_count": 1,
+                "total_billed": total,
+                "total_paid": paid,
+                "last_invoice_date": invoice.date,
+            }))),
+        }
+    }
+    clients.into_iter().map(|(_, record)| record).collect()
+}
+
+// Settled Lightning and stablecoin payments, plus invoices marked paid by hand
+fn payment_records(invoices: &[Invoice]) -> Vec<serde_json::Value> {
+    let mut payments = Vec::new();
+    for invoice in invoices {
+        let total = calculate_invoice_total(invoice);
+        let base = serde_json::json!({
+            "invoice_id": invoice.id,
+            "invoice_number": invoice.number,
+            "invoice_date": invoice.date,
+            "client": invoice.invoicee.company.as_ref().filter(|c| !c.is_empty()).unwrap_or(&invoice.invoicee.name),
+            "amount": total,
+        });
+
+        let mut recorded = false;
+        if let Some(settled_at) = invoice.lightning_payment.as_ref().and_then(|p| p.settled_at) {
+            let mut record = base.clone();
+            record["method"] = serde_json::json!("lightning");
+            record["paid_at"] = serde_json::json!(settled_at);
+            payments.push(record);
+            recorded = true;
+        }
+        if let Some(ref crypto) = invoice.crypto_payment {
+            if let Some(paid_at) = crypto.paid_at {
+                let mut record = base.clone();
+                record["method"] = serde_json::json!(crypto.symbol);
+                record["paid_at"] = serde_json::json!(paid_at);
+                record["reference"] = serde_json::json!(crypto.tx_hash);
+                payments.push(record);
+                recorded = true;
+            }
+        }
+        if let Some(ref payment) = invoice.payment {
+            let mut record = base.clone();
+            record["amount"] = serde_json::json!(payment.amount);
+            record["adjustment"] = serde_json::json!(payment.adjustment);
+            record["early_payment_discount"] = serde_json::json!(payment.early_payment_discount);
+            record["method"] = serde_json::json!(payment.method.as_deref().unwrap_or("manual"));
+            record["paid_on"] = serde_json::json!(payment.paid_on);
+            record["paid_at"] = serde_json::json!(payment.recorded_at);
+            record["reference"] = serde_json::json!(payment.reference);
+            payments.push(record);
+            recorded = true;
+        }
+        if !recorded && invoice.status == InvoiceStatus::Paid {
+            let mut record = base;
+            record["method"] = serde_json::json!("manual");
+            record["paid_at"] = serde_json::Value::Null;
+            payments.push(record);
+        }
+    }
+    payments
+}
+
+// Replaces every identifying field of a contact with the placeholder name
+fn anonymize_contact(contact: &mut ContactInfo, placeholder: &str) {
+    *contact = ContactInfo {
+        name: placeholder.to_string(),
+        ..ContactInfo::default()
+    };
+}
+
+fn index_receipt(receipt_hashes: &mut HashMap<String, Vec<String>>, data: &[u8], path: &str) {
+    let hash = hex::encode(Sha256::digest(data));
+    let paths = receipt_hashes.entry(hash).or_default();
+    if !paths.iter().any(|p| p == path) {
+        paths.push(path.to_string());
+    }
+}
+
+// Keeps the index pointing at a receipt after it moves out of the unfiled inbox
+fn rename_indexed_receipt(receipt_hashes: &mut HashMap<String, Vec<String>>, old_path: &str, new_path: &str) {
+    for paths in receipt_hashes.values_mut() {
+        for path in paths.iter_mut() {
+            if path == old_path {
+                *path = new_path.to_string();
             }
         }
+    }
+}
+
+fn remove_unfiled_receipt(receipt: &UnfiledReceipt) {
+    let _ = remove_blob(&receipt.path);
+    if let Some((dir, _)) = receipt.path.rsplit_once('/') {
+        let _ = with_store(|store| store.remove_blob_dir(dir));
+    }
+}
+
+// Directory holding an invoice's JSON, receipts and generated documents
+fn invoice_dir_path(invoice: &Invoice) -> String {
+    let location = invoice_location(invoice);
+    format!("/{}/invoice/{}/{}", host::address().package_id(), location.date, location.dir)
+}
+
+// Folder for one client's files, named after the client ID and a hash of it so
+// IDs that differ only in punctuation get folders of their own
+fn client_dir_path(client_id: &str) -> String {
+    let slug: String = client_id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!(
+        "/{}/invoice/clients/{}-{}",
+        host::address().package_id(),
+        slug.trim_matches('-'),
+        &hex::encode(Sha256::digest(client_id.as_bytes()))[..8]
+    )
+}
 
-        // Load invoice from VFS
-        let package_id = our().package_id();
-        let drive_path = format!("/{}/invoice", package_id);
+// The format of an uploaded logo and the bytes to store, sanitized if it is an SVG
+fn logo_upload(data: Vec<u8>) -> Result<(logos::ImageFormat, Vec<u8>), String> {
+    let format = logos::ImageFormat::detect(&data)
+        .ok_or("Logos must be PNG, JPEG, WebP or SVG images")?;
+    if format != logos::ImageFormat::Svg {
+        return Ok((format, data));
+    }
+    let text = String::from_utf8(data).map_err(|_| "SVG logos must be UTF-8".to_string())?;
+    Ok((format, logos::sanitize_svg(&text)?.into_bytes()))
+}
 
-        // Find the invoice in any date directory
-        match self.invoices.get(&id) {
-            Some(summary) => {
-                let date = &summary.date;
-                let invoice_dir = if let Some(name) = &summary.name {
-                    name.clone()
-                } else {
-                    summary.number.clone()
-                };
+// An uploaded logo as an inline image, or nothing if it can't be read. Its format
+// comes from its contents, since logos from before SVG and WebP were all logo.png.
+fn logo_img(path: &str, alt: &str, style: &str, background: &logos::LogoBackground) -> String {
+    let Ok(data) = load_blob(path) else {
+        return String::new();
+    };
+    let Ok((format, data)) = logo_upload(data) else {
+        return String::new();
+    };
+    format!(
+        r#"<img src="data:{};base64,{}" alt="{}" style="{}{}" />"#,
+        format.mime_type(), general_purpose::STANDARD.encode(&data), alt, style, background.css()
+    )
+}
 
-                let invoice_path = format!("{}/{}/{}/invoice.json", drive_path, date, invoice_dir);
-                match open_file(&invoice_path, false, Some(5)) {
-                    Ok(file) => {
-                        match file.read_to_string() {
-                            Ok(data) => {
-                                let invoice: Invoice = serde_json::from_str(&data)
-                                    .map_err(|e| format!("Failed to parse invoice: {}", e))?;
-                                self.current_invoice = Some(invoice.clone());
-                                serde_json::to_string(&invoice)
-                                    .map_err(|e| format!("Failed to serialize invoice: {}", e))
-                            }
-                            Err(e) => Err(format!("Failed to read invoice: {}", e)),
-                        }
-                    }
-                    Err(e) => Err(format!("Invoice not found: {}", e)),
-                }
-            }
-            None => Err("Invoice not found".to_string()),
-        }
+// Where an invoice is stored: under its date, in a directory named after it, or
+// after its number when it has no name
+fn invoice_location(invoice: &Invoice) -> store::Location {
+    store::Location {
+        id: invoice.id.clone(),
+        date: invoice.date.clone(),
+        dir: invoice.name.clone().filter(|n| !n.is_empty()).unwrap_or_else(|| invoice.number.clone()),
     }
+}
 
-    #[http]
-    async fn update_invoice(&mut self, request_body: String) -> Result<String, String> {
-        let updates: Invoice = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid invoice data: {}", e))?;
+fn summary_location(summary: &InvoiceSummary) -> store::Location {
+    store::Location {
+        id: summary.id.clone(),
+        date: summary.date.clone(),
+        dir: summary.name.clone().filter(|n| !n.is_empty()).unwrap_or_else(|| summary.number.clone()),
+    }
+}
 
-        // Update timestamp
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+fn vfs_store() -> store::VfsStore {
+    store::VfsStore {
+        drive_path: format!("/{}/invoice", host::address().package_id()),
+        seal: storage::seal,
+        open: storage::open,
+    }
+}
 
-        // Push current state to undo stack if there is one
-        if let Some(ref current) = self.current_invoice {
-            if current.id == updates.id {
-                let snapshot = InvoiceSnapshot {
-                    invoice: current.clone(),
-                    timestamp: current.updated_at,
-                };
-                self.undo_stack.push(snapshot);
+fn sqlite_store() -> Result<store::SqliteStore, String> {
+    store::SqliteStore::open(storage::seal, storage::open)
+}
 
-                // Limit undo stack size
-                if self.undo_stack.len() > 50 {
-                    self.undo_stack.remove(0);
-                }
+// Runs f against the store everything is kept in: the installed one, else the VFS.
+// Endpoints only reach storage through here, so any backend can be swapped in.
+fn with_store<T>(f: impl FnOnce(&dyn store::InvoiceStore) -> T) -> T {
+    store::with(|| Box::new(vfs_store()), f)
+}
 
-                // Clear redo stack on new change
-                self.redo_stack.clear();
-            }
-        }
+// Starts tracing an incoming request; endpoints hold this until they return
+fn trace(operation: &str) -> diagnostics::Trace {
+    diagnostics::trace(operation, host::now_millis())
+}
 
-        // Update invoice
-        let mut updated_invoice = updates;
-        updated_invoice.updated_at = timestamp;
+// Write an invoice to the store
+fn save_invoice(invoice: &Invoice) -> Result<(), String> {
+    let data = serde_json::to_value(invoice)
+        .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+    with_store(|store| store.save(&invoice_location(invoice), &data))
+        .inspect_err(|e| log!(Error, "store", "save_invoice", Some(invoice.id.as_str()), "{}", e))
+        .map_err(diagnostics::tagged)
+}
 
-        self.current_invoice = Some(updated_invoice.clone());
-        self.has_unsaved_changes = true;
+fn save_settings(settings: &InvoiceSettings) -> Result<(), String> {
+    let data = serde_json::to_value(settings)
+        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+    with_store(|store| store.save_settings(&data))
+        .inspect_err(|e| log!(Error, "store", "save_settings", None, "{}", e))
+        .map_err(diagnostics::tagged)
+}
 
-        // Update summary
-        let summary = InvoiceSummary {
-            id: updated_invoice.id.clone(),
-            number: updated_invoice.number.clone(),
-            name: updated_invoice.name.clone(),
-            date: updated_invoice.date.clone(),
-            total: calculate_invoice_total(&updated_invoice),
-            status: updated_invoice.status.clone(),
-        };
-        self.invoices.insert(updated_invoice.id.clone(), summary);
+// Receipts, signatures, logos and other files, kept through the store like invoices
+fn save_blob(path: &str, data: &[u8]) -> Result<(), String> {
+    with_store(|store| store.save_blob(path, data))
+}
 
-        // Auto-save after 1 second
-        self.last_save_time = timestamp;
-        self.save_current_invoice()?;
+fn load_blob(path: &str) -> Result<Vec<u8>, String> {
+    with_store(|store| store.load_blob(path))
+}
 
-        serde_json::to_string(&updated_invoice)
-            .map_err(|e| format!("Failed to serialize invoice: {}", e))
+fn remove_blob(path: &str) -> Result<(), String> {
+    with_store(|store| store.remove_blob(path))
+}
+
+// Helper methods implementation
+impl AppState {
+    // Helper method to load invoice summaries
+    // Invoices that can't be read are quarantined and recovered where possible
+    fn load_invoice_summaries(&mut self) {
+        let stored = match with_store(|store| store.load_all()) {
+            Ok(stored) => stored,
+            Err(e) => {
+                log!(Error, "store", "init", None, "Failed to load invoices: {}", e);
+                Vec::new()
+            }
+        };
+        let mut unreadable = Vec::new();
+        for stored in stored {
+            let invoice = stored.contents.and_then(|data| serde_json::from_value::<Invoice>(data)
+                .map_err(|e| format!("Failed to parse invoice: {}", e)));
+            match invoice {
+                Ok(invoice) => self.load_summary(&invoice),
+                Err(e) => unreadable.push((stored.key, e)),
+            }
+        }
+        for (key, error) in unreadable {
+            self.recover_invoice(&key, error);
+        }
     }
 
-    #[http]
-    async fn delete_invoice(&mut self, request_body: String) -> Result<String, String> {
-        let id: String = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid invoice ID: {}", e))?;
+    // Lists an invoice and indexes it; every summary is stored through here so the
+    // list and the index never disagree
+    fn load_summary(&mut self, invoice: &Invoice) {
+        self.summary_log.insert(&mut self.invoices, invoice.id.clone(), summary(invoice));
+        self.invoice_index.insert(&invoice.id, index_keys(invoice));
+    }
 
-        // Remove from summaries
-        if let Some(summary) = self.invoices.remove(&id) {
-            // Delete from VFS
-            let package_id = our().package_id();
-            let drive_path = format!("/{}/invoice", package_id);
-            let invoice_dir = if let Some(name) = &summary.name {
-                name.clone()
-            } else {
-                summary.number.clone()
-            };
+    // load_summary for the open invoice, after an edit made through current_invoice
+    fn load_current_summary(&mut self) {
+        if let Some(invoice) = self.current_invoice.take() {
+            self.load_summary(&invoice);
+            self.current_invoice = Some(invoice);
+        }
+    }
 
-            let invoice_path = format!("{}/{}/{}/invoice.json", drive_path, summary.date, invoice_dir);
-            let _ = remove_file(&invoice_path, Some(5));
+    // Moves an unreadable invoice aside and, when a summary says which invoice it
+    // was, puts back the newest copy still held in memory: the open invoice, or a
+    // snapshot in its undo or redo history. Without one its summary stays listed
+    // from the last run and loading it fails until the quarantined copy is repaired.
+    fn recover_invoice(&mut self, key: &str, error: String) {
+        let invoice_id = self.invoices.values()
+            .find(|summary| with_store(|store| store.key(&summary_location(summary))) == key)
+            .map(|summary| summary.id.clone());
+        let moved_to = match with_store(|store| store.quarantine(key)) {
+            Ok(moved_to) => Some(moved_to),
+            Err(e) => {
+                log!(Error, "store", "init", invoice_id.as_deref(), "Could not quarantine {}: {}", key, e);
+                None
+            }
+        };
 
-            // Clear current invoice if it's the deleted one
-            if let Some(ref current) = self.current_invoice {
-                if current.id == id {
-                    self.current_invoice = None;
+        let copy = invoice_id.as_ref().and_then(|id| {
+            let open = self.current_invoice.clone()
+                .filter(|invoice| &invoice.id == id)
+                .map(|invoice| (invoice, "open invoice"));
+            let history = self.undo_stack.iter().chain(&self.redo_stack)
+                .filter(|snapshot| &snapshot.invoice.id == id)
+                .max_by_key(|snapshot| snapshot.invoice.updated_at)
+                .map(|snapshot| (snapshot.invoice.clone(), "undo history"));
+            open.into_iter().chain(history).max_by_key(|(invoice, _)| invoice.updated_at)
+        });
+        // A copy is only written over a file that was moved aside first
+        let recovered_from = match copy {
+            Some((invoice, source)) if moved_to.is_some() => match save_invoice(&invoice) {
+                Ok(()) => {
+                    self.load_summary(&invoice);
+                    Some(source.to_string())
                 }
-            }
+                Err(_) => None, // Logged by save_invoice
+            },
+            _ => None,
+        };
 
-            Ok("Invoice deleted".to_string())
-        } else {
-            Err("Invoice not found".to_string())
+        match recovered_from {
+            Some(ref source) => log!(Warn, "store", "init", invoice_id.as_deref(), "{} was unreadable ({}); restored from the {}", key, error, source),
+            None => log!(Error, "store", "init", invoice_id.as_deref(), "{} is unreadable: {}", key, error),
         }
+        self.quarantined.push(QuarantinedInvoice {
+            key: key.to_string(),
+            moved_to,
+            error,
+            at: host::now(),
+            invoice_id,
+            recovered_from,
+        });
     }
 
-    // Line Item Operations
+    // Helper method to build a new draft invoice, consuming the next invoice
+    // number of the given business profile (or of the default profile in settings)
+    // A manual number is used as given; callers check it isn't taken
+    // A draft dated by the given timestamp, which callers also use for anything else
+    // the same operation stamps
+    fn new_invoice(&mut self, profile_id: Option<&str>, manual_number: Option<String>, timestamp: u64) -> Result<Invoice, String> {
+
+        let profile = match profile_id {
+            Some(profile_id) => Some(
+                self.settings.as_ref()
+                    .and_then(|s| s.profiles.iter().find(|p| p.id == profile_id))
+                    .cloned()
+                    .ok_or("Business profile not found")?
+            ),
+            None => None,
+        };
 
-    #[http]
-    async fn add_line_item(&mut self) -> Result<String, String> {
-        if let Some(ref mut invoice) = self.current_invoice {
-            // Save current state for undo
-            let snapshot = InvoiceSnapshot {
-                invoice: invoice.clone(),
-                timestamp: invoice.updated_at,
+        // Generate invoice number, skipping any already taken by a manual number
+        let used_numbers = self.used_numbers();
+        let invoice_number = if let Some(number) = manual_number {
+            number
+        } else if let Some(ref mut settings) = self.settings {
+            let number = match profile_id.and_then(|id| settings.profiles.iter_mut().find(|p| p.id == id)) {
+                Some(profile) => loop {
+                    let number = format!("{}{:04}", profile.invoice_number_prefix, profile.next_invoice_number);
+                    profile.next_invoice_number += 1;
+                    if !used_numbers.contains(&number.to_lowercase()) {
+                        break number;
+                    }
+                },
+                None => loop {
+                    let number = format!("{}{:04}", settings.invoice_number_prefix, settings.next_invoice_number);
+                    settings.next_invoice_number += 1;
+                    if !used_numbers.contains(&number.to_lowercase()) {
+                        break number;
+                    }
+                },
             };
-            self.undo_stack.push(snapshot);
-            if self.undo_stack.len() > 50 {
-                self.undo_stack.remove(0);
-            }
-            self.redo_stack.clear();
 
-            // Create new line item
-            let id = format!("item-{}", std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis());
-            let new_item = LineItem {
-                id,
-                description: String::new(),
-                quantity: 1.0,
-                rate: 0.0,
-                discount_percent: 0.0,
-                receipt_path: None,
-            };
+            // Save the advanced counter
+            let _ = save_settings(settings);
 
-            invoice.line_items.push(new_item);
-            invoice.updated_at = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+            number
+        } else {
+            format!("INV-{:04}", self.invoices.len() + 1)
+        };
 
-            self.has_unsaved_changes = true;
+        // Generate unique ID
+        let id = format!("{}-{}", timestamp, invoice_number);
 
-            // Update summary
-            let total = calculate_invoice_total(&invoice.clone());
-            let summary = InvoiceSummary {
-                id: invoice.id.clone(),
-                number: invoice.number.clone(),
-                name: invoice.name.clone(),
-                date: invoice.date.clone(),
-                total,
-                status: invoice.status.clone(),
-            };
-            self.invoices.insert(invoice.id.clone(), summary);
+        // Today in the configured timezone
+        let date = date_from_timestamp(self.local_time(timestamp));
+        let payment_terms = self.default_payment_terms();
+        let due_date = payment_terms.and_then(|terms| self.due_date_from_terms(&date, terms.days()));
 
-            serde_json::to_string(invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("No invoice currently loaded".to_string())
-        }
+        // Create new invoice
+        Ok(Invoice {
+            id: id.clone(),
+            number: invoice_number.clone(),
+            name: None,
+            date: date.clone(),
+            due_date,
+            invoicer: match profile {
+                Some(ref profile) => profile.invoicer.clone(),
+                None => self.settings.as_ref().map(|s| s.invoicer.clone())
+                    .unwrap_or_default(),
+            },
+            invoicee: self.settings.as_ref().map(|s| s.invoicee.clone())
+                .unwrap_or_default(),
+            line_items: vec![],
+            discount_percent: 0.0,
+            tax_percent: self.settings.as_ref().map(|s| s.tax.default_percent).unwrap_or(0.0),
+            notes: None,
+            payment_info: match profile {
+                Some(ref profile) => profile.payment_info.clone(),
+                None => self.settings.as_ref().and_then(|s| s.payment_info.clone()),
+            },
+            payment_image_path: match profile {
+                Some(ref profile) => profile.payment_image_path.clone(),
+                None => self.settings.as_ref().and_then(|s| s.payment_image_path.clone()),
+            },
+            status: InvoiceStatus::Draft,
+            created_at: timestamp,
+            updated_at: timestamp,
+            kind: DocumentKind::Invoice,
+            signature: None,
+            terms: None,
+            terms_acceptances: vec![],
+            lightning_payment: None,
+            crypto_payment: None,
+            service_period_start: None,
+            service_period_end: None,
+            source_documents: vec![],
+            consolidated_into: None,
+            profile_id: profile_id.map(String::from),
+            reissued_from: None,
+            reminders_sent: vec![],
+            approval: None,
+            deposits_applied: vec![],
+            applied_to: vec![],
+            language: None,
+            place_of_supply: None,
+            sales_taxes: vec![],
+            tax_lines: vec![],
+            shipping: None,
+            rounding: self.settings.as_ref().map(|s| s.rounding).unwrap_or_default(),
+            payment: None,
+            early_payment: self.settings.as_ref().and_then(|s| s.early_payment_terms.clone()),
+            payment_terms,
+            project: None,
+            tags: vec![],
+            commission: None,
+            currency: self.home_currency(),
+            exchange_rate: None,
+            purchase_order: None,
+            reference: None,
+            delivery_address: None,
+            custom_fields: HashMap::new(),
+            status_history: vec![],
+            sent_at: None,
+            issued_documents: vec![],
+            terms_text: None,
+            thank_you: None,
+        })
     }
 
-    #[http]
-    async fn update_line_item(&mut self, request_body: String) -> Result<String, String> {
-        #[derive(Deserialize)]
-        struct UpdateLineItemRequest {
-            item_id: String,
-            updates: LineItem,
+    fn custom_field_definitions(&self) -> &[custom_fields::FieldDefinition] {
+        self.settings.as_ref().map(|s| s.custom_fields.as_slice()).unwrap_or_default()
+    }
+
+    // Values on the invoice and each of its lines must match the fields in settings
+    fn check_custom_fields(&self, invoice: &Invoice) -> Result<(), String> {
+        let definitions = self.custom_field_definitions();
+        custom_fields::validate(definitions, custom_fields::Scope::Invoice, &invoice.custom_fields)?;
+        for item in &invoice.line_items {
+            custom_fields::validate(definitions, custom_fields::Scope::LineItem, &item.custom_fields)?;
         }
+        Ok(())
+    }
 
-        let req: UpdateLineItemRequest = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid request: {}", e))?;
+    // Helper method to load any invoice from VFS by ID
+    fn load_invoice(&self, id: &str) -> Result<Invoice, String> {
+        let summary = self.invoices.get(id).ok_or("Invoice not found")?;
+        let data = with_store(|store| store.load(&summary_location(summary)))
+            .inspect_err(|e| log!(Error, "store", "load_invoice", Some(id), "{}", e))
+            .map_err(diagnostics::tagged)?;
+        serde_json::from_value(data)
+            .map_err(|e| format!("Failed to parse invoice: {}", e))
+            .inspect_err(|e| log!(Error, "store", "load_invoice", Some(id), "{}", e))
+            .map_err(diagnostics::tagged)
+    }
 
-        if let Some(ref mut invoice) = self.current_invoice {
-            // Save current state for undo
-            let snapshot = InvoiceSnapshot {
-                invoice: invoice.clone(),
-                timestamp: invoice.updated_at,
+    // Every invoice billed to the client with the given client_key
+    fn client_invoices(&self, client_id: &str) -> Result<Vec<Invoice>, String> {
+        let mut invoices = Vec::new();
+        for id in self.invoice_index.client(client_id) {
+            let invoice = match self.current_invoice {
+                Some(ref current) if current.id == id => current.clone(),
+                _ => self.load_invoice(&id)?,
             };
-            self.undo_stack.push(snapshot);
-            if self.undo_stack.len() > 50 {
-                self.undo_stack.remove(0);
-            }
-            self.redo_stack.clear();
-
-            // Find and update line item
-            if let Some(item) = invoice.line_items.iter_mut().find(|i| i.id == req.item_id) {
-                *item = req.updates;
-            } else {
-                return Err("Line item not found".to_string());
-            }
-
-            invoice.updated_at = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+            invoices.push(invoice);
+        }
+        invoices.sort_by(|a, b| a.date.cmp(&b.date));
+        Ok(invoices)
+    }
 
-            self.has_unsaved_changes = true;
+    // IDs of invoices in any of the statuses, dated within the range (ISO dates,
+    // inclusive) when one is given
+    fn indexed_ids(&self, statuses: &[InvoiceStatus], from: Option<&str>, to: Option<&str>) -> Vec<String> {
+        let statuses: Vec<String> = statuses.iter().map(|s| format!("{:?}", s)).collect();
+        let ids = self.invoice_index.statuses(&statuses);
+        if from.is_none() && to.is_none() {
+            return ids.into_iter().collect();
+        }
+        ids.intersection(&self.invoice_index.dated(from, to)).cloned().collect()
+    }
 
-            // Update summary
-            let total = calculate_invoice_total(&invoice.clone());
-            let summary = InvoiceSummary {
-                id: invoice.id.clone(),
-                number: invoice.number.clone(),
-                name: invoice.name.clone(),
-                date: invoice.date.clone(),
-                total,
-                status: invoice.status.clone(),
+    // Sends an invoice over the channels chosen when it was scheduled, recording
+    // each one tried in the delivery log
+    async fn deliver_invoice(&mut self, invoice: &Invoice, scheduled: &ScheduledSend, mail_process: Option<&str>) -> Result<(), String> {
+        let total = amount_due(invoice);
+        if scheduled.email {
+            let to = invoice.invoicee.email.clone().unwrap_or_default();
+            let result = match mail_process {
+                None => Err("No mail process configured".to_string()),
+                Some(_) if to.is_empty() => Err("Invoicee has no email address".to_string()),
+                Some(process) => {
+                    let due = invoice.due_date.as_ref()
+                        .map(|d| format!(", due {}", d))
+                        .unwrap_or_default();
+                    send_email(
+                        process,
+                        &to,
+                        &format!("Invoice {} from {}", invoice.number, invoice.invoicer.name),
+                        &format!(
+                            "Hi {},\n\nPlease find invoice {} for {}{}.\n\n{}",
+                            invoice.invoicee.name,
+                            invoice.number,
+                            format_money(total, &invoice.currency),
+                            due,
+                            invoice.payment_info.clone().unwrap_or_default()
+                        ),
+                    ).await
+                }
             };
-            self.invoices.insert(invoice.id.clone(), summary);
+            self.log_delivery(&invoice.id, DeliveryChannel::Email, &to, &result, None);
+            result?;
+        }
+        if scheduled.p2p {
+            let result = self.deliver_p2p(invoice, total).await;
+            let node = invoice.invoicee.node.clone().unwrap_or_default();
+            self.log_delivery(&invoice.id, DeliveryChannel::P2p, &node, &result, None);
+            result?;
+        }
+        Ok(())
+    }
 
-            serde_json::to_string(invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("No invoice currently loaded".to_string())
+    async fn deliver_p2p(&self, invoice: &Invoice, total: Money) -> Result<(), String> {
+        let node = invoice.invoicee.node.clone().ok_or("Invoicee has no node")?;
+        let options = ExportOptions {
+            redact_rates: false,
+            hide_internal_fields: true,
+            totals: None,
+            copy_label: None,
+            template_id: None,
+            page_layout: None,
+        };
+        let payload = serde_json::json!({
+            "number": invoice.number,
+            "total": total,
+            "due_date": invoice.due_date,
+            "html": self.generate_invoice_html(invoice, &options),
+        });
+        let body = serde_json::to_vec(&serde_json::json!({ "ReceiveInvoice": payload.to_string() }))
+            .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
+        let target = Address::new(node.clone(), host::address().process);
+        match send::<Result<String, String>>(Request::to(target).body(body).expects_response(30)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(format!("{} rejected the invoice: {}", node, e)),
+            Err(e) => Err(format!("Failed to reach {}: {}", node, e)),
         }
     }
 
-    #[http]
-    async fn delete_line_item(&mut self, request_body: String) -> Result<String, String> {
-        let item_id: String = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid item ID: {}", e))?;
+    // Numbers of every current and deleted invoice, lowercased
+    fn used_numbers(&self) -> std::collections::HashSet<String> {
+        self.invoices.values()
+            .map(|s| s.number.to_lowercase())
+            .chain(self.deleted_numbers.iter().map(|n| n.to_lowercase()))
+            .collect()
+    }
 
-        if let Some(ref mut invoice) = self.current_invoice {
-            // Save current state for undo
-            let snapshot = InvoiceSnapshot {
-                invoice: invoice.clone(),
-                timestamp: invoice.updated_at,
-            };
-            self.undo_stack.push(snapshot);
-            if self.undo_stack.len() > 50 {
-                self.undo_stack.remove(0);
+    fn number_in_use(&self, number: &str) -> bool {
+        self.used_numbers().contains(&number.to_lowercase())
+    }
+
+    // The number the next invoice in a series will get, without using it up
+    fn peek_number(&self, profile_id: Option<&str>) -> Result<String, String> {
+        let settings = self.settings.as_ref().ok_or("Settings not configured")?;
+        let (prefix, mut next) = match profile_id {
+            Some(profile_id) => {
+                let profile = settings.profiles.iter().find(|p| p.id == profile_id)
+                    .ok_or("Business profile not found")?;
+                (&profile.invoice_number_prefix, profile.next_invoice_number)
             }
-            self.redo_stack.clear();
+            None => (&settings.invoice_number_prefix, settings.next_invoice_number),
+        };
+        let used_numbers = self.used_numbers();
+        loop {
+            let number = format!("{}{:04}", prefix, next);
+            if !used_numbers.contains(&number.to_lowercase()) {
+                return Ok(number);
+            }
+            next += 1;
+        }
+    }
 
-            // Remove line item
-            invoice.line_items.retain(|item| item.id != item_id);
+    // Last day the early payment discount can be taken, and the discount it gives
+    // off the balance due
+    fn early_payment_offer(&self, invoice: &Invoice) -> Option<(String, Money)> {
+        let terms = invoice.early_payment.as_ref()?;
+        let pay_by = self.due_date_from_terms(&invoice.date, terms.days)?;
+        Some((pay_by, amount_due(invoice).percent(terms.percent, currency_decimals(invoice))))
+    }
 
-            invoice.updated_at = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+    fn default_payment_terms(&self) -> Option<PaymentTerms> {
+        let settings = self.settings.as_ref()?;
+        settings.payment_terms.or(settings.payment_terms_days.map(PaymentTerms::Net))
+    }
 
-            self.has_unsaved_changes = true;
+    fn due_date_from_terms(&self, date: &str, payment_terms_days: u32) -> Option<String> {
+        let mut due = days_from_date(date)? + payment_terms_days as i64;
+        if let Some(settings) = self.settings.as_ref().filter(|s| s.roll_due_dates) {
+            while !is_business_day(due, &settings.holidays) {
+                due += 1;
+            }
+        }
+        Some(date_from_timestamp((due * 86400) as u64))
+    }
 
-            // Update summary
-            let total = calculate_invoice_total(&invoice.clone());
-            let summary = InvoiceSummary {
-                id: invoice.id.clone(),
-                number: invoice.number.clone(),
-                name: invoice.name.clone(),
-                date: invoice.date.clone(),
-                total,
-                status: invoice.status.clone(),
-            };
-            self.invoices.insert(invoice.id.clone(), summary);
+    // Offset of local time at a UTC timestamp, with summer time if it is in effect
+    fn utc_offset_minutes(&self, timestamp: u64) -> i32 {
+        let Some(settings) = self.settings.as_ref() else {
+            return 0;
+        };
+        let summer = settings.daylight_saving
+            .map(|rule| rule.in_effect(timestamp, settings.utc_offset_minutes))
+            .unwrap_or(false);
+        settings.utc_offset_minutes + if summer { 60 } else { 0 }
+    }
 
-            serde_json::to_string(invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("No invoice currently loaded".to_string())
+    // Currency new invoices default to and reports are converted to
+    fn home_currency(&self) -> String {
+        self.settings.as_ref().map(|s| s.default_currency.clone()).unwrap_or_else(default_currency)
+    }
+
+    // Takes the current rate onto a foreign-currency invoice being sent. Invoices
+    // in the home currency, or in one without a rate, get none.
+    fn take_exchange_rate(&self, invoice: &mut Invoice, timestamp: u64) {
+        let home_currency = self.home_currency();
+        if invoice.currency == home_currency {
+            invoice.exchange_rate = None;
+            return;
         }
+        invoice.exchange_rate = self.exchange_rates.iter()
+            .find(|r| r.currency == invoice.currency && r.home_currency == home_currency)
+            .map(|r| ExchangeRateSnapshot {
+                home_currency: home_currency.clone(),
+                rate: r.rate,
+                home_total: convert(calculate_invoice_total(invoice), r.rate, &home_currency),
+                taken_at: timestamp,
+            });
     }
 
-    #[http]
-    async fn reorder_line_items(&mut self, request_body: String) -> Result<String, String> {
-        let item_ids: Vec<String> = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid item IDs: {}", e))?;
+    // Shifts a UTC timestamp to the configured timezone, for calendar dates and hours
+    fn local_time(&self, timestamp: u64) -> u64 {
+        (timestamp as i64 + self.utc_offset_minutes(timestamp) as i64 * 60).max(0) as u64
+    }
 
-        if let Some(ref mut invoice) = self.current_invoice {
-            // Save current state for undo
-            let snapshot = InvoiceSnapshot {
-                invoice: invoice.clone(),
-                timestamp: invoice.updated_at,
-            };
-            self.undo_stack.push(snapshot);
-            if self.undo_stack.len() > 50 {
-                self.undo_stack.remove(0);
+    fn check_approver(&mut self, node: &str, endpoint: &str, now: u64) -> Result<(), String> {
+        self.access_guard.admit(node, endpoint, now)?;
+        let allowed = self.settings.as_ref()
+            .map(|s| s.approvers.iter().any(|a| a == node))
+            .unwrap_or(false);
+        if !allowed {
+            self.access_guard.deny(node, endpoint, now, "not an approver");
+            return Err(format!("{} is not an approver", node));
+        }
+        self.access_guard.allow(node, endpoint, now);
+        Ok(())
+    }
+
+    // The link a request was made through, once its passcode and email code check
+    // out. The code is left in place; whatever the request goes on to do decides
+    // whether to spend it.
+    fn admit_share_link(&mut self, node: &str, endpoint: &str, access: &ShareAccess, now: u64) -> Result<ShareLink, String> {
+        self.access_guard.admit_link(node, endpoint, &access.token, now)?;
+        let link = match self.share_links.get(&access.token) {
+            Some(link) if share_link_active(link, now) => link.clone(),
+            _ => {
+                self.access_guard.deny(node, endpoint, now, "unknown or expired link");
+                return Err("Share link not found".to_string());
             }
-            self.redo_stack.clear();
+        };
 
-            // Reorder line items
-            let mut new_items = Vec::new();
-            for id in item_ids {
-                if let Some(item) = invoice.line_items.iter().find(|i| i.id == id) {
-                    new_items.push(item.clone());
+        if let Some(ref passcode_hash) = link.passcode_hash {
+            let matches = access.passcode.as_ref()
+                .map(|p| &share_secret_hash(&link.token, p) == passcode_hash)
+                .unwrap_or(false);
+            if !matches {
+                if self.access_guard.deny_link(node, endpoint, &link.token, now, "wrong passcode") {
+                    self.share_codes.remove(&link.token);
+                }
+                return Err("Incorrect passcode".to_string());
+            }
+        }
+        if link.allowed_email.is_some() {
+            let verified = match (self.share_codes.get(&link.token), access.code.as_ref()) {
+                (Some(pending), Some(code)) => {
+                    pending.expires_at > now && share_secret_hash(&link.token, code) == pending.code_hash
                 }
+                _ => false,
+            };
+            if !verified {
+                // A code is thrown away after a few wrong guesses, from whichever nodes
+                if let (Some(pending), Some(_)) = (self.share_codes.get_mut(&link.token), access.code.as_ref()) {
+                    pending.misses += 1;
+                    if pending.misses >= access::MAX_CODE_GUESSES {
+                        self.share_codes.remove(&link.token);
+                    }
+                }
+                if self.access_guard.deny_link(node, endpoint, &link.token, now, "missing or wrong email code") {
+                    self.share_codes.remove(&link.token);
+                }
+                return Err("A valid code sent to the link's email address is required".to_string());
             }
-            invoice.line_items = new_items;
+        }
+        self.access_guard.allow(node, endpoint, now);
+        Ok(link)
+    }
 
-            invoice.updated_at = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+    // Records acceptance of the terms attached to an invoice, by the owner on the
+    // recipient's behalf or by the recipient through a share link
+    fn record_terms_acceptance(
+        &mut self,
+        invoice_id: &str,
+        accepted_by: String,
+        email: Option<String>,
+        via_link_from: Option<String>,
+    ) -> Result<TermsAcceptance, String> {
+        if accepted_by.trim().is_empty() {
+            return Err("Name is required to accept terms".to_string());
+        }
+        let (mut invoice, is_current) = self.invoice_for_update(invoice_id)?;
+        let terms = invoice.terms.clone().ok_or("Invoice has no terms attached")?;
+        let accepted_at = host::now();
+
+        let acceptance = TermsAcceptance {
+            terms_id: terms.terms_id,
+            version: terms.version,
+            accepted_by,
+            email,
+            accepted_at,
+            via_link_from,
+        };
+        invoice.terms_acceptances.push(acceptance.clone());
+        invoice.updated_at = accepted_at;
+        self.notify(
+            notifications::NotificationKind::TermsAccepted,
+            format!("Terms accepted on {}", invoice.number),
+            format!("{} accepted version {}", acceptance.accepted_by, acceptance.version),
+            Some(invoice.id.clone()),
+        );
+        self.store_managed_change(invoice, is_current)?;
+        Ok(acceptance)
+    }
 
-            self.has_unsaved_changes = true;
+    // Signs an invoice once its terms are accepted, binding the signer to the
+    // document as it stands
+    fn record_signature(
+        &mut self,
+        invoice_id: &str,
+        signer_name: String,
+        signature_image: &[u8],
+        signer_node: Option<String>,
+        signer_email: Option<String>,
+    ) -> Result<Invoice, String> {
+        if signer_name.trim().is_empty() {
+            return Err("Signer name is required".to_string());
+        }
+        if signature_image.is_empty() {
+            return Err("Signature image is required".to_string());
+        }
+        let (mut invoice, is_current) = self.invoice_for_update(invoice_id)?;
+        if invoice.signature.is_some() {
+            return Err("Document has already been signed".to_string());
+        }
+        if !terms_accepted(&invoice) {
+            return Err("Terms must be accepted before signing".to_string());
+        }
 
-            serde_json::to_string(invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("No invoice currently loaded".to_string())
+        let signed_at = host::now();
+
+        // Save the signature image next to the invoice
+        let image_path = format!("{}/signature.png", invoice_dir_path(&invoice));
+        save_blob(&image_path, signature_image)
+            .map_err(|e| format!("Failed to write signature: {}", e))?;
+
+        let document_hash = document_hash(&invoice);
+        let mut hasher = Sha256::new();
+        hasher.update(document_hash.as_bytes());
+        hasher.update(signer_name.as_bytes());
+        hasher.update(signed_at.to_be_bytes());
+        hasher.update(signature_image);
+        for identity in [&signer_node, &signer_email].into_iter().flatten() {
+            hasher.update(identity.as_bytes());
         }
+        let signature_hash = hex::encode(hasher.finalize());
+
+        self.notify(
+            notifications::NotificationKind::DocumentSigned,
+            format!("{} {} signed", invoice.kind.label(), invoice.number),
+            match signer_node {
+                Some(ref node) => format!("Signed by {} from {}", signer_name, node),
+                None => format!("Signed by {}", signer_name),
+            },
+            Some(invoice.id.clone()),
+        );
+        invoice.signature = Some(Signature {
+            signer_name,
+            signed_at,
+            image_path,
+            document_hash,
+            signature_hash,
+            signer_node,
+            signer_email,
+        });
+        invoice.updated_at = signed_at;
+
+        // Undoing past a signature would silently drop it
+        self.store_managed_change(invoice.clone(), is_current)?;
+        Ok(invoice)
     }
 
-    // Receipt Upload
+    // The configured jurisdictions with these IDs, as applied to an invoice
+    fn applied_taxes(&self, ids: &[String]) -> Result<Vec<tax_regimes::AppliedTax>, String> {
+        let jurisdictions = self.settings.as_ref()
+            .map(|s| s.tax.jurisdictions.as_slice())
+            .unwrap_or(&[]);
+        ids.iter()
+            .map(|id| jurisdictions.iter()
+                .find(|j| j.id == *id)
+                .map(tax_regimes::AppliedTax::from)
+                .ok_or_else(|| format!("Unknown tax jurisdiction: {}", id)))
+            .collect()
+    }
 
-    #[http]
-    async fn upload_receipt(&mut self, request_body: Vec<u8>) -> Result<String, String> {
-        #[derive(Deserialize)]
-        struct ReceiptUploadRequest {
-            item_id: String,
-            file_name: String,
-            file_data: Vec<u8>,
+    // An invoice to change, and whether it is the one currently loaded
+    fn invoice_for_update(&self, id: &str) -> Result<(Invoice, bool), String> {
+        match self.current_invoice {
+            Some(ref current) if current.id == id => Ok((current.clone(), true)),
+            _ => Ok((self.load_invoice(id)?, false)),
         }
+    }
 
-        let request: ReceiptUploadRequest = serde_json::from_slice(&request_body)
-            .map_err(|e| format!("Invalid request: {}", e))?;
-
-        if let Some(ref mut invoice) = self.current_invoice {
-            // Find the line item
-            let item_index = invoice.line_items.iter().position(|item| item.id == request.item_id)
-                .ok_or("Line item not found")?;
+    // Saves an invoice whose managed fields (approval, deposits) changed and refreshes its summary
+    fn store_managed_change(&mut self, invoice: Invoice, is_current: bool) -> Result<(), String> {
+        self.load_summary(&invoice);
 
-            // Save current state for undo
-            let snapshot = InvoiceSnapshot {
-                invoice: invoice.clone(),
-                timestamp: invoice.updated_at,
-            };
-            self.undo_stack.push(snapshot);
-            if self.undo_stack.len() > 50 {
-                self.undo_stack.remove(0);
-            }
+        if is_current {
+            // Undo must not step back across an approval decision or deposit link
+            self.undo_stack.clear();
             self.redo_stack.clear();
+            self.current_invoice = Some(invoice);
+            self.has_unsaved_changes = true;
+            self.save_current_invoice()
+        } else {
+            save_invoice(&invoice)
+        }
+    }
 
-            // Save receipt file to VFS
-            let package_id = our().package_id();
-            let drive_path = format!("/{}/invoice", package_id);
-
-            // Create receipts directory for this invoice
-            let invoice_dir = if let Some(ref name) = invoice.name {
-                name.clone()
-            } else {
-                invoice.number.clone()
-            };
-
-            let receipts_dir = format!("{}/{}/{}/receipts", drive_path, invoice.date, invoice_dir);
-            let _ = open_dir(&receipts_dir, true, Some(5));
-
-            // Save the receipt file
-            let receipt_path = format!("{}/{}", receipts_dir, request.file_name);
-            match create_file(&receipt_path, Some(5)) {
-                Ok(file) => {
-                    file.write(&request.file_data)
-                        .map_err(|e| format!("Failed to write receipt: {}", e))?;
-
-                    // Update the line item with the receipt path
-                    invoice.line_items[item_index].receipt_path = Some(receipt_path.clone());
-
-                    // If the line item description is empty or default, use the filename without extension
-                    if invoice.line_items[item_index].description.is_empty() ||
-                       invoice.line_items[item_index].description == "Click to add description" {
-                        let file_stem = request.file_name
-                            .rsplit('.')
-                            .skip(1)
-                            .collect::<Vec<_>>()
-                            .into_iter()
-                            .rev()
-                            .collect::<Vec<_>>()
-                            .join(".");
-                        let file_stem = if file_stem.is_empty() {
-                            request.file_name.clone()
-                        } else {
-                            file_stem
-                        };
-                        invoice.line_items[item_index].description = file_stem;
-                    }
-
-                    invoice.updated_at = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
+    fn audit(&mut self, timestamp: u64, actor: &str, action: AuditAction, invoice: &Invoice, detail: Option<String>) {
+        self.audit_log.push(AuditEntry {
+            timestamp,
+            actor: actor.to_string(),
+            action,
+            invoice_id: invoice.id.clone(),
+            invoice_number: invoice.number.clone(),
+            detail,
+        });
+    }
 
-                    self.has_unsaved_changes = true;
-                    self.save_current_invoice()?;
+    fn log_delivery(&mut self, invoice_id: &str, channel: DeliveryChannel, destination: &str, result: &Result<(), String>, detail: Option<String>) {
+        self.delivery_log.push(DeliveryRecord {
+            at: host::now(),
+            invoice_id: invoice_id.to_string(),
+            channel,
+            destination: destination.to_string(),
+            error: result.as_ref().err().cloned(),
+            detail,
+            request_id: diagnostics::request_id(),
+        });
+    }
 
-                    // Return the path
-                    Ok(receipt_path)
-                }
-                Err(e) => Err(format!("Failed to create receipt file: {}", e)),
-            }
-        } else {
-            Err("No invoice currently loaded".to_string())
+    // Checks a move from one status to the invoice's new one and, when it has
+    // moved, records it, issues the document if it is now Sent and notifies.
+    // Both set_invoice_status and update_invoice change status through here.
+    fn apply_status_change(
+        &mut self,
+        invoice: &mut Invoice,
+        from: InvoiceStatus,
+        metadata: HashMap<String, String>,
+        timestamp: u64,
+    ) -> Result<(), String> {
+        check_transition(&from, &invoice.status)?;
+        check_status_change(&from, invoice, self.settings.as_ref())?;
+        if invoice.status == from {
+            return Ok(());
         }
+        let detail = metadata.get("note").cloned().or_else(|| {
+            invoice.approval.as_ref()
+                .and_then(|a| a.decided_by.as_ref())
+                .map(|approver| format!("Approved by {}", approver))
+        });
+        self.record_status_change(invoice, from.clone(), metadata, timestamp);
+        if invoice.status == InvoiceStatus::Sent {
+            self.issue_document(invoice, timestamp)?;
+            self.audit(timestamp, &host::address().node, AuditAction::Sent, invoice, detail);
+        }
+        self.notify(
+            notifications::NotificationKind::StatusChanged,
+            format!("Invoice {} is {:?}", invoice.number, invoice.status),
+            format!("Changed from {:?}", from),
+            Some(invoice.id.clone()),
+        );
+        Ok(())
     }
 
-    #[http]
-    async fn get_receipt(&self, request_body: String) -> Result<Vec<u8>, String> {
-        let receipt_path: String = serde_json::from_str(&request_body)
-            .map_err(|e| format!("Invalid receipt path: {}", e))?;
-
-        match open_file(&receipt_path, false, Some(5)) {
-            Ok(file) => {
-                file.read()
-                    .map_err(|e| format!("Failed to read receipt: {}", e))
-            }
-            Err(e) => Err(format!("Receipt not found: {}", e)),
+    // Bookkeeping for an invoice whose status just changed from `from`: the history
+    // entry, and for Sent the sent timestamp and the exchange rate it was issued at
+    fn record_status_change(&self, invoice: &mut Invoice, from: InvoiceStatus, metadata: HashMap<String, String>, timestamp: u64) {
+        if invoice.status == InvoiceStatus::Sent && from != InvoiceStatus::Sent {
+            self.take_exchange_rate(invoice, timestamp);
+            invoice.sent_at.get_or_insert(timestamp);
         }
+        invoice.status_history.push(StatusChange {
+            from,
+            to: invoice.status.clone(),
+            at: timestamp,
+            by: host::address().node,
+            metadata,
+        });
     }
 
-    // Undo/Redo Operations
+    // Freezes the document the client is sent, as rendered now, before the invoice
+    // is marked Sent. Fails rather than send something that couldn't be kept.
+    fn issue_document(&self, invoice: &mut Invoice, timestamp: u64) -> Result<(), String> {
+        let options = ExportOptions { hide_internal_fields: true, ..ExportOptions::default() };
+        let html = self.generate_invoice_html(invoice, &options);
+        let document = IssuedDocument {
+            invoice_id: invoice.id.clone(),
+            number: invoice.number.clone(),
+            sequence: invoice.issued_documents.len(),
+            issued_at: timestamp,
+            sha256: hex::encode(Sha256::digest(html.as_bytes())),
+            html,
+        };
+        let data = serde_json::to_value(&document)
+            .map_err(|e| format!("Failed to serialize issued document: {}", e))?;
+        with_store(|store| store.save_issued(&invoice.id, document.sequence, &data))
+            .inspect_err(|e| log!(Error, "store", "issue_document", Some(invoice.id.as_str()), "{}", e))
+            .map_err(diagnostics::tagged)?;
+        invoice.issued_documents.push(IssuedRecord { issued_at: timestamp, sha256: document.sha256 });
+        Ok(())
+    }
 
-    #[http]
-    async fn undo(&mut self) -> Result<String, String> {
-        if let Some(snapshot) = self.undo_stack.pop() {
-            // Save current state to redo stack
-            if let Some(ref current) = self.current_invoice {
-                let redo_snapshot = InvoiceSnapshot {
-                    invoice: current.clone(),
-                    timestamp: current.updated_at,
-                };
-                self.redo_stack.push(redo_snapshot);
-            }
+    // Queues a notification for check_notifications unless its kind is turned off
+    fn notify(&mut self, kind: notifications::NotificationKind, title: String, body: String, invoice_id: Option<String>) {
+        if !self.notification_preferences.wants(kind) {
+            return;
+        }
+        let timestamp = host::now();
+        self.notifications.push(notifications::Notification {
+            id: format!("notification-{}", access::random_token()),
+            kind,
+            title,
+            body,
+            invoice_id,
+            created_at: timestamp,
+            delivered_at: None,
+        });
+    }
 
-            // Restore from undo stack
-            self.current_invoice = Some(snapshot.invoice.clone());
-            self.has_unsaved_changes = true;
+    // Helper method to save current invoice
+    fn save_current_invoice(&mut self) -> Result<(), String> {
+        if let Some(ref invoice) = self.current_invoice {
+            save_invoice(invoice)?;
+            self.has_unsaved_changes = false;
+            self.draft_buffers.saved(&invoice.id);
+        }
+        Ok(())
+    }
 
-            // Update summary
-            let summary = InvoiceSummary {
-                id: snapshot.invoice.id.clone(),
-                number: snapshot.invoice.number.clone(),
-                name: snapshot.invoice.name.clone(),
-                date: snapshot.invoice.date.clone(),
-                total: calculate_invoice_total(&snapshot.invoice),
-                status: snapshot.invoice.status.clone(),
-            };
-            self.invoices.insert(snapshot.invoice.id.clone(), summary);
+    // Values a custom template is rendered with, after the export's filters
+    fn template_context(&self, invoice: &Invoice, options: &ExportOptions) -> templates::RenderContext {
+        let invoice = &apply_export_filters(invoice, options);
+        let tax_settings = self.settings.as_ref().map(|s| s.tax.clone()).unwrap_or_default();
+        let language = invoice.language
+            .or_else(|| self.settings.as_ref().map(|s| s.language))
+            .unwrap_or_default();
+
+        let field_definitions = self.custom_field_definitions();
+        let fields = |scope, values| custom_fields::displayed(field_definitions, scope, values).into_iter()
+            .map(|(name, value)| templates::FieldContext { name: name.to_string(), value })
+            .collect::<Vec<_>>();
+        // Exports without amounts leave every price, total and payment offer out
+        let shown = |amount: Money| (!options.redact_rates).then(|| amount.to_f64());
+        let line_items: Vec<templates::LineItemContext> = invoice.line_items.iter()
+            .zip(line_amounts(invoice))
+            .map(|(item, amount)| {
+                templates::LineItemContext {
+                    text_only: item.kind == LineItemKind::Text,
+                    late_fee: item.kind == LineItemKind::LateFee,
+                    section: item.section.clone(),
+                    description: item.description.clone(),
+                    item_code: item.item_code.clone(),
+                    hsn_sac: item.hsn_sac.clone(),
+                    service_date: Some(format_period(&item.service_date_start, &item.service_date_end))
+                        .filter(|period| !period.is_empty()),
+                    quantity: item.quantity,
+                    unit: item.unit.clone(),
+                    rate: Some(item.rate).filter(|_| !options.redact_rates),
+                    list_rate: item.list_rate.filter(|list| *list > item.rate && !options.redact_rates),
+                    discount_percent: item.discount_percent,
+                    amount: shown(amount),
+                    custom_fields: fields(custom_fields::Scope::LineItem, &item.custom_fields),
+                }
+            })
+            .collect();
+        let subtotal = subtotal(invoice);
+        let discount = subtotal - taxable_amount(invoice);
+        let balance_due = amount_due(invoice);
+        let offers_payment = invoice.status != InvoiceStatus::Paid && balance_due > Money::ZERO && !options.redact_rates;
+        let taxes = tax_components(invoice, &tax_settings);
+        let tax_amounts = tax_amounts(invoice, &taxes);
+
+        let party = |contact: &ContactInfo| templates::PartyContext {
+            name: contact.name.clone(),
+            company: contact.company.clone(),
+            address: contact.address.clone(),
+            email: contact.email.clone(),
+            phone: contact.phone.clone(),
+            tax_id: contact.tax_id.clone(),
+            tax_id_label: tax_settings.tax_id_label.clone(),
+        };
+        let title = match tax_settings.document_title {
+            Some(ref title) if invoice.kind == DocumentKind::Invoice => title.clone(),
+            _ => invoice.kind.label().to_string(),
+        };
 
-            serde_json::to_string(&snapshot.invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("Nothing to undo".to_string())
+        templates::RenderContext {
+            document: templates::DocumentContext {
+                title,
+                number: invoice.number.clone(),
+                date: invoice.date.clone(),
+                due_date: invoice.due_date.clone(),
+                payment_terms: terms_label(invoice),
+                service_period: Some(format_period(&invoice.service_period_start, &invoice.service_period_end))
+                    .filter(|period| !period.is_empty()),
+                status: format!("{:?}", invoice.status),
+                copy_label: options.copy_label.clone(),
+                place_of_supply: place_of_supply(invoice).cloned(),
+                purchase_order: invoice.purchase_order.clone(),
+                reference: invoice.reference.clone(),
+                custom_fields: fields(custom_fields::Scope::Invoice, &invoice.custom_fields),
+            },
+            invoicer: party(&invoice.invoicer),
+            invoicee: party(&invoice.invoicee),
+            delivery_address: invoice.delivery_address.as_ref()
+                .filter(|_| self.settings.as_ref().map(|s| s.delivery_addresses_enabled).unwrap_or(false))
+                .map(|delivery| templates::DeliveryContext {
+                    name: delivery.name.clone(),
+                    company: delivery.company.clone(),
+                    address: delivery.address.clone(),
+                    phone: delivery.phone.clone(),
+                }),
+            line_items,
+            totals: templates::TotalsContext {
+                sections: section_subtotals(invoice).into_iter()
+                    .map(|(name, subtotal)| templates::SectionContext { name, subtotal: shown(subtotal) })
+                    .collect(),
+                subtotal: shown(subtotal),
+                discount_percent: invoice.discount_percent,
+                discount: shown(discount),
+                shipping: invoice.shipping.and_then(|s| shown(s.amount)),
+                taxes: taxes.into_iter()
+                    .zip(tax_amounts)
+                    .map(|((name, percent), amount)| templates::TaxContext {
+                        name,
+                        percent,
+                        amount: shown(amount),
+                    })
+                    .collect(),
+                total: shown(calculate_invoice_total(invoice)),
+                deposits: invoice.deposits_applied.iter()
+                    .map(|d| templates::DepositContext { number: d.number.clone(), amount: shown(d.amount) })
+                    .collect(),
+                balance_due: shown(balance_due),
+                amount_in_words: shown(balance_due)
+                    .map(|due| words::amount_in_words(due, language, &invoice.currency, currency_decimals(invoice))),
+                payment_methods: self.settings.as_ref()
+                    .filter(|_| offers_payment)
+                    .map(|s| s.payment_method_fees.iter()
+                        .map(|fee| templates::PaymentMethodContext {
+                            label: fee.label.clone(),
+                            terms: fee_terms(fee, &invoice.currency),
+                            amount: amount_with_fee(balance_due, fee, currency_decimals(invoice)).to_f64(),
+                        })
+                        .collect())
+                    .unwrap_or_default(),
+                early_payment: invoice.early_payment.as_ref()
+                    .filter(|_| offers_payment)
+                    .zip(self.early_payment_offer(invoice))
+                    .map(|(terms, (pay_by, discount))| templates::EarlyPaymentContext {
+                        terms: early_payment_label(terms, invoice),
+                        pay_by,
+                        discount: discount.to_f64(),
+                        amount: (balance_due - discount).to_f64(),
+                    }),
+            },
+            notes: self.invoice_text(invoice, &invoice.notes, options),
+            terms_text: self.invoice_text(invoice, &invoice.terms_text, options),
+            thank_you: self.invoice_text(invoice, &invoice.thank_you, options),
+            payment_info: invoice.payment_info.clone(),
+            tax_wording: tax_settings.wording.clone().filter(|_| invoice.kind == DocumentKind::Invoice),
+            currency: currency_context(&invoice.currency),
         }
     }
 
-    #[http]
-    async fn redo(&mut self) -> Result<String, String> {
-        if let Some(snapshot) = self.redo_stack.pop() {
-            // Save current state to undo stack
-            if let Some(ref current) = self.current_invoice {
-                let undo_snapshot = InvoiceSnapshot {
-                    invoice: current.clone(),
-                    timestamp: current.updated_at,
-                };
-                self.undo_stack.push(undo_snapshot);
-            }
+    // A custom template renders the document body; the default stylesheet still applies
+    fn render_template(
+        &self,
+        template: &templates::Template,
+        assets: &[TemplateAsset],
+        invoice: &Invoice,
+        options: &ExportOptions,
+    ) -> Result<(String, Vec<templates::TemplateError>), String> {
+        let context = serde_json::to_value(self.template_context(invoice, options))
+            .map_err(|e| format!("Failed to serialize template context: {}", e))?;
+        Ok(self.render_custom(template, assets, &context))
+    }
 
-            // Restore from redo stack
-            self.current_invoice = Some(snapshot.invoice.clone());
-            self.has_unsaved_changes = true;
+    // Full document for a custom template, locked down to embedded resources
+    fn render_custom(
+        &self,
+        template: &templates::Template,
+        assets: &[TemplateAsset],
+        context: &serde_json::Value,
+    ) -> (String, Vec<templates::TemplateError>) {
+        let (body, errors) = template.render(context);
+        let body = templates::embed_assets(&body, &template_asset_uris(assets));
+        // Uploaded fonts are embedded when the template names their family. The
+        // template's own styles come later, so its font-family wins over the fallbacks.
+        let head = self.font_styles(&body, None, |family| body.contains(family));
+        (html_document(&body, &head, TEMPLATE_CONTENT_SECURITY_POLICY), errors)
+    }
 
-            // Update summary
-            let summary = InvoiceSummary {
-                id: snapshot.invoice.id.clone(),
-                number: snapshot.invoice.number.clone(),
-                name: snapshot.invoice.name.clone(),
-                date: snapshot.invoice.date.clone(),
-                total: calculate_invoice_total(&snapshot.invoice),
-                status: snapshot.invoice.status.clone(),
-            };
-            self.invoices.insert(snapshot.invoice.id.clone(), summary);
+    // @font-face rules for the uploaded fonts of matching families
+    fn font_faces(&self, matches: impl Fn(&str) -> bool) -> String {
+        self.fonts.iter()
+            .filter(|font| matches(&font.family))
+            .filter_map(|font| {
+                let data = load_blob(&font.path).ok()?;
+                let uri = format!("data:{};base64,{}", font.content_type, general_purpose::STANDARD.encode(&data));
+                Some(fonts::font_face(font, &uri))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-            serde_json::to_string(&snapshot.invoice)
-                .map_err(|e| format!("Failed to serialize invoice: {}", e))
-        } else {
-            Err("Nothing to redo".to_string())
+    // Fonts for a document: the primary family, then uploaded fonts and common system
+    // fonts covering the scripts the body uses, so emoji, CJK, Arabic and the like
+    // render wherever it's opened. Browsers keep embedded fonts when printing to PDF.
+    fn font_styles(&self, body: &str, primary: Option<&str>, embed: impl Fn(&str) -> bool) -> String {
+        let scripts = fonts::detect_scripts(body);
+        let mut fallbacks: Vec<&str> = Vec::new();
+        for font in self.fonts.iter().filter(|f| f.scripts.iter().any(|s| scripts.contains(s))) {
+            if Some(font.family.as_str()) != primary && !fallbacks.contains(&font.family.as_str()) {
+                fallbacks.push(&font.family);
+            }
+        }
+        let faces = self.font_faces(|family| primary == Some(family) || fallbacks.contains(&family) || embed(family));
+        if primary.is_none() && scripts.is_empty() && faces.is_empty() {
+            return String::new();
         }
+        format!(
+            "    <style>\n{}\n        body {{ font-family: {}; }}\n    </style>\n",
+            faces,
+            fonts::font_stack(primary, &fallbacks, &scripts)
+        )
     }
 
-    #[http]
-    async fn can_undo(&self) -> Result<bool, String> {
-        Ok(!self.undo_stack.is_empty())
+    // The built-in layout uses the font family chosen in settings
+    fn default_layout_head(&self, body: &str) -> String {
+        let family = self.settings.as_ref().and_then(|s| s.font_family.as_deref());
+        self.font_styles(body, family, |_| false)
     }
 
-    #[http]
-    async fn can_redo(&self) -> Result<bool, String> {
-        Ok(!self.redo_stack.is_empty())
+    fn custom_template(&self, id: &str) -> Result<&CustomTemplate, String> {
+        self.custom_templates.iter()
+            .find(|t| t.id == id)
+            .ok_or_else(|| "Template not found".to_string())
     }
 
-    // PDF Generation
+    // Uses the export's custom template when it has one. Templates are checked when
+    // saved, so the built-in layout is only a fallback for one deleted since.
+    fn generate_invoice_html(&self, invoice: &Invoice, options: &ExportOptions) -> String {
+        let custom = options.template_id.as_deref().and_then(|id| self.custom_template(id).ok());
+        if let Some(custom) = custom {
+            if let Ok(template) = templates::Template::parse(&custom.source) {
+                if let Ok((html, _)) = self.render_template(&template, &custom.assets, invoice, options) {
+                    return html;
+                }
+            }
+        }
+        let body = self.invoice_body_html(invoice, options, "");
+        html_document(&body, &self.default_layout_head(&body), DOCUMENT_CONTENT_SECURITY_POLICY)
+    }
 
-    #[http]
-    async fn generate_pdf(&mut self) -> Result<String, String> {
-        if let Some(ref invoice) = self.current_invoice {
-            // Generate HTML for the invoice
-            let html = self.generate_invoice_html(invoice);
+    // Everything inside <body> for one invoice. receipt_key keeps the embedded
+    // receipt IDs unique when several invoices share a document.
+    // Notes, terms or thank-you text as printed, with its tokens filled in from the
+    // invoice. Totals are left blank in exports without amounts.
+    fn invoice_text(&self, invoice: &Invoice, text: &Option<String>, options: &ExportOptions) -> Option<String> {
+        let text = text.as_ref().filter(|t| !t.trim().is_empty())?;
+        let values = [
+            ("invoice_number", invoice.number.clone()),
+            ("client_name", invoice.invoicee.name.clone()),
+            ("due_date", invoice.due_date.clone().unwrap_or_default()),
+            ("payment_terms", terms_label(invoice).unwrap_or_default()),
+            ("late_fee_policy", self.settings.as_ref()
+                .and_then(|s| s.late_fee.as_ref())
+                .map(|policy| policy.describe(&invoice.currency))
+                .unwrap_or_default()),
+            ("total", if options.redact_rates {
+                String::new()
+            } else {
+                format_money(amount_due(invoice), &invoice.currency)
+            }),
+        ];
+        Some(boilerplate::substitute(text, &values))
+    }
 
-            // Save the HTML to VFS
-            let package_id = our().package_id();
-            let drive_path = format!("/{}/invoice", package_id);
+    fn invoice_body_html(&self, invoice: &Invoice, options: &ExportOptions, receipt_key: &str) -> String {
+        let invoice = &apply_export_filters(invoice, options);
+
+        let subtotal = subtotal(invoice);
+        let after_discount = taxable_amount(invoice);
+        let invoice_discount = subtotal - after_discount;
+        let shipping = shipping_amount(invoice);
+        let total = calculate_invoice_total(invoice);
+        let tax = total - after_discount - shipping;
+        let balance_due = amount_due(invoice);
+
+        // Generate logo HTML if available; the client's own logo goes beside their address
+        let logo_background = self.settings.as_ref().map(|s| s.logo_background.clone()).unwrap_or_default();
+        let logo_html = invoice.invoicer.logo_path.as_deref()
+            .map(|path| logo_img(path, "Company Logo", "max-height: 80px; margin-bottom: 1rem; display: block;", &logo_background))
+            .unwrap_or_default();
+        let client_logo_html = invoice.invoicee.logo_path.as_deref()
+            .map(|path| logo_img(path, "Client Logo", "max-height: 50px; margin-bottom: 0.5rem; display: block;", &logo_background))
+            .unwrap_or_default();
+
+        // Tax names, labels and wording from the tax regime in settings
+        let tax_settings = self.settings.as_ref().map(|s| s.tax.clone()).unwrap_or_default();
+
+        let place_of_supply = place_of_supply(invoice);
+        let tax_components = tax_components(invoice, &tax_settings);
+
+        let document_label = match tax_settings.document_title {
+            Some(ref title) if invoice.kind == DocumentKind::Invoice => title.as_str(),
+            _ => invoice.kind.label(),
+        };
+        let document_title = document_label.to_uppercase();
+
+        let delivery_enabled = self.settings.as_ref().map(|s| s.delivery_addresses_enabled).unwrap_or(false);
+        let field_definitions = self.custom_field_definitions();
+
+        // Tear-off remittance slip returned with the payment
+        let slip_enabled = self.settings.as_ref().map(|s| s.remittance_slip_enabled).unwrap_or(false);
+        let remittance_html = if slip_enabled && !options.redact_rates {
+            let pay_to = invoice.payment_info.as_ref()
+                .and_then(|info| qr::find_iban(info).map(|iban| format!("IBAN {}", iban))
+                    .or_else(|| info.lines().next().map(|line| line.to_string())))
+                .unwrap_or_default();
+            format!(
+                r#"<div class="remittance-slip">
+        <p>&#9986; Please detach and return this slip with your payment</p>
+        <table>
+            <tr><td><strong>From:</strong></td><td>{}</td><td><strong>{} #:</strong></td><td>{}</td></tr>
+            <tr><td><strong>Pay to:</strong></td><td>{}</td><td><strong>Due Date:</strong></td><td>{}</td></tr>
+            <tr><td><strong>Reference:</strong></td><td>{}</td><td><strong>Amount Due:</strong></td><td>{}</td></tr>
+            <tr><td></td><td>{}</td><td><strong>Amount Enclosed:</strong></td><td><span class="amount-enclosed">&nbsp;</span></td></tr>
+        </table>
+    </div>"#,
+                invoice.invoicee.company.as_ref().filter(|c| !c.is_empty()).unwrap_or(&invoice.invoicee.name),
+                document_label,
+                invoice.number,
+                invoice.invoicer.company.as_ref().filter(|c| !c.is_empty()).unwrap_or(&invoice.invoicer.name),
+                invoice.due_date.as_ref().unwrap_or(&String::new()),
+                invoice.number,
+                format_money(balance_due, &invoice.currency),
+                pay_to
+            )
+        } else {
+            String::new()
+        };
 
-            let invoice_dir = if let Some(ref name) = invoice.name {
-                name.clone()
+        // Signature block for signed documents
+        let signature_html = if let Some(ref signature) = invoice.signature {
+            let image_html = load_blob(&signature.image_path)
+                .map(|data| format!(
+                    r#"<img src="data:image/png;base64,{}" alt="Signature" style="max-height: 80px; display: block;" />"#,
+                    general_purpose::STANDARD.encode(&data)
+                ))
+                .unwrap_or_default();
+            let modified_html = if signature.document_hash != document_hash(invoice) {
+                "<p><strong>This document has been modified since it was signed.</strong></p>"
             } else {
-                invoice.number.clone()
+                ""
+            };
+            let signer = match signature.signer_node {
+                Some(ref node) => format!("{} ({})", escape_html(&signature.signer_name), escape_html(node)),
+                None => escape_html(&signature.signer_name),
             };
+            format!(
+                "<div class='signature'><h3>Signature:</h3>{}<p>Signed by {} on {}</p><p style='font-size: 0.8em;'>Document hash: {}</p>{}</div>",
+                image_html,
+                signer,
+                date_from_timestamp(self.local_time(signature.signed_at)),
+                signature.document_hash,
+                modified_html
+            )
+        } else {
+            String::new()
+        };
 
-            let html_path = format!("{}/{}/{}/invoice.html", drive_path, invoice.date, invoice_dir);
-            match create_file(&html_path, Some(5)) {
-                Ok(file) => {
-                    file.write(html.as_bytes())
-                        .map_err(|e| format!("Failed to write HTML: {}", e))?;
-
-                    // Return both the path and the HTML content as JSON
-                    let response = serde_json::json!({
-                        "path": html_path,
-                        "html": html,
-                        "filename": format!("invoice_{}.html", invoice.number)
-                    });
-                    serde_json::to_string(&response)
-                        .map_err(|e| format!("Failed to serialize response: {}", e))
-                }
-                Err(e) => Err(format!("Failed to create invoice file: {}", e)),
-            }
+        // Terms reference and acceptance record
+        let terms_html = if let Some(ref terms) = invoice.terms {
+            let title = self.terms_documents.iter()
+                .find(|d| d.id == terms.terms_id && d.version == terms.version)
+                .map(|d| d.title.clone())
+                .unwrap_or_else(|| "Terms and Conditions".to_string());
+            let acceptance_html = invoice.terms_acceptances.iter()
+                .filter(|a| a.terms_id == terms.terms_id && a.version == terms.version)
+                .map(|a| format!(
+                    "<p>Accepted by {} on {}</p>",
+                    escape_html(&a.accepted_by),
+                    date_from_timestamp(self.local_time(a.accepted_at))
+                ))
+                .collect::<Vec<_>>()
+                .join("");
+            format!(
+                "<div class='terms'><h3>Terms:</h3><p>{} (version {})</p>{}</div>",
+                title, terms.version, acceptance_html
+            )
         } else {
-            Err("No invoice currently loaded".to_string())
-        }
-    }
+            String::new()
+        };
 
-    // Auto-save timer method
-    #[http]
-    async fn check_autosave(&mut self) -> Result<String, String> {
-        if self.has_unsaved_changes {
-            let current_time = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+        // ZATCA e-invoice QR code (seller name, VAT number, timestamp, total, VAT),
+        // when the profile that issued the invoice has it turned on
+        let zatca_enabled = self.settings.as_ref()
+            .map(|s| match invoice.profile_id {
+                Some(ref id) => s.profiles.iter().any(|p| &p.id == id && p.zatca_qr_enabled),
+                None => s.zatca_qr_enabled,
+            })
+            .unwrap_or(false);
+        let zatca_html = if zatca_enabled && !options.redact_rates {
+            let seller_name = invoice.invoicer.company.clone()
+                .filter(|c| !c.is_empty())
+                .unwrap_or_else(|| invoice.invoicer.name.clone());
+            let vat_number = invoice.invoicer.tax_id.clone().unwrap_or_default();
+            qr::zatca_tlv_base64(
+                &seller_name,
+                &vat_number,
+                &datetime_from_timestamp(invoice.created_at),
+                total.to_f64(),
+                tax.to_f64(),
+            )
+            .and_then(|payload| qr::qr_svg(&payload))
+            .map(|svg| format!("<div class='zatca-qr' style='margin-top: 20px;'>{}</div>", svg))
+            .unwrap_or_else(|e| {
+                log!(Warn, "render", "render_invoice", Some(invoice.id.as_str()), "Failed to generate ZATCA QR code: {}", e);
+                String::new()
+            })
+        } else {
+            String::new()
+        };
 
-            if current_time - self.last_save_time >= 1 {
-                self.save_current_invoice()?;
-                self.last_save_time = current_time;
-                Ok("saved".to_string())
-            } else {
-                Ok("waiting".to_string())
+        // Invoice number as a barcode for document-scanning AP systems; numbers
+        // Code 128 can't encode fall back to a QR code
+        let number_barcode = self.settings.as_ref().and_then(|s| s.number_barcode);
+        let number_barcode_html = match number_barcode {
+            Some(kind) => {
+                let svg = match kind {
+                    barcode::NumberBarcode::Code128 => barcode::code128_svg(&invoice.number)
+                        .or_else(|_| qr::qr_svg(&invoice.number)),
+                    barcode::NumberBarcode::Qr => qr::qr_svg(&invoice.number),
+                };
+                svg.map(|svg| format!("<div class='number-barcode'>{}</div>", svg))
+                    .unwrap_or_else(|e| {
+                        log!(Warn, "render", "render_invoice", Some(invoice.id.as_str()), "Failed to generate invoice number barcode: {}", e);
+                        String::new()
+                    })
             }
-        } else {
-            Ok("no_changes".to_string())
-        }
-    }
-}
+            None => String::new(),
+        };
 
-// Standalone helper function for calculating invoice total
-fn calculate_invoice_total(invoice: &Invoice) -> f64 {
-    let mut subtotal = 0.0;
+        // Collect all receipt data for embedding
+        let mut embedded_receipts = String::new();
+        for (index, item) in invoice.line_items.iter().enumerate() {
+            if let Some(ref receipt_path) = item.receipt_path {
+                if let Ok(data) = load_blob(receipt_path) {
+                    let mime_type = if receipt_path.ends_with(".pdf") {
+                        "application/pdf"
+                    } else if receipt_path.ends_with(".jpg") || receipt_path.ends_with(".jpeg") {
+                        "image/jpeg"
+                    } else if receipt_path.ends_with(".png") {
+                        "image/png"
+                    } else {
+                        "application/octet-stream"
+                    };
 
-    for item in &invoice.line_items {
-        let line_total = item.quantity * item.rate;
-        let line_discount = line_total * (item.discount_percent / 100.0);
-        subtotal += line_total - line_discount;
-    }
+                    // Convert to base64
+                    let base64_data = general_purpose::STANDARD.encode(&data);
+                    embedded_receipts.push_str(&format!(
+                        r#"<div id="receipt-{}{}" style="display:none;" data-mime="{}" data-filename="{}">{}</div>"#,
+                        receipt_key,
+                        index,
+                        mime_type,
+                        receipt_path.split('/').last().unwrap_or("receipt"),
+                        base64_data
+                    ));
+                }
+            }
+        }
 
-    let invoice_discount = subtotal * (invoice.discount_percent / 100.0);
-    let after_discount = subtotal - invoice_discount;
-    let tax = after_discount * (invoice.tax_percent / 100.0);
+        // Line item table; rates and amounts are left out of redacted exports
+        let show_amounts = !options.redact_rates;
+        let show_receipts = !options.hide_internal_fields;
+        let show_codes = invoice.line_items.iter().any(|item| item.item_code.is_some());
+        let show_hsn = invoice.line_items.iter().any(|item| item.hsn_sac.is_some());
+        let show_period = invoice.line_items.iter()
+            .any(|item| item.service_date_start.is_some() || item.service_date_end.is_some());
+        // A column for each line item field that some line has a value for
+        let field_columns: Vec<&str> = field_definitions.iter()
+            .filter(|d| d.scope == custom_fields::Scope::LineItem)
+            .filter(|d| invoice.line_items.iter()
+                .any(|item| item.custom_fields.get(&d.name).and_then(custom_fields::display).is_some()))
+            .map(|d| d.name.as_str())
+            .collect();
+        let column_count = 2 + if show_codes { 1 } else { 0 } + if show_hsn { 1 } else { 0 } + if show_period { 1 } else { 0 } + field_columns.len()
+            + if show_amounts { 3 } else { 0 } + if show_receipts { 1 } else { 0 };
+        let mut table_header = String::new();
+        if show_codes {
+            table_header.push_str("<th>Item Code</th>");
+        }
+        table_header.push_str("<th>Description</th>");
+        if show_hsn {
+            table_header.push_str("<th>HSN/SAC</th>");
+        }
+        if show_period {
+            table_header.push_str("<th>Period</th>");
+        }
+        for name in &field_columns {
+            table_header.push_str(&format!("<th>{}</th>", escape_html(name)));
+        }
+        table_header.push_str("<th>Quantity</th>");
+        if show_amounts {
+            table_header.push_str("<th>Rate</th><th>Discount</th><th>Amount</th>");
+        }
+        if show_receipts {
+            table_header.push_str("<th>Receipt</th>");
+        }
+        let line_amounts = line_amounts(invoice);
+        let item_row = |index: usize, item: &LineItem| {
+            if item.kind == LineItemKind::Text {
+                return format!("<tr class=\"text-row\"><td colspan=\"{}\">{}</td></tr>", column_count, item.description);
+            }
+            let amount = line_amounts[index];
+            let mut row = String::from(if item.kind == LineItemKind::LateFee { "<tr class=\"late-fee\">" } else { "<tr>" });
+            if show_codes {
+                row.push_str(&format!("<td>{}</td>", item.item_code.as_deref().map(escape_html).unwrap_or_default()));
+            }
+            row.push_str(&format!("<td>{}</td>", item.description));
+            if show_hsn {
+                row.push_str(&format!("<td>{}</td>", item.hsn_sac.as_deref().map(escape_html).unwrap_or_default()));
+            }
+            if show_period {
+                row.push_str(&format!("<td>{}</td>", format_period(&item.service_date_start, &item.service_date_end)));
+            }
+            for name in &field_columns {
+                let value = item.custom_fields.get(*name).and_then(custom_fields::display);
+                row.push_str(&format!("<td>{}</td>", value.as_deref().map(escape_html).unwrap_or_default()));
+            }
+            row.push_str(&format!("<td>{}</td>", format_quantity(item)));
+            if show_amounts {
+                // Client and volume prices show the catalog price they replace
+                let list_rate = item.list_rate
+                    .filter(|list| *list > item.rate)
+                    .map(|list| format!("<s class=\"list-rate\">{}</s> ", currency::format_amount(list, &invoice.currency)))
+                    .unwrap_or_default();
+                row.push_str(&format!(
+                    "<td>{}{}</td><td>{}%</td><td>{}</td>",
+                    list_rate,
+                    currency::format_amount(item.rate, &invoice.currency),
+                    item.discount_percent,
+                    format_money(amount, &invoice.currency)
+                ));
+            }
+            if show_receipts {
+                if item.receipt_path.is_some() {
+                    row.push_str(&format!(r#"<td><a class="receipt-link" onclick="showReceipt('{}{}')">View Receipt</a></td>"#, receipt_key, index));
+                } else {
+                    row.push_str("<td></td>");
+                }
+            }
+            row.push_str("</tr>");
+            row
+        };
 
-    after_discount + tax
-}
+        // Long tables are split into pages that repeat the table header, with the
+        // running total carried forward from page to page
+        let layout = options.page_layout.clone().unwrap_or_default();
+        let amounts: Vec<(Option<&str>, f64)> = invoice.line_items.iter()
+            .zip(&line_amounts)
+            .map(|(item, amount)| (item.section.as_deref(), amount.to_f64()))
+            .collect();
+        let pages = pagination::paginate(&amounts, &layout);
+        let page_count = pages.len();
+        let running_row = |label: &str, amount: f64| if show_amounts {
+            format!(
+                "<tr class=\"running-total\"><td colspan=\"{}\">{}</td><td>{}</td>{}</tr>",
+                column_count - 1 - if show_receipts { 1 } else { 0 },
+                label,
+                currency::format_amount(amount, &invoice.currency),
+                if show_receipts { "<td></td>" } else { "" }
+            )
+        } else {
+            String::new()
+        };
+        let line_items_html = pages.iter().enumerate()
+            .map(|(page_index, page)| {
+                let mut rows = Vec::new();
+                if page_index > 0 {
+                    rows.push(running_row("Brought forward", page.brought_forward));
+                }
+                for row in &page.rows {
+                    rows.push(match row {
+                        pagination::Row::Section { name, continued } => format!(
+                            "<tr class=\"section-row\"><td colspan=\"{}\"><strong>{}</strong>{}</td></tr>",
+                            column_count, name, if *continued { " (continued)" } else { "" }
+                        ),
+                        pagination::Row::Item(index) => item_row(*index, &invoice.line_items[*index]),
+                        pagination::Row::Subtotal { section, amount } => running_row(&format!("{} subtotal", section), *amount)
+                            .replace("running-total", "section-subtotal"),
+                    });
+                }
+                if page_index + 1 < page_count {
+                    rows.push(running_row("Carried forward", page.carried_forward));
+                }
 
-// Helper methods implementation
-impl AppState {
-    // Helper method to load invoice summaries
-    fn load_invoice_summaries(&mut self, drive_path: &str) {
-        match open_dir(drive_path, false, Some(5)) {
-            Ok(dir) => {
-                if let Ok(entries) = dir.read() {
-                    for entry in entries {
-                        if entry.file_type == vfs::FileType::Directory {
-                            self.load_invoices_from_date_dir(&format!("{}/{}", drive_path, entry.path));
-                        }
-                    }
+                // Continuation pages start with the document number and page count
+                let page_header = if page_index > 0 {
+                    format!(
+                        "<div class=\"page-header\"><strong>{} #{}</strong> &middot; {}<span>Page {} of {}</span></div>",
+                        document_label,
+                        invoice.number,
+                        invoice.invoicee.company.as_ref().filter(|c| !c.is_empty()).unwrap_or(&invoice.invoicee.name),
+                        page_index + 1,
+                        page_count
+                    )
+                } else {
+                    String::new()
+                };
+                format!(
+                    r#"<div class="line-item-page">{}
+    <table>
+        <thead>
+            <tr>{}</tr>
+        </thead>
+        <tbody>
+            {}
+        </tbody>
+    </table>
+    </div>"#,
+                    page_header,
+                    table_header,
+                    rows.join("\n")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Totals rows as configured in settings or overridden for this export
+        let display = options.totals.clone()
+            .or_else(|| self.settings.as_ref().map(|s| s.totals_display.clone()))
+            .unwrap_or_default();
+        let language = invoice.language
+            .or_else(|| self.settings.as_ref().map(|s| s.language))
+            .unwrap_or_default();
+        let totals_html = if show_amounts {
+            let money = |amount: Money| format_money(amount, &invoice.currency);
+            let row = |label: String, value: String| format!(
+                r#"
+        <div class="total-row">
+            <span class="total-label">{}:</span>
+            <span class="total-value">{}</span>
+        </div>"#,
+                label, value
+            );
+            let final_row = |label: &str, value: Money| format!(
+                r#"
+        <div class="total-row" style="font-weight: bold; font-size: 1.2em;">
+            <span class="total-label">{}:</span>
+            <span class="total-value">{}</span>
+        </div>"#,
+                label, money(value)
+            );
+
+            let mut rows = String::new();
+            if display.show_section_subtotals {
+                for (section, amount) in section_subtotals(invoice) {
+                    rows.push_str(&row(format!("{} subtotal", escape_html(&section)), money(amount)));
                 }
             }
-            Err(_) => println!("Could not open drive directory"),
-        }
-    }
-
-    // Helper method to load invoices from a date directory
-    fn load_invoices_from_date_dir(&mut self, date_dir_path: &str) {
-        match open_dir(date_dir_path, false, Some(5)) {
-            Ok(dir) => {
-                if let Ok(entries) = dir.read() {
-                    for entry in entries {
-                        if entry.file_type == vfs::FileType::Directory {
-                            let invoice_path = format!("{}/{}/invoice.json", date_dir_path, entry.path);
-                            if let Ok(file) = open_file(&invoice_path, false, Some(5)) {
-                                if let Ok(data) = file.read_to_string() {
-                                    if let Ok(invoice) = serde_json::from_str::<Invoice>(&data) {
-                                        let summary = InvoiceSummary {
-                                            id: invoice.id.clone(),
-                                            number: invoice.number.clone(),
-                                            name: invoice.name.clone(),
-                                            date: invoice.date.clone(),
-                                            total: calculate_invoice_total(&invoice),
-                                            status: invoice.status.clone(),
-                                        };
-                                        self.invoices.insert(invoice.id.clone(), summary);
-                                    }
-                                }
-                            }
-                        }
-                    }
+            if display.show_subtotal {
+                rows.push_str(&row("Subtotal".to_string(), money(subtotal)));
+            }
+            if !(display.hide_zero_discount && invoice_discount.is_zero()) {
+                rows.push_str(&row(format!("Discount ({}%)", invoice.discount_percent), format!("-{}", money(invoice_discount))));
+            }
+            if invoice.shipping.is_some() {
+                rows.push_str(&row("Shipping &amp; handling".to_string(), money(shipping)));
+            }
+            if !(display.hide_zero_tax && tax.is_zero()) {
+                for ((name, percent), amount) in tax_components.iter().zip(tax_amounts(invoice, &tax_components)) {
+                    rows.push_str(&row(format!("{} ({}%)", name, percent), money(amount)));
                 }
             }
-            Err(_) => {}
-        }
-    }
-
-
-    // Helper method to save current invoice
-    fn save_current_invoice(&mut self) -> Result<(), String> {
-        if let Some(ref invoice) = self.current_invoice {
-            let package_id = our().package_id();
-            let drive_path = format!("/{}/invoice", package_id);
-
-            // Create date directory
-            let date_dir = format!("{}/{}", drive_path, invoice.date);
-            let _ = open_dir(&date_dir, true, Some(5));
+            rows.push_str(&final_row("Total", total));
 
-            // Determine the invoice directory name
-            let invoice_dir_name = if let Some(ref name) = invoice.name {
-                if !name.is_empty() {
-                    name.clone()
-                } else {
-                    invoice.number.clone()
+            // Deposits credited against this invoice, by the deposit invoice's number
+            for deposit in &invoice.deposits_applied {
+                rows.push_str(&row(format!("Deposit (Invoice {})", escape_html(&deposit.number)), format!("-{}", money(deposit.amount))));
+            }
+            // Discounts and surcharges taken when the invoice was paid
+            let payment = invoice.payment.as_ref().filter(|_| invoice.status == InvoiceStatus::Paid);
+            if let Some(payment) = payment.filter(|p| p.early_payment_discount > Money::ZERO) {
+                rows.push_str(&row("Early payment discount".to_string(), format!("-{}", money(payment.early_payment_discount))));
+            }
+            if let Some(payment) = payment.filter(|p| !p.adjustment.is_zero()) {
+                let label = if payment.adjustment > Money::ZERO { "Surcharge" } else { "Discount" };
+                let method = payment.method.as_deref().unwrap_or("payment method");
+                rows.push_str(&row(
+                    format!("{} ({})", label, escape_html(method)),
+                    money(payment.adjustment),
+                ));
+            }
+            let owed = if invoice.status == InvoiceStatus::Paid { Money::ZERO } else { balance_due };
+            let words_amount = if display.show_balance_due {
+                if invoice.status == InvoiceStatus::Paid {
+                    let paid = payment.map(|p| p.amount).unwrap_or(balance_due);
+                    rows.push_str(&row("Paid".to_string(), format!("-{}", money(paid))));
                 }
+                rows.push_str(&final_row("Balance Due", owed));
+                owed
+            } else if !invoice.deposits_applied.is_empty() {
+                rows.push_str(&final_row("Balance Due", balance_due));
+                balance_due
             } else {
-                invoice.number.clone()
+                total
             };
+            if display.show_amount_in_words {
+                rows.push_str(&format!(
+                    r#"
+        <div class="total-row total-words">
+            <span class="total-label">{}:</span>
+            <span class="total-value">{}</span>
+        </div>"#,
+                    language.words_label(words_amount != total),
+                    words::amount_in_words(words_amount.to_f64(), language, &invoice.currency, currency::decimals(&invoice.currency))
+                ));
+            }
 
-            // Check if we need to rename the directory (if the name changed)
-            // For now, we'll just save to the new location
-            // In production, you'd want to move the old directory
-
-            let invoice_dir = format!("{}/{}", date_dir, invoice_dir_name);
-            let _ = open_dir(&invoice_dir, true, Some(5));
-
-            // Save invoice.json
-            let invoice_path = format!("{}/invoice.json", invoice_dir);
-            match create_file(&invoice_path, Some(5)) {
-                Ok(file) => {
-                    let data = serde_json::to_vec(invoice)
-                        .map_err(|e| format!("Failed to serialize invoice: {}", e))?;
-                    file.write(&data)
-                        .map_err(|e| format!("Failed to write invoice: {}", e))?;
-                    self.has_unsaved_changes = false;
-                    Ok(())
+            // What to pay by each payment method with a fee, until it is paid
+            if owed > Money::ZERO {
+                let fees = self.settings.as_ref().map(|s| s.payment_method_fees.as_slice()).unwrap_or(&[]);
+                for fee in fees {
+                    rows.push_str(&row(
+                        format!("Paying by {} ({})", escape_html(&fee.label), fee_terms(fee, &invoice.currency)),
+                        money(amount_with_fee(owed, fee, currency_decimals(invoice))),
+                    ));
+                }
+                if let (Some(terms), Some((pay_by, discount))) = (invoice.early_payment.as_ref(), self.early_payment_offer(invoice)) {
+                    rows.push_str(&row(
+                        format!("Pay by {} ({})", pay_by, early_payment_label(terms, invoice)),
+                        money(owed - discount),
+                    ));
                 }
-                Err(e) => Err(format!("Failed to create invoice file: {}", e)),
             }
-        } else {
-            Ok(())
-        }
-    }
 
-    // Helper method to generate invoice HTML with embedded receipts
-    fn generate_invoice_html(&self, invoice: &Invoice) -> String {
-        let subtotal = invoice.line_items.iter()
-            .map(|item| {
-                let line_total = item.quantity * item.rate;
-                line_total - (line_total * item.discount_percent / 100.0)
-            })
-            .sum::<f64>();
-
-        let invoice_discount = subtotal * invoice.discount_percent / 100.0;
-        let after_discount = subtotal - invoice_discount;
-        let tax = after_discount * invoice.tax_percent / 100.0;
-        let total = after_discount + tax;
-
-        // Generate logo HTML if available
-        let logo_html = if let Some(ref logo_path) = invoice.invoicer.logo_path {
-            if let Ok(file) = open_file(logo_path, false, Some(5)) {
-                if let Ok(data) = file.read() {
-                    let mime_type = if logo_path.ends_with(".png") {
-                        "image/png"
-                    } else if logo_path.ends_with(".jpg") || logo_path.ends_with(".jpeg") {
-                        "image/jpeg"
-                    } else {
-                        "image/png" // default
-                    };
-                    let base64_data = general_purpose::STANDARD.encode(&data);
-                    format!(r#"<img src="data:{};base64,{}" alt="Company Logo" style="max-height: 80px; margin-bottom: 1rem; display: block;" />"#, mime_type, base64_data)
-                } else {
-                    String::new()
-                }
+            let hsn_summary = if show_hsn {
+                hsn_summary_html(invoice, &tax_components)
             } else {
                 String::new()
-            }
+            };
+            format!("<div class=\"totals\">{}\n    </div>{}", rows, hsn_summary)
         } else {
             String::new()
         };
 
-        // Collect all receipt data for embedding
-        let mut embedded_receipts = String::new();
-        for (index, item) in invoice.line_items.iter().enumerate() {
-            if let Some(ref receipt_path) = item.receipt_path {
-                if let Ok(file) = open_file(receipt_path, false, Some(5)) {
-                    if let Ok(data) = file.read() {
-                        let mime_type = if receipt_path.ends_with(".pdf") {
-                            "application/pdf"
-                        } else if receipt_path.ends_with(".jpg") || receipt_path.ends_with(".jpeg") {
-                            "image/jpeg"
-                        } else if receipt_path.ends_with(".png") {
+        // Payment details and payment request codes
+        let payment_html = {
+            let mut payment_html = String::new();
+            if let Some(ref payment_info) = invoice.payment_info {
+                payment_html.push_str(&format!("<div class='payment'><h3>Payment Information:</h3><p>{}</p>", payment_info));
+
+                // Add payment image if available
+                if let Some(ref payment_image_path) = invoice.payment_image_path {
+                    if let Ok(data) = load_blob(payment_image_path) {
+                        let mime_type = if payment_image_path.ends_with(".png") {
                             "image/png"
+                        } else if payment_image_path.ends_with(".jpg") || payment_image_path.ends_with(".jpeg") {
+                            "image/jpeg"
                         } else {
-                            "application/octet-stream"
+                            "image/png"
                         };
-
-                        // Convert to base64
                         let base64_data = general_purpose::STANDARD.encode(&data);
-                        embedded_receipts.push_str(&format!(
-                            r#"<div id="receipt-{}" style="display:none;" data-mime="{}" data-filename="{}">{}</div>"#,
-                            index,
-                            mime_type,
-                            receipt_path.split('/').last().unwrap_or("receipt"),
-                            base64_data
+                        payment_html.push_str(&format!(
+                            r#"<img src="data:{};base64,{}" alt="Payment QR Code" style="max-width: 200px; margin-top: 1rem; display: block;" />"#,
+                            mime_type, base64_data
                         ));
                     }
                 }
+
+                // Add SEPA transfer QR code when the payment details contain an IBAN;
+                // SEPA transfers are in euros only
+                if self.settings.as_ref().map(|s| s.epc_qr_enabled).unwrap_or(false) && invoice.currency == "EUR" {
+                    if let Some(iban) = qr::find_iban(payment_info) {
+                        let creditor_name = invoice.invoicer.company.clone()
+                            .filter(|c| !c.is_empty())
+                            .unwrap_or_else(|| invoice.invoicer.name.clone());
+                        let remittance = format!("Invoice {}", invoice.number);
+                        match qr::epc_payload(&creditor_name, &iban, balance_due.to_f64(), &remittance)
+                            .and_then(|payload| qr::qr_svg(&payload))
+                        {
+                            Ok(svg) => payment_html.push_str(&format!(
+                                "<div class='epc-qr' style='margin-top: 1rem;'>{}<p style='font-size: 0.8em;'>Scan with your banking app to pay by SEPA transfer</p></div>",
+                                svg
+                            )),
+                            Err(e) => log!(Warn, "render", "render_invoice", Some(invoice.id.as_str()), "Failed to generate EPC QR code: {}", e),
+                        }
+                    }
+                }
+                payment_html.push_str("</div>");
             }
-        }
 
-        format!(r#"
-<!DOCTYPE html>
-<html>
-<head>
-    <style>
-        /* Light theme (default) */
-        :root {{
-            --primary-color: #4a6fa5;
-            --background: #f5f5dc;
-            --surface: #fffef9;
-            --text-primary: #333333;
-            --text-secondary: #666666;
-            --border-color: rgba(0, 0, 0, 0.15);
-            --table-header-bg: #e8e8d5;
-        }}
+            // Lightning invoice QR code and string until it is paid
+            if let Some(ref payment) = invoice.lightning_payment {
+                if payment.settled_at.is_none() {
+                    let uri = format!("lightning:{}", payment.bolt11).to_uppercase();
+                    let svg = qr::qr_svg(&uri).unwrap_or_else(|e| {
+                        log!(Warn, "render", "render_invoice", Some(invoice.id.as_str()), "Failed to generate Lightning QR code: {}", e);
+                        String::new()
+                    });
+                    payment_html.push_str(&format!(
+                        "<div class='lightning'><h3>Pay with Lightning:</h3>{}<p style='font-family: monospace; font-size: 0.8em; word-break: break-all;'>{}</p></div>",
+                        svg, payment.bolt11
+                    ));
+                }
+            }
 
-        /* Dark theme */
-        @media (prefers-color-scheme: dark) {{
-            :root {{
-                --primary-color: #6b8bc4;
-                --background: #1a1a1a;
-                --surface: #2a2a2a;
-                --text-primary: #e0e0e0;
-                --text-secondary: #b0b0b0;
-                --border-color: rgba(255, 255, 255, 0.15);
-                --table-header-bg: #333333;
-            }}
-        }}
+            // Stablecoin payment request until it is paid
+            if let Some(ref payment) = invoice.crypto_payment {
+                if payment.paid_at.is_none() {
+                    let svg = qr::qr_svg(&payment.uri).unwrap_or_else(|e| {
+                        log!(Warn, "render", "render_invoice", Some(invoice.id.as_str()), "Failed to generate payment request QR code: {}", e);
+                        String::new()
+                    });
+                    payment_html.push_str(&format!(
+                        "<div class='crypto-payment'><h3>Pay with {} (chain {}):</h3>{}<p>Send exactly {} base units to {}</p><p style='font-family: monospace; font-size: 0.8em; word-break: break-all;'>{}</p></div>",
+                        payment.symbol, payment.chain_id, svg, payment.amount, payment.recipient, payment.uri
+                    ));
+                }
+            }
+            payment_html
+        };
 
-        body {{
-            font-family: Arial, sans-serif;
-            margin: 40px;
-            background-color: var(--background);
-            color: var(--text-primary);
-        }}
-        .header {{ display: flex; justify-content: space-between; margin-bottom: 40px; }}
-        .invoice-details {{ text-align: right; }}
-        .contact-info {{ margin-bottom: 30px; }}
-        table {{
-            width: 100%;
-            border-collapse: collapse;
-            margin: 20px 0;
-            background-color: var(--surface);
-        }}
-        th, td {{
-            padding: 10px;
-            text-align: left;
-            border-bottom: 1px solid var(--border-color);
-        }}
-        th {{ background-color: var(--table-header-bg); }}
-        .totals {{ text-align: right; margin-top: 20px; }}
-        .total-row {{ display: flex; justify-content: flex-end; margin: 5px 0; }}
-        .total-label {{ width: 150px; }}
-        .total-value {{ width: 100px; text-align: right; }}
-        .receipt-link {{
-            color: var(--primary-color);
-            text-decoration: underline;
-            cursor: pointer;
-            font-size: 0.9em;
-        }}
-        .receipt-link:hover {{ opacity: 0.8; }}
-        .modal {{
-            display: none;
-            position: fixed;
-            z-index: 1000;
-            left: 0;
-            top: 0;
-            width: 100%;
-            height: 100%;
-            background-color: rgba(0,0,0,0.9);
-        }}
-        .modal-content {{
-            margin: 2% auto;
-            display: block;
-            max-width: 90%;
-            max-height: 90%;
-        }}
-        .close {{
-            position: absolute;
-            top: 15px;
-            right: 35px;
-            color: #f1f1f1;
-            font-size: 40px;
-            font-weight: bold;
-            cursor: pointer;
-        }}
-        .close:hover {{ color: #bbb; }}
-        @media print {{
-            .receipt-link {{ display: none; }}
-            body {{ background-color: white; color: black; }}
-            table {{ background-color: white; }}
-            th {{ background-color: #f5f5f5; }}
-        }}
-    </style>
-</head>
-<body>
+        format!(r#"
     <div class="header">
         <div class="invoicer">
             {}
@@ -1200,172 +11251,153 @@ impl AppState {
                 <p>{}</p>
                 <p>{}</p>
                 <p>{}</p>
+                {}
             </div>
         </div>
         <div class="invoice-details">
-            <h1>INVOICE</h1>
-            <p><strong>Invoice #:</strong> {}</p>
+            <h1>{}</h1>
+            {}
+            <p><strong>{} #:</strong> {}</p>
+            {}
             <p><strong>Date:</strong> {}</p>
             <p><strong>Due Date:</strong> {}</p>
+            {}
+            {}
+            {}
+            {}
+            {}
         </div>
     </div>
 
-    <div class="invoicee">
-        <h3>Bill To:</h3>
-        <div class="contact-info">
-            <p><strong>{}</strong></p>
-            <p>{}</p>
-            <p>{}</p>
-            <p>{}</p>
-        </div>
-    </div>
-
-    <table>
-        <thead>
-            <tr>
-                <th>Description</th>
-                <th>Quantity</th>
-                <th>Rate</th>
-                <th>Discount</th>
-                <th>Amount</th>
-                <th>Receipt</th>
-            </tr>
-        </thead>
-        <tbody>
+    <div class="addressees">
+        <div class="invoicee">
             {}
-        </tbody>
-    </table>
-
-    <div class="totals">
-        <div class="total-row">
-            <span class="total-label">Subtotal:</span>
-            <span class="total-value">${:.2}</span>
-        </div>
-        <div class="total-row">
-            <span class="total-label">Discount ({}%):</span>
-            <span class="total-value">-${:.2}</span>
-        </div>
-        <div class="total-row">
-            <span class="total-label">Tax ({}%):</span>
-            <span class="total-value">${:.2}</span>
-        </div>
-        <div class="total-row" style="font-weight: bold; font-size: 1.2em;">
-            <span class="total-label">Total:</span>
-            <span class="total-value">${:.2}</span>
+            <h3>Bill To:</h3>
+            <div class="contact-info">
+                <p><strong>{}</strong>{}</p>
+                <p>{}</p>
+                <p>{}</p>
+                <p>{}</p>
+                {}
+            </div>
         </div>
+        {}
     </div>
 
     {}
 
     {}
 
-    <!-- Embedded receipt data -->
     {}
 
-    <!-- Receipt viewer modal -->
-    <div id="receiptModal" class="modal">
-        <span class="close" onclick="closeModal()">&times;</span>
-        <iframe id="receiptFrame" class="modal-content" style="width: 90%; height: 90%; border: none;"></iframe>
-    </div>
+    {}
 
-    <script>
-        function showReceipt(index) {{
-            const receiptDiv = document.getElementById('receipt-' + index);
-            if (!receiptDiv) return;
+    {}
 
-            const base64Data = receiptDiv.textContent;
-            const mimeType = receiptDiv.getAttribute('data-mime');
-            const dataUri = 'data:' + mimeType + ';base64,' + base64Data;
+    {}
 
-            const modal = document.getElementById('receiptModal');
-            const frame = document.getElementById('receiptFrame');
-            frame.src = dataUri;
-            modal.style.display = 'block';
-        }}
+    {}
 
-        function closeModal() {{
-            const modal = document.getElementById('receiptModal');
-            const frame = document.getElementById('receiptFrame');
-            modal.style.display = 'none';
-            frame.src = '';
-        }}
+    {}
 
-        window.onclick = function(event) {{
-            const modal = document.getElementById('receiptModal');
-            if (event.target === modal) {{
-                closeModal();
-            }}
-        }}
-    </script>
-</body>
-</html>
-        "#,
+    <!-- Embedded receipt data -->
+    {}
+"#,
             logo_html,
             invoice.invoicer.name,
             invoice.invoicer.company.as_ref().unwrap_or(&String::new()),
             invoice.invoicer.address,
             invoice.invoicer.email.as_ref().unwrap_or(&String::new()),
+            invoice.invoicer.tax_id.as_ref()
+                .map(|id| format!("<p>{}: {}</p>", tax_settings.tax_id_label, id))
+                .unwrap_or_default(),
+            document_title,
+            options.copy_label.as_ref()
+                .map(|label| format!("<p class=\"copy-label\">{}</p>", escape_html(label)))
+                .unwrap_or_default(),
+            document_label,
             invoice.number,
+            // Quoted right under the number, where AP departments look for them
+            [("PO #", &invoice.purchase_order), ("Reference", &invoice.reference)].iter()
+                .filter_map(|(label, value)| value.as_ref().map(|value| format!(
+                    "<p class=\"client-reference\"><strong>{}:</strong> {}</p>", label, escape_html(value)
+                )))
+                .collect::<String>()
+                + &custom_fields::displayed(field_definitions, custom_fields::Scope::Invoice, &invoice.custom_fields).iter()
+                    .map(|(name, value)| format!("<p><strong>{}:</strong> {}</p>", escape_html(name), escape_html(value)))
+                    .collect::<String>(),
             invoice.date,
             invoice.due_date.as_ref().unwrap_or(&String::new()),
+            terms_label(invoice)
+                .map(|terms| format!("<p><strong>Terms:</strong> {}</p>", terms))
+                .unwrap_or_default(),
+            match format_period(&invoice.service_period_start, &invoice.service_period_end) {
+                period if period.is_empty() => String::new(),
+                period => format!("<p><strong>Service Period:</strong> {}</p>", period),
+            },
+            if invoice.source_documents.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "<p><strong>Includes:</strong> {}</p>",
+                    invoice.source_documents.iter()
+                        .map(|d| format!("{} {}", d.kind.label(), d.number))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            },
+            match place_of_supply {
+                Some(place) if tax_settings.interstate_name.is_some() => {
+                    format!("<p><strong>Place of Supply:</strong> {}</p>", escape_html(place))
+                }
+                _ => String::new(),
+            },
+            number_barcode_html,
+            client_logo_html,
             invoice.invoicee.name,
+            invoice.invoicee.verified_identity.as_ref()
+                .map(|v| format!(
+                    r#" <span class="verified-badge" title="Owner {}">&#10003; Verified Hyperware identity: {}</span>"#,
+                    v.owner, v.node
+                ))
+                .unwrap_or_default(),
             invoice.invoicee.company.as_ref().unwrap_or(&String::new()),
             invoice.invoicee.address,
             invoice.invoicee.email.as_ref().unwrap_or(&String::new()),
-            invoice.line_items.iter().enumerate()
-                .map(|(index, item)| {
-                    let line_total = item.quantity * item.rate;
-                    let amount = line_total - (line_total * item.discount_percent / 100.0);
-                    let receipt_cell = if item.receipt_path.is_some() {
-                        format!(r#"<a class="receipt-link" onclick="showReceipt({})">View Receipt</a>"#, index)
-                    } else {
-                        String::new()
-                    };
-                    format!(
-                        "<tr><td>{}</td><td>{}</td><td>${:.2}</td><td>{}%</td><td>${:.2}</td><td>{}</td></tr>",
-                        item.description, item.quantity, item.rate, item.discount_percent, amount, receipt_cell
-                    )
-                })
-                .collect::<Vec<_>>()
-                .join("\n"),
-            subtotal,
-            invoice.discount_percent,
-            invoice_discount,
-            invoice.tax_percent,
-            tax,
-            total,
-            invoice.notes.as_ref()
-                .map(|n| format!("<div class='notes'><h3>Notes:</h3><p>{}</p></div>", n))
+            invoice.invoicee.tax_id.as_ref()
+                .map(|id| format!("<p>{}: {}</p>", tax_settings.tax_id_label, id))
                 .unwrap_or_default(),
-            {
-                let mut payment_html = String::new();
-                if let Some(ref payment_info) = invoice.payment_info {
-                    payment_html.push_str(&format!("<div class='payment'><h3>Payment Information:</h3><p>{}</p>", payment_info));
-
-                    // Add payment image if available
-                    if let Some(ref payment_image_path) = invoice.payment_image_path {
-                        if let Ok(file) = open_file(payment_image_path, false, Some(5)) {
-                            if let Ok(data) = file.read() {
-                                let mime_type = if payment_image_path.ends_with(".png") {
-                                    "image/png"
-                                } else if payment_image_path.ends_with(".jpg") || payment_image_path.ends_with(".jpeg") {
-                                    "image/jpeg"
-                                } else {
-                                    "image/png"
-                                };
-                                let base64_data = general_purpose::STANDARD.encode(&data);
-                                payment_html.push_str(&format!(
-                                    r#"<img src="data:{};base64,{}" alt="Payment QR Code" style="max-width: 200px; margin-top: 1rem; display: block;" />"#,
-                                    mime_type, base64_data
-                                ));
-                            }
-                        }
-                    }
-                    payment_html.push_str("</div>");
-                }
-                payment_html
+            match invoice.delivery_address {
+                Some(ref delivery) if delivery_enabled => format!(
+                    "<div class=\"delivery-address\">\n            <h3>Ship To:</h3>\n            <div class=\"contact-info\">{}</div>\n        </div>",
+                    delivery.lines().iter().map(|line| format!("<p>{}</p>", line)).collect::<String>()
+                ),
+                _ => String::new(),
             },
+            line_items_html,
+            totals_html,
+            zatca_html,
+            self.invoice_text(invoice, &invoice.notes, options)
+                .map(|n| format!("<div class='notes'><h3>Notes:</h3><p>{}</p></div>", n))
+                .unwrap_or_default()
+                + &self.invoice_text(invoice, &invoice.terms_text, options)
+                    .map(|t| format!("<div class='notes terms-text'><h3>Terms:</h3><p>{}</p></div>", escape_html(&t)))
+                    .unwrap_or_default()
+                + &tax_settings.wording.as_ref()
+                    .filter(|_| invoice.kind == DocumentKind::Invoice)
+                    .map(|w| format!("<p class='tax-wording'>{}</p>", escape_html(w)))
+                    .unwrap_or_default()
+                + &self.invoice_text(invoice, &invoice.thank_you, options)
+                    .map(|t| format!("<p class='thank-you'>{}</p>", escape_html(&t)))
+                    .unwrap_or_default(),
+            payment_html,
+            terms_html,
+            signature_html,
+            remittance_html,
             embedded_receipts
         )
     }
 }
+
+#[cfg(test)]
+mod tests;
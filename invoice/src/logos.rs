@@ -0,0 +1,230 @@
+// Logo images: the formats accepted, recognized from their contents rather than a
+// file name, and SVG held to elements and attributes that can't run or load
+// anything from elsewhere.
+// Exports embed logos as data URIs, which browsers (and PDFs printed from them)
+// draw in every format here, so nothing needs converting.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Svg,
+}
+
+impl ImageFormat {
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(ImageFormat::Jpeg)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else if std::str::from_utf8(data).map(|text| text.contains("<svg")).unwrap_or(false) {
+            Some(ImageFormat::Svg)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Svg => "svg",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+// How a logo sits on the page, so one made for a white page looks right on a
+// coloured template and a transparent one stays legible on a dark one
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum LogoBackground {
+    #[default]
+    AsUploaded,
+    Knockout, // White in the logo lets the page show through
+    Fill(String), // Hex colour drawn behind the logo, e.g. "#ffffff"
+}
+
+impl LogoBackground {
+    pub fn validate(&self) -> Result<(), String> {
+        if let LogoBackground::Fill(ref color) = self {
+            let digits = color.strip_prefix('#').unwrap_or("");
+            if ![3, 6].contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Logo background must be a hex colour, not {}", color));
+            }
+        }
+        Ok(())
+    }
+
+    // Extra inline style for the logo's img tag
+    pub fn css(&self) -> String {
+        match self {
+            LogoBackground::AsUploaded => String::new(),
+            LogoBackground::Knockout => " mix-blend-mode: multiply;".to_string(),
+            LogoBackground::Fill(color) => format!(" background-color: {}; padding: 6px; border-radius: 4px;", color),
+        }
+    }
+}
+
+// Everything a logo may be drawn with. Anything else, including animation
+// elements that can rewrite attributes, is refused rather than stripped.
+const ELEMENTS: &[&str] = &[
+    "svg", "g", "defs", "title", "desc", "style", "symbol", "use", "image",
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon",
+    "text", "tspan", "textPath",
+    "linearGradient", "radialGradient", "stop", "pattern", "clipPath", "mask",
+    "filter", "feBlend", "feColorMatrix", "feComposite", "feFlood", "feGaussianBlur",
+    "feMerge", "feMergeNode", "feOffset",
+];
+
+const ATTRIBUTES: &[&str] = &[
+    "id", "class", "style", "version", "baseProfile", "viewBox", "preserveAspectRatio",
+    "x", "y", "x1", "y1", "x2", "y2", "cx", "cy", "r", "rx", "ry", "fx", "fy", "fr",
+    "width", "height", "d", "points", "transform", "pathLength",
+    "fill", "fill-opacity", "fill-rule", "stroke", "stroke-width", "stroke-linecap",
+    "stroke-linejoin", "stroke-miterlimit", "stroke-dasharray", "stroke-dashoffset",
+    "stroke-opacity", "opacity", "color", "display", "visibility", "overflow",
+    "clip-path", "clip-rule", "clipPathUnits", "mask", "maskUnits", "maskContentUnits",
+    "filter", "filterUnits", "primitiveUnits", "in", "in2", "result", "mode", "type",
+    "values", "operator", "k1", "k2", "k3", "k4", "stdDeviation",
+    "flood-color", "flood-opacity", "offset", "stop-color", "stop-opacity",
+    "gradientUnits", "gradientTransform", "spreadMethod",
+    "patternUnits", "patternContentUnits", "patternTransform",
+    "font-family", "font-size", "font-weight", "font-style", "text-anchor",
+    "dominant-baseline", "letter-spacing", "word-spacing", "dx", "dy", "rotate",
+    "textLength", "lengthAdjust", "startOffset", "shape-rendering", "vector-effect",
+    "href", "xlink:href", "xml:space", "xmlns", "xmlns:xlink",
+];
+
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+// The SVG as parsed, if every element and attribute in it is on the lists above
+// and nothing refers outside the file. Comments and the XML declaration are
+// dropped; doctypes and processing instructions are refused, since they can
+// pull in other files.
+pub fn sanitize_svg(text: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().check_comments = true;
+    let mut writer = Writer::new(Vec::with_capacity(text.len()));
+    let mut depth = 0usize;
+    let mut in_style = false;
+    let mut seen_root = false;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("SVG logo isn't well-formed XML: {}", e))?;
+        match &event {
+            Event::Eof => break,
+            Event::Decl(_) | Event::Comment(_) => continue,
+            Event::DocType(_) => return Err("SVG logos can't declare a doctype or entities".to_string()),
+            Event::PI(_) => return Err("SVG logos can't contain processing instructions".to_string()),
+            Event::Start(tag) | Event::Empty(tag) => {
+                let name = check_element(tag)?;
+                if depth == 0 && (seen_root || name != "svg") {
+                    return Err("Not an SVG image".to_string());
+                }
+                seen_root = true;
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                    in_style = name == "style";
+                }
+            }
+            Event::End(_) => {
+                depth -= 1;
+                in_style = false;
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|e| format!("SVG logo has a bad entity: {}", e))?;
+                if depth == 0 {
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+                    return Err("SVG logos can't have text outside the image".to_string());
+                }
+                if in_style {
+                    check_css(&text)?;
+                }
+            }
+            Event::CData(data) => {
+                if depth == 0 {
+                    return Err("SVG logos can't have text outside the image".to_string());
+                }
+                if in_style {
+                    check_css(&String::from_utf8_lossy(data))?;
+                }
+            }
+        }
+        writer.write_event(event).map_err(|e| e.to_string())?;
+    }
+    if !seen_root {
+        return Err("Not an SVG image".to_string());
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| e.to_string())
+}
+
+// The element's name, once it and its attributes pass the allowlists
+fn check_element(tag: &BytesStart) -> Result<String, String> {
+    let name = String::from_utf8_lossy(tag.name().as_ref()).into_owned();
+    if !ELEMENTS.contains(&name.as_str()) {
+        return Err(format!("SVG logos can't contain <{}> elements", name));
+    }
+    for attribute in tag.attributes() {
+        let attribute = attribute.map_err(|e| format!("SVG logo has a bad attribute: {}", e))?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(|e| format!("SVG logo has a bad entity: {}", e))?;
+        if !ATTRIBUTES.contains(&key.as_str()) && !key.starts_with("data-") {
+            return Err(format!("SVG logos can't use the {} attribute", key));
+        }
+        let allowed = match key.as_str() {
+            "xmlns" => value == SVG_NAMESPACE,
+            "xmlns:xlink" => value == XLINK_NAMESPACE,
+            "href" | "xlink:href" => local_reference(&value),
+            _ => {
+                check_css(&value)?;
+                true
+            }
+        };
+        if !allowed {
+            return Err(format!("SVG logo's {} attribute refers outside the image", key));
+        }
+    }
+    Ok(name)
+}
+
+fn local_reference(target: &str) -> bool {
+    let target = target.trim().to_ascii_lowercase();
+    target.starts_with('#') || target.starts_with("data:image/png") || target.starts_with("data:image/jpeg")
+}
+
+// Styles, in a style element or attribute, with no imports, no url() other than
+// to a fragment, and no escapes that could spell either in disguise
+fn check_css(css: &str) -> Result<(), String> {
+    let lower = css.to_ascii_lowercase();
+    if lower.contains('\\') {
+        return Err("SVG logo styles can't use escapes".to_string());
+    }
+    if lower.contains("@import") || lower.contains("expression(") || lower.contains("javascript:") {
+        return Err("SVG logo styles can't import or run anything".to_string());
+    }
+    for part in lower.split("url(").skip(1) {
+        if !part.trim_start_matches(['"', '\'', ' ']).starts_with('#') {
+            return Err("SVG logo styles can't load anything from outside the image".to_string());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Profitability of invoiced work from the costs recorded against line items,
+// per invoice and totalled per client and per project
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+// Revenue and cost of one invoice's line items. Revenue is before tax and after
+// discounts; lines without a cost count as uncosted revenue, so their margin
+// isn't mistaken for pure profit.
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct InvoiceMargin {
+    pub invoice_id: String,
+    pub number: String,
+    pub date: String,
+    pub client: String,
+    pub project: Option<String>,
+    pub revenue: f64,
+    pub cost: f64,
+    pub uncosted_revenue: f64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct MarginTotal {
+    pub name: String, // Client or project
+    pub invoices: usize,
+    pub revenue: f64,
+    pub cost: f64,
+    pub margin: f64,
+    pub margin_percent: Option<f64>, // Of costed revenue; None when nothing was costed
+    pub uncosted_revenue: f64,
+}
+
+impl InvoiceMargin {
+    pub fn margin(&self) -> f64 {
+        self.revenue - self.cost
+    }
+
+    pub fn margin_percent(&self) -> Option<f64> {
+        margin_percent(self.revenue, self.cost, self.uncosted_revenue)
+    }
+}
+
+fn margin_percent(revenue: f64, cost: f64, uncosted_revenue: f64) -> Option<f64> {
+    let costed = revenue - uncosted_revenue;
+    if costed <= 0.0 {
+        return None;
+    }
+    Some((costed - cost) / costed * 100.0)
+}
+
+// Totals per name, largest revenue first; invoices without a name are left out
+pub fn totals_by(margins: &[InvoiceMargin], name: impl Fn(&InvoiceMargin) -> Option<String>) -> Vec<MarginTotal> {
+    let mut totals: Vec<MarginTotal> = Vec::new();
+    for margin in margins {
+        let Some(name) = name(margin) else {
+            continue;
+        };
+        let index = match totals.iter().position(|t| t.name == name) {
+            Some(index) => index,
+            None => {
+                totals.push(MarginTotal {
+                    name,
+                    invoices: 0,
+                    revenue: 0.0,
+                    cost: 0.0,
+                    margin: 0.0,
+                    margin_percent: None,
+                    uncosted_revenue: 0.0,
+                });
+                totals.len() - 1
+            }
+        };
+        let total = &mut totals[index];
+        total.invoices += 1;
+        total.revenue += margin.revenue;
+        total.cost += margin.cost;
+        total.uncosted_revenue += margin.uncosted_revenue;
+    }
+    for total in &mut totals {
+        total.margin = total.revenue - total.cost;
+        total.margin_percent = margin_percent(total.revenue, total.cost, total.uncosted_revenue);
+    }
+    totals.sort_by(|a, b| b.revenue.total_cmp(&a.revenue));
+    totals
+}
@@ -0,0 +1,202 @@
+// Amounts of money held as whole thousandths of the currency unit, the finest any
+// currency divides into, so totals add up exactly and every rounding step is
+// explicit. Rounding is to the currency's own minor unit: yen to the whole yen,
+// dinars to the fils. Stored and sent as plain decimal numbers.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64); // Thousandths
+
+// Decimal places a Money holds
+pub const DECIMALS: usize = 3;
+
+// What happens to an amount exactly halfway between two minor units
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub enum RoundingMode {
+    #[default]
+    HalfUp, // Away from zero, e.g. 0.125 to 0.13
+    HalfEven, // To the even minor unit, e.g. 0.125 to 0.12 and 0.135 to 0.14 (banker's rounding)
+}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub fn from_cents(cents: i64) -> Self {
+        Money(cents * 10)
+    }
+
+    // Rounds to the nearest thousandth, halves away from zero
+    pub fn from_f64(amount: f64) -> Self {
+        Money::round(amount, DECIMALS, RoundingMode::HalfUp)
+    }
+
+    // Rounds to the given number of decimal places (at most DECIMALS), halves as
+    // the mode says. The nudge keeps amounts like 1.005, stored as 1.00499999...,
+    // rounding the way they were written.
+    pub fn round(amount: f64, decimals: usize, mode: RoundingMode) -> Self {
+        if !amount.is_finite() {
+            return Money::ZERO;
+        }
+        let decimals = decimals.min(DECIMALS);
+        let units = amount * 10f64.powi(decimals as i32);
+        let tolerance = units.abs() * f64::EPSILON * 4.0;
+        let rounded = match mode {
+            RoundingMode::HalfUp => (units + tolerance.copysign(units)).round() as i64,
+            RoundingMode::HalfEven => {
+                let floor = units.floor();
+                if (units - floor - 0.5).abs() > tolerance {
+                    units.round() as i64
+                } else if floor as i64 % 2 == 0 {
+                    floor as i64
+                } else {
+                    floor as i64 + 1
+                }
+            }
+        };
+        Money(rounded * step(decimals))
+    }
+
+    // Whole minor units, e.g. cents for 2 decimals, rounded half up
+    pub fn minor_units(self, decimals: usize) -> i64 {
+        let step = step(decimals.min(DECIMALS));
+        let half = if self.0 < 0 { -step / 2 } else { step / 2 };
+        (self.0 + half) / step
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / 1000.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn abs(self) -> Self {
+        Money(self.0.abs())
+    }
+
+    // A quantity of a unit price, e.g. 7.5 hours at 85.00
+    pub fn times(unit_price: f64, quantity: f64, decimals: usize) -> Self {
+        Money::round(unit_price * quantity, decimals, RoundingMode::HalfUp)
+    }
+
+    // A percentage of this amount, to the minor unit
+    pub fn percent(self, percent: f64, decimals: usize) -> Self {
+        self.percent_rounded(percent, decimals, RoundingMode::HalfUp)
+    }
+
+    pub fn percent_rounded(self, percent: f64, decimals: usize, mode: RoundingMode) -> Self {
+        Money::round(self.to_f64() * percent / 100.0, decimals, mode)
+    }
+
+    // Splits the amount in proportion to the weights, in minor units, with leftover
+    // units going to the parts that lost the most to rounding, so the parts add up
+    // to the whole. Anything finer than a minor unit stays with the first part.
+    pub fn allocate(self, weights: &[f64], decimals: usize) -> Vec<Money> {
+        let total: f64 = weights.iter().sum();
+        if weights.is_empty() {
+            return Vec::new();
+        }
+        if total == 0.0 {
+            let mut parts = vec![Money::ZERO; weights.len()];
+            parts[0] = self;
+            return parts;
+        }
+        let step = step(decimals.min(DECIMALS));
+        let units = self.0 / step;
+        let exact: Vec<f64> = weights.iter().map(|w| units as f64 * w / total).collect();
+        let mut parts: Vec<i64> = exact.iter().map(|e| e.trunc() as i64).collect();
+        let mut remainder = units - parts.iter().sum::<i64>();
+        let mut order: Vec<usize> = (0..parts.len()).collect();
+        order.sort_by(|a, b| (exact[*b] - parts[*b] as f64).abs().total_cmp(&(exact[*a] - parts[*a] as f64).abs()));
+        for index in order.iter().cycle() {
+            if remainder == 0 {
+                break;
+            }
+            let step = remainder.signum();
+            parts[*index] += step;
+            remainder -= step;
+        }
+        let mut parts: Vec<Money> = parts.into_iter().map(|units| Money(units * step)).collect();
+        parts[0].0 += self.0 % step;
+        parts
+    }
+}
+
+// Thousandths in one minor unit of a currency with this many decimals
+fn step(decimals: usize) -> i64 {
+    10i64.pow((DECIMALS - decimals) as u32)
+}
+
+// Two decimal places, or three when the amount has a third, e.g. "12.50" or "1.234"
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let (whole, fraction) = (self.0.abs() / 1000, self.0.abs() % 1000);
+        if fraction % 10 == 0 {
+            write!(f, "{}{}.{:02}", sign, whole, fraction / 10)
+        } else {
+            write!(f, "{}{}.{:03}", sign, whole, fraction)
+        }
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        Money(iter.map(|m| m.0).sum())
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        Money(iter.map(|m| m.0).sum())
+    }
+}
+
+// A plain number in JSON, e.g. 1234.5, so stored invoices and the UI are unchanged
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Money::from_f64)
+    }
+}
+
+impl JsonSchema for Money {
+    fn schema_name() -> String {
+        "Money".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        f64::json_schema(generator)
+    }
+}
@@ -0,0 +1,149 @@
+// Notification preferences and the rules deciding when queued notifications go out
+
+use serde::{Deserialize, Serialize};
+
+const HISTORY_CAPACITY: usize = 200; // Delivered notifications kept for the in-app list
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum NotificationKind {
+    PaymentReceived,
+    DocumentSigned,
+    TermsAccepted,
+    ShareLinkOpened,
+    ExpenseReceived,
+    FollowUpDue,
+    ApprovalDecided,
+    StatusChanged, // Through set_invoice_status, e.g. by an integration
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub enum Delivery {
+    #[default]
+    Immediate,
+    DailyDigest,
+}
+
+// Hours are local time per the timezone in settings; a range like 22 to 7 wraps past midnight
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuietHours {
+    pub start_hour: u8,
+    pub end_hour: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NotificationPreferences {
+    #[serde(default)]
+    pub delivery: Delivery,
+    #[serde(default = "default_digest_hour")]
+    pub digest_hour: u8, // Local hour the daily digest goes out
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>, // Immediate notifications wait until these end
+    #[serde(default)]
+    pub disabled_kinds: Vec<NotificationKind>,
+    #[serde(default)]
+    pub push_process: Option<String>, // Hyperware process notifications are pushed to
+    #[serde(default)]
+    pub email: Option<String>, // Sent through the mail process in settings
+}
+
+fn default_digest_hour() -> u8 {
+    8
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            delivery: Delivery::Immediate,
+            digest_hour: default_digest_hour(),
+            quiet_hours: None,
+            disabled_kinds: Vec::new(),
+            push_process: None,
+            email: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Notification {
+    pub id: String,
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+    pub invoice_id: Option<String>,
+    pub created_at: u64,
+    pub delivered_at: Option<u64>,
+}
+
+impl NotificationPreferences {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.digest_hour > 23 {
+            return Err("Digest hour must be between 0 and 23".to_string());
+        }
+        if let Some(ref quiet) = self.quiet_hours {
+            if quiet.start_hour > 23 || quiet.end_hour > 23 {
+                return Err("Quiet hours must be between 0 and 23".to_string());
+            }
+        }
+        if let Some(ref email) = self.email {
+            if !email.contains('@') {
+                return Err("Invalid email address".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn wants(&self, kind: NotificationKind) -> bool {
+        !self.disabled_kinds.contains(&kind)
+    }
+
+    pub fn in_quiet_hours(&self, now: u64) -> bool {
+        let Some(ref quiet) = self.quiet_hours else {
+            return false;
+        };
+        let hour = ((now % 86400) / 3600) as u8;
+        if quiet.start_hour <= quiet.end_hour {
+            hour >= quiet.start_hour && hour < quiet.end_hour
+        } else {
+            hour >= quiet.start_hour || hour < quiet.end_hour
+        }
+    }
+
+    // Whether queued notifications should be sent now, with both times already
+    // shifted to local time. Digests go out once a day at the digest hour; quiet
+    // hours only hold back immediate notifications.
+    pub fn due(&self, now: u64, last_digest_at: u64) -> bool {
+        match self.delivery {
+            Delivery::Immediate => !self.in_quiet_hours(now),
+            Delivery::DailyDigest => {
+                let hour = ((now % 86400) / 3600) as u8;
+                hour >= self.digest_hour && last_digest_at / 86400 < now / 86400
+            }
+        }
+    }
+}
+
+// Title and body of a single message covering every queued notification
+pub fn digest(pending: &[Notification]) -> (String, String) {
+    if let [single] = pending {
+        return (single.title.clone(), single.body.clone());
+    }
+    let body = pending.iter()
+        .map(|n| format!("- {}: {}", n.title, n.body))
+        .collect::<Vec<_>>()
+        .join("\n");
+    (format!("{} invoice updates", pending.len()), body)
+}
+
+// Drops the oldest delivered notifications beyond the history limit
+pub fn trim_history(notifications: &mut Vec<Notification>) {
+    let delivered = notifications.iter().filter(|n| n.delivered_at.is_some()).count();
+    let mut excess = delivered.saturating_sub(HISTORY_CAPACITY);
+    notifications.retain(|n| {
+        if excess > 0 && n.delivered_at.is_some() {
+            excess -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
@@ -0,0 +1,124 @@
+// Gap and sequence audit of invoice numbers, one report per numbering series
+
+use serde::Serialize;
+
+// A numbering series: the default one from settings or a business profile's
+pub struct Series {
+    pub name: String,
+    pub prefix: String,
+    pub next_number: u32,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct OutOfSequence {
+    pub number: String,
+    pub date: String,
+    pub previous_number: String,
+    pub previous_date: String, // Later than this invoice's date despite the lower number
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SeriesReport {
+    pub series: String,
+    pub prefix: String,
+    pub issued: usize,
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub next: String,
+    pub gaps: Vec<String>, // Numbers never issued
+    pub voided: Vec<String>, // Issued, then deleted
+    pub out_of_sequence: Vec<OutOfSequence>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SequenceReport {
+    pub series: Vec<SeriesReport>,
+    pub unmatched: Vec<String>, // Manual numbers outside every series
+}
+
+// Sequence number of an invoice number in a series, e.g. 12 for "INV-0012"
+fn sequence(number: &str, prefix: &str) -> Option<u32> {
+    let digits = number.strip_prefix(prefix)?;
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn format_number(prefix: &str, sequence: u32) -> String {
+    format!("{}{:04}", prefix, sequence)
+}
+
+// `invoices` are (number, date) of current invoices; `deleted` are numbers of
+// deleted ones. Numbers go to the series with the longest matching prefix.
+pub fn report(series: &[Series], invoices: &[(String, String)], deleted: &[String]) -> SequenceReport {
+    let series_of = |number: &str| {
+        series.iter()
+            .enumerate()
+            .filter_map(|(index, s)| sequence(number, &s.prefix).map(|seq| (index, seq, s.prefix.len())))
+            .max_by_key(|(_, _, prefix_len)| *prefix_len)
+            .map(|(index, seq, _)| (index, seq))
+    };
+
+    let mut issued: Vec<Vec<(u32, &String, &String)>> = vec![Vec::new(); series.len()];
+    let mut voided: Vec<Vec<u32>> = vec![Vec::new(); series.len()];
+    let mut unmatched = Vec::new();
+    for (number, date) in invoices {
+        match series_of(number) {
+            Some((index, seq)) => issued[index].push((seq, number, date)),
+            None => unmatched.push(number.clone()),
+        }
+    }
+    for number in deleted {
+        match series_of(number) {
+            Some((index, seq)) => voided[index].push(seq),
+            None => unmatched.push(number.clone()),
+        }
+    }
+
+    let reports = series.iter().enumerate().map(|(index, s)| {
+        let invoices = &mut issued[index];
+        invoices.sort_by_key(|(seq, _, _)| *seq);
+        let voided = &mut voided[index];
+        voided.sort();
+        voided.dedup();
+
+        let used: Vec<u32> = invoices.iter().map(|(seq, _, _)| *seq).chain(voided.iter().copied()).collect();
+        // Numbers below the next one in the sequence should all have been used
+        let gaps = match used.iter().min() {
+            Some(&first) => (first..s.next_number.max(first))
+                .filter(|seq| !used.contains(seq))
+                .map(|seq| format_number(&s.prefix, seq))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let out_of_sequence = invoices.windows(2)
+            .filter(|pair| pair[1].2 < pair[0].2)
+            .map(|pair| OutOfSequence {
+                number: pair[1].1.clone(),
+                date: pair[1].2.clone(),
+                previous_number: pair[0].1.clone(),
+                previous_date: pair[0].2.clone(),
+            })
+            .collect();
+
+        SeriesReport {
+            series: s.name.clone(),
+            prefix: s.prefix.clone(),
+            issued: invoices.len(),
+            first: invoices.first().map(|(_, number, _)| (*number).clone()),
+            last: invoices.last().map(|(_, number, _)| (*number).clone()),
+            next: format_number(&s.prefix, s.next_number),
+            gaps,
+            voided: voided.iter().map(|seq| format_number(&s.prefix, *seq)).collect(),
+            out_of_sequence,
+        }
+    }).collect();
+
+    unmatched.sort();
+    SequenceReport {
+        series: reports,
+        unmatched,
+    }
+}
@@ -0,0 +1,122 @@
+// Splits long line item tables into printed pages, with running totals carried
+// from one page to the next and sections kept together with their subtotals
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Table rows that fit on a page; the first page has less room below the header
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct PageLayout {
+    pub first_page_rows: usize,
+    pub rows_per_page: usize,
+}
+
+impl Default for PageLayout {
+    fn default() -> Self {
+        PageLayout {
+            first_page_rows: 18,
+            rows_per_page: 30,
+        }
+    }
+}
+
+impl PageLayout {
+    pub fn validate(&self) -> Result<(), String> {
+        // A section heading, an item and a subtotal must fit on a page
+        if self.first_page_rows < 3 || self.rows_per_page < 3 {
+            return Err("Pages need room for at least 3 rows".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Row {
+    Section { name: String, continued: bool },
+    Item(usize), // Index into the line items
+    Subtotal { section: String, amount: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub rows: Vec<Row>,
+    pub brought_forward: f64, // Items on earlier pages
+    pub carried_forward: f64, // Items on this page and earlier ones
+}
+
+// Headings, items and section subtotals in table order, from each item's section and amount
+fn table_rows(items: &[(Option<&str>, f64)]) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for (index, (section, _)) in items.iter().enumerate() {
+        let Some(section) = section else {
+            rows.push(Row::Item(index));
+            continue;
+        };
+        if index == 0 || items[index - 1].0 != Some(*section) {
+            rows.push(Row::Section { name: section.to_string(), continued: false });
+        }
+        rows.push(Row::Item(index));
+        if items.get(index + 1).map(|(next, _)| *next != Some(*section)).unwrap_or(true) {
+            let amount = items[..=index].iter()
+                .rev()
+                .take_while(|(s, _)| *s == Some(*section))
+                .map(|(_, amount)| amount)
+                .sum();
+            rows.push(Row::Subtotal { section: section.to_string(), amount });
+        }
+    }
+    rows
+}
+
+// One page when everything fits. Otherwise pages never end on a section heading
+// or start with a subtotal (the item before it moves along), and a section that
+// runs over repeats its heading on the next page.
+pub fn paginate(items: &[(Option<&str>, f64)], layout: &PageLayout) -> Vec<Page> {
+    let rows = table_rows(items);
+    let item_amount = |rows: &[Row]| -> f64 {
+        rows.iter()
+            .map(|row| match row {
+                Row::Item(index) => items[*index].1,
+                _ => 0.0,
+            })
+            .sum()
+    };
+
+    let mut pages = Vec::new();
+    let mut start = 0;
+    let mut brought_forward = 0.0;
+    while start < rows.len() {
+        let mut page_rows = Vec::new();
+        // Heading of a section carried over from the previous page
+        if let Some(Row::Item(index)) = rows.get(start) {
+            if let Some(section) = items[*index].0 {
+                if *index > 0 && items[*index - 1].0 == Some(section) {
+                    page_rows.push(Row::Section { name: section.to_string(), continued: true });
+                }
+            }
+        }
+        let capacity = if pages.is_empty() { layout.first_page_rows } else { layout.rows_per_page }
+            .saturating_sub(page_rows.len())
+            .max(1);
+
+        let mut end = (start + capacity).min(rows.len());
+        if end < rows.len() {
+            if matches!(rows[end], Row::Subtotal { .. }) && end - start > 2 {
+                end -= 1;
+            }
+            if matches!(rows[end - 1], Row::Section { .. }) && end - start > 1 {
+                end -= 1;
+            }
+        }
+        page_rows.extend_from_slice(&rows[start..end]);
+
+        let carried_forward = brought_forward + item_amount(&rows[start..end]);
+        pages.push(Page { rows: page_rows, brought_forward, carried_forward });
+        brought_forward = carried_forward;
+        start = end;
+    }
+    if pages.is_empty() {
+        pages.push(Page { rows: Vec::new(), brought_forward: 0.0, carried_forward: 0.0 });
+    }
+    pages
+}
@@ -0,0 +1,198 @@
+// Line items from a table pasted out of a spreadsheet, document or chat: tab- or
+// comma-separated rows, or a Markdown table. Parsing only previews the items;
+// nothing is added until they are confirmed.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, JsonSchema)]
+pub enum TableFormat {
+    Tsv,
+    Csv,
+    Semicolon, // CSV as spreadsheets export it where the decimal separator is a comma
+    Markdown,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct PastedLine {
+    pub row: usize, // 1-based line in the pasted text
+    pub description: String,
+    pub quantity: f64,
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct PastedTable {
+    pub format: TableFormat,
+    pub has_header: bool,
+    pub items: Vec<PastedLine>,
+    pub warnings: Vec<String>, // Rows skipped or read with defaults
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Description,
+    Quantity,
+    Rate,
+    Amount,
+}
+
+fn header_column(cell: &str) -> Option<Column> {
+    let cell = cell.trim().to_lowercase();
+    let cell = cell.trim_matches(|c: char| !c.is_alphanumeric() && c != ' ');
+    match cell {
+        "description" | "item" | "items" | "service" | "services" | "task" | "name" | "product" | "details" => Some(Column::Description),
+        "qty" | "quantity" | "hours" | "hrs" | "units" | "days" | "count" => Some(Column::Quantity),
+        "rate" | "price" | "unit price" | "unit cost" | "cost" | "hourly rate" | "each" => Some(Column::Rate),
+        "amount" | "total" | "line total" | "subtotal" => Some(Column::Amount),
+        _ => None,
+    }
+}
+
+fn detect_format(lines: &[&str]) -> TableFormat {
+    if lines.iter().all(|l| l.trim_start().starts_with('|')) {
+        TableFormat::Markdown
+    } else if lines.iter().any(|l| l.contains('\t')) {
+        TableFormat::Tsv
+    } else if lines.iter().all(|l| l.contains(';')) {
+        TableFormat::Semicolon
+    } else {
+        TableFormat::Csv
+    }
+}
+
+// Splits on the separator outside double quotes; "" inside quotes is a quote
+fn split_quoted(line: &str, separator: char) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+fn split_row(line: &str, format: TableFormat) -> Vec<String> {
+    let cells = match format {
+        TableFormat::Tsv => line.split('\t').map(|c| c.to_string()).collect(),
+        TableFormat::Csv => split_quoted(line, ','),
+        TableFormat::Semicolon => split_quoted(line, ';'),
+        TableFormat::Markdown => {
+            let line = line.trim();
+            let line = line.strip_prefix('|').unwrap_or(line);
+            let line = line.strip_suffix('|').unwrap_or(line);
+            line.split('|').map(|c| c.to_string()).collect()
+        }
+    };
+    cells.into_iter().map(|c| c.trim().to_string()).collect()
+}
+
+// Markdown's |---|:---:| line under the header
+fn is_separator_row(cells: &[String]) -> bool {
+    !cells.is_empty() && cells.iter().all(|c| !c.is_empty() && c.chars().all(|ch| matches!(ch, '-' | ':' | ' ')))
+}
+
+// Reads amounts like "$1,234.50", "1.234,50 €", "(20.00)" or "12 h". A comma
+// followed by one or two digits at the end is taken as a decimal comma.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let negative = text.starts_with('(') && text.ends_with(')') || text.starts_with('-');
+    let cleaned: String = text.chars().filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',').collect();
+    if cleaned.is_empty() {
+        return None;
+    }
+    let normalized = match (cleaned.rfind('.'), cleaned.rfind(',')) {
+        (Some(dot), Some(comma)) if comma > dot => cleaned.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => cleaned.replace(',', ""),
+        (None, Some(comma)) if cleaned.len() - comma - 1 <= 2 && cleaned.matches(',').count() == 1 => cleaned.replace(',', "."),
+        (None, Some(_)) => cleaned.replace(',', ""),
+        _ => cleaned,
+    };
+    let value: f64 = normalized.parse().ok()?;
+    Some(if negative { -value } else { value })
+}
+
+pub fn parse_table(text: &str) -> Result<PastedTable, String> {
+    let lines: Vec<(usize, &str)> = text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return Err("Nothing to import".to_string());
+    }
+    let format = detect_format(&lines.iter().map(|(_, l)| *l).collect::<Vec<_>>());
+
+    let mut rows: Vec<(usize, Vec<String>)> = lines.iter()
+        .map(|(number, line)| (*number, split_row(line, format)))
+        .filter(|(_, cells)| !is_separator_row(cells))
+        .collect();
+
+    // A first row naming the columns decides which is which; otherwise they are
+    // description, quantity and rate in that order. A first row without numbers
+    // above rows with them is a header in some other language.
+    let header: Vec<Option<Column>> = rows[0].1.iter().map(|c| header_column(c)).collect();
+    let numeric = |cells: &[String]| cells.iter().skip(1).any(|c| parse_number(c).is_some());
+    let has_header = header.contains(&Some(Column::Description))
+        || header.iter().filter(|c| c.is_some()).count() >= 2
+        || (rows.len() > 1 && !numeric(&rows[0].1) && numeric(&rows[1].1));
+    let columns = if has_header && header.iter().any(|c| c.is_some()) {
+        rows.remove(0);
+        header
+    } else {
+        if has_header {
+            rows.remove(0);
+        }
+        vec![Some(Column::Description), Some(Column::Quantity), Some(Column::Rate)]
+    };
+    let find = |column: Column| columns.iter().position(|c| *c == Some(column));
+    let description_column = find(Column::Description).unwrap_or(0);
+    let quantity_column = find(Column::Quantity);
+    let rate_column = find(Column::Rate);
+    let amount_column = find(Column::Amount);
+
+    let mut items = Vec::new();
+    let mut warnings = Vec::new();
+    for (row, cells) in rows {
+        let cell = |index: Option<usize>| index.and_then(|i| cells.get(i)).map(|c| c.as_str()).unwrap_or("");
+        let description = cell(Some(description_column)).to_string();
+        if description.is_empty() {
+            warnings.push(format!("Line {}: no description, skipped", row));
+            continue;
+        }
+
+        let quantity = match parse_number(cell(quantity_column)) {
+            Some(quantity) => quantity,
+            None => {
+                if quantity_column.is_some() {
+                    warnings.push(format!("Line {}: no quantity, 1 used", row));
+                }
+                1.0
+            }
+        };
+        let rate = match (parse_number(cell(rate_column)), parse_number(cell(amount_column))) {
+            (Some(rate), _) => rate,
+            (None, Some(amount)) if quantity != 0.0 => amount / quantity,
+            _ => {
+                warnings.push(format!("Line {}: no rate, 0 used", row));
+                0.0
+            }
+        };
+        items.push(PastedLine { row, description, quantity, rate });
+    }
+    if items.is_empty() {
+        return Err("No line items found in the pasted text".to_string());
+    }
+
+    Ok(PastedTable { format, has_header, items, warnings })
+}
@@ -0,0 +1,126 @@
+// Catalog of billable items, with client-specific prices and volume discount tiers
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// From min_quantity units up, every unit is billed at this rate
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct VolumeTier {
+    pub min_quantity: f64,
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct CatalogItem {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub item_code: Option<String>, // SKU or part number, copied onto lines added from the item
+    #[serde(default)]
+    pub description: Option<String>, // Line item description; the name when empty
+    #[serde(default)]
+    pub unit: Option<String>, // e.g. "hour", "license"
+    pub rate: f64, // List price per unit
+    #[serde(default)]
+    pub hsn_sac: Option<String>,
+    #[serde(default)]
+    pub tiers: Vec<VolumeTier>,
+}
+
+// A client's price for one catalog item. Tiers replace the item's tiers when set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ClientPrice {
+    #[serde(default)]
+    pub rate: Option<f64>,
+    #[serde(default)]
+    pub tiers: Vec<VolumeTier>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum PriceSource {
+    List,
+    Client,
+    Tier,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct Quote {
+    pub list_rate: f64,
+    pub rate: f64,
+    pub source: PriceSource,
+    pub min_quantity: Option<f64>, // Of the tier applied
+}
+
+fn validate_rate(rate: f64) -> Result<(), String> {
+    if !rate.is_finite() || rate < 0.0 {
+        return Err("Rates must be zero or more".to_string());
+    }
+    Ok(())
+}
+
+fn validate_tiers(tiers: &[VolumeTier]) -> Result<(), String> {
+    for (index, tier) in tiers.iter().enumerate() {
+        validate_rate(tier.rate)?;
+        if !tier.min_quantity.is_finite() || tier.min_quantity <= 0.0 {
+            return Err("Tier quantities must be more than zero".to_string());
+        }
+        if tiers[..index].iter().any(|t| t.min_quantity == tier.min_quantity) {
+            return Err(format!("Two tiers start at {}", tier.min_quantity));
+        }
+    }
+    Ok(())
+}
+
+impl CatalogItem {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Catalog items need a name".to_string());
+        }
+        validate_rate(self.rate)?;
+        validate_tiers(&self.tiers)
+    }
+
+    pub fn line_description(&self) -> String {
+        self.description.clone()
+            .filter(|d| !d.trim().is_empty())
+            .unwrap_or_else(|| self.name.clone())
+    }
+}
+
+impl ClientPrice {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(rate) = self.rate {
+            validate_rate(rate)?;
+        }
+        validate_tiers(&self.tiers)
+    }
+}
+
+// Rate for a quantity of an item: the client's rate if they have one, lowered to
+// the highest volume tier the quantity reaches
+pub fn quote(item: &CatalogItem, client: Option<&ClientPrice>, quantity: f64) -> Quote {
+    let client_rate = client.and_then(|c| c.rate);
+    let tiers = match client {
+        Some(c) if !c.tiers.is_empty() => &c.tiers,
+        _ => &item.tiers,
+    };
+    let tier = tiers.iter()
+        .filter(|t| quantity >= t.min_quantity)
+        .max_by(|a, b| a.min_quantity.total_cmp(&b.min_quantity));
+
+    let base = client_rate.unwrap_or(item.rate);
+    match tier {
+        Some(tier) if tier.rate < base => Quote {
+            list_rate: item.rate,
+            rate: tier.rate,
+            source: PriceSource::Tier,
+            min_quantity: Some(tier.min_quantity),
+        },
+        _ => Quote {
+            list_rate: item.rate,
+            rate: base,
+            source: if client_rate.is_some() { PriceSource::Client } else { PriceSource::List },
+            min_quantity: None,
+        },
+    }
+}
@@ -0,0 +1,125 @@
+// QR code payloads and rendering for payment and compliance codes embedded in invoices
+
+use base64::{Engine as _, engine::general_purpose};
+use qrcode::{render::svg, EcLevel, QrCode};
+
+// Render data as an inline SVG QR code
+pub fn qr_svg(data: &str) -> Result<String, String> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)
+        .map_err(|e| format!("Failed to encode QR code: {}", e))?;
+    let svg = code.render::<svg::Color>()
+        .min_dimensions(160, 160)
+        .build();
+
+    // Drop the XML declaration so the SVG can be embedded directly in HTML
+    Ok(match svg.find("<svg") {
+        Some(start) => svg[start..].to_string(),
+        None => svg,
+    })
+}
+
+// ZATCA (Saudi Arabia) e-invoice QR payload: TLV-encoded fields, Base64 encoded
+//   1: seller name, 2: VAT registration number, 3: timestamp (ISO 8601),
+//   4: invoice total including VAT, 5: VAT total
+pub fn zatca_tlv_base64(
+    seller_name: &str,
+    vat_number: &str,
+    timestamp: &str,
+    total: f64,
+    vat_total: f64,
+) -> Result<String, String> {
+    let fields = [
+        seller_name.to_string(),
+        vat_number.to_string(),
+        timestamp.to_string(),
+        format!("{:.2}", total),
+        format!("{:.2}", vat_total),
+    ];
+
+    let mut tlv = Vec::new();
+    for (index, value) in fields.iter().enumerate() {
+        let bytes = value.as_bytes();
+        if bytes.len() > 255 {
+            return Err(format!("ZATCA field {} is too long", index + 1));
+        }
+        tlv.push(index as u8 + 1);
+        tlv.push(bytes.len() as u8);
+        tlv.extend_from_slice(bytes);
+    }
+
+    Ok(general_purpose::STANDARD.encode(tlv))
+}
+
+// EPC069-12 ("GiroCode") SEPA credit transfer payload for EU bank apps
+pub fn epc_payload(creditor_name: &str, iban: &str, amount: f64, remittance: &str) -> Result<String, String> {
+    if !(0.01..=999_999_999.99).contains(&amount) {
+        return Err("Amount is outside the range allowed in an EPC QR code".to_string());
+    }
+
+    let lines = [
+        "BCD".to_string(),
+        "002".to_string(), // Version 002 makes the BIC optional within the EEA
+        "1".to_string(), // UTF-8
+        "SCT".to_string(),
+        String::new(), // BIC
+        creditor_name.chars().take(70).collect(),
+        iban.to_string(),
+        format!("EUR{:.2}", amount),
+        String::new(), // Purpose
+        String::new(), // Structured reference
+        remittance.chars().take(140).collect(),
+    ];
+    Ok(lines.join("\n"))
+}
+
+// Find the first valid IBAN in free-form payment details, either unbroken
+// or printed in the usual groups of four ("DE89 3704 0044 0532 0130 00")
+pub fn find_iban(text: &str) -> Option<String> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != ' ')
+        .flat_map(|segment| segment.split(' '))
+        .collect();
+
+    for start in 0..words.len() {
+        let mut candidate = words[start].to_ascii_uppercase();
+        if candidate.len() == 4 {
+            for word in &words[start + 1..] {
+                if word.is_empty() || word.len() > 4 || candidate.len() + word.len() > 34 {
+                    break;
+                }
+                candidate.push_str(&word.to_ascii_uppercase());
+                if word.len() < 4 {
+                    break;
+                }
+            }
+        }
+        if is_valid_iban(&candidate) {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+// Structure and ISO 7064 mod-97 checksum validation
+fn is_valid_iban(iban: &str) -> bool {
+    let bytes = iban.as_bytes();
+    if !(15..=34).contains(&bytes.len())
+        || !bytes[..2].iter().all(|b| b.is_ascii_uppercase())
+        || !bytes[2..4].iter().all(|b| b.is_ascii_digit())
+        || !bytes.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    {
+        return false;
+    }
+
+    // Move the country code and check digits to the end, map letters to 10..35
+    let mut remainder = 0u32;
+    for &b in bytes[4..].iter().chain(&bytes[..4]) {
+        let value = if b.is_ascii_digit() { (b - b'0') as u32 } else { (b - b'A') as u32 + 10 };
+        remainder = if value >= 10 {
+            (remainder * 100 + value) % 97
+        } else {
+            (remainder * 10 + value) % 97
+        };
+    }
+    remainder == 1
+}
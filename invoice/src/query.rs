@@ -0,0 +1,155 @@
+// Field selection, filtering and sorting over JSON records for the query endpoint
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueryDocument {
+    pub collection: Collection,
+    #[serde(default)]
+    pub fields: Vec<String>, // Dotted paths, e.g. "invoicee.name"; empty selects everything
+    #[serde(default)]
+    pub filters: Vec<Filter>, // All must match
+    #[serde(default)]
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Collection {
+    Invoices,
+    Clients,
+    Payments,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Filter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Contains, // Case-insensitive substring, or membership for arrays
+    In, // Value is an array of accepted values
+}
+
+pub fn execute(records: Vec<Value>, query: &QueryDocument) -> Vec<Value> {
+    let mut records: Vec<Value> = records.into_iter()
+        .filter(|record| query.filters.iter().all(|filter| matches(record, filter)))
+        .collect();
+
+    if let Some(ref sort_by) = query.sort_by {
+        // Records missing the field sort last either way
+        records.sort_by(|a, b| {
+            let ordering = match (lookup(a, sort_by), lookup(b, sort_by)) {
+                (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            if query.descending { ordering.reverse() } else { ordering }
+        });
+    }
+
+    records.into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|record| select(&record, &query.fields))
+        .collect()
+}
+
+// Follows a dotted path through objects and, by index, arrays
+pub fn lookup<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(record, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+fn matches(record: &Value, filter: &Filter) -> bool {
+    let value = lookup(record, &filter.field).unwrap_or(&Value::Null);
+    match filter.op {
+        FilterOp::Eq => values_equal(value, &filter.value),
+        FilterOp::Ne => !values_equal(value, &filter.value),
+        FilterOp::Gt => compare(value, &filter.value) == Some(Ordering::Greater),
+        FilterOp::Gte => matches!(compare(value, &filter.value), Some(Ordering::Greater | Ordering::Equal)),
+        FilterOp::Lt => compare(value, &filter.value) == Some(Ordering::Less),
+        FilterOp::Lte => matches!(compare(value, &filter.value), Some(Ordering::Less | Ordering::Equal)),
+        FilterOp::Contains => match (value, &filter.value) {
+            (Value::String(haystack), Value::String(needle)) => {
+                haystack.to_lowercase().contains(&needle.to_lowercase())
+            }
+            (Value::Array(items), needle) => items.iter().any(|item| values_equal(item, needle)),
+            _ => false,
+        },
+        FilterOp::In => match filter.value {
+            Value::Array(ref accepted) => accepted.iter().any(|a| values_equal(value, a)),
+            _ => false,
+        },
+    }
+}
+
+// Numbers compare by value so 100 and 100.0 are equal
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => compare(a, b) == Some(Ordering::Equal),
+        _ => a == b,
+    }
+}
+
+// Numbers numerically, strings lexically (which orders ISO dates correctly)
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+// Copies only the requested paths, keeping their nesting
+fn select(record: &Value, fields: &[String]) -> Value {
+    if fields.is_empty() {
+        return record.clone();
+    }
+    let mut selected = Value::Object(Map::new());
+    for field in fields {
+        if let Some(value) = lookup(record, field) {
+            insert_path(&mut selected, field, value.clone());
+        }
+    }
+    selected
+}
+
+fn insert_path(target: &mut Value, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((key, rest)) => {
+            if let Value::Object(map) = target {
+                let child = map.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+                insert_path(child, rest, value);
+            }
+        }
+        None => {
+            if let Value::Object(map) = target {
+                map.insert(path.to_string(), value);
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Payment reminder policies: the escalation steps an overdue invoice goes through
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum ReminderTone {
+    Friendly,
+    Firm,
+    FinalNotice,
+}
+
+// Subject and body are templates; {number}, {client}, {total}, {due_date} and
+// {days_overdue} are replaced when the reminder is sent
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ReminderStep {
+    pub days_after_due: i64, // Negative to remind before the due date
+    pub tone: ReminderTone,
+    pub subject: String,
+    pub body: String,
+}
+
+// Steps are sent in order, each once; reminders stop after the last step
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ReminderPolicy {
+    pub steps: Vec<ReminderStep>,
+    #[serde(default)]
+    pub paused: bool, // Never remind, e.g. for clients who pay on their own schedule
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct SentReminder {
+    pub step: usize,
+    pub tone: ReminderTone,
+    pub to: String,
+    pub sent_at: u64,
+}
+
+impl Default for ReminderPolicy {
+    fn default() -> Self {
+        ReminderPolicy {
+            steps: vec![
+                ReminderStep {
+                    days_after_due: 1,
+                    tone: ReminderTone::Friendly,
+                    subject: "Friendly reminder: invoice {number}".to_string(),
+                    body: "Hi {client},\n\nJust a reminder that invoice {number} for {total} was due on {due_date}. If you've already paid, thank you and please ignore this message.".to_string(),
+                },
+                ReminderStep {
+                    days_after_due: 14,
+                    tone: ReminderTone::Firm,
+                    subject: "Invoice {number} is {days_overdue} days overdue".to_string(),
+                    body: "Hi {client},\n\nInvoice {number} for {total} is now {days_overdue} days past its due date of {due_date}. Please arrange payment as soon as possible.".to_string(),
+                },
+                ReminderStep {
+                    days_after_due: 30,
+                    tone: ReminderTone::FinalNotice,
+                    subject: "Final notice: invoice {number}".to_string(),
+                    body: "Hi {client},\n\nThis is a final notice that invoice {number} for {total}, due on {due_date}, remains unpaid. Please pay it immediately or contact us to discuss.".to_string(),
+                },
+            ],
+            paused: false,
+        }
+    }
+}
+
+impl ReminderPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.paused && self.steps.is_empty() {
+            return Err("A reminder policy needs at least one step".to_string());
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            if step.subject.trim().is_empty() || step.body.trim().is_empty() {
+                return Err(format!("Step {} needs a subject and body", index + 1));
+            }
+            if index > 0 && step.days_after_due <= self.steps[index - 1].days_after_due {
+                return Err("Steps must be in order of increasing days after the due date".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // The step to send now, given the reminders already sent. Steps whose day
+    // has passed are not sent late; only the latest one due is.
+    pub fn next_step(&self, sent: &[SentReminder], days_overdue: i64) -> Option<(usize, &ReminderStep)> {
+        if self.paused {
+            return None;
+        }
+        self.steps.iter()
+            .enumerate()
+            .skip(sent.last().map(|r| r.step + 1).unwrap_or(0))
+            .take_while(|(_, step)| step.days_after_due <= days_overdue)
+            .last()
+    }
+}
+
+pub fn render(template: &str, fields: &[(&str, String)]) -> String {
+    fields.iter().fold(template.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
@@ -0,0 +1,167 @@
+// Optional encryption at rest for the invoice and settings JSON, in whichever store
+// keeps them
+
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Mutex;
+
+// Encrypted files start with this marker; anything else is read as plaintext,
+// so files written before encryption was turned on stay readable
+const MAGIC: &[u8] = b"INVOICE-ENC1\n";
+const NONCE_LEN: usize = 24;
+const PBKDF2_ROUNDS: u32 = 600_000;
+const CHECK_PLAINTEXT: &[u8] = b"invoice storage key check";
+const LOCKED: &str = "Storage is locked; unlock it with the passphrase first";
+
+// What seal/open do right now. The key is set at startup in node-key mode, or by
+// unlocking in passphrase mode, and is never written anywhere.
+enum KeyState {
+    Off,
+    Locked,
+    Unlocked([u8; 32]),
+}
+
+static KEY: Mutex<KeyState> = Mutex::new(KeyState::Off);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub enum EncryptionMode {
+    #[default]
+    Off,
+    NodeKey, // Random key kept in process state, outside the VFS
+    Passphrase, // Key derived from a passphrase that must be entered after each restart
+}
+
+// Kept in process state, never in the VFS
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    pub mode: EncryptionMode,
+    pub node_key: Option<String>, // Hex, node-key mode only
+    pub salt: Option<String>, // Hex, passphrase mode only
+    pub check: Option<Vec<u8>>, // Known plaintext sealed with the passphrase key, to verify unlocks
+}
+
+impl EncryptionConfig {
+    // Key available without user input, if any
+    pub fn startup_key(&self) -> Option<[u8; 32]> {
+        match self.mode {
+            EncryptionMode::NodeKey => self.node_key.as_ref().and_then(|k| decode_key(k)),
+            _ => None,
+        }
+    }
+
+    pub fn passphrase_key(&self, passphrase: &str) -> Result<[u8; 32], String> {
+        let salt = self.salt.as_ref()
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or("Encryption is not set up with a passphrase")?;
+        let key = derive_key(passphrase, &salt);
+        let check = self.check.as_ref().ok_or("Missing passphrase check value")?;
+        match open_with(&key, check) {
+            Ok(plaintext) if plaintext == CHECK_PLAINTEXT => Ok(key),
+            _ => Err("Incorrect passphrase".to_string()),
+        }
+    }
+
+    pub fn node_key() -> (Self, [u8; 32]) {
+        let key = random_bytes::<32>();
+        let config = EncryptionConfig {
+            mode: EncryptionMode::NodeKey,
+            node_key: Some(hex::encode(key)),
+            salt: None,
+            check: None,
+        };
+        (config, key)
+    }
+
+    pub fn passphrase(passphrase: &str) -> Result<(Self, [u8; 32]), String> {
+        if passphrase.chars().count() < 12 {
+            return Err("Passphrase must be at least 12 characters".to_string());
+        }
+        let salt = random_bytes::<16>();
+        let key = derive_key(passphrase, &salt);
+        let config = EncryptionConfig {
+            mode: EncryptionMode::Passphrase,
+            node_key: None,
+            salt: Some(hex::encode(salt)),
+            check: Some(seal_with(&key, CHECK_PLAINTEXT)?),
+        };
+        Ok((config, key))
+    }
+}
+
+// Call at startup and whenever the configuration changes; a missing key for an
+// encrypting mode leaves storage locked
+pub fn set_key(mode: &EncryptionMode, key: Option<[u8; 32]>) {
+    *KEY.lock().unwrap() = match (mode, key) {
+        (EncryptionMode::Off, _) => KeyState::Off,
+        (_, Some(key)) => KeyState::Unlocked(key),
+        (_, None) => KeyState::Locked,
+    };
+}
+
+pub fn is_locked() -> bool {
+    matches!(*KEY.lock().unwrap(), KeyState::Locked)
+}
+
+// Encrypts with the current key, or passes data through when encryption is off
+pub fn seal(data: &[u8]) -> Result<Vec<u8>, String> {
+    match *KEY.lock().unwrap() {
+        KeyState::Off => Ok(data.to_vec()),
+        KeyState::Locked => Err(LOCKED.to_string()),
+        KeyState::Unlocked(ref key) => seal_with(key, data),
+    }
+}
+
+// Decrypts sealed data with the current key; plaintext is returned as is
+pub fn open(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data.to_vec());
+    }
+    match *KEY.lock().unwrap() {
+        KeyState::Unlocked(ref key) => open_with(key, data),
+        _ => Err(LOCKED.to_string()),
+    }
+}
+
+fn seal_with(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, data)
+        .map_err(|_| "Failed to encrypt data".to_string())?;
+
+    let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open_with(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+    let body = data.strip_prefix(MAGIC).ok_or("Data is not encrypted")?;
+    if body.len() < NONCE_LEN {
+        return Err("Encrypted data is truncated".to_string());
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher.decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt data; wrong key or corrupted file".to_string())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+fn decode_key(hex_key: &str) -> Option<[u8; 32]> {
+    hex::decode(hex_key).ok()?.try_into().ok()
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
@@ -0,0 +1,662 @@
+// Where invoices and settings are kept: as JSON files in the VFS, an invoice per
+// directory laid out by date and name, or in SQLite with invoices, line items and
+// payments as tables so they can be queried. Receipts, signatures, logos and other
+// files (blobs) go through here too, kept in the VFS by either backend. Invoices
+// cross this boundary as JSON so backends don't depend on the invoice types, and
+// any backend can be installed in place of the VFS without touching the endpoints.
+
+use hyperware_process_lib::{
+    our, sqlite,
+    vfs::{self, create_file, open_dir, open_file, remove_file},
+};
+use base64::{Engine as _, engine::general_purpose};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(test)]
+use std::sync::Mutex;
+
+const DB_NAME: &str = "invoice";
+const TIMEOUT: u64 = 5;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum Backend {
+    #[default]
+    Vfs,
+    Sqlite,
+}
+
+// Where a stored invoice is found. The VFS finds invoices by date and directory
+// name, SQLite by ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub id: String,
+    pub date: String,
+    pub dir: String, // The invoice's name, or its number when it has none
+}
+
+// An invoice as found by load_all, or why it couldn't be read
+#[derive(Debug, Clone)]
+pub struct StoredInvoice {
+    pub key: String, // What key() gives for its location
+    pub contents: Result<Value, String>,
+}
+
+pub trait InvoiceStore {
+    fn save(&self, location: &Location, invoice: &Value) -> Result<(), String>;
+    fn load(&self, location: &Location) -> Result<Value, String>;
+    fn remove(&self, location: &Location) -> Result<(), String>;
+    // Every stored invoice, including ones that can't be read
+    fn load_all(&self) -> Result<Vec<StoredInvoice>, String>;
+    // What an invoice is stored under: its file path in the VFS, its ID in SQLite
+    fn key(&self, location: &Location) -> String;
+    // Moves an unreadable invoice out of the way with its contents untouched, so it
+    // is no longer loaded, and says where it went
+    fn quarantine(&self, key: &str) -> Result<String, String>;
+    fn save_settings(&self, settings: &Value) -> Result<(), String>;
+    // None until settings are first saved
+    fn load_settings(&self) -> Result<Option<Value>, String>;
+    // Documents as they were issued, kept apart from the invoice so later edits
+    // never reach them. Sequence counts an invoice's issues from 0.
+    fn save_issued(&self, id: &str, sequence: usize, document: &Value) -> Result<(), String>;
+    fn load_issued(&self, id: &str, sequence: usize) -> Result<Value, String>;
+    // Files by their VFS path; saving creates any directories the path needs
+    fn save_blob(&self, path: &str, data: &[u8]) -> Result<(), String>;
+    fn load_blob(&self, path: &str) -> Result<Vec<u8>, String>;
+    fn remove_blob(&self, path: &str) -> Result<(), String>;
+    // Removes a directory of blobs and anything left in it
+    fn remove_blob_dir(&self, path: &str) -> Result<(), String>;
+}
+
+// Blobs as files in the VFS, for every backend that runs on the node
+fn save_vfs_blob(path: &str, data: &[u8]) -> Result<(), String> {
+    // The first two parts name the drive, which already exists
+    let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    for end in 3..parts.len() {
+        let _ = open_dir(&format!("/{}", parts[..end].join("/")), true, Some(TIMEOUT));
+    }
+    create_file(path, Some(TIMEOUT))
+        .map_err(|e| format!("Failed to create {}: {}", path, e))?
+        .write(data)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+fn load_vfs_blob(path: &str) -> Result<Vec<u8>, String> {
+    open_file(path, false, Some(TIMEOUT))
+        .and_then(|file| file.read())
+        .map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+fn remove_vfs_blob(path: &str) -> Result<(), String> {
+    remove_file(path, Some(TIMEOUT))
+        .map_err(|e| format!("Failed to remove {}: {}", path, e))
+}
+
+fn remove_vfs_blob_dir(path: &str) -> Result<(), String> {
+    vfs::remove_dir(path, Some(TIMEOUT))
+        .map_err(|e| format!("Failed to remove {}: {}", path, e))
+}
+
+// Invoice and settings files in the VFS, sealed with the given functions so they
+// can be encrypted
+pub struct VfsStore {
+    pub drive_path: String,
+    pub seal: fn(&[u8]) -> Result<Vec<u8>, String>,
+    pub open: fn(&[u8]) -> Result<Vec<u8>, String>,
+}
+
+impl VfsStore {
+    fn invoice_path(&self, location: &Location) -> String {
+        format!("{}/{}/{}/invoice.json", self.drive_path, location.date, location.dir)
+    }
+
+    fn settings_path(&self) -> String {
+        format!("{}/settings.json", self.drive_path)
+    }
+
+    fn issued_path(&self, id: &str, sequence: usize) -> String {
+        format!("{}/issued/{}-{}.json", self.drive_path, id, sequence)
+    }
+
+    fn read(&self, path: &str) -> Result<Value, String> {
+        let data = open_file(path, false, Some(TIMEOUT))
+            .map_err(|e| format!("Invoice not found: {}", e))?
+            .read()
+            .map_err(|e| format!("Failed to read invoice: {}", e))?;
+        self.decode(&data)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Value, String> {
+        serde_json::from_slice(&(self.open)(data)?)
+            .map_err(|e| format!("Failed to parse invoice: {}", e))
+    }
+
+    fn write(&self, path: &str, value: &Value) -> Result<(), String> {
+        let data = serde_json::to_vec(value)
+            .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+        create_file(path, Some(TIMEOUT))
+            .map_err(|e| format!("Failed to create {}: {}", path, e))?
+            .write(&(self.seal)(&data)?)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))
+    }
+
+    fn subdirectories(path: &str) -> Result<Vec<String>, String> {
+        open_dir(path, false, Some(TIMEOUT))
+            .and_then(|dir| dir.read())
+            .map(|entries| entries.into_iter()
+                .filter(|entry| entry.file_type == vfs::FileType::Directory)
+                .map(|entry| entry.path)
+                .collect())
+            .map_err(|e| format!("Could not read {}: {}", path, e))
+    }
+}
+
+impl InvoiceStore for VfsStore {
+    fn save(&self, location: &Location, invoice: &Value) -> Result<(), String> {
+        let date_dir = format!("{}/{}", self.drive_path, location.date);
+        let _ = open_dir(&date_dir, true, Some(TIMEOUT));
+        let _ = open_dir(&format!("{}/{}", date_dir, location.dir), true, Some(TIMEOUT));
+        self.write(&self.invoice_path(location), invoice)
+    }
+
+    fn load(&self, location: &Location) -> Result<Value, String> {
+        self.read(&self.invoice_path(location))
+    }
+
+    fn remove(&self, location: &Location) -> Result<(), String> {
+        remove_file(&self.invoice_path(location), Some(TIMEOUT))
+            .map_err(|e| format!("Failed to remove invoice: {}", e))
+    }
+
+    fn load_all(&self) -> Result<Vec<StoredInvoice>, String> {
+        let mut invoices = Vec::new();
+        for date_dir in Self::subdirectories(&self.drive_path)? {
+            let date_path = format!("{}/{}", self.drive_path, date_dir);
+            for invoice_dir in Self::subdirectories(&date_path).unwrap_or_default() {
+                let path = format!("{}/{}/invoice.json", date_path, invoice_dir);
+                // Directories without one hold expenses, terms and the like
+                let Ok(file) = open_file(&path, false, Some(TIMEOUT)) else {
+                    continue;
+                };
+                let contents = file.read()
+                    .map_err(|e| format!("Failed to read invoice: {}", e))
+                    .and_then(|data| self.decode(&data));
+                invoices.push(StoredInvoice { key: path, contents });
+            }
+        }
+        Ok(invoices)
+    }
+
+    fn key(&self, location: &Location) -> String {
+        self.invoice_path(location)
+    }
+
+    // Copied as is into the quarantine directory, which load_all never finds an
+    // invoice in since it has no subdirectories
+    fn quarantine(&self, key: &str) -> Result<String, String> {
+        let data = open_file(key, false, Some(TIMEOUT))
+            .and_then(|file| file.read())
+            .map_err(|e| format!("Failed to read {}: {}", key, e))?;
+        let quarantine_dir = format!("{}/quarantine", self.drive_path);
+        let _ = open_dir(&quarantine_dir, true, Some(TIMEOUT));
+        let name = key.strip_prefix(&format!("{}/", self.drive_path)).unwrap_or(key).replace('/', "_");
+        let target = format!("{}/{}", quarantine_dir, name);
+        create_file(&target, Some(TIMEOUT))
+            .and_then(|file| file.write(&data))
+            .map_err(|e| format!("Failed to write {}: {}", target, e))?;
+        remove_file(key, Some(TIMEOUT))
+            .map_err(|e| format!("Failed to remove {}: {}", key, e))?;
+        Ok(target)
+    }
+
+    fn save_settings(&self, settings: &Value) -> Result<(), String> {
+        self.write(&self.settings_path(), settings)
+    }
+
+    fn load_settings(&self) -> Result<Option<Value>, String> {
+        let Ok(file) = open_file(&self.settings_path(), false, Some(TIMEOUT)) else {
+            return Ok(None);
+        };
+        let data = file.read().map_err(|e| format!("Failed to read settings: {}", e))?;
+        serde_json::from_slice(&(self.open)(&data)?)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse settings: {}", e))
+    }
+
+    // Flat in the issued directory, which like quarantine has no subdirectories
+    // for load_all to look in
+    fn save_issued(&self, id: &str, sequence: usize, document: &Value) -> Result<(), String> {
+        let _ = open_dir(&format!("{}/issued", self.drive_path), true, Some(TIMEOUT));
+        self.write(&self.issued_path(id, sequence), document)
+    }
+
+    fn load_issued(&self, id: &str, sequence: usize) -> Result<Value, String> {
+        let data = open_file(&self.issued_path(id, sequence), false, Some(TIMEOUT))
+            .and_then(|file| file.read())
+            .map_err(|e| format!("Issued document not found: {}", e))?;
+        self.decode(&data)
+    }
+
+    fn save_blob(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        save_vfs_blob(path, data)
+    }
+
+    fn load_blob(&self, path: &str) -> Result<Vec<u8>, String> {
+        load_vfs_blob(path)
+    }
+
+    fn remove_blob(&self, path: &str) -> Result<(), String> {
+        remove_vfs_blob(path)
+    }
+
+    fn remove_blob_dir(&self, path: &str) -> Result<(), String> {
+        remove_vfs_blob_dir(path)
+    }
+}
+
+// Invoices and settings in SQLite. The invoices table holds each invoice whole, with
+// the columns reports filter on; line items and the recorded payment are copied into
+// their own tables on every save. Data is sealed with the same functions as the VFS
+// store; while that encrypts, nothing from an invoice is copied out in plaintext.
+#[derive(Clone)]
+pub struct SqliteStore {
+    db: sqlite::Sqlite,
+    seal: fn(&[u8]) -> Result<Vec<u8>, String>,
+    open: fn(&[u8]) -> Result<Vec<u8>, String>,
+}
+
+// Marks data columns holding sealed bytes (as base64) rather than JSON
+const SEALED: &str = "sealed:";
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS invoices (
+        id TEXT PRIMARY KEY,
+        number TEXT NOT NULL,
+        date TEXT NOT NULL,
+        due_date TEXT,
+        status TEXT NOT NULL,
+        client TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        data TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS line_items (
+        invoice_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        id TEXT NOT NULL,
+        description TEXT NOT NULL,
+        quantity REAL NOT NULL,
+        rate REAL NOT NULL,
+        discount_percent REAL NOT NULL,
+        PRIMARY KEY (invoice_id, position)
+    )",
+    "CREATE TABLE IF NOT EXISTS payments (
+        invoice_id TEXT PRIMARY KEY,
+        amount REAL NOT NULL,
+        paid_on TEXT NOT NULL,
+        method TEXT,
+        reference TEXT
+    )",
+    "CREATE TABLE IF NOT EXISTS quarantine (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS issued_documents (
+        invoice_id TEXT NOT NULL,
+        sequence INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (invoice_id, sequence)
+    )",
+    "CREATE TABLE IF NOT EXISTS settings (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        data TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS invoices_by_date ON invoices (date)",
+    "CREATE INDEX IF NOT EXISTS invoices_by_client ON invoices (client)",
+];
+
+impl SqliteStore {
+    // Opens the database, creating the tables the first time
+    pub fn open(
+        seal: fn(&[u8]) -> Result<Vec<u8>, String>,
+        open: fn(&[u8]) -> Result<Vec<u8>, String>,
+    ) -> Result<Self, String> {
+        let db = sqlite::open(our().package_id(), DB_NAME, Some(TIMEOUT))
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        for statement in SCHEMA {
+            db.write(statement.to_string(), vec![], None)
+                .map_err(|e| format!("Failed to create tables: {}", e))?;
+        }
+        Ok(SqliteStore { db, seal, open })
+    }
+
+    fn write(&self, statement: &str, params: Vec<Value>, tx_id: u64) -> Result<(), String> {
+        self.db.write(statement.to_string(), params, Some(tx_id))
+            .map_err(|e| format!("Database write failed: {}", e))
+    }
+
+    fn parse(&self, row: &std::collections::HashMap<String, Value>) -> Result<Value, String> {
+        let data = row.get("data").and_then(Value::as_str).ok_or("Stored invoice has no data")?;
+        let data = match data.strip_prefix(SEALED) {
+            Some(encoded) => (self.open)(&general_purpose::STANDARD.decode(encoded)
+                .map_err(|e| format!("Failed to decode sealed data: {}", e))?)?,
+            None => data.as_bytes().to_vec(),
+        };
+        serde_json::from_slice(&data).map_err(|e| format!("Failed to parse invoice: {}", e))
+    }
+
+    fn execute(&self, statements: Vec<(&'static str, Vec<Value>)>) -> Result<(), String> {
+        let tx_id = self.db.begin_tx().map_err(|e| format!("Failed to start transaction: {}", e))?;
+        for (statement, params) in statements {
+            self.write(statement, params, tx_id)?;
+        }
+        self.db.commit_tx(tx_id).map_err(|e| format!("Failed to commit: {}", e))
+    }
+}
+
+fn text(invoice: &Value, field: &str) -> Value {
+    invoice.get(field).filter(|v| !v.is_null()).cloned().unwrap_or(Value::String(String::new()))
+}
+
+// What goes in a data column, and whether it was sealed: JSON as is while
+// encryption is off, else the sealed bytes as base64
+pub fn seal_text(seal: fn(&[u8]) -> Result<Vec<u8>, String>, value: &Value) -> Result<(String, bool), String> {
+    let data = serde_json::to_vec(value).map_err(|e| format!("Failed to serialize: {}", e))?;
+    let sealed = seal(&data)?;
+    if sealed == data {
+        Ok((String::from_utf8(data).map_err(|e| format!("Failed to serialize: {}", e))?, false))
+    } else {
+        Ok((format!("{}{}", SEALED, general_purpose::STANDARD.encode(sealed)), true))
+    }
+}
+
+// The statements that save an invoice, with their parameters. A sealed invoice
+// leaves the other columns blank and gets no line item or payment rows.
+pub fn save_statements(
+    seal: fn(&[u8]) -> Result<Vec<u8>, String>,
+    location: &Location,
+    invoice: &Value,
+) -> Result<Vec<(&'static str, Vec<Value>)>, String> {
+    let (data, sealed) = seal_text(seal, invoice)?;
+    let id = Value::String(location.id.clone());
+    let mut statements = vec![
+        ("DELETE FROM line_items WHERE invoice_id = ?", vec![id.clone()]),
+        ("DELETE FROM payments WHERE invoice_id = ?", vec![id.clone()]),
+    ];
+    if sealed {
+        let blank = Value::String(String::new());
+        statements.insert(0, (
+            "INSERT OR REPLACE INTO invoices (id, number, date, due_date, status, client, currency, data)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            vec![id, blank.clone(), blank.clone(), Value::Null, blank.clone(), blank.clone(), blank, Value::String(data)],
+        ));
+        return Ok(statements);
+    }
+
+    let invoicee = invoice.get("invoicee");
+    let client = invoicee.and_then(|c| c.get("company")).and_then(Value::as_str)
+        .filter(|c| !c.trim().is_empty())
+        .or_else(|| invoicee.and_then(|c| c.get("name")).and_then(Value::as_str))
+        .unwrap_or_default();
+    statements.insert(0, (
+        "INSERT OR REPLACE INTO invoices (id, number, date, due_date, status, client, currency, data)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        vec![
+            id.clone(),
+            text(invoice, "number"),
+            text(invoice, "date"),
+            invoice.get("due_date").cloned().unwrap_or(Value::Null),
+            text(invoice, "status"),
+            Value::String(client.to_string()),
+            text(invoice, "currency"),
+            Value::String(data),
+        ],
+    ));
+    let items = invoice.get("line_items").and_then(Value::as_array).cloned().unwrap_or_default();
+    for (position, item) in items.iter().enumerate() {
+        statements.push((
+            "INSERT INTO line_items (invoice_id, position, id, description, quantity, rate, discount_percent)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            vec![
+                id.clone(),
+                Value::from(position as u64),
+                text(item, "id"),
+                text(item, "description"),
+                item.get("quantity").cloned().unwrap_or(Value::from(0.0)),
+                item.get("rate").cloned().unwrap_or(Value::from(0.0)),
+                item.get("discount_percent").cloned().unwrap_or(Value::from(0.0)),
+            ],
+        ));
+    }
+    if let Some(payment) = invoice.get("payment").filter(|p| !p.is_null()) {
+        statements.push((
+            "INSERT INTO payments (invoice_id, amount, paid_on, method, reference) VALUES (?, ?, ?, ?, ?)",
+            vec![
+                id,
+                payment.get("amount").cloned().unwrap_or(Value::from(0.0)),
+                text(payment, "paid_on"),
+                payment.get("method").cloned().unwrap_or(Value::Null),
+                payment.get("reference").cloned().unwrap_or(Value::Null),
+            ],
+        ));
+    }
+    Ok(statements)
+}
+
+impl InvoiceStore for SqliteStore {
+    fn save(&self, location: &Location, invoice: &Value) -> Result<(), String> {
+        self.execute(save_statements(self.seal, location, invoice)?)
+    }
+
+    fn load(&self, location: &Location) -> Result<Value, String> {
+        let rows = self.db.read("SELECT data FROM invoices WHERE id = ?".to_string(), vec![Value::String(location.id.clone())])
+            .map_err(|e| format!("Database read failed: {}", e))?;
+        self.parse(rows.first().ok_or("Invoice not found")?)
+    }
+
+    fn remove(&self, location: &Location) -> Result<(), String> {
+        let id = Value::String(location.id.clone());
+        let tx_id = self.db.begin_tx().map_err(|e| format!("Failed to start transaction: {}", e))?;
+        for table in ["line_items", "payments"] {
+            self.write(&format!("DELETE FROM {} WHERE invoice_id = ?", table), vec![id.clone()], tx_id)?;
+        }
+        self.write("DELETE FROM invoices WHERE id = ?", vec![id], tx_id)?;
+        self.db.commit_tx(tx_id).map_err(|e| format!("Failed to commit removal: {}", e))
+    }
+
+    fn load_all(&self) -> Result<Vec<StoredInvoice>, String> {
+        let rows = self.db.read("SELECT id, data FROM invoices".to_string(), vec![])
+            .map_err(|e| format!("Could not read invoices from the database: {}", e))?;
+        Ok(rows.iter()
+            .map(|row| StoredInvoice {
+                key: row.get("id").and_then(Value::as_str).unwrap_or_default().to_string(),
+                contents: self.parse(row),
+            })
+            .collect())
+    }
+
+    fn key(&self, location: &Location) -> String {
+        location.id.clone()
+    }
+
+    // Moved into the quarantine table in the same transaction that deletes it
+    fn quarantine(&self, key: &str) -> Result<String, String> {
+        let id = Value::String(key.to_string());
+        let tx_id = self.db.begin_tx().map_err(|e| format!("Failed to start transaction: {}", e))?;
+        self.write("INSERT OR REPLACE INTO quarantine (id, data) SELECT id, data FROM invoices WHERE id = ?", vec![id.clone()], tx_id)?;
+        for table in ["line_items", "payments"] {
+            self.write(&format!("DELETE FROM {} WHERE invoice_id = ?", table), vec![id.clone()], tx_id)?;
+        }
+        self.write("DELETE FROM invoices WHERE id = ?", vec![id], tx_id)?;
+        self.db.commit_tx(tx_id).map_err(|e| format!("Failed to commit quarantine: {}", e))?;
+        Ok(format!("quarantine table, id {}", key))
+    }
+
+    fn save_settings(&self, settings: &Value) -> Result<(), String> {
+        let (data, _) = seal_text(self.seal, settings)?;
+        self.db.write("INSERT OR REPLACE INTO settings (id, data) VALUES (1, ?)".to_string(), vec![Value::String(data)], None)
+            .map_err(|e| format!("Failed to write settings: {}", e))
+    }
+
+    fn load_settings(&self) -> Result<Option<Value>, String> {
+        let rows = self.db.read("SELECT data FROM settings WHERE id = 1".to_string(), vec![])
+            .map_err(|e| format!("Database read failed: {}", e))?;
+        rows.first()
+            .map(|row| self.parse(row))
+            .transpose()
+    }
+
+    fn save_issued(&self, id: &str, sequence: usize, document: &Value) -> Result<(), String> {
+        let (data, _) = seal_text(self.seal, document)?;
+        self.db.write(
+            "INSERT OR REPLACE INTO issued_documents (invoice_id, sequence, data) VALUES (?, ?, ?)".to_string(),
+            vec![Value::String(id.to_string()), Value::from(sequence as u64), Value::String(data)],
+            None,
+        ).map_err(|e| format!("Failed to write issued document: {}", e))
+    }
+
+    fn load_issued(&self, id: &str, sequence: usize) -> Result<Value, String> {
+        let rows = self.db.read(
+            "SELECT data FROM issued_documents WHERE invoice_id = ? AND sequence = ?".to_string(),
+            vec![Value::String(id.to_string()), Value::from(sequence as u64)],
+        ).map_err(|e| format!("Database read failed: {}", e))?;
+        self.parse(rows.first().ok_or("Issued document not found")?)
+    }
+
+    fn save_blob(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        save_vfs_blob(path, data)
+    }
+
+    fn load_blob(&self, path: &str) -> Result<Vec<u8>, String> {
+        load_vfs_blob(path)
+    }
+
+    fn remove_blob(&self, path: &str) -> Result<(), String> {
+        remove_vfs_blob(path)
+    }
+
+    fn remove_blob_dir(&self, path: &str) -> Result<(), String> {
+        remove_vfs_blob_dir(path)
+    }
+}
+
+// The store in use instead of the default one, once installed; per thread like the
+// clock in host
+thread_local! {
+    static INSTALLED: std::cell::RefCell<Option<Box<dyn InvoiceStore + Send>>> = const { std::cell::RefCell::new(None) };
+}
+
+// Replaces the store everything is kept in; None goes back to the default
+pub fn install(store: Option<Box<dyn InvoiceStore + Send>>) {
+    INSTALLED.with(|installed| *installed.borrow_mut() = store);
+}
+
+// Runs f against the installed store, or against the default when none is
+pub fn with<T>(default: impl FnOnce() -> Box<dyn InvoiceStore>, f: impl FnOnce(&dyn InvoiceStore) -> T) -> T {
+    INSTALLED.with(|installed| match installed.borrow().as_ref() {
+        Some(store) => f(store.as_ref()),
+        None => f(default().as_ref()),
+    })
+}
+
+// Invoices and settings kept in memory, for running endpoint handlers off-node.
+// Clones share the same contents, so a test can keep one and install another.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    invoices: std::sync::Arc<Mutex<std::collections::HashMap<String, Value>>>, // Key is invoice ID
+    quarantined: std::sync::Arc<Mutex<std::collections::HashMap<String, Value>>>,
+    issued: std::sync::Arc<Mutex<std::collections::HashMap<(String, usize), Value>>>,
+    settings: std::sync::Arc<Mutex<Option<Value>>>,
+    blobs: std::sync::Arc<Mutex<std::collections::HashMap<String, Vec<u8>>>>, // Key is path
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    pub fn invoice(&self, id: &str) -> Option<Value> {
+        self.invoices.lock().unwrap().get(id).cloned()
+    }
+
+    // Overwrites what's stored for an ID, e.g. with something that isn't an invoice
+    pub fn put(&self, id: &str, data: Value) {
+        self.invoices.lock().unwrap().insert(id.to_string(), data);
+    }
+
+    pub fn quarantined(&self, id: &str) -> Option<Value> {
+        self.quarantined.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn blob(&self, path: &str) -> Option<Vec<u8>> {
+        self.blobs.lock().unwrap().get(path).cloned()
+    }
+}
+
+#[cfg(test)]
+impl InvoiceStore for MemoryStore {
+    fn save(&self, location: &Location, invoice: &Value) -> Result<(), String> {
+        self.invoices.lock().unwrap().insert(location.id.clone(), invoice.clone());
+        Ok(())
+    }
+
+    fn load(&self, location: &Location) -> Result<Value, String> {
+        self.invoice(&location.id).ok_or_else(|| "Invoice not found".to_string())
+    }
+
+    fn remove(&self, location: &Location) -> Result<(), String> {
+        self.invoices.lock().unwrap().remove(&location.id);
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<Vec<StoredInvoice>, String> {
+        Ok(self.invoices.lock().unwrap().iter()
+            .map(|(id, data)| StoredInvoice { key: id.clone(), contents: Ok(data.clone()) })
+            .collect())
+    }
+
+    fn key(&self, location: &Location) -> String {
+        location.id.clone()
+    }
+
+    fn quarantine(&self, key: &str) -> Result<String, String> {
+        let data = self.invoices.lock().unwrap().remove(key).ok_or("Invoice not found")?;
+        self.quarantined.lock().unwrap().insert(key.to_string(), data);
+        Ok(format!("quarantine/{}", key))
+    }
+
+    fn save_settings(&self, settings: &Value) -> Result<(), String> {
+        *self.settings.lock().unwrap() = Some(settings.clone());
+        Ok(())
+    }
+
+    fn load_settings(&self) -> Result<Option<Value>, String> {
+        Ok(self.settings.lock().unwrap().clone())
+    }
+
+    fn save_issued(&self, id: &str, sequence: usize, document: &Value) -> Result<(), String> {
+        self.issued.lock().unwrap().insert((id.to_string(), sequence), document.clone());
+        Ok(())
+    }
+
+    fn load_issued(&self, id: &str, sequence: usize) -> Result<Value, String> {
+        self.issued.lock().unwrap().get(&(id.to_string(), sequence)).cloned()
+            .ok_or_else(|| "Issued document not found".to_string())
+    }
+
+    fn save_blob(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        self.blobs.lock().unwrap().insert(path.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn load_blob(&self, path: &str) -> Result<Vec<u8>, String> {
+        self.blob(path).ok_or_else(|| format!("Failed to read {}: not found", path))
+    }
+
+    fn remove_blob(&self, path: &str) -> Result<(), String> {
+        self.blobs.lock().unwrap().remove(path).map(|_| ()).ok_or_else(|| format!("Failed to remove {}: not found", path))
+    }
+
+    fn remove_blob_dir(&self, path: &str) -> Result<(), String> {
+        let prefix = format!("{}/", path);
+        self.blobs.lock().unwrap().retain(|blob, _| !blob.starts_with(&prefix));
+        Ok(())
+    }
+}
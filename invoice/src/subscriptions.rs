@@ -0,0 +1,221 @@
+// Monthly subscription plans billed in advance on a fixed day of the month. Partial
+// first and last periods and mid-cycle plan changes are prorated by day.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// From effective_date on, the plan costs this much per month
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct PlanChange {
+    pub effective_date: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Plan {
+    pub amount: f64, // Per month
+    pub billing_day: u32, // Day of the month periods start on, 1 to 28
+    pub start_date: String,
+    #[serde(default)]
+    pub end_date: Option<String>, // Last day of service
+    #[serde(default)]
+    pub changes: Vec<PlanChange>, // Sorted by date
+}
+
+// Part of a period at one plan amount
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Charge {
+    pub start: String,
+    pub end: String,
+    pub plan_amount: f64,
+    pub days: i64,
+    pub cycle_days: i64, // Days in the full monthly cycle
+    pub amount: f64, // plan_amount scaled by days / cycle_days, to the cent
+}
+
+impl Charge {
+    pub fn prorated(&self) -> bool {
+        self.days != self.cycle_days
+    }
+}
+
+// A period already invoiced and what was charged for it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct BilledPeriod {
+    pub start: String,
+    pub end: String,
+    pub amount: f64,
+    pub invoice_id: String,
+}
+
+fn parse(date: &str) -> Option<(i64, i64, i64)> {
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return None;
+    }
+    let (year, month, day) = (parts[0].parse().ok()?, parts[1].parse().ok()?, parts[2].parse().ok()?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some((year, month, day))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01
+fn to_days(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    era * 146097 + year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year - 719468
+}
+
+fn from_days(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn days(date: &str) -> i64 {
+    parse(date).map(|(y, m, d)| to_days(y, m, d)).unwrap_or_default()
+}
+
+pub fn add_days(date: &str, count: i64) -> String {
+    from_days(days(date) + count)
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+impl Plan {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.amount.is_finite() || self.amount < 0.0 {
+            return Err("Plan amount must be zero or more".to_string());
+        }
+        if !(1..=28).contains(&self.billing_day) {
+            return Err("Billing day must be between 1 and 28".to_string());
+        }
+        if parse(&self.start_date).is_none() {
+            return Err(format!("Invalid start date: {}", self.start_date));
+        }
+        if let Some(ref end) = self.end_date {
+            if parse(end).is_none() {
+                return Err(format!("Invalid end date: {}", end));
+            }
+            if days(end) < days(&self.start_date) {
+                return Err("Subscription can't end before it starts".to_string());
+            }
+        }
+        for change in &self.changes {
+            if parse(&change.effective_date).is_none() {
+                return Err(format!("Invalid plan change date: {}", change.effective_date));
+            }
+            if !change.amount.is_finite() || change.amount < 0.0 {
+                return Err("Plan amount must be zero or more".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Monthly amount in effect on a date
+    pub fn amount_on(&self, date: &str) -> f64 {
+        self.changes.iter()
+            .filter(|c| days(&c.effective_date) <= days(date))
+            .max_by_key(|c| days(&c.effective_date))
+            .map(|c| c.amount)
+            .unwrap_or(self.amount)
+    }
+
+    // First and last day (exclusive) of the monthly cycle containing a date
+    fn cycle(&self, date: &str) -> (i64, i64) {
+        let (year, month, day) = parse(date).unwrap_or((1970, 1, 1));
+        let billing_day = self.billing_day as i64;
+        let (year, month) = if day >= billing_day {
+            (year, month)
+        } else if month == 1 {
+            (year - 1, 12)
+        } else {
+            (year, month - 1)
+        };
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        (to_days(year, month, billing_day), to_days(next_year, next_month, billing_day))
+    }
+
+    // The billing period starting on a date: to the day before the next billing day,
+    // or the end date. None once the subscription has ended.
+    pub fn period(&self, start: &str) -> Option<(String, String)> {
+        if let Some(ref end_date) = self.end_date {
+            if days(start) > days(end_date) {
+                return None;
+            }
+        }
+        let mut end = self.cycle(start).1 - 1;
+        if let Some(ref end_date) = self.end_date {
+            end = end.min(days(end_date));
+        }
+        Some((start.to_string(), from_days(end)))
+    }
+
+    // Charges for a period, split wherever the plan amount changes within it
+    pub fn charges(&self, start: &str, end: &str) -> Vec<Charge> {
+        let (start, end) = (days(start), days(end));
+        let mut boundaries: Vec<i64> = self.changes.iter()
+            .map(|c| days(&c.effective_date))
+            .filter(|d| *d > start && *d <= end)
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+
+        let mut charges = Vec::new();
+        let mut segment_start = start;
+        for segment_end in boundaries.iter().map(|b| b - 1).chain(std::iter::once(end)) {
+            let (cycle_start, cycle_end) = self.cycle(&from_days(segment_start));
+            let cycle_days = cycle_end - cycle_start;
+            let segment_days = segment_end - segment_start + 1;
+            let plan_amount = self.amount_on(&from_days(segment_start));
+            charges.push(Charge {
+                start: from_days(segment_start),
+                end: from_days(segment_end),
+                plan_amount,
+                days: segment_days,
+                cycle_days,
+                amount: round_cents(plan_amount * segment_days as f64 / cycle_days as f64),
+            });
+            segment_start = segment_end + 1;
+        }
+        charges
+    }
+
+    // Amount owed for a period, leaving out any days after the end date
+    pub fn period_amount(&self, start: &str, end: &str) -> f64 {
+        let end = match self.end_date {
+            Some(ref end_date) if days(end_date) < days(end) => end_date.as_str(),
+            _ => end,
+        };
+        if days(end) < days(start) {
+            return 0.0;
+        }
+        round_cents(self.charges(start, end).iter().map(|c| c.amount).sum())
+    }
+}
+
+// Whether a date is on or before another, both YYYY-MM-DD
+pub fn on_or_before(date: &str, other: &str) -> bool {
+    days(date) <= days(other)
+}
@@ -0,0 +1,311 @@
+// Country tax-regime presets: tax names, rates, invoice wording and required fields
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum TaxRegime {
+    UkVat,
+    GermanUst,
+    GermanKleinunternehmer, // Small business exemption, § 19 UStG
+    UsSalesTax,
+    IndiaGst,
+    AustraliaGst,
+}
+
+pub const ALL_REGIMES: [TaxRegime; 6] = [
+    TaxRegime::UkVat,
+    TaxRegime::GermanUst,
+    TaxRegime::GermanKleinunternehmer,
+    TaxRegime::UsSalesTax,
+    TaxRegime::IndiaGst,
+    TaxRegime::AustraliaGst,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TaxRate {
+    pub name: String, // e.g. "Standard", "Reduced"
+    pub percent: f64,
+}
+
+// Fields an invoice must have before it can be marked Sent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum RequiredField {
+    InvoicerTaxId,
+    InvoiceeTaxId,
+    InvoiceeAddress,
+    SupplyDate, // Service period, or service dates on every line item
+    DueDate,
+}
+
+impl RequiredField {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RequiredField::InvoicerTaxId => "your tax ID",
+            RequiredField::InvoiceeTaxId => "the client's tax ID",
+            RequiredField::InvoiceeAddress => "the client's address",
+            RequiredField::SupplyDate => "the date or period of supply",
+            RequiredField::DueDate => "a due date",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TaxSettings {
+    #[serde(default)]
+    pub regime: Option<TaxRegime>, // Preset these settings came from, if any
+    #[serde(default = "default_tax_name")]
+    pub tax_name: String, // Shown on the tax row, e.g. "VAT (20%)"
+    #[serde(default = "default_tax_id_label")]
+    pub tax_id_label: String, // Shown before tax IDs, e.g. "GSTIN"
+    #[serde(default)]
+    pub rates: Vec<TaxRate>, // Rates offered when editing an invoice
+    #[serde(default)]
+    pub default_percent: f64, // Tax rate of new invoices
+    #[serde(default)]
+    pub split_names: Vec<String>, // Tax shown as equal parts under these names, e.g. CGST and SGST
+    #[serde(default)]
+    pub interstate_name: Option<String>, // Tax name instead of the split when the place of supply is another state, e.g. IGST
+    #[serde(default)]
+    pub document_title: Option<String>, // Replaces "Invoice" as the title of invoices, e.g. "Tax Invoice"
+    #[serde(default)]
+    pub wording: Option<String>, // Statement printed on every invoice, e.g. a VAT exemption note
+    #[serde(default)]
+    pub required_fields: Vec<RequiredField>,
+    #[serde(default)]
+    pub jurisdictions: Vec<Jurisdiction>, // Separately reported taxes, e.g. US state, county and city sales tax
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum JurisdictionLevel {
+    State,
+    County,
+    City,
+    District,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Jurisdiction {
+    pub id: String,
+    pub name: String, // e.g. "Travis County"
+    pub level: JurisdictionLevel,
+    pub percent: f64,
+}
+
+// A jurisdiction's tax as applied to an invoice; the rate is kept so later rate
+// changes don't alter issued invoices
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct AppliedTax {
+    pub jurisdiction_id: String,
+    pub name: String,
+    pub level: JurisdictionLevel,
+    pub percent: f64,
+}
+
+impl From<&Jurisdiction> for AppliedTax {
+    fn from(jurisdiction: &Jurisdiction) -> Self {
+        AppliedTax {
+            jurisdiction_id: jurisdiction.id.clone(),
+            name: jurisdiction.name.clone(),
+            level: jurisdiction.level,
+            percent: jurisdiction.percent,
+        }
+    }
+}
+
+// Tax invoiced and collected for one jurisdiction over a filing period
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct JurisdictionTotal {
+    pub jurisdiction_id: String,
+    pub name: String,
+    pub level: JurisdictionLevel,
+    pub invoices: usize,
+    pub taxable_sales: f64,
+    pub tax_invoiced: f64,
+    pub tax_collected: f64, // On invoices marked Paid
+}
+
+fn default_tax_name() -> String {
+    "Tax".to_string()
+}
+
+fn default_tax_id_label() -> String {
+    "Tax ID".to_string()
+}
+
+impl Default for TaxSettings {
+    fn default() -> Self {
+        TaxSettings {
+            regime: None,
+            tax_name: default_tax_name(),
+            tax_id_label: default_tax_id_label(),
+            rates: Vec::new(),
+            default_percent: 0.0,
+            split_names: Vec::new(),
+            interstate_name: None,
+            document_title: None,
+            wording: None,
+            required_fields: Vec::new(),
+            jurisdictions: Vec::new(),
+        }
+    }
+}
+
+fn rates(rates: &[(&str, f64)]) -> Vec<TaxRate> {
+    rates.iter()
+        .map(|(name, percent)| TaxRate { name: name.to_string(), percent: *percent })
+        .collect()
+}
+
+impl TaxRegime {
+    pub fn preset(&self) -> TaxSettings {
+        let regime = Some(*self);
+        match self {
+            TaxRegime::UkVat => TaxSettings {
+                regime,
+                tax_name: "VAT".to_string(),
+                tax_id_label: "VAT Reg. No.".to_string(),
+                rates: rates(&[("Standard", 20.0), ("Reduced", 5.0), ("Zero", 0.0)]),
+                default_percent: 20.0,
+                split_names: Vec::new(),
+                interstate_name: None,
+                document_title: None,
+                wording: None,
+                jurisdictions: Vec::new(),
+                required_fields: vec![RequiredField::InvoicerTaxId, RequiredField::InvoiceeAddress],
+            },
+            TaxRegime::GermanUst => TaxSettings {
+                regime,
+                tax_name: "USt.".to_string(),
+                tax_id_label: "USt-IdNr.".to_string(),
+                rates: rates(&[("Regelsatz", 19.0), ("Ermäßigt", 7.0), ("Steuerfrei", 0.0)]),
+                default_percent: 19.0,
+                split_names: Vec::new(),
+                interstate_name: None,
+                document_title: None,
+                wording: None,
+                jurisdictions: Vec::new(),
+                required_fields: vec![RequiredField::InvoicerTaxId, RequiredField::InvoiceeAddress, RequiredField::SupplyDate],
+            },
+            TaxRegime::GermanKleinunternehmer => TaxSettings {
+                regime,
+                tax_name: "USt.".to_string(),
+                tax_id_label: "Steuernummer".to_string(),
+                rates: rates(&[("Steuerfrei", 0.0)]),
+                default_percent: 0.0,
+                split_names: Vec::new(),
+                interstate_name: None,
+                document_title: None,
+                wording: Some("Gemäß § 19 UStG wird keine Umsatzsteuer berechnet.".to_string()),
+                jurisdictions: Vec::new(),
+                required_fields: vec![RequiredField::InvoicerTaxId, RequiredField::InvoiceeAddress, RequiredField::SupplyDate],
+            },
+            TaxRegime::UsSalesTax => TaxSettings {
+                regime,
+                tax_name: "Sales Tax".to_string(),
+                tax_id_label: "EIN".to_string(),
+                rates: Vec::new(), // Set per state and locality
+                default_percent: 0.0,
+                split_names: Vec::new(),
+                interstate_name: None,
+                document_title: None,
+                wording: None,
+                jurisdictions: Vec::new(),
+                required_fields: Vec::new(),
+            },
+            TaxRegime::IndiaGst => TaxSettings {
+                regime,
+                tax_name: "GST".to_string(),
+                tax_id_label: "GSTIN".to_string(),
+                rates: rates(&[("Nil", 0.0), ("5%", 5.0), ("12%", 12.0), ("18%", 18.0), ("28%", 28.0)]),
+                default_percent: 18.0,
+                split_names: vec!["CGST".to_string(), "SGST".to_string()],
+                interstate_name: Some("IGST".to_string()),
+                document_title: Some("Tax Invoice".to_string()),
+                wording: None,
+                jurisdictions: Vec::new(),
+                required_fields: vec![RequiredField::InvoicerTaxId, RequiredField::InvoiceeAddress],
+            },
+            TaxRegime::AustraliaGst => TaxSettings {
+                regime,
+                tax_name: "GST".to_string(),
+                tax_id_label: "ABN".to_string(),
+                rates: rates(&[("GST", 10.0), ("GST-free", 0.0)]),
+                default_percent: 10.0,
+                split_names: Vec::new(),
+                interstate_name: None,
+                document_title: Some("Tax Invoice".to_string()),
+                wording: None,
+                jurisdictions: Vec::new(),
+                required_fields: vec![RequiredField::InvoicerTaxId],
+            },
+        }
+    }
+}
+
+impl TaxSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tax_name.trim().is_empty() {
+            return Err("Tax name cannot be empty".to_string());
+        }
+        let percents = self.rates.iter().map(|r| r.percent).chain(std::iter::once(self.default_percent));
+        for percent in percents {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err("Tax rates must be between 0 and 100".to_string());
+            }
+        }
+        if self.interstate_name.as_ref().map(|n| n.trim().is_empty()).unwrap_or(false) {
+            return Err("Interstate tax name cannot be empty".to_string());
+        }
+        if self.split_names.len() == 1 {
+            return Err("Split the tax into at least two parts, or none".to_string());
+        }
+        for (index, jurisdiction) in self.jurisdictions.iter().enumerate() {
+            if jurisdiction.id.trim().is_empty() || jurisdiction.name.trim().is_empty() {
+                return Err("Jurisdictions need an ID and a name".to_string());
+            }
+            if !(0.0..=100.0).contains(&jurisdiction.percent) {
+                return Err(format!("Tax rate of {} must be between 0 and 100", jurisdiction.name));
+            }
+            if self.jurisdictions[..index].iter().any(|j| j.id == jurisdiction.id) {
+                return Err(format!("Duplicate jurisdiction ID: {}", jurisdiction.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Totals per jurisdiction from (taxes applied, taxable amount, paid) of each
+// invoice, ordered by level then name
+pub fn jurisdiction_report(invoices: &[(&[AppliedTax], f64, bool)]) -> Vec<JurisdictionTotal> {
+    let mut totals: Vec<JurisdictionTotal> = Vec::new();
+    for (taxes, taxable, paid) in invoices {
+        for tax in taxes.iter() {
+            let amount = taxable * tax.percent / 100.0;
+            let index = match totals.iter().position(|t| t.jurisdiction_id == tax.jurisdiction_id) {
+                Some(index) => index,
+                None => {
+                    totals.push(JurisdictionTotal {
+                        jurisdiction_id: tax.jurisdiction_id.clone(),
+                        name: tax.name.clone(),
+                        level: tax.level,
+                        invoices: 0,
+                        taxable_sales: 0.0,
+                        tax_invoiced: 0.0,
+                        tax_collected: 0.0,
+                    });
+                    totals.len() - 1
+                }
+            };
+            let total = &mut totals[index];
+            total.invoices += 1;
+            total.taxable_sales += taxable;
+            total.tax_invoiced += amount;
+            if *paid {
+                total.tax_collected += amount;
+            }
+        }
+    }
+    totals.sort_by(|a, b| (a.level as u8, &a.name).cmp(&(b.level as u8, &b.name)));
+    totals
+}
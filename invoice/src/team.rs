@@ -0,0 +1,95 @@
+// Billed revenue per team member from the line items credited to them, by month,
+// quarter or year, for sharing out profits
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum Period {
+    #[default]
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Period {
+    // The period an ISO date falls in, e.g. "2025-03", "2025-Q1" or "2025"
+    pub fn of(self, date: &str) -> String {
+        let year = date.get(..4).unwrap_or(date);
+        let month: u32 = date.get(5..7).and_then(|m| m.parse().ok()).unwrap_or(1);
+        match self {
+            Period::Month => format!("{}-{:02}", year, month),
+            Period::Quarter => format!("{}-Q{}", year, (month.clamp(1, 12) - 1) / 3 + 1),
+            Period::Year => year.to_string(),
+        }
+    }
+}
+
+// A line item credited to a team member, at its amount after discounts
+pub struct BilledLine {
+    pub member_id: String,
+    pub invoice_id: String,
+    pub date: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct PeriodRevenue {
+    pub period: String,
+    pub revenue: f64,
+    pub invoices: usize, // Invoices with lines credited to the member in the period
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+pub struct MemberRevenue {
+    pub member_id: String,
+    pub name: String,
+    pub revenue: f64,
+    pub periods: Vec<PeriodRevenue>, // Oldest first
+}
+
+// Revenue of every team member with lines billed, in the order the members are
+// given. Lines of unknown members are left out.
+pub fn revenue_by_member(members: &[(String, String)], lines: &[BilledLine], period: Period) -> Vec<MemberRevenue> {
+    let mut totals = Vec::new();
+    for (id, name) in members {
+        let mut periods: Vec<(PeriodRevenue, Vec<&str>)> = Vec::new();
+        for line in lines.iter().filter(|l| &l.member_id == id) {
+            let key = period.of(&line.date);
+            let index = match periods.iter().position(|(p, _)| p.period == key) {
+                Some(index) => index,
+                None => {
+                    periods.push((PeriodRevenue { period: key, revenue: 0.0, invoices: 0 }, Vec::new()));
+                    periods.len() - 1
+                }
+            };
+            let (revenue, invoices) = &mut periods[index];
+            revenue.revenue += line.amount;
+            if !invoices.contains(&line.invoice_id.as_str()) {
+                invoices.push(&line.invoice_id);
+            }
+        }
+        if periods.is_empty() {
+            continue;
+        }
+        let mut periods: Vec<PeriodRevenue> = periods.into_iter()
+            .map(|(mut revenue, invoices)| {
+                revenue.revenue = round_cents(revenue.revenue);
+                revenue.invoices = invoices.len();
+                revenue
+            })
+            .collect();
+        periods.sort_by(|a, b| a.period.cmp(&b.period));
+        totals.push(MemberRevenue {
+            member_id: id.clone(),
+            name: name.clone(),
+            revenue: round_cents(periods.iter().map(|p| p.revenue).sum()),
+            periods,
+        });
+    }
+    totals
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
@@ -0,0 +1,800 @@
+// Render context for custom invoice templates: every value a template can use
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RenderContext {
+    pub document: DocumentContext,
+    pub invoicer: PartyContext,
+    pub invoicee: PartyContext,
+    pub delivery_address: Option<DeliveryContext>, // Ship To, when delivery addresses are enabled
+    pub line_items: Vec<LineItemContext>,
+    pub totals: TotalsContext,
+    pub notes: Option<String>,
+    pub terms_text: Option<String>,
+    pub thank_you: Option<String>,
+    pub payment_info: Option<String>,
+    pub tax_wording: Option<String>, // Statement required by the tax regime, e.g. a VAT exemption note
+    pub currency: CurrencyContext,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DocumentContext {
+    pub title: String, // e.g. "Invoice", "Tax Invoice", "Estimate"
+    pub number: String,
+    pub date: String,
+    pub due_date: Option<String>,
+    pub payment_terms: Option<String>, // e.g. "Net 30", "Due on receipt" or "2/10 net 30"
+    pub service_period: Option<String>,
+    pub status: String,
+    pub copy_label: Option<String>, // e.g. "ORIGINAL FOR RECIPIENT"
+    pub place_of_supply: Option<String>,
+    pub purchase_order: Option<String>,
+    pub reference: Option<String>,
+    pub custom_fields: Vec<FieldContext>, // Invoice fields defined in settings that have a value
+}
+
+// The currency amounts are in, which the money filter formats them with
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CurrencyContext {
+    pub code: String, // ISO 4217, e.g. "EUR"
+    pub symbol: String,
+    pub decimals: usize,
+    pub symbol_after: bool, // e.g. "100.00 kr"
+}
+
+impl Default for CurrencyContext {
+    fn default() -> Self {
+        CurrencyContext { code: "USD".to_string(), symbol: "$".to_string(), decimals: 2, symbol_after: false }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PartyContext {
+    pub name: String,
+    pub company: Option<String>,
+    pub address: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub tax_id: Option<String>,
+    pub tax_id_label: String, // e.g. "VAT Reg. No.", "GSTIN"
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DeliveryContext {
+    pub name: String,
+    pub company: Option<String>,
+    pub address: String,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LineItemContext {
+    pub text_only: bool, // A heading or note; quantity, rate and amount are 0
+    pub late_fee: bool, // Charged for paying late, kept up to date while the invoice is overdue
+    pub section: Option<String>,
+    pub description: String,
+    pub item_code: Option<String>,
+    pub hsn_sac: Option<String>,
+    pub service_date: Option<String>,
+    pub quantity: f64,
+    pub unit: Option<String>,
+    pub rate: Option<f64>, // None, like every amount, in exports without amounts
+    pub list_rate: Option<f64>, // Catalog price when a client or volume price lowered the rate
+    pub discount_percent: f64,
+    pub amount: Option<f64>, // After the line discount
+    pub custom_fields: Vec<FieldContext>,
+}
+
+// A custom field with its value as printed, e.g. Yes/No for true/false
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FieldContext {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TotalsContext {
+    pub sections: Vec<SectionContext>, // In table order; empty when no line has a section
+    // Amounts are None, and the payment offers empty, in exports without amounts
+    pub subtotal: Option<f64>,
+    pub discount_percent: f64,
+    pub discount: Option<f64>,
+    pub shipping: Option<f64>, // None when the invoice has no shipping charge
+    pub taxes: Vec<TaxContext>,
+    pub total: Option<f64>,
+    pub deposits: Vec<DepositContext>,
+    pub balance_due: Option<f64>,
+    pub amount_in_words: Option<String>, // Of the balance due, in the invoice's language and currency
+    pub payment_methods: Vec<PaymentMethodContext>, // Empty once the invoice is paid
+    pub early_payment: Option<EarlyPaymentContext>, // None once the invoice is paid
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SectionContext {
+    pub name: String,
+    pub subtotal: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct EarlyPaymentContext {
+    pub terms: String, // e.g. "2/10 net 30"
+    pub pay_by: String, // Last day the discount applies
+    pub discount: f64,
+    pub amount: f64, // Balance due less the discount
+}
+
+// Balance due when paying by a method with a surcharge or discount
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PaymentMethodContext {
+    pub label: String,
+    pub terms: String, // e.g. "+2% + $0.30"
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TaxContext {
+    pub name: String, // e.g. "VAT", "CGST", "Travis County"
+    pub percent: f64,
+    pub amount: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DepositContext {
+    pub number: String, // Of the deposit invoice
+    pub amount: Option<f64>,
+}
+
+// Render context for price lists generated from the catalog
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PriceListContext {
+    pub title: String,
+    pub date: String,
+    pub valid_from: Option<String>,
+    pub valid_until: Option<String>,
+    pub issuer: PartyContext,
+    pub client: Option<String>, // Whose prices these are, if not the list prices
+    pub items: Vec<PriceListItemContext>,
+    pub notes: Option<String>,
+    pub currency: CurrencyContext,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PriceListItemContext {
+    pub name: String,
+    pub description: Option<String>,
+    pub unit: Option<String>,
+    pub rate: f64, // The client's price for a single unit
+    pub list_rate: Option<f64>, // Catalog price when the client's is lower
+    pub tiers: Vec<TierContext>, // Volume prices below the rate
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TierContext {
+    pub min_quantity: f64,
+    pub rate: f64,
+}
+
+// Built-in layout of price lists, styled by the default invoice stylesheet
+pub const PRICE_LIST_TEMPLATE: &str = r#"
+    <div class="header">
+        <div class="invoicer">
+            <h2>{{issuer.name}}</h2>
+            <div class="contact-info">
+                {{#if issuer.company}}<p>{{issuer.company}}</p>{{/if}}
+                <p>{{issuer.address}}</p>
+                {{#if issuer.email}}<p>{{issuer.email}}</p>{{/if}}
+                {{#if issuer.phone}}<p>{{issuer.phone}}</p>{{/if}}
+            </div>
+        </div>
+        <div class="invoice-details">
+            <h1>{{title}}</h1>
+            <p><strong>Date:</strong> {{date}}</p>
+            {{#if client}}<p><strong>Prepared for:</strong> {{client}}</p>{{/if}}
+            {{#if valid_from}}<p><strong>Valid from:</strong> {{valid_from}}</p>{{/if}}
+            {{#if valid_until}}<p><strong>Valid until:</strong> {{valid_until}}</p>{{/if}}
+        </div>
+    </div>
+
+    <table>
+        <thead>
+            <tr><th>Item</th><th>Unit</th><th>Price</th><th>Volume pricing</th></tr>
+        </thead>
+        <tbody>
+            {{#each items}}
+            <tr>
+                <td><strong>{{name}}</strong>{{#if description}}<br>{{description}}{{/if}}</td>
+                <td>{{unit}}</td>
+                <td>{{#if list_rate}}<s class="list-rate">{{list_rate | money}}</s> {{/if}}{{rate | money}}</td>
+                <td>{{#each tiers}}{{min_quantity}}+ at {{rate | money}}<br>{{/each}}</td>
+            </tr>
+            {{/each}}
+        </tbody>
+    </table>
+
+    {{#if notes}}<div class="notes"><p>{{notes}}</p></div>{{/if}}
+"#;
+
+// Every variable path in a context with its value, e.g. ("invoicer.name", "Acme Ltd").
+// Array elements appear once, from the first element, as "line_items[].description".
+pub fn variables(context: &Value) -> Vec<(String, Value)> {
+    let mut variables = Vec::new();
+    collect_variables(context, "", &mut variables);
+    variables
+}
+
+fn collect_variables(value: &Value, path: &str, variables: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                let child = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+                collect_variables(field, &child, variables);
+            }
+        }
+        Value::Array(items) => match items.first() {
+            Some(first) => collect_variables(first, &format!("{}[]", path), variables),
+            None => variables.push((path.to_string(), value.clone())),
+        },
+        _ => variables.push((path.to_string(), value.clone())),
+    }
+}
+
+// Template engine. Tags:
+//   {{ path }} or {{ path | filter }}  value, HTML-escaped; filters are money, upper and lower
+//   {{#each path}} ... {{/each}}        repeats for each element; {{this}} and {{@index}} inside
+//   {{#if path}} ... {{else}} ... {{/if}}, and {{#unless path}} ... {{/unless}}
+//   {{! comment }}
+// Names inside a block resolve against the current element first, then outer scopes.
+// money formats amounts in the context's currency.
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TemplateError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Each,
+    If,
+    Unless,
+}
+
+impl BlockKind {
+    fn name(&self) -> &'static str {
+        match self {
+            BlockKind::Each => "each",
+            BlockKind::If => "if",
+            BlockKind::Unless => "unless",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Variable { path: String, filters: Vec<String>, line: usize },
+    Block { kind: BlockKind, path: String, body: Vec<Node>, otherwise: Vec<Node>, line: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+// An open block while parsing
+struct Frame {
+    kind: BlockKind,
+    path: String,
+    line: usize,
+    body: Vec<Node>,
+    otherwise: Option<Vec<Node>>, // Some after {{else}}
+}
+
+impl Frame {
+    fn push(&mut self, node: Node) {
+        match self.otherwise {
+            Some(ref mut otherwise) => otherwise.push(node),
+            None => self.body.push(node),
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut root: Vec<Node> = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut rest = source;
+        let mut line = 1;
+
+        let error = |line: usize, message: String| TemplateError { line, message };
+
+        while let Some(start) = rest.find("{{") {
+            let text = &rest[..start];
+            line += text.matches('\n').count();
+            if !text.is_empty() {
+                let node = Node::Text(text.to_string());
+                match stack.last_mut() {
+                    Some(frame) => frame.push(node),
+                    None => root.push(node),
+                }
+            }
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| error(line, "Unclosed tag, expected }}".to_string()))?;
+            let tag = after[..end].trim();
+            let tag_line = line;
+            line += after[..end].matches('\n').count();
+            rest = &after[end + 2..];
+
+            if tag.starts_with('!') {
+                continue;
+            }
+            if let Some(open) = tag.strip_prefix('#') {
+                let (name, path) = open.split_once(char::is_whitespace).unwrap_or((open, ""));
+                let kind = match name {
+                    "each" => BlockKind::Each,
+                    "if" => BlockKind::If,
+                    "unless" => BlockKind::Unless,
+                    _ => return Err(error(tag_line, format!("Unknown block #{}", name))),
+                };
+                let path = path.trim();
+                if path.is_empty() {
+                    return Err(error(tag_line, format!("#{} needs a variable", name)));
+                }
+                stack.push(Frame { kind, path: path.to_string(), line: tag_line, body: Vec::new(), otherwise: None });
+                continue;
+            }
+            if let Some(close) = tag.strip_prefix('/') {
+                let frame = stack.pop()
+                    .ok_or_else(|| error(tag_line, format!("{{{{/{}}}}} without an open block", close.trim())))?;
+                if frame.kind.name() != close.trim() {
+                    return Err(error(tag_line, format!(
+                        "{{{{/{}}}}} closes #{} opened on line {}",
+                        close.trim(), frame.kind.name(), frame.line
+                    )));
+                }
+                let node = Node::Block {
+                    kind: frame.kind,
+                    path: frame.path,
+                    body: frame.body,
+                    otherwise: frame.otherwise.unwrap_or_default(),
+                    line: frame.line,
+                };
+                match stack.last_mut() {
+                    Some(parent) => parent.push(node),
+                    None => root.push(node),
+                }
+                continue;
+            }
+            if tag == "else" {
+                match stack.last_mut() {
+                    Some(frame) if frame.kind != BlockKind::Each && frame.otherwise.is_none() => {
+                        frame.otherwise = Some(Vec::new());
+                    }
+                    _ => return Err(error(tag_line, "{{else}} outside #if or #unless".to_string())),
+                }
+                continue;
+            }
+
+            let mut parts = tag.split('|').map(str::trim);
+            let path = parts.next().unwrap_or_default();
+            if path.is_empty() {
+                return Err(error(tag_line, "Empty tag".to_string()));
+            }
+            let node = Node::Variable {
+                path: path.to_string(),
+                filters: parts.map(str::to_string).collect(),
+                line: tag_line,
+            };
+            match stack.last_mut() {
+                Some(frame) => frame.push(node),
+                None => root.push(node),
+            }
+        }
+
+        if let Some(frame) = stack.last() {
+            return Err(error(frame.line, format!("#{} is never closed", frame.kind.name())));
+        }
+        if !rest.is_empty() {
+            root.push(Node::Text(rest.to_string()));
+        }
+        Ok(Template { nodes: root })
+    }
+
+    // Renders as much as it can; unknown variables and filters render as nothing
+    // and are reported with their line
+    pub fn render(&self, context: &Value) -> (String, Vec<TemplateError>) {
+        let mut output = String::new();
+        let mut errors = Vec::new();
+        let mut scopes = vec![(context.clone(), None)];
+        render_nodes(&self.nodes, &mut scopes, &mut output, &mut errors);
+        (output, errors)
+    }
+}
+
+type Scope = (Value, Option<usize>); // Value and its index in an #each
+
+fn lookup(path: &str, scopes: &[Scope]) -> Option<Value> {
+    if path == "this" {
+        return scopes.last().map(|(value, _)| value.clone());
+    }
+    if path == "@index" {
+        return scopes.iter().rev().find_map(|(_, index)| *index).map(Value::from);
+    }
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    scopes.iter().rev()
+        .find_map(|(value, _)| value.get(first))
+        .and_then(|value| segments.try_fold(value, |value, segment| value.get(segment)))
+        .cloned()
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map(|n| n != 0.0).unwrap_or(true),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+// e.g. "€1234.50" or "1234.50 kr"; the sign goes in front unless it rounds to zero
+fn format_money(amount: f64, currency: &CurrencyContext) -> String {
+    let digits = format!("{:.*}", currency.decimals, amount.abs());
+    let sign = if amount < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+    if currency.symbol_after {
+        format!("{}{} {}", sign, digits, currency.symbol)
+    } else {
+        format!("{}{}{}", sign, currency.symbol, digits)
+    }
+}
+
+fn display(value: &Value, filters: &[String], currency: &CurrencyContext) -> Result<String, String> {
+    let mut text = match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => format_number(n.as_f64().unwrap_or_default()),
+        Value::String(s) => s.clone(),
+        Value::Array(_) => return Err("is a list; use {{#each}}".to_string()),
+        Value::Object(_) => return Err("is an object; use one of its fields".to_string()),
+    };
+    for filter in filters {
+        text = match filter.as_str() {
+            "money" => match value {
+                Value::Null => String::new(), // An amount left out of the export
+                _ => match value.as_f64() {
+                    Some(n) => format_money(n, currency),
+                    None => return Err("money needs a number".to_string()),
+                },
+            },
+            "upper" => text.to_uppercase(),
+            "lower" => text.to_lowercase(),
+            other => return Err(format!("unknown filter {}", other)),
+        };
+    }
+    Ok(text)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// The context's currency, or dollars for contexts without one
+fn root_currency(scopes: &[Scope]) -> CurrencyContext {
+    scopes.first()
+        .and_then(|(root, _)| root.get("currency"))
+        .and_then(|currency| serde_json::from_value(currency.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<Scope>, output: &mut String, errors: &mut Vec<TemplateError>) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable { path, filters, line } => match lookup(path, scopes) {
+                Some(value) => match display(&value, filters, &root_currency(scopes)) {
+                    Ok(text) => output.push_str(&escape(&text)),
+                    Err(e) => errors.push(TemplateError { line: *line, message: format!("{} {}", path, e) }),
+                },
+                None => errors.push(TemplateError { line: *line, message: format!("Unknown variable {}", path) }),
+            },
+            Node::Block { kind, path, body, otherwise, line } => {
+                let Some(value) = lookup(path, scopes) else {
+                    errors.push(TemplateError { line: *line, message: format!("Unknown variable {}", path) });
+                    continue;
+                };
+                match kind {
+                    BlockKind::Each => {
+                        let Value::Array(items) = value else {
+                            errors.push(TemplateError { line: *line, message: format!("{} is not a list", path) });
+                            continue;
+                        };
+                        for (index, item) in items.into_iter().enumerate() {
+                            scopes.push((item, Some(index)));
+                            render_nodes(body, scopes, output, errors);
+                            scopes.pop();
+                        }
+                    }
+                    BlockKind::If | BlockKind::Unless => {
+                        let branch = if truthy(&value) == (*kind == BlockKind::If) { body } else { otherwise };
+                        render_nodes(branch, scopes, output, errors);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Sandboxing. Templates can't load anything from outside the document, so a
+// shared template can't send invoice data elsewhere: URLs in attributes and CSS
+// must point at the template's own assets ("asset:letterhead.png"), data: URIs
+// or in-page anchors, and scripts, frames, forms and links are refused.
+
+const FORBIDDEN_TAGS: [&str; 13] = [
+    "script", "iframe", "frame", "frameset", "object", "embed", "applet", "link", "meta", "base",
+    "form", "portal", "noscript",
+];
+const URL_ATTRIBUTES: [&str; 13] = [
+    "src", "href", "xlink:href", "srcset", "action", "formaction", "poster", "background", "data",
+    "ping", "cite", "longdesc", "manifest",
+];
+const ALLOWED_URL_PREFIXES: [&str; 3] = ["asset:", "data:", "#"];
+
+// Prefix of asset references; replaced with the asset's data URI when rendering
+pub const ASSET_SCHEME: &str = "asset:";
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+// Offsets of `needle` in `haystack` that start a word (not preceded by a letter,
+// digit, dash or colon)
+fn word_offsets<'a>(haystack: &'a str, needle: &'a str) -> impl Iterator<Item = usize> + 'a {
+    haystack.match_indices(needle).map(|(offset, _)| offset).filter(move |&offset| {
+        haystack[..offset].chars().next_back()
+            .map(|c| !(c.is_ascii_alphanumeric() || c == '-' || c == ':' || c == '_'))
+            .unwrap_or(true)
+    })
+}
+
+// The value following an attribute name or "url(", with quotes and whitespace skipped
+fn value_after(text: &str) -> &str {
+    text.trim_start().trim_start_matches(['"', '\'']).trim_start()
+}
+
+fn allowed_url(value: &str) -> bool {
+    ALLOWED_URL_PREFIXES.iter().any(|prefix| value.starts_with(prefix))
+}
+
+// Byte ranges of <style> element contents and style attribute values
+fn style_spans(lower: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    for (offset, _) in lower.match_indices("<style") {
+        let start = offset + "<style".len();
+        if lower[start..].starts_with(|c: char| c.is_whitespace() || c == '>') {
+            let end = lower[start..].find("</style").map(|end| start + end).unwrap_or(lower.len());
+            spans.push((start, end));
+        }
+    }
+    for offset in word_offsets(lower, "style") {
+        let rest = lower[offset + "style".len()..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else { continue };
+        let value = value.trim_start();
+        let start = lower.len() - value.len();
+        let end = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].find(quote).map(|end| end + 2).unwrap_or(value.len()),
+            _ => value.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(value.len()),
+        };
+        spans.push((start, start + end));
+    }
+    spans
+}
+
+// Everything in a template that could load or send to an outside resource
+pub fn sandbox_violations(source: &str) -> Vec<TemplateError> {
+    // ASCII lowercasing keeps byte offsets, so lines can be found in the original
+    let lower = source.to_ascii_lowercase();
+    let mut violations = Vec::new();
+    let mut violation = |offset: usize, message: String| {
+        violations.push(TemplateError { line: line_at(source, offset), message });
+    };
+
+    for tag in FORBIDDEN_TAGS {
+        for (offset, _) in lower.match_indices(&format!("<{}", tag)) {
+            let next = lower[offset + tag.len() + 1..].chars().next();
+            if next.map(|c| c.is_whitespace() || c == '>' || c == '/').unwrap_or(true) {
+                violation(offset, format!("<{}> is not allowed in templates", tag));
+            }
+        }
+    }
+    for scheme in ["javascript:", "vbscript:"] {
+        for (offset, _) in lower.match_indices(scheme) {
+            violation(offset, format!("{} URLs are not allowed", scheme.trim_end_matches(':')));
+        }
+    }
+    for (offset, _) in lower.match_indices("@import") {
+        violation(offset, "@import is not allowed; put the styles in the template".to_string());
+    }
+    for (offset, _) in lower.match_indices("image-set(") {
+        violation(offset, "image-set() is not allowed; use url(asset:...)".to_string());
+    }
+    // Event handler attributes such as onload= and onerror=
+    for offset in word_offsets(&lower, "on") {
+        let rest = &lower[offset + 2..];
+        let name_len = rest.chars().take_while(|c| c.is_ascii_lowercase()).count();
+        let after_attribute = lower[..offset].ends_with(|c: char| c.is_whitespace() || c == '/' || c == '"' || c == '\'');
+        if name_len > 0 && after_attribute && rest[name_len..].trim_start().starts_with('=') {
+            violation(offset, format!("Event handler on{} is not allowed", &rest[..name_len]));
+        }
+    }
+    for attribute in URL_ATTRIBUTES {
+        for offset in word_offsets(&lower, attribute) {
+            let rest = lower[offset + attribute.len()..].trim_start();
+            let Some(value) = rest.strip_prefix('=') else { continue };
+            if !allowed_url(value_after(value)) {
+                violation(offset, format!("{} must be an asset:, data: or # URL", attribute));
+            }
+        }
+    }
+    for (offset, _) in lower.match_indices("url(") {
+        if !allowed_url(value_after(&lower[offset + 4..])) {
+            violation(offset, "url() must be an asset:, data: or # URL".to_string());
+        }
+    }
+    // CSS escapes such as \75rl( or @\69mport would spell the above past these checks
+    for (start, end) in style_spans(&lower) {
+        if let Some(index) = lower[start..end].find('\\') {
+            violation(start + index, "Backslash escapes are not allowed in styles".to_string());
+        }
+    }
+
+    violations.sort_by_key(|v| v.line);
+    violations
+}
+
+// Names of the assets a template refers to, e.g. "letterhead.png" for "asset:letterhead.png"
+pub fn asset_references(source: &str) -> Vec<String> {
+    let mut names: Vec<String> = source.match_indices(ASSET_SCHEME)
+        .map(|(offset, _)| {
+            source[offset + ASSET_SCHEME.len()..].chars()
+                .take_while(|c| valid_asset_char(*c))
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Replaces asset references in rendered output with (name, data URI) pairs;
+// longer names first so "logo.png" doesn't match inside "logo.png2"
+pub fn embed_assets(html: &str, assets: &[(String, String)]) -> String {
+    let mut assets: Vec<&(String, String)> = assets.iter().collect();
+    assets.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    assets.iter().fold(html.to_string(), |html, (name, uri)| {
+        html.replace(&format!("{}{}", ASSET_SCHEME, name), uri)
+    })
+}
+
+// Assets: images for letterheads and logos, and fonts
+
+pub const MAX_ASSET_BYTES: usize = 2 * 1024 * 1024;
+pub const MAX_ASSETS: usize = 20;
+
+fn valid_asset_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-'
+}
+
+// Content type of an asset from its file extension, if it's a kind templates may embed.
+// SVG is left out as it can carry scripts.
+pub fn asset_content_type(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "woff2" => Some("font/woff2"),
+        "woff" => Some("font/woff"),
+        "ttf" => Some("font/ttf"),
+        "otf" => Some("font/otf"),
+        _ => None,
+    }
+}
+
+pub fn validate_asset(name: &str, size: usize) -> Result<&'static str, String> {
+    if name.is_empty() || name.len() > 64 || name.starts_with('.') || !name.chars().all(valid_asset_char) {
+        return Err(format!("Invalid asset name {:?}: use letters, digits, dots, dashes and underscores", name));
+    }
+    let content_type = asset_content_type(name)
+        .ok_or_else(|| format!("{}: only PNG, JPEG, GIF, WebP and WOFF/WOFF2/TTF/OTF fonts can be embedded", name))?;
+    if size > MAX_ASSET_BYTES {
+        return Err(format!("{} is larger than {} MB", name, MAX_ASSET_BYTES / (1024 * 1024)));
+    }
+    Ok(content_type)
+}
+
+// A template with its assets in one file, for sharing
+
+pub const BUNDLE_FORMAT: &str = "invoice-template-bundle";
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TemplateBundle {
+    pub format: String,
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub source: String,
+    #[serde(default)]
+    pub assets: Vec<BundleAsset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BundleAsset {
+    pub name: String,
+    pub content_type: String,
+    pub data: String, // Base64
+}
+
+impl TemplateBundle {
+    // Checks everything except asset contents, which the caller decodes
+    pub fn validate(&self) -> Result<(), String> {
+        if self.format != BUNDLE_FORMAT {
+            return Err("Not a template bundle".to_string());
+        }
+        if self.version > BUNDLE_VERSION {
+            return Err(format!("Bundle version {} needs a newer version of the app", self.version));
+        }
+        if self.name.trim().is_empty() {
+            return Err("Template name cannot be empty".to_string());
+        }
+        if self.assets.len() > MAX_ASSETS {
+            return Err(format!("A template can have at most {} assets", MAX_ASSETS));
+        }
+        for (index, asset) in self.assets.iter().enumerate() {
+            // Base64 is 4/3 the size of the data
+            let content_type = validate_asset(&asset.name, asset.data.len() / 4 * 3)?;
+            if asset.content_type != content_type {
+                return Err(format!("{} is labelled {} but its name says {}", asset.name, asset.content_type, content_type));
+            }
+            if self.assets[..index].iter().any(|a| a.name == asset.name) {
+                return Err(format!("Duplicate asset {}", asset.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Parses and sandbox-checks a template source; all problems are returned together
+pub fn check_source(source: &str) -> Result<Template, Vec<TemplateError>> {
+    let violations = sandbox_violations(source);
+    match Template::parse(source) {
+        Ok(template) if violations.is_empty() => Ok(template),
+        Ok(_) => Err(violations),
+        Err(error) => {
+            let mut errors = violations;
+            errors.push(error);
+            errors.sort_by_key(|e| e.line);
+            Err(errors)
+        }
+    }
+}
@@ -0,0 +1,1279 @@
+// Endpoint handlers run off-node, against an in-memory store, a manual clock and a
+// fixed identity
+
+use super::*;
+use host::{FixedIdentity, ManualClock};
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use store::{InvoiceStore, MemoryStore};
+
+const NOW: u64 = 1_736_942_400; // 2025-01-15T12:00:00Z
+
+// The store, clock and identity are installed per thread, but the encryption key and
+// the diagnostics log are process-wide, so tests take turns
+static TURN: Mutex<()> = Mutex::new(());
+
+struct Harness {
+    app: AppState,
+    store: MemoryStore,
+    clock: ManualClock,
+    _turn: MutexGuard<'static, ()>,
+}
+
+fn harness() -> Harness {
+    let turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+    let store = MemoryStore::default();
+    let clock = ManualClock::at(NOW);
+    store::install(Some(Box::new(store.clone())));
+    storage::set_key(&storage::EncryptionMode::Off, None);
+    host::install_caller(None);
+    host::install_clock(Some(Box::new(clock.clone())));
+    host::install_identity(Some(Box::new(FixedIdentity(
+        "tester.os@invoice:invoice:nick.hypr".parse().unwrap(),
+    ))));
+    Harness { app: AppState::default(), store, clock, _turn: turn }
+}
+
+// Handlers only wait on the node when they message it, so one poll finishes them
+fn run<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("Handler waited on the node"),
+    }
+}
+
+fn contact(name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "company": null,
+        "address": "1 Main St",
+        "email": null,
+        "phone": null,
+        "logo_path": null,
+    })
+}
+
+fn settings(prefix: &str, next_number: u32) -> String {
+    settings_with(prefix, next_number, serde_json::json!({}))
+}
+
+// Settings with extra fields set
+fn settings_with(prefix: &str, next_number: u32, extra: serde_json::Value) -> String {
+    let mut settings = serde_json::json!({
+        "invoicer": contact("Me"),
+        "invoicee": contact("Client"),
+        "payment_info": null,
+        "payment_image_path": null,
+        "invoice_number_prefix": prefix,
+        "next_invoice_number": next_number,
+    });
+    for (key, value) in extra.as_object().unwrap() {
+        settings[key] = value.clone();
+    }
+    settings.to_string()
+}
+
+fn create(app: &mut AppState, payload: serde_json::Value) -> Invoice {
+    let response = run(app.create_invoice(payload.to_string())).unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+#[test]
+fn totals_apply_line_and_invoice_discounts_then_tax() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "description": "Design", "quantity": 10.0, "rate": 100.0 },
+            { "description": "Hosting", "quantity": 1.0, "rate": 50.0, "discount_percent": 20.0 },
+        ],
+        "discount_percent": 10.0,
+        "tax_percent": 8.0,
+    }));
+    // (1000 + 40) less 10% is 936, plus 8% tax
+    assert_eq!(calculate_invoice_total(&invoice), Money::from_cents(101_088));
+    assert_eq!(h.app.invoices[&invoice.id].total, Money::from_cents(101_088));
+}
+
+#[test]
+fn compound_tax_lines_are_charged_on_earlier_taxes() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Consulting", "quantity": 1.0, "rate": 100.0 }],
+        "tax_lines": [
+            { "name": "GST", "percent": 5.0, "compound": false },
+            { "name": "QST", "percent": 10.0, "compound": true },
+        ],
+    }));
+    assert_eq!(invoice.tax_percent, 15.0);
+    // 100 + 5 GST + 10.50 QST on 105
+    assert_eq!(calculate_invoice_total(&invoice), Money::from_cents(11_550));
+}
+
+#[test]
+fn totals_round_to_the_currency_minor_unit() {
+    let mut h = harness();
+    let yen = create(&mut h.app, serde_json::json!({
+        "currency": "JPY",
+        "line_items": [
+            { "description": "Design", "quantity": 1.0, "rate": 1000.0, "discount_percent": 15.0 },
+            { "description": "Prints", "quantity": 1.0, "rate": 99.5 },
+        ],
+        "tax_percent": 7.0,
+    }));
+    // 850 + 100 (from 99.5), then 66.5 tax rounded to 67; no fractions of a yen anywhere
+    assert_eq!(line_amounts(&yen), vec![Money::from_f64(850.0), Money::from_f64(100.0)]);
+    assert_eq!(subtotal(&yen), Money::from_f64(950.0));
+    assert_eq!(calculate_invoice_total(&yen), Money::from_f64(1017.0));
+    assert_eq!(tax_amounts(&yen, &[("Tax".to_string(), 7.0)]), vec![Money::from_f64(67.0)]);
+
+    let dinars = create(&mut h.app, serde_json::json!({
+        "currency": "KWD",
+        "line_items": [{ "description": "Consulting", "quantity": 2.0, "rate": 1.2345 }],
+        "tax_percent": 5.0,
+    }));
+    // 2.469 plus 0.123 tax (from 0.12345), kept to the fils
+    assert_eq!(subtotal(&dinars), Money::from_f64(2.469));
+    assert_eq!(calculate_invoice_total(&dinars), Money::from_f64(2.592));
+    assert_eq!(h.app.invoices[&dinars.id].total.to_string(), "2.592");
+    let html = h.app.generate_invoice_html(&dinars, &ExportOptions::default());
+    assert!(html.contains("2.592 KWD"));
+}
+
+#[test]
+fn numbers_follow_the_sequence_and_skip_manual_ones() {
+    let mut h = harness();
+    run(h.app.update_settings(settings("ACME-", 7))).unwrap();
+    let manual = create(&mut h.app, serde_json::json!({ "number": "ACME-0008" }));
+    assert_eq!(manual.number, "ACME-0008");
+
+    let first = create(&mut h.app, serde_json::json!({}));
+    let second = create(&mut h.app, serde_json::json!({}));
+    assert_eq!(first.number, "ACME-0007");
+    assert_eq!(second.number, "ACME-0009");
+
+    let duplicate = run(h.app.create_invoice(serde_json::json!({ "number": "acme-0007" }).to_string()));
+    assert!(duplicate.is_err());
+
+    // The advanced counter is saved with the settings
+    let saved = h.store.load_settings().unwrap().unwrap();
+    assert_eq!(saved["next_invoice_number"], 10);
+}
+
+#[test]
+fn undo_and_redo_restore_edits() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "notes": "First" }));
+
+    let mut edited = serde_json::to_value(&invoice).unwrap();
+    edited["notes"] = "Second".into();
+    h.clock.advance(60);
+    run(h.app.update_invoice(edited.to_string())).unwrap();
+    assert_eq!(h.app.current_invoice.as_ref().unwrap().notes.as_deref(), Some("Second"));
+
+    let undone: Invoice = serde_json::from_str(&run(h.app.undo()).unwrap()).unwrap();
+    assert_eq!(undone.notes.as_deref(), Some("First"));
+    let redone: Invoice = serde_json::from_str(&run(h.app.redo()).unwrap()).unwrap();
+    assert_eq!(redone.notes.as_deref(), Some("Second"));
+    assert!(run(h.app.redo()).is_err());
+
+    // A rejected edit leaves both stacks as they were
+    run(h.app.undo()).unwrap();
+    let mut rejected = serde_json::to_value(&invoice).unwrap();
+    rejected["currency"] = "dollars".into();
+    assert!(run(h.app.update_invoice(rejected.to_string())).is_err());
+    let redone: Invoice = serde_json::from_str(&run(h.app.redo()).unwrap()).unwrap();
+    assert_eq!(redone.notes.as_deref(), Some("Second"));
+    let undone: Invoice = serde_json::from_str(&run(h.app.undo()).unwrap()).unwrap();
+    assert_eq!(undone.notes.as_deref(), Some("First"));
+}
+
+#[test]
+fn invoices_and_settings_survive_a_restart() {
+    let mut h = harness();
+    run(h.app.update_settings(settings("INV-", 1))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Support", "quantity": 2.0, "rate": 75.0 }],
+    }));
+    assert!(h.store.invoice(&invoice.id).is_some());
+
+    let mut restarted = AppState::default();
+    let settings: InvoiceSettings = serde_json::from_value(h.store.load_settings().unwrap().unwrap()).unwrap();
+    assert_eq!(settings.next_invoice_number, 2);
+    restarted.load_invoice_summaries();
+    assert_eq!(restarted.invoices[&invoice.id].total, Money::from_cents(15_000));
+
+    let loaded: Invoice = serde_json::from_str(
+        &run(restarted.get_invoice(serde_json::to_string(&invoice.id).unwrap())).unwrap(),
+    ).unwrap();
+    assert_eq!(loaded.line_items.len(), 1);
+    assert_eq!(loaded.number, invoice.number);
+
+    run(restarted.delete_invoice(serde_json::to_string(&invoice.id).unwrap())).unwrap();
+    assert!(h.store.invoice(&invoice.id).is_none());
+}
+
+#[test]
+fn dates_come_from_the_clock() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "payment_terms": { "Net": 30 } }));
+    assert_eq!(invoice.date, "2025-01-15");
+    assert_eq!(invoice.due_date.as_deref(), Some("2025-02-14"));
+    assert_eq!(invoice.created_at, NOW);
+
+    h.clock.advance(86_400);
+    let next = create(&mut h.app, serde_json::json!({}));
+    assert_eq!(next.date, "2025-01-16");
+}
+
+#[test]
+fn due_dates_roll_off_weekends_and_holidays() {
+    let mut h = harness();
+    run(h.app.update_settings(settings_with("INV-", 1, serde_json::json!({
+        "roll_due_dates": true,
+        "holidays": ["2025-01-27"],
+        "payment_terms": { "Net": 10 },
+    })))).unwrap();
+    // Net 10 from Wednesday the 15th lands on Saturday; Monday is a holiday
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    assert_eq!(invoice.due_date.as_deref(), Some("2025-01-28"));
+}
+
+#[test]
+fn edits_are_stamped_with_one_instant() {
+    let mut h = harness();
+    create(&mut h.app, serde_json::json!({}));
+    h.clock.advance(90);
+    run(h.app.add_line_item()).unwrap();
+    let invoice = h.app.current_invoice.clone().unwrap();
+    assert_eq!(invoice.updated_at, NOW + 90);
+    assert_eq!(invoice.line_items[0].id, format!("item-{}", (NOW + 90) * 1000));
+}
+
+#[test]
+fn subscriptions_bill_each_period_once_it_starts() {
+    let mut h = harness();
+    run(h.app.update_settings(settings("SUB-", 1))).unwrap();
+    run(h.app.save_subscription(serde_json::json!({
+        "name": "Hosting",
+        "invoicee": contact("Client"),
+        "amount": 100.0,
+        "billing_day": 1,
+        "start_date": "2025-01-01",
+    }).to_string())).unwrap();
+
+    let created: Vec<String> = serde_json::from_str(&run(h.app.check_subscriptions()).unwrap()).unwrap();
+    assert_eq!(created.len(), 1);
+    assert_eq!(h.app.invoices[&created[0]].date, "2025-01-15");
+    assert_eq!(h.app.invoices[&created[0]].total, Money::from_cents(10_000));
+    assert_eq!(run(h.app.check_subscriptions()).unwrap(), "[]");
+
+    // February's period starts on the 1st
+    h.clock.advance(16 * 86_400);
+    assert_eq!(run(h.app.check_subscriptions()).unwrap(), "[]");
+    h.clock.advance(86_400);
+    let created: Vec<String> = serde_json::from_str(&run(h.app.check_subscriptions()).unwrap()).unwrap();
+    assert_eq!(created.len(), 1);
+    assert_eq!(h.app.invoices[&created[0]].date, "2025-02-01");
+}
+
+#[test]
+fn reminders_escalate_as_an_invoice_goes_overdue() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "due_date": "2025-01-20" }));
+    let due = days_from_date(invoice.due_date.as_deref().unwrap()).unwrap();
+    let policy = reminders::ReminderPolicy::default();
+    let days_overdue = || (h.app.local_time(host::now()) / 86_400) as i64 - due;
+
+    assert!(policy.next_step(&[], days_overdue()).is_none());
+    h.clock.advance(6 * 86_400);
+    assert_eq!(policy.next_step(&[], days_overdue()).map(|(step, _)| step), Some(0));
+    h.clock.advance(14 * 86_400);
+    assert_eq!(policy.next_step(&[], days_overdue()).map(|(step, _)| step), Some(1));
+}
+
+#[test]
+fn shipping_is_added_after_discount_and_taxed_only_when_taxable() {
+    let mut h = harness();
+    let payload = |taxable: bool| serde_json::json!({
+        "line_items": [{ "description": "Chair", "quantity": 2.0, "rate": 100.0 }],
+        "discount_percent": 10.0,
+        "tax_percent": 10.0,
+        "shipping": { "amount": 20.0, "taxable": taxable },
+    });
+    // 180 after discount, 20 shipping, and 10% tax on 180 or on 200
+    let untaxed = create(&mut h.app, payload(false));
+    assert_eq!(h.app.invoices[&untaxed.id].total, Money::from_cents(21_800));
+    let taxed = create(&mut h.app, payload(true));
+    assert_eq!(h.app.invoices[&taxed.id].total, Money::from_cents(22_000));
+
+    let negative = serde_json::json!({ "shipping": { "amount": -5.0 } });
+    assert!(run(h.app.create_invoice(negative.to_string())).is_err());
+}
+
+#[test]
+fn rounding_follows_the_configured_rules() {
+    let mut h = harness();
+    let lines = serde_json::json!({
+        "line_items": [
+            { "description": "A", "quantity": 1.0, "rate": 0.125 },
+            { "description": "B", "quantity": 1.0, "rate": 0.125 },
+            { "description": "C", "quantity": 1.0, "rate": 0.125 },
+        ],
+    });
+    let mut total = |stage: &str, mode: &str| {
+        let extra = serde_json::json!({ "rounding": { "stage": stage, "mode": mode } });
+        run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+        let invoice = create(&mut h.app, lines.clone());
+        h.app.invoices[&invoice.id].total
+    };
+    assert_eq!(total("PerLine", "HalfUp"), Money::from_cents(39));
+    assert_eq!(total("PerLine", "HalfEven"), Money::from_cents(36));
+    assert_eq!(total("AtTotal", "HalfUp"), Money::from_cents(38));
+    assert_eq!(total("AtTotal", "HalfEven"), Money::from_cents(38));
+}
+
+#[test]
+fn line_shares_and_hsn_taxes_follow_the_invoice_rounding() {
+    let mut h = harness();
+    let lines = serde_json::json!({
+        "line_items": [
+            { "description": "A", "quantity": 1.0, "rate": 0.125, "hsn_sac": "9983" },
+            { "description": "B", "quantity": 1.0, "rate": 0.125, "hsn_sac": "9983" },
+            { "description": "C", "quantity": 1.0, "rate": 0.125, "hsn_sac": "9983" },
+            { "description": "D", "quantity": 1.0, "rate": 0.5, "hsn_sac": "9984" },
+        ],
+    });
+    for stage in ["PerLine", "AtTotal"] {
+        let extra = serde_json::json!({ "rounding": { "stage": stage, "mode": "HalfEven" } });
+        run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+        let invoice = create(&mut h.app, lines.clone());
+        let shares = line_taxable_amounts(&invoice);
+        assert_eq!(shares.iter().sum::<Money>(), taxable_amount(&invoice), "{}", stage);
+        if stage == "PerLine" {
+            assert_eq!(shares[0], Money::from_cents(12));
+            // 5% of 0.50 is 0.025, which rounds to the even cent
+            let html = hsn_summary_html(&invoice, &[("IGST".to_string(), 5.0)]);
+            assert!(html.contains("<td>9984</td><td>$0.50</td><td>5%</td><td>$0.02</td>"));
+        }
+    }
+}
+
+#[test]
+fn persistence_failures_are_kept_for_diagnostics() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    // A store that has lost the invoice the summaries still list
+    store::install(Some(Box::new(MemoryStore::default())));
+    assert!(h.app.load_invoice(&invoice.id).is_err());
+
+    let latest = &diagnostics::last_errors()[0];
+    assert_eq!(latest.operation, "load_invoice");
+    assert_eq!(latest.invoice_id.as_deref(), Some(invoice.id.as_str()));
+    assert!(diagnostics::recent(diagnostics::Level::Error).iter().any(|e| e.message == latest.message));
+}
+
+#[test]
+fn health_degrades_after_a_storage_error() {
+    let mut h = harness();
+    // Any errors left by earlier tests have aged out
+    h.clock.advance(3600);
+    let status = |app: &AppState| {
+        let health: serde_json::Value = serde_json::from_str(&run(app.health()).unwrap()).unwrap();
+        health["status"].as_str().unwrap().to_string()
+    };
+    assert_eq!(status(&h.app), "ok");
+
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    let before = diagnostics::error_count("store");
+    store::install(Some(Box::new(MemoryStore::default())));
+    assert!(h.app.load_invoice(&invoice.id).is_err());
+    assert_eq!(status(&h.app), "degraded");
+
+    let metrics: serde_json::Value = serde_json::from_str(&run(h.app.get_metrics()).unwrap()).unwrap();
+    assert_eq!(metrics["invoices"], 1);
+    assert_eq!(metrics["storage_errors"], before + 1);
+}
+
+#[test]
+fn units_default_from_settings_and_show_with_the_quantity() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "default_unit": "hours" });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "description": "Design", "quantity": 7.5, "rate": 100.0 },
+            { "description": "Cable", "quantity": 3.0, "rate": 5.0, "unit": "m" },
+        ],
+    }));
+    let units: Vec<_> = invoice.line_items.iter().map(|item| item.unit.as_deref()).collect();
+    assert_eq!(units, vec![Some("hours"), Some("m")]);
+    assert_eq!(format_quantity(&invoice.line_items[0]), "7.5 hours");
+}
+
+#[test]
+fn unreadable_invoices_are_quarantined_and_recovered_when_a_copy_is_held() {
+    let mut h = harness();
+    let lost = create(&mut h.app, serde_json::json!({}));
+    let open = create(&mut h.app, serde_json::json!({}));
+    h.store.put(&lost.id, serde_json::json!("not an invoice"));
+    h.store.put(&open.id, serde_json::json!({ "id": open.id }));
+
+    // Restarting with the state saved before, in which the second is still open
+    h.app.load_invoice_summaries();
+    assert_eq!(h.store.quarantined(&lost.id), Some(serde_json::json!("not an invoice")));
+    assert!(h.store.invoice(&lost.id).is_none());
+    assert!(h.store.quarantined(&open.id).is_some());
+    assert_eq!(h.store.invoice(&open.id), Some(serde_json::to_value(&open).unwrap()));
+
+    let recovered: Vec<_> = h.app.quarantined.iter()
+        .map(|q| (q.invoice_id.as_deref(), q.recovered_from.as_deref()))
+        .collect();
+    assert_eq!(recovered.len(), 2);
+    assert!(recovered.contains(&(Some(lost.id.as_str()), None)));
+    assert!(recovered.contains(&(Some(open.id.as_str()), Some("open invoice"))));
+}
+
+#[test]
+fn service_periods_are_validated_and_get_a_column_only_when_used() {
+    let mut h = harness();
+    let backwards = serde_json::json!({
+        "line_items": [{ "service_date_start": "2025-02-28", "service_date_end": "2025-02-01" }],
+    });
+    assert!(run(h.app.create_invoice(backwards.to_string())).is_err());
+
+    let undated = create(&mut h.app, serde_json::json!({ "line_items": [{ "description": "Fee" }] }));
+    let html = h.app.generate_invoice_html(&undated, &ExportOptions::default());
+    assert!(!html.contains("<th>Period</th>"));
+
+    let dated = create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "description": "Audit", "service_date_start": "2025-02-01", "service_date_end": "2025-02-28" },
+            { "description": "Fee" },
+        ],
+    }));
+    let html = h.app.generate_invoice_html(&dated, &ExportOptions::default());
+    assert!(html.contains("<th>Period</th>"));
+    assert!(html.contains("<td>2025-02-01 &ndash; 2025-02-28</td>"));
+
+    let mut edited = serde_json::to_value(&dated).unwrap();
+    edited["line_items"][0]["service_date_end"] = "2025-01-31".into();
+    assert!(run(h.app.update_invoice(edited.to_string())).is_err());
+    assert_eq!(h.app.load_invoice(&dated.id).unwrap().line_items[0].service_date_end.as_deref(), Some("2025-02-28"));
+}
+
+#[test]
+fn purchase_orders_default_per_client_and_print_under_the_number() {
+    let mut h = harness();
+    let mut orders = serde_json::Map::new();
+    orders.insert("globex".to_string(), serde_json::json!("PO-1001"));
+    let extra = serde_json::json!({ "client_purchase_orders": orders });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+
+    let defaulted = create(&mut h.app, serde_json::json!({ "invoicee": contact("Globex") }));
+    assert_eq!(defaulted.purchase_order.as_deref(), Some("PO-1001"));
+    let quoted = create(&mut h.app, serde_json::json!({
+        "invoicee": contact("Globex"),
+        "purchase_order": "PO-2002",
+        "reference": "Project <Falcon>",
+    }));
+    assert_eq!(quoted.purchase_order.as_deref(), Some("PO-2002"));
+    let other = create(&mut h.app, serde_json::json!({ "invoicee": contact("Initech") }));
+    assert_eq!(other.purchase_order, None);
+
+    let html = h.app.generate_invoice_html(&quoted, &ExportOptions::default());
+    assert!(html.contains("<strong>PO #:</strong> PO-2002"));
+    assert!(html.contains("<strong>Reference:</strong> Project &lt;Falcon&gt;"));
+}
+
+#[test]
+fn errors_quote_the_request_their_log_entries_carry() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    h.app.current_invoice = None;
+    store::install(Some(Box::new(MemoryStore::default())));
+
+    let error = run(h.app.get_invoice(serde_json::to_string(&invoice.id).unwrap())).unwrap_err();
+    let request_id = diagnostics::last_errors()[0].request_id.clone().unwrap();
+    assert!(request_id.contains("get_invoice"));
+    assert!(error.ends_with(&format!("(request {})", request_id)));
+    assert_eq!(diagnostics::request_id(), None);
+}
+
+#[test]
+fn invoices_are_found_by_tag() {
+    let mut h = harness();
+    let tagged = create(&mut h.app, serde_json::json!({}));
+    create(&mut h.app, serde_json::json!({}));
+    let find = |app: &mut AppState, tags: &[&str]| {
+        let found: Vec<InvoiceSummary> = serde_json::from_str(&run(app.find_invoices(serde_json::json!({ "tags": tags }).to_string())).unwrap()).unwrap();
+        found.into_iter().map(|s| s.id).collect::<Vec<_>>()
+    };
+
+    let mut edited = serde_json::to_value(&tagged).unwrap();
+    edited["tags"] = serde_json::json!([" retainer ", "q1", "q1", ""]);
+    let saved: Invoice = serde_json::from_str(&run(h.app.update_invoice(edited.to_string())).unwrap()).unwrap();
+    assert_eq!(saved.tags, ["q1", "retainer"]);
+    assert_eq!(find(&mut h.app, &["retainer"]), vec![tagged.id.clone()]);
+    assert_eq!(find(&mut h.app, &["retainer", "q1"]), vec![tagged.id.clone()]);
+    assert!(find(&mut h.app, &["retainer", "q2"]).is_empty());
+
+    let mut edited = serde_json::to_value(&saved).unwrap();
+    edited["tags"] = serde_json::json!(["q1"]);
+    run(h.app.update_invoice(edited.to_string())).unwrap();
+    assert!(find(&mut h.app, &["retainer"]).is_empty());
+}
+
+#[test]
+fn interleaved_requests_can_finish_in_any_order() {
+    let _h = harness();
+    let first = diagnostics::trace("first", 1);
+    let second = diagnostics::trace("second", 2);
+    drop(first);
+    assert!(diagnostics::request_id().unwrap().contains("second"));
+    let third = diagnostics::trace("third", 3);
+    drop(second);
+    assert!(diagnostics::request_id().unwrap().contains("third"));
+    drop(third);
+    assert_eq!(diagnostics::request_id(), None);
+}
+
+#[test]
+fn every_documented_v1_operation_is_routed() {
+    let mut h = harness();
+    let mut call = |method: &str, params: serde_json::Value| -> serde_json::Value {
+        let request = serde_json::json!({ "method": method, "params": params });
+        serde_json::from_str(&run(h.app.api_v1(request.to_string())).unwrap()).unwrap()
+    };
+    // Importing checks which node is calling, which needs the node
+    for operation in api::OPERATIONS.iter().filter(|op| op.method != "import_time_entries") {
+        let response = call(operation.method, serde_json::Value::Null);
+        assert_eq!(response["version"], 1);
+        assert_ne!(response["error"]["code"], "UnknownMethod", "{}", operation.method);
+    }
+
+    let created = call("create_invoice", serde_json::json!({ "notes": "Via v1" }));
+    assert_eq!(created["result"]["notes"], "Via v1");
+    assert_eq!(call("delete_invoice", created["result"]["id"].clone())["result"], serde_json::Value::Null);
+    assert_eq!(call("send_invoice", serde_json::Value::Null)["error"]["code"], "UnknownMethod");
+}
+
+#[test]
+fn ship_to_prints_beside_bill_to_when_enabled() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "invoicee": contact("Globex"),
+        "delivery_address": { "name": "Loading dock", "address": "9 Wharf Rd\nPortsmouth" },
+    }));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(!html.contains("Ship To:"));
+
+    let extra = serde_json::json!({ "delivery_addresses_enabled": true });
+    run(h.app.update_settings(settings_with("INV-", 2, extra))).unwrap();
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<h3>Ship To:</h3>"));
+    assert!(html.contains("<p>Loading dock</p><p>9 Wharf Rd</p><p>Portsmouth</p>"));
+
+    let empty = serde_json::json!({ "delivery_address": { "name": " ", "address": "" } });
+    assert!(run(h.app.create_invoice(empty.to_string())).is_err());
+}
+
+#[test]
+fn header_fields_change_without_sending_line_items() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Support", "quantity": 2.0, "rate": 75.0 }],
+        "notes": "Thanks",
+    }));
+    let meta = |meta: serde_json::Value| serde_json::json!({ "id": invoice.id, "meta": meta }).to_string();
+
+    let updated: Invoice = serde_json::from_str(&run(h.app.update_invoice_meta(
+        meta(serde_json::json!({ "due_date": "2025-03-01", "notes": null })),
+    )).unwrap()).unwrap();
+    assert_eq!(updated.due_date.as_deref(), Some("2025-03-01"));
+    assert_eq!(updated.notes, None);
+    assert_eq!(updated.line_items.len(), 1);
+    assert_eq!(updated.date, invoice.date);
+
+    assert!(run(h.app.update_invoice_meta(meta(serde_json::json!({ "line_items": [] })))).is_err());
+    assert!(run(h.app.update_invoice_meta(meta(serde_json::json!({ "due_date": "March 1" })))).is_err());
+}
+
+#[test]
+fn custom_fields_are_type_checked_and_printed() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "custom_fields": [
+        { "name": "Cost center", "field_type": "Text", "scope": "Invoice" },
+        { "name": "Delivered", "field_type": "Date", "scope": "LineItem" },
+        { "name": "Batch", "field_type": "Number", "scope": "LineItem" },
+    ]});
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+
+    let invoice = create(&mut h.app, serde_json::json!({
+        "custom_fields": { "Cost center": "R&D" },
+        "line_items": [
+            { "description": "Crates", "custom_fields": { "Delivered": "2025-02-03" } },
+            { "description": "Pallets" },
+        ],
+    }));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<p><strong>Cost center:</strong> R&amp;D</p>"));
+    assert!(html.contains("<th>Delivered</th>"));
+    assert!(html.contains("<td>2025-02-03</td>"));
+    assert!(!html.contains("<th>Batch</th>"));
+
+    let wrong_type = serde_json::json!({ "line_items": [{ "custom_fields": { "Batch": "seven" } }] });
+    assert!(run(h.app.create_invoice(wrong_type.to_string())).is_err());
+    let wrong_scope = serde_json::json!({ "custom_fields": { "Delivered": "2025-02-03" } });
+    assert!(run(h.app.create_invoice(wrong_scope.to_string())).is_err());
+
+    let mut updated = invoice.clone();
+    updated.custom_fields.insert("Cost center".to_string(), serde_json::json!(12));
+    assert!(run(h.app.update_invoice(serde_json::to_string(&updated).unwrap())).is_err());
+}
+
+#[test]
+fn sections_stay_together_with_their_subtotals() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "totals_display": { "show_section_subtotals": true } });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "description": "Wireframes", "rate": 400.0, "section": "Design" },
+            { "description": "API", "rate": 900.0, "section": "Development" },
+        ],
+    }));
+    h.clock.advance(1);
+    let added: Invoice = serde_json::from_str(&run(h.app.add_line_items(serde_json::json!([
+        { "description": "Mockups", "rate": 600.0, "section": "Design" },
+    ]).to_string())).unwrap()).unwrap();
+    let descriptions = |invoice: &Invoice| invoice.line_items.iter().map(|i| i.description.clone()).collect::<Vec<_>>();
+    assert_eq!(descriptions(&added), ["Wireframes", "Mockups", "API"]);
+
+    let html = h.app.generate_invoice_html(&added, &ExportOptions::default());
+    assert!(html.contains("Design subtotal:</span>\n            <span class=\"total-value\">$1000.00"));
+    assert!(html.contains("Development subtotal:</span>\n            <span class=\"total-value\">$900.00"));
+
+    // Dragging a design item below development pulls it back into its section
+    let ids: Vec<String> = [0, 2, 1].iter().map(|i| added.line_items[*i].id.clone()).collect();
+    let reordered: Invoice = serde_json::from_str(&run(h.app.reorder_line_items(serde_json::to_string(&ids).unwrap())).unwrap()).unwrap();
+    assert_eq!(descriptions(&reordered), ["Wireframes", "Mockups", "API"]);
+
+    let moved: Invoice = serde_json::from_str(&run(h.app.reorder_sections(r#"["Development"]"#.to_string())).unwrap()).unwrap();
+    assert_eq!(descriptions(&moved), ["API", "Wireframes", "Mockups"]);
+    assert!(run(h.app.reorder_sections(r#"["Expenses"]"#.to_string())).is_err());
+}
+
+#[test]
+fn status_changes_follow_the_rules_and_are_recorded() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "line_items": [{ "description": "Audit", "rate": 500.0 }] }));
+    let set = |app: &mut AppState, status: &str, metadata: serde_json::Value| {
+        let request = serde_json::json!({ "id": invoice.id, "status": status, "metadata": metadata });
+        run(app.set_invoice_status(request.to_string())).map(|json| serde_json::from_str::<Invoice>(&json).unwrap())
+    };
+
+    assert!(set(&mut h.app, "Paid", serde_json::json!({})).is_err());
+    h.clock.advance(60);
+    let sent = set(&mut h.app, "Sent", serde_json::json!({ "channel": "email" })).unwrap();
+    assert_eq!(sent.sent_at, Some(sent.updated_at));
+    assert_eq!(sent.status_history.len(), 1);
+    assert_eq!(sent.status_history[0].from, InvoiceStatus::Draft);
+    assert_eq!(sent.status_history[0].metadata["channel"], "email");
+    assert!(h.app.notifications.iter().any(|n| n.kind == notifications::NotificationKind::StatusChanged));
+
+    // Marking it Sent again after a recall keeps the first sent time
+    h.clock.advance(60);
+    set(&mut h.app, "Draft", serde_json::json!({})).unwrap();
+    let resent = set(&mut h.app, "Sent", serde_json::json!({})).unwrap();
+    assert_eq!(resent.sent_at, sent.sent_at);
+    assert_eq!(resent.status_history.len(), 3);
+
+    let paid = set(&mut h.app, "Paid", serde_json::json!({})).unwrap();
+    let mut draft = paid.clone();
+    draft.status = InvoiceStatus::Draft;
+    assert!(run(h.app.update_invoice(serde_json::to_string(&draft).unwrap())).is_err());
+
+    // Sending by editing the whole invoice is recorded and notified the same way
+    let other = create(&mut h.app, serde_json::json!({ "line_items": [{ "description": "Review", "rate": 100.0 }] }));
+    let mut edited = serde_json::to_value(&other).unwrap();
+    edited["status"] = "Sent".into();
+    let notified = h.app.notifications.len();
+    let sent: Invoice = serde_json::from_str(&run(h.app.update_invoice(edited.to_string())).unwrap()).unwrap();
+    assert_eq!(sent.status_history.len(), 1);
+    assert_eq!(sent.issued_documents.len(), 1);
+    assert_eq!(h.app.notifications.len(), notified + 1);
+}
+
+#[test]
+fn the_issued_document_outlives_later_edits() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "notes": "Net 30, thanks" }));
+    let issued = |app: &mut AppState, sequence: Option<usize>| {
+        let request = serde_json::json!({ "id": invoice.id, "sequence": sequence });
+        run(app.get_issued_document(request.to_string()))
+            .map(|json| serde_json::from_str::<IssuedDocument>(&json).unwrap())
+    };
+    assert!(issued(&mut h.app, None).is_err());
+
+    let request = serde_json::json!({ "id": invoice.id, "status": "Sent" });
+    let sent: Invoice = serde_json::from_str(&run(h.app.set_invoice_status(request.to_string())).unwrap()).unwrap();
+    let mut edited = sent.clone();
+    edited.notes = Some("Pay whenever".to_string());
+    run(h.app.update_invoice(serde_json::to_string(&edited).unwrap())).unwrap();
+
+    let document = issued(&mut h.app, None).unwrap();
+    assert_eq!(document.sequence, 0);
+    assert!(document.html.contains("Net 30, thanks"));
+    assert!(!document.html.contains("Pay whenever"));
+    assert_eq!(document.sha256, sent.issued_documents[0].sha256);
+    assert!(issued(&mut h.app, Some(1)).is_err());
+}
+
+#[test]
+fn issued_documents_follow_an_encryption_change_and_outlive_forget_client() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({ "invoicee": contact("Initech") }));
+    let request = serde_json::json!({ "id": invoice.id, "status": "Sent" });
+    run(h.app.set_invoice_status(request.to_string())).unwrap();
+
+    let request = serde_json::json!({ "mode": "NodeKey" });
+    let changed: serde_json::Value = serde_json::from_str(&run(h.app.configure_encryption(request.to_string())).unwrap()).unwrap();
+    assert_eq!(changed["issued_documents_rewritten"], 1);
+    let request = serde_json::json!({ "id": invoice.id });
+    let document: IssuedDocument = serde_json::from_str(&run(h.app.get_issued_document(request.to_string())).unwrap()).unwrap();
+    assert!(document.html.contains("Initech"));
+
+    let report: serde_json::Value = serde_json::from_str(&run(h.app.forget_client("\"initech\"".to_string())).unwrap()).unwrap();
+    assert_eq!(report["issued_documents_retained"][0]["number"], invoice.number);
+    assert_eq!(report["issued_documents_retained"][0]["issues"], 1);
+    storage::set_key(&storage::EncryptionMode::Off, None);
+}
+
+#[test]
+fn text_rows_print_across_the_table_and_add_nothing() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "kind": "Text", "description": "Phase 1: discovery", "quantity": 3.0, "rate": 100.0 },
+            { "description": "Workshop", "rate": 1200.0 },
+        ],
+    }));
+    assert_eq!(calculate_invoice_total(&invoice), Money::from_f64(1200.0));
+
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<tr class=\"text-row\"><td colspan=\"6\">Phase 1: discovery</td></tr>"));
+
+    let blank = serde_json::json!([{ "kind": "Text", "description": " " }]);
+    assert!(run(h.app.add_line_items(blank.to_string())).is_err());
+}
+
+#[test]
+fn deliveries_show_in_the_log_and_activity_feed() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    let request = serde_json::json!({ "invoice_id": invoice.id, "passcode": "s3cret!" });
+    run(h.app.create_share_link(request.to_string())).unwrap();
+    h.clock.advance(60);
+    let request = serde_json::json!({ "id": invoice.id, "status": "Sent" });
+    run(h.app.set_invoice_status(request.to_string())).unwrap();
+
+    let id = serde_json::to_string(&invoice.id).unwrap();
+    let log: Vec<DeliveryRecord> = serde_json::from_str(&run(h.app.get_delivery_log(id.clone())).unwrap()).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].channel, DeliveryChannel::ShareLink);
+    assert_eq!(log[0].destination, "Anyone with the link");
+    assert_eq!(log[0].detail.as_deref(), Some("Passcode required"));
+
+    let activity: Vec<serde_json::Value> = serde_json::from_str(&run(h.app.get_activity(id)).unwrap()).unwrap();
+    assert!(activity[0]["text"].as_str().unwrap().starts_with("Draft to Sent"));
+    assert_eq!(activity[1]["text"], "Share link created for Anyone with the link");
+}
+
+#[test]
+fn item_codes_lead_the_table_and_filter_the_catalog() {
+    let mut h = harness();
+    for (name, code) in [("Widget", Some("WID-100")), ("Bracket", Some("BRK-7")), ("Labour", None)] {
+        let item = serde_json::json!({ "id": "", "name": name, "item_code": code, "rate": 10.0 });
+        run(h.app.save_catalog_item(item.to_string())).unwrap();
+    }
+    let found: Vec<pricing::CatalogItem> = serde_json::from_str(&run(h.app.list_catalog("\"wid\"".to_string())).unwrap()).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].name, "Widget");
+    let all: Vec<pricing::CatalogItem> = serde_json::from_str(&run(h.app.list_catalog(String::new())).unwrap()).unwrap();
+    assert_eq!(all.len(), 3);
+
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [
+            { "item_code": "WID-100", "description": "Widget", "rate": 10.0 },
+            { "description": "Delivery", "rate": 25.0 },
+        ],
+    }));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<th>Item Code</th><th>Description</th>"));
+    assert!(html.contains("<tr><td>WID-100</td><td>Widget</td>"));
+    assert!(html.contains("<tr><td></td><td>Delivery</td>"));
+}
+
+#[test]
+fn default_text_fills_new_invoices_and_its_tokens_follow_the_invoice() {
+    let mut h = harness();
+    let extra = serde_json::json!({
+        "default_text": { "terms": "Payment due {due_date} ({payment_terms}).", "thank_you": "Thank you!" },
+        "client_default_text": { "globex": { "thank_you": "Thanks, {client_name}, for {invoice_number}." } },
+    });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+
+    let invoice = create(&mut h.app, serde_json::json!({
+        "invoicee": contact("Globex"),
+        "payment_terms": { "Net": 30 },
+        "due_date": "2026-05-31",
+    }));
+    assert_eq!(invoice.terms_text.as_deref(), Some("Payment due {due_date} ({payment_terms})."));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<p>Payment due 2026-05-31 (Net 30).</p>"));
+    assert!(html.contains("<p class='thank-you'>Thanks, Globex, for INV-0001.</p>"));
+
+    h.clock.advance(1);
+    let other = create(&mut h.app, serde_json::json!({ "invoicee": contact("Initech"), "thank_you": "Cheers {unknown}" }));
+    let html = h.app.generate_invoice_html(&other, &ExportOptions::default());
+    assert!(html.contains("<p class='thank-you'>Cheers {unknown}</p>"));
+
+    let bad = serde_json::json!({ "default_text": { "notes": "Pay by {due}" } });
+    assert!(run(h.app.update_settings(settings_with("INV-", 2, bad))).is_err());
+}
+
+#[test]
+fn client_logos_go_on_their_new_invoices() {
+    let mut h = harness();
+    let gif = serde_json::json!({ "client_id": "Globex", "file_data": b"GIF89a" });
+    assert!(run(h.app.upload_client_logo(gif.to_string().into_bytes())).is_err());
+    let png = b"\x89PNG\r\n\x1a\n logo";
+    let upload = serde_json::json!({ "client_id": "Globex", "file_data": png });
+    let logo_path = run(h.app.upload_client_logo(upload.to_string().into_bytes())).unwrap();
+    assert!(logo_path.ends_with("/logo.png"));
+    assert_eq!(h.store.blob(&logo_path).as_deref(), Some(&png[..]));
+
+    let globex = create(&mut h.app, serde_json::json!({ "invoicee": contact("Globex") }));
+    assert_eq!(globex.invoicee.logo_path.as_deref(), Some(logo_path.as_str()));
+    let html = h.app.generate_invoice_html(&globex, &ExportOptions::default());
+    assert!(html.contains(&format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(png))));
+    let initech = create(&mut h.app, serde_json::json!({ "invoicee": contact("Initech") }));
+    assert_eq!(initech.invoicee.logo_path, None);
+
+    run(h.app.remove_client_logo("\"Globex\"".to_string())).unwrap();
+    assert!(h.app.client_logos.is_empty());
+}
+
+#[test]
+fn overdue_invoices_get_one_late_fee_per_period() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "late_fee": { "charge": { "Percent": 1.5 }, "period_days": 30 } });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "date": "2024-11-01",
+        "due_date": "2024-12-01",
+        "line_items": [{ "description": "Retainer", "rate": 1000.0 }],
+    }));
+    let request = serde_json::json!({ "id": invoice.id, "status": "Sent" });
+    run(h.app.set_invoice_status(request.to_string())).unwrap();
+
+    // 45 days overdue is two periods of 1.5% of 1000.00
+    let charged: Vec<String> = serde_json::from_str(&run(h.app.check_late_fees()).unwrap()).unwrap();
+    assert_eq!(charged, vec![invoice.id.clone()]);
+    let current = h.app.current_invoice.clone().unwrap();
+    let fee = current.line_items.iter().find(|i| i.kind == LineItemKind::LateFee).unwrap();
+    assert_eq!((fee.quantity, fee.rate), (2.0, 15.0));
+    assert_eq!(calculate_invoice_total(&current), Money::from_f64(1030.0));
+    assert_eq!(run(h.app.check_late_fees()).unwrap(), "[]");
+
+    h.clock.advance(20 * 86400);
+    run(h.app.check_late_fees()).unwrap();
+    let current = h.app.current_invoice.clone().unwrap();
+    assert_eq!(current.line_items.iter().filter(|i| i.kind == LineItemKind::LateFee).count(), 1);
+    assert_eq!(calculate_invoice_total(&current), Money::from_f64(1045.0));
+    let html = h.app.generate_invoice_html(&current, &ExportOptions::default());
+    assert!(html.contains("<tr class=\"late-fee\"><td>Late fee: 1.5% of the amount due every 30 days overdue</td>"));
+}
+
+#[test]
+fn early_payment_terms_print_and_discount_a_payment_in_the_window() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "date": "2025-01-10",
+        "payment_terms": { "Net": 30 },
+        "early_payment": { "percent": 2.0, "days": 10 },
+        "line_items": [{ "description": "Consulting", "rate": 500.0 }],
+    }));
+    assert_eq!(invoice.due_date.as_deref(), Some("2025-02-09"));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<strong>Terms:</strong> 2/10 net 30"));
+
+    let late = serde_json::json!({ "invoice_id": invoice.id, "amount": 490.0, "paid_on": "2025-01-21" });
+    assert!(run(h.app.record_payment(late.to_string())).is_err());
+    let early = serde_json::json!({ "invoice_id": invoice.id, "amount": 490.0, "paid_on": "2025-01-20" });
+    let paid: Invoice = serde_json::from_str(&run(h.app.record_payment(early.to_string())).unwrap()).unwrap();
+    let payment = paid.payment.clone().unwrap();
+    assert_eq!(payment.early_payment_discount, Money::from_f64(10.0));
+    assert_eq!(payment.amount, Money::from_f64(490.0));
+    let html = h.app.generate_invoice_html(&paid, &ExportOptions::default());
+    assert!(html.contains("Early payment discount"));
+}
+
+#[test]
+fn svg_logos_are_held_to_known_elements_and_attributes() {
+    let svg = r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 10 10"><!-- note -->
+<style>.a { fill: url(#g); }</style>
+<rect class="a" width="10" height="10" style="fill:url('#g')"/>
+<use xlink:href="#shape"/></svg>"##;
+    let clean = logos::sanitize_svg(svg).unwrap();
+    assert_eq!(clean, r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 10 10">
+<style>.a { fill: url(#g); }</style>
+<rect class="a" width="10" height="10" style="fill:url('#g')"/>
+<use xlink:href="#shape"/></svg>"##);
+
+    for bad in [
+        r##"<svg/onload=alert(1)>"##,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)"/>"##,
+        r##"<svg><script>alert(2)</script></svg>"##,
+        r##"<svg><a href="#x"><set attributeName="href" to="javascript:alert(3)"/></a></svg>"##,
+        r##"<svg><use href="#x"><animate attributeName="href" to="javascript:alert(4)"/></use></svg>"##,
+        r##"<svg><use href="#x"><set attributeName="href" to="javascript:alert(5)"/></use></svg>"##,
+        r##"<svg><foreignObject><div>hi</div></foreignObject></svg>"##,
+        r##"<svg><use href="https://example.com/x.svg#a"/></svg>"##,
+        r##"<svg><use href="&#106;avascript:alert(6)"/></svg>"##,
+        r##"<svg><style>.b { background: url(https://example.com/x.png); }</style></svg>"##,
+        r##"<svg><style>.b { background: \75rl(https://example.com/x.png); }</style></svg>"##,
+        r##"<svg><rect style="fill:&#x75;rl(http://evil/p)"/></svg>"##,
+        r##"<?xml-stylesheet href="https://example.com/x.css"?><svg/>"##,
+        r##"<svg xmlns="http://www.w3.org/1999/xhtml"/>"##,
+        r##"<!DOCTYPE svg [<!ENTITY x SYSTEM "file:///etc/passwd">]><svg>&x;</svg>"##,
+    ] {
+        assert!(logos::sanitize_svg(bad).is_err(), "{}", bad);
+    }
+
+    assert_eq!(logos::ImageFormat::detect(clean.as_bytes()), Some(logos::ImageFormat::Svg));
+    assert_eq!(logos::ImageFormat::detect(b"RIFF\x10\0\0\0WEBPVP8 "), Some(logos::ImageFormat::Webp));
+}
+
+#[test]
+fn amounts_in_words_name_the_invoice_currency() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "totals_display": { "show_amount_in_words": true } });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Consulting", "rate": 1221.05 }],
+    }));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("One thousand two hundred twenty-one dollars and five cents only"));
+
+    assert_eq!(words::amount_in_words(21.0, words::Language::Spanish, "USD", 2), "Veintiún dólares");
+    assert_eq!(words::amount_in_words(1.5, words::Language::German, "EUR", 2), "Ein Euro und fünfzig Cent");
+    assert_eq!(words::amount_in_words(2_000_000.0, words::Language::French, "EUR", 2), "Deux millions d'euros");
+    assert_eq!(words::amount_in_words(5000.0, words::Language::English, "JPY", 0), "Five thousand yen only");
+    assert_eq!(words::amount_in_words(100.25, words::Language::English, "KWD", 3), "One hundred KWD and 250/1000");
+}
+
+#[test]
+fn sqlite_rows_hold_no_plaintext_while_encryption_is_on() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "invoicee": contact("Wayne Enterprises"),
+        "line_items": [{ "description": "Grappling hook", "rate": 700.0 }],
+    }));
+    let location = invoice_location(&invoice);
+    let data = serde_json::to_value(&invoice).unwrap();
+
+    let (_, key) = storage::EncryptionConfig::node_key();
+    storage::set_key(&storage::EncryptionMode::NodeKey, Some(key));
+    let statements = store::save_statements(storage::seal, &location, &data).unwrap();
+    assert_eq!(statements.iter().filter(|(sql, _)| sql.starts_with("INSERT")).count(), 1);
+    let stored = serde_json::to_string(&statements.iter().map(|(_, params)| params).collect::<Vec<_>>()).unwrap();
+    for plaintext in ["Wayne", "Grappling", "700"] {
+        assert!(!stored.contains(plaintext), "{} stored in plaintext", plaintext);
+    }
+    // Only the ID is left beside the sealed data
+    assert!(statements[0].1[1..7].iter().all(|v| v.is_null() || v == ""));
+
+    storage::set_key(&storage::EncryptionMode::Off, None);
+    let statements = store::save_statements(storage::seal, &location, &data).unwrap();
+    assert!(serde_json::to_string(&statements[0].1).unwrap().contains("Wayne Enterprises"));
+}
+
+#[test]
+fn shared_invoices_open_once_their_terms_are_accepted() {
+    let mut h = harness();
+    assert_eq!(safe_file_name("../../settings.json").unwrap(), "settings.json");
+    assert_eq!(safe_file_name("..\\receipts\\scan.png").unwrap(), "scan.png");
+    let bad = serde_json::json!({ "title": "Terms", "file_name": "..", "file_data": [1, 2] });
+    assert!(run(h.app.upload_terms(bad.to_string().into_bytes())).is_err());
+
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Consulting", "rate": 500.0 }],
+    }));
+    h.app.terms_documents.push(TermsDocument {
+        id: "terms-1".to_string(),
+        version: 1,
+        title: "Terms".to_string(),
+        file_name: "terms.pdf".to_string(),
+        path: "/terms/terms-1-v1-terms.pdf".to_string(),
+        hash: String::new(),
+        uploaded_at: NOW,
+    });
+    run(h.app.attach_terms("\"terms-1\"".to_string())).unwrap();
+    let link: ShareLink = serde_json::from_str(&run(h.app.create_share_link(
+        serde_json::json!({ "invoice_id": invoice.id }).to_string(),
+    )).unwrap()).unwrap();
+
+    host::install_caller(Some("client.os@invoice:invoice:nick.hypr".parse().unwrap()));
+    let open = serde_json::json!({ "token": link.token });
+    assert!(run(h.app.open_share_link(open.to_string())).unwrap_err().contains("Accept the terms"));
+    let accept = serde_json::json!({ "token": link.token, "accept_terms": { "accepted_by": "Pat Client" } });
+    assert!(run(h.app.open_share_link(accept.to_string())).unwrap().contains(&invoice.number));
+
+    let acceptance = h.app.current_invoice.clone().unwrap().terms_acceptances.pop().unwrap();
+    assert_eq!(acceptance.accepted_by, "Pat Client");
+    assert_eq!(acceptance.via_link_from.as_deref(), Some("client.os"));
+    assert!(run(h.app.open_share_link(open.to_string())).is_ok());
+}
+
+#[test]
+fn recipients_sign_through_their_share_link() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Consulting", "rate": 500.0 }],
+    }));
+    let link: ShareLink = serde_json::from_str(&run(h.app.create_share_link(
+        serde_json::json!({ "invoice_id": invoice.id, "passcode": "open-sesame" }).to_string(),
+    )).unwrap()).unwrap();
+
+    host::install_caller(Some("client.os@invoice:invoice:nick.hypr".parse().unwrap()));
+    let mut request = serde_json::json!({
+        "token": link.token,
+        "passcode": "guess-again",
+        "signer_name": "Pat Client",
+        "signature_image": [137, 80, 78, 71],
+    });
+    assert!(run(h.app.sign_shared_invoice(request.to_string())).is_err());
+    request["passcode"] = serde_json::json!("open-sesame");
+    let signature: Signature = serde_json::from_str(&run(h.app.sign_shared_invoice(request.to_string())).unwrap()).unwrap();
+    assert_eq!(signature.signer_name, "Pat Client");
+    assert_eq!(signature.signer_node.as_deref(), Some("client.os"));
+    assert_eq!(h.store.blob(&signature.image_path), Some(vec![137, 80, 78, 71]));
+
+    let signed = h.app.current_invoice.clone().unwrap();
+    assert!(h.app.generate_invoice_html(&signed, &ExportOptions::default()).contains("Signed by Pat Client (client.os)"));
+    assert!(run(h.app.sign_shared_invoice(request.to_string())).unwrap_err().contains("already been signed"));
+}
+
+#[test]
+fn share_codes_run_out_of_guesses_across_nodes() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "email_send_process": "mailer:mail:nick.hypr" });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    let link: ShareLink = serde_json::from_str(&run(h.app.create_share_link(
+        serde_json::json!({ "invoice_id": invoice.id, "allowed_email": "pat@example.com" }).to_string(),
+    )).unwrap()).unwrap();
+    let issue_code = |app: &mut AppState| {
+        app.share_codes.insert(link.token.clone(), ShareCode {
+            code_hash: share_secret_hash(&link.token, "123456"),
+            expires_at: NOW + 3600,
+            misses: 0,
+        });
+    };
+    let open_from = |app: &mut AppState, node: &str, code: &str| {
+        host::install_caller(Some(format!("{}@invoice:invoice:nick.hypr", node).parse().unwrap()));
+        run(app.open_share_link(serde_json::json!({ "token": link.token, "code": code }).to_string()))
+    };
+
+    // Each wrong guess comes from a new node, but the code still goes after three
+    issue_code(&mut h.app);
+    for (node, guess) in [("a.os", "000001"), ("b.os", "000002"), ("c.os", "000003")] {
+        assert!(open_from(&mut h.app, node, guess).is_err());
+    }
+    assert!(h.app.share_codes.is_empty());
+    assert!(open_from(&mut h.app, "d.os", "123456").is_err());
+
+    // Two more misses lock the link itself
+    issue_code(&mut h.app);
+    assert!(open_from(&mut h.app, "e.os", "000005").is_err());
+    assert!(h.app.share_codes.is_empty());
+    issue_code(&mut h.app);
+    assert!(open_from(&mut h.app, "f.os", "123456").unwrap_err().contains("locked"));
+
+    h.clock.advance(900);
+    assert!(open_from(&mut h.app, "f.os", "123456").is_ok());
+}
+
+#[test]
+fn template_styles_cannot_hide_urls_in_escapes() {
+    let mut h = harness();
+    for source in [
+        r"<style>body { background: \75rl(https://example.com/x.png); }</style>",
+        r"<style>@\69mport 'https://example.com/x.css';</style>",
+        r#"<div style="background: \75rl(https://example.com/x.png)"></div>"#,
+        r"<div style=background:\75rl(https://example.com/x.png)></div>",
+    ] {
+        assert!(!templates::sandbox_violations(source).is_empty(), "{}", source);
+    }
+    assert!(templates::sandbox_violations(r"<p>C:\invoices</p><style>p { color: red; }</style>").is_empty());
+
+    run(h.app.update_settings(settings("INV-", 1))).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    let html = h.app.generate_invoice_html(&invoice, &ExportOptions::default());
+    assert!(html.contains("<meta http-equiv=\"Content-Security-Policy\" content=\"default-src 'none';"));
+}
+
+#[test]
+fn zatca_codes_print_only_for_the_profiles_that_turn_them_on() {
+    let mut h = harness();
+    let riyadh = serde_json::json!({
+        "id": "riyadh",
+        "name": "Riyadh branch",
+        "invoicer": contact("Riyadh Co"),
+        "payment_info": null,
+        "payment_image_path": null,
+        "invoice_number_prefix": "RUH-",
+        "next_invoice_number": 1,
+        "zatca_qr_enabled": true,
+    });
+    let extra = serde_json::json!({ "profiles": [riyadh] });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let lines = serde_json::json!([{ "description": "Audit", "rate": 100.0 }]);
+
+    let home = create(&mut h.app, serde_json::json!({ "line_items": lines }));
+    let html = h.app.generate_invoice_html(&home, &ExportOptions::default());
+    assert!(!html.contains("zatca-qr"));
+
+    let branch = create(&mut h.app, serde_json::json!({ "profile_id": "riyadh", "line_items": lines }));
+    let html = h.app.generate_invoice_html(&branch, &ExportOptions::default());
+    assert!(html.contains("zatca-qr"));
+}
+
+#[test]
+fn time_entries_are_billed_once_even_when_listed_twice() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    h.app.time_entries.insert("time-1".to_string(), TimeEntry {
+        id: "time-1".to_string(),
+        source: "tracker:tracker:nick.hypr".to_string(),
+        external_id: "42".to_string(),
+        date: "2025-01-14".to_string(),
+        description: "Pairing".to_string(),
+        hours: 2.0,
+        rate: Some(80.0),
+        billed_invoice_id: None,
+        imported_at: NOW,
+    });
+
+    assert!(run(h.app.bill_time_entries(r#"["time-1", "time-1"]"#.to_string())).is_err());
+    assert!(h.app.current_invoice.as_ref().unwrap().line_items.is_empty());
+    assert_eq!(h.app.time_entries["time-1"].billed_invoice_id, None);
+
+    run(h.app.bill_time_entries(r#"["time-1"]"#.to_string())).unwrap();
+    assert_eq!(h.app.current_invoice.as_ref().unwrap().line_items.len(), 1);
+    assert_eq!(h.app.time_entries["time-1"].billed_invoice_id, Some(invoice.id));
+}
+
+#[test]
+fn time_entries_are_released_with_the_line_that_billed_them() {
+    let mut h = harness();
+    host::install_caller(Some("tester.os@tracker:tracker:nick.hypr".parse().unwrap()));
+    let import = |app: &mut AppState, date: &str, hours: f64, rate: f64| {
+        let entries = serde_json::json!([{ "external_id": "42", "date": date, "description": "Pairing", "hours": hours, "rate": rate }]);
+        run(app.import_time_entries(entries.to_string()))
+    };
+    assert!(import(&mut h.app, "14/01/2025", 2.0, 80.0).is_err());
+    assert!(import(&mut h.app, "2025-01-14", -2.0, 80.0).is_err());
+    assert!(import(&mut h.app, "2025-01-14", 2.0, -80.0).is_err());
+    assert!(h.app.time_entries.is_empty());
+    import(&mut h.app, "2025-01-14", 2.0, 80.0).unwrap();
+    host::install_caller(None);
+    let entry_id = h.app.time_entries.keys().next().unwrap().clone();
+    let billed = |app: &AppState| app.time_entries[&entry_id].billed_invoice_id.clone();
+
+    let invoice = create(&mut h.app, serde_json::json!({}));
+    let bill = serde_json::json!([entry_id]).to_string();
+    let billing: Invoice = serde_json::from_str(&run(h.app.bill_time_entries(bill.clone())).unwrap()).unwrap();
+    let line_id = serde_json::to_string(&billing.line_items[0].id).unwrap();
+    run(h.app.delete_line_item(line_id)).unwrap();
+    assert_eq!(billed(&h.app), None);
+    run(h.app.undo()).unwrap();
+    assert_eq!(billed(&h.app), Some(invoice.id.clone()));
+    run(h.app.undo()).unwrap();
+    assert_eq!(billed(&h.app), None);
+    run(h.app.redo()).unwrap();
+    assert_eq!(billed(&h.app), Some(invoice.id.clone()));
+
+    run(h.app.delete_invoice(serde_json::to_string(&invoice.id).unwrap())).unwrap();
+    assert_eq!(billed(&h.app), None);
+}
+
+#[test]
+fn payment_methods_charge_in_their_own_currency() {
+    let mut h = harness();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "currency": "EUR",
+        "line_items": [{ "description": "Audit", "rate": 1000.0 }],
+    }));
+    assert_eq!(amount_due_in(&invoice, "EUR"), Ok(Money::from_f64(1000.0)));
+    assert!(amount_due_in(&invoice, "USD").is_err());
+
+    let request = serde_json::json!({ "invoice_id": invoice.id, "rate": 1.08 });
+    let converted: Invoice = serde_json::from_str(&run(h.app.set_invoice_exchange_rate(request.to_string())).unwrap()).unwrap();
+    assert_eq!(amount_due_in(&converted, "USD"), Ok(Money::from_f64(1080.0)));
+    assert!(amount_due_in(&converted, "GBP").is_err());
+}
+
+#[test]
+fn margin_report_adds_up_in_the_home_currency() {
+    let mut h = harness();
+    run(h.app.set_exchange_rate(serde_json::json!({ "currency": "EUR", "rate": 1.1 }).to_string())).unwrap();
+    for currency in ["USD", "EUR", "GBP"] {
+        let invoice = create(&mut h.app, serde_json::json!({
+            "currency": currency,
+            "line_items": [{ "description": "Audit", "rate": 1000.0, "cost": 400.0 }],
+        }));
+        let request = serde_json::json!({ "id": invoice.id, "status": "Sent" });
+        run(h.app.set_invoice_status(request.to_string())).unwrap();
+    }
+
+    let report: serde_json::Value = serde_json::from_str(&run(h.app.get_margin_report(String::new())).unwrap()).unwrap();
+    assert_eq!(report["currency"], "USD");
+    assert_eq!(report["unconverted"], 1);
+    assert_eq!(report["total"]["revenue"], 2100.0);
+    assert_eq!(report["total"]["cost"], 840.0);
+}
+
+#[test]
+fn custom_templates_leave_amounts_out_of_redacted_exports() {
+    let mut h = harness();
+    let extra = serde_json::json!({ "payment_method_fees": [{ "id": "card", "label": "Card", "percent": 2.0, "fixed": 0.3 }] });
+    run(h.app.update_settings(settings_with("INV-", 1, extra))).unwrap();
+    let source = "{{#each line_items}}<p>{{description}} {{quantity}} x {{rate | money}} = {{amount | money}}</p>{{/each}}\
+        <p>Total {{totals.total | money}} due {{totals.balance_due | money}} {{totals.amount_in_words}}</p>\
+        {{#each totals.payment_methods}}<p>{{label}} {{amount | money}}</p>{{/each}}";
+    let template: CustomTemplate = serde_json::from_str(&run(h.app.save_template(
+        serde_json::json!({ "name": "Plain", "source": source }).to_string(),
+    )).unwrap()).unwrap();
+    let invoice = create(&mut h.app, serde_json::json!({
+        "line_items": [{ "description": "Audit", "quantity": 2.0, "rate": 450.0 }],
+        "tax_percent": 10.0,
+    }));
+
+    let options = ExportOptions { template_id: Some(template.id.clone()), ..ExportOptions::default() };
+    let html = h.app.generate_invoice_html(&invoice, &options);
+    assert!(html.contains("Audit 2 x $450.00 = $900.00"));
+    assert!(html.contains("Total $990.00"));
+    assert!(html.contains("Card"));
+
+    let redacted = ExportOptions { redact_rates: true, ..options };
+    let html = h.app.generate_invoice_html(&invoice, &redacted);
+    assert!(html.contains("<p>Audit 2 x  = </p>"));
+    assert!(html.contains("<p>Total  due  </p>"));
+    assert!(!html.contains("Card"));
+    assert!(!html.contains('$'));
+}
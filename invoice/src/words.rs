@@ -0,0 +1,394 @@
+// Amounts written out in words with the currency named, e.g. "One thousand two
+// hundred thirty-four dollars and fifty-six cents only"
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+impl Language {
+    // Label for the amount-in-words row of the totals block
+    pub fn words_label(&self, balance_due: bool) -> &'static str {
+        match (self, balance_due) {
+            (Language::English, false) => "Total in words",
+            (Language::English, true) => "Balance due in words",
+            (Language::German, false) => "Gesamtbetrag in Worten",
+            (Language::German, true) => "Offener Betrag in Worten",
+            (Language::French, false) => "Total en lettres",
+            (Language::French, true) => "Solde dû en lettres",
+            (Language::Spanish, false) => "Total en letras",
+            (Language::Spanish, true) => "Saldo pendiente en letras",
+        }
+    }
+}
+
+// Whole units and the minor units of a currency with `decimals` places, first
+// letter capitalized. Currencies without known names are written with their code
+// and the minor units as a fraction, e.g. "One hundred KWD and 250/1000". Amounts
+// of a trillion or more fall back to digits.
+pub fn amount_in_words(amount: f64, language: Language, currency: &str, decimals: usize) -> String {
+    let scale = 10u64.pow(decimals as u32);
+    let minor_total = (amount.abs() * scale as f64).round() as u64;
+    let (units, minor) = (minor_total / scale, minor_total % scale);
+    let (and, minus) = match language {
+        Language::English => ("and", "minus"),
+        Language::German => ("und", "minus"),
+        Language::French => ("et", "moins"),
+        Language::Spanish => ("con", "menos"),
+    };
+    let words = match unit_names(currency, language).filter(|_| decimals <= 2) {
+        Some([one, many, minor_one, minor_many]) => {
+            let mut words = format!("{} {}", counted(units, language), unit_noun(units, one, many, language));
+            if minor > 0 {
+                let minor_noun = if minor == 1 { minor_one } else { minor_many };
+                words.push_str(&format!(" {} {} {}", and, counted(minor, language), minor_noun));
+            }
+            if language == Language::English {
+                words.push_str(" only");
+            }
+            words
+        }
+        None if decimals == 0 => format!("{} {}", number(units, language), currency),
+        None => format!("{} {} {} {:0width$}/{}", number(units, language), currency, and, minor, scale, width = decimals),
+    };
+    let words = if amount < 0.0 { format!("{} {}", minus, words) } else { words };
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
+
+fn number(n: u64, language: Language) -> String {
+    if n >= 1_000_000_000_000 {
+        return n.to_string();
+    }
+    match language {
+        Language::English => english(n),
+        Language::German => german(n),
+        Language::French => french(n),
+        Language::Spanish => spanish(n, false),
+    }
+}
+
+// A number as it reads before the name of what it counts: "ein Euro", "veintiún dólares"
+fn counted(n: u64, language: Language) -> String {
+    match language {
+        Language::German if n == 1 => "ein".to_string(),
+        Language::Spanish if n < 1_000_000_000_000 => spanish(n, true),
+        _ => number(n, language),
+    }
+}
+
+// Plural except for one (and in French, zero); French and Spanish put "de" between
+// whole millions and the currency ("un million d'euros", "dos millones de dólares")
+fn unit_noun(n: u64, one: &str, many: &str, language: Language) -> String {
+    let singular = n == 1 || (n == 0 && language == Language::French);
+    let noun = if singular { one } else { many };
+    match language {
+        Language::French if n >= 1_000_000 && n.is_multiple_of(1_000_000) => {
+            if noun.starts_with(['a', 'e', 'i', 'o', 'u', 'y']) {
+                format!("d'{}", noun)
+            } else {
+                format!("de {}", noun)
+            }
+        }
+        Language::Spanish if n >= 1_000_000 && n.is_multiple_of(1_000_000) => format!("de {}", noun),
+        _ => noun.to_string(),
+    }
+}
+
+// Singular and plural names of a currency and of its hundredths
+fn unit_names(code: &str, language: Language) -> Option<[&'static str; 4]> {
+    use Language::*;
+    Some(match (code, language) {
+        ("USD" | "CAD" | "AUD" | "NZD" | "HKD" | "SGD", English) => ["dollar", "dollars", "cent", "cents"],
+        ("USD" | "CAD" | "AUD" | "NZD" | "HKD" | "SGD", German) => ["Dollar", "Dollar", "Cent", "Cent"],
+        ("USD" | "CAD" | "AUD" | "NZD" | "HKD" | "SGD", French) => ["dollar", "dollars", "cent", "cents"],
+        ("USD" | "CAD" | "AUD" | "NZD" | "HKD" | "SGD", Spanish) => ["dólar", "dólares", "centavo", "centavos"],
+        ("EUR", English) => ["euro", "euros", "cent", "cents"],
+        ("EUR", German) => ["Euro", "Euro", "Cent", "Cent"],
+        ("EUR", French) => ["euro", "euros", "centime", "centimes"],
+        ("EUR", Spanish) => ["euro", "euros", "céntimo", "céntimos"],
+        ("GBP", English) => ["pound", "pounds", "penny", "pence"],
+        ("GBP", German) => ["Pfund", "Pfund", "Penny", "Pence"],
+        ("GBP", French) => ["livre", "livres", "penny", "pence"],
+        ("GBP", Spanish) => ["libra", "libras", "penique", "peniques"],
+        ("CHF", English) => ["franc", "francs", "centime", "centimes"],
+        ("CHF", German) => ["Franken", "Franken", "Rappen", "Rappen"],
+        ("CHF", French) => ["franc", "francs", "centime", "centimes"],
+        ("CHF", Spanish) => ["franco", "francos", "céntimo", "céntimos"],
+        ("INR", English) => ["rupee", "rupees", "paisa", "paise"],
+        ("INR", German) => ["Rupie", "Rupien", "Paisa", "Paise"],
+        ("INR", French) => ["roupie", "roupies", "paisa", "paise"],
+        ("INR", Spanish) => ["rupia", "rupias", "paisa", "paisas"],
+        ("MXN", German) => ["Peso", "Pesos", "Centavo", "Centavos"],
+        ("MXN", _) => ["peso", "pesos", "centavo", "centavos"],
+        ("JPY", German) => ["Yen", "Yen", "", ""],
+        ("JPY", French) => ["yen", "yens", "", ""],
+        ("JPY", Spanish) => ["yen", "yenes", "", ""],
+        ("JPY", English) => ["yen", "yen", "", ""],
+        _ => return None,
+    })
+}
+
+// Splits n (below a trillion) into billions, millions, thousands and the rest
+fn groups(n: u64) -> [u64; 4] {
+    [n / 1_000_000_000, (n / 1_000_000) % 1000, (n / 1000) % 1000, n % 1000]
+}
+
+// English
+
+const EN_ONES: [&str; 20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+];
+const EN_TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+fn english_below_thousand(n: u64) -> String {
+    let mut parts = Vec::new();
+    if n >= 100 {
+        parts.push(format!("{} hundred", EN_ONES[(n / 100) as usize]));
+    }
+    let rest = n % 100;
+    if rest >= 20 {
+        match rest % 10 {
+            0 => parts.push(EN_TENS[(rest / 10) as usize].to_string()),
+            ones => parts.push(format!("{}-{}", EN_TENS[(rest / 10) as usize], EN_ONES[ones as usize])),
+        }
+    } else if rest > 0 {
+        parts.push(EN_ONES[rest as usize].to_string());
+    }
+    parts.join(" ")
+}
+
+fn english(n: u64) -> String {
+    if n == 0 {
+        return EN_ONES[0].to_string();
+    }
+    groups(n).iter()
+        .zip(["billion", "million", "thousand", ""])
+        .filter(|(group, _)| **group > 0)
+        .map(|(group, scale)| match scale {
+            "" => english_below_thousand(*group),
+            scale => format!("{} {}", english_below_thousand(*group), scale),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// German: everything below a million is written as one word
+
+const DE_ONES: [&str; 20] = [
+    "null", "eins", "zwei", "drei", "vier", "fünf", "sechs", "sieben", "acht", "neun", "zehn",
+    "elf", "zwölf", "dreizehn", "vierzehn", "fünfzehn", "sechzehn", "siebzehn", "achtzehn", "neunzehn",
+];
+const DE_TENS: [&str; 10] = [
+    "", "", "zwanzig", "dreißig", "vierzig", "fünfzig", "sechzig", "siebzig", "achtzig", "neunzig",
+];
+
+// `inflected` drops the s of a trailing "eins" before tausend ("eintausend")
+fn german_below_thousand(n: u64, inflected: bool) -> String {
+    let mut words = String::new();
+    if n >= 100 {
+        let hundreds = n / 100;
+        words.push_str(if hundreds == 1 { "ein" } else { DE_ONES[hundreds as usize] });
+        words.push_str("hundert");
+    }
+    let rest = n % 100;
+    if rest == 1 {
+        words.push_str(if inflected { "ein" } else { "eins" });
+    } else if rest >= 20 {
+        match rest % 10 {
+            0 => {}
+            1 => words.push_str("einund"),
+            ones => {
+                words.push_str(DE_ONES[ones as usize]);
+                words.push_str("und");
+            }
+        }
+        words.push_str(DE_TENS[(rest / 10) as usize]);
+    } else if rest > 0 {
+        words.push_str(DE_ONES[rest as usize]);
+    }
+    words
+}
+
+fn german(n: u64) -> String {
+    if n == 0 {
+        return DE_ONES[0].to_string();
+    }
+    let [billions, millions, thousands, rest] = groups(n);
+    let mut parts = Vec::new();
+    for (count, singular, plural) in [(billions, "eine Milliarde", "Milliarden"), (millions, "eine Million", "Millionen")] {
+        match count {
+            0 => {}
+            1 => parts.push(singular.to_string()),
+            count => parts.push(format!("{} {}", german_below_thousand(count, false), plural)),
+        }
+    }
+    let mut below_million = String::new();
+    if thousands > 0 {
+        below_million.push_str(&german_below_thousand(thousands, true));
+        below_million.push_str("tausend");
+    }
+    if rest > 0 {
+        below_million.push_str(&german_below_thousand(rest, false));
+    }
+    if !below_million.is_empty() {
+        parts.push(below_million);
+    }
+    parts.join(" ")
+}
+
+// French
+
+const FR_ONES: [&str; 17] = [
+    "zéro", "un", "deux", "trois", "quatre", "cinq", "six", "sept", "huit", "neuf", "dix",
+    "onze", "douze", "treize", "quatorze", "quinze", "seize",
+];
+const FR_TENS: [&str; 7] = ["", "", "vingt", "trente", "quarante", "cinquante", "soixante"];
+
+// `before_mille` keeps vingt and cent invariable, as in "quatre-vingt mille"
+fn french_below_hundred(n: u64, before_mille: bool) -> String {
+    match n {
+        0..=16 => FR_ONES[n as usize].to_string(),
+        17..=19 => format!("dix-{}", FR_ONES[(n - 10) as usize]),
+        20..=69 => {
+            let tens = FR_TENS[(n / 10) as usize];
+            match n % 10 {
+                0 => tens.to_string(),
+                1 => format!("{} et un", tens),
+                ones => format!("{}-{}", tens, FR_ONES[ones as usize]),
+            }
+        }
+        71 => "soixante et onze".to_string(),
+        70..=79 => format!("soixante-{}", french_below_hundred(n - 60, before_mille)),
+        80 if !before_mille => "quatre-vingts".to_string(),
+        80 => "quatre-vingt".to_string(),
+        _ => format!("quatre-vingt-{}", french_below_hundred(n - 80, before_mille)),
+    }
+}
+
+fn french_below_thousand(n: u64, before_mille: bool) -> String {
+    let hundreds = n / 100;
+    let rest = n % 100;
+    let mut parts = Vec::new();
+    match hundreds {
+        0 => {}
+        1 => parts.push("cent".to_string()),
+        hundreds if rest == 0 && !before_mille => parts.push(format!("{} cents", FR_ONES[hundreds as usize])),
+        hundreds => parts.push(format!("{} cent", FR_ONES[hundreds as usize])),
+    }
+    if rest > 0 {
+        parts.push(french_below_hundred(rest, before_mille));
+    }
+    parts.join(" ")
+}
+
+fn french(n: u64) -> String {
+    if n == 0 {
+        return FR_ONES[0].to_string();
+    }
+    let [billions, millions, thousands, rest] = groups(n);
+    let mut parts = Vec::new();
+    for (count, singular, plural) in [(billions, "milliard", "milliards"), (millions, "million", "millions")] {
+        match count {
+            0 => {}
+            1 => parts.push(format!("un {}", singular)),
+            count => parts.push(format!("{} {}", french_below_thousand(count, false), plural)),
+        }
+    }
+    match thousands {
+        0 => {}
+        1 => parts.push("mille".to_string()),
+        thousands => parts.push(format!("{} mille", french_below_thousand(thousands, true))),
+    }
+    if rest > 0 {
+        parts.push(french_below_thousand(rest, false));
+    }
+    parts.join(" ")
+}
+
+// Spanish
+
+const ES_BELOW_THIRTY: [&str; 30] = [
+    "cero", "uno", "dos", "tres", "cuatro", "cinco", "seis", "siete", "ocho", "nueve", "diez",
+    "once", "doce", "trece", "catorce", "quince", "dieciséis", "diecisiete", "dieciocho", "diecinueve",
+    "veinte", "veintiuno", "veintidós", "veintitrés", "veinticuatro", "veinticinco", "veintiséis",
+    "veintisiete", "veintiocho", "veintinueve",
+];
+const ES_TENS: [&str; 10] = [
+    "", "", "", "treinta", "cuarenta", "cincuenta", "sesenta", "setenta", "ochenta", "noventa",
+];
+const ES_HUNDREDS: [&str; 10] = [
+    "", "ciento", "doscientos", "trescientos", "cuatrocientos", "quinientos", "seiscientos",
+    "setecientos", "ochocientos", "novecientos",
+];
+
+// `apocope` shortens a trailing uno before mil and millones ("veintiún mil")
+fn spanish_below_thousand(n: u64, apocope: bool) -> String {
+    if n == 100 {
+        return "cien".to_string();
+    }
+    let mut parts = Vec::new();
+    if n >= 100 {
+        parts.push(ES_HUNDREDS[(n / 100) as usize].to_string());
+    }
+    let rest = n % 100;
+    let below_hundred = match rest {
+        0 => None,
+        1 if apocope => Some("un".to_string()),
+        21 if apocope => Some("veintiún".to_string()),
+        1..=29 => Some(ES_BELOW_THIRTY[rest as usize].to_string()),
+        _ => Some(match rest % 10 {
+            0 => ES_TENS[(rest / 10) as usize].to_string(),
+            1 if apocope => format!("{} y un", ES_TENS[(rest / 10) as usize]),
+            ones => format!("{} y {}", ES_TENS[(rest / 10) as usize], ES_BELOW_THIRTY[ones as usize]),
+        }),
+    };
+    parts.extend(below_hundred);
+    parts.join(" ")
+}
+
+// Below a million; a billion is "mil millones", so millions can count past a thousand
+fn spanish_below_million(n: u64, apocope: bool) -> String {
+    let (thousands, rest) = (n / 1000, n % 1000);
+    let mut parts = Vec::new();
+    match thousands {
+        0 => {}
+        1 => parts.push("mil".to_string()),
+        thousands => parts.push(format!("{} mil", spanish_below_thousand(thousands, true))),
+    }
+    if rest > 0 {
+        parts.push(spanish_below_thousand(rest, apocope));
+    }
+    parts.join(" ")
+}
+
+// `apocope` shortens a trailing uno before a noun ("veintiún dólares")
+fn spanish(n: u64, apocope: bool) -> String {
+    if n == 0 {
+        return ES_BELOW_THIRTY[0].to_string();
+    }
+    let (millions, rest) = (n / 1_000_000, n % 1_000_000);
+    let mut parts = Vec::new();
+    match millions {
+        0 => {}
+        1 => parts.push("un millón".to_string()),
+        millions => parts.push(format!("{} millones", spanish_below_million(millions, true))),
+    }
+    if rest > 0 {
+        parts.push(spanish_below_million(rest, apocope));
+    }
+    parts.join(" ")
+}
@@ -4,6 +4,7 @@ import InvoiceHeader from './InvoiceHeader';
 import LineItemTable from './LineItemTable';
 import InvoiceTotals from './InvoiceTotals';
 import './InvoiceEditor.css';
+import type { ExportOptions } from '../../types/invoice';
 
 interface InvoiceEditorProps {
   onBack: () => void;
@@ -41,9 +42,9 @@ const InvoiceEditor: React.FC<InvoiceEditorProps> = ({ onBack }) => {
     return () => window.removeEventListener('keydown', handleKeyDown);
   }, [canUndo, canRedo, undo, redo]);
 
-  const handleGeneratePDF = async () => {
+  const handleGeneratePDF = async (options?: ExportOptions) => {
     try {
-      const result = await generatePDF();
+      const result = await generatePDF(options);
       const { html, filename } = JSON.parse(result);
       
       // Create a blob from the HTML content
@@ -116,7 +117,14 @@ const InvoiceEditor: React.FC<InvoiceEditorProps> = ({ onBack }) => {
         </div>
         <div className="toolbar-right">
           <button 
-            onClick={handleGeneratePDF}
+            onClick={() => handleGeneratePDF({ redact_rates: true, hide_internal_fields: true })}
+            className="btn btn-secondary"
+            title="Quantities and descriptions only, without rates, totals or receipts"
+          >
+            Generate Redacted Copy
+          </button>
+          <button 
+            onClick={() => handleGeneratePDF()}
             className="btn btn-primary"
           >
             Generate Invoice
@@ -42,6 +42,8 @@ const InvoiceHeader: React.FC<InvoiceHeaderProps> = ({ invoice, onUpdate, onUpda
           <label>Status</label>
           <select value={invoice.status} onChange={handleStatusChange}>
             <option value={InvoiceStatus.Draft}>Draft</option>
+            <option value={InvoiceStatus.PendingApproval} disabled>Pending Approval</option>
+            <option value={InvoiceStatus.Approved} disabled>Approved</option>
             <option value={InvoiceStatus.Sent}>Sent</option>
             <option value={InvoiceStatus.Paid}>Paid</option>
             <option value={InvoiceStatus.Overdue}>Overdue</option>
@@ -291,6 +291,13 @@ const LineItemTable: React.FC<LineItemTableProps> = ({ items, onAddItem }) => {
                     step="0.01"
                     className="number-input"
                   />
+                  <input
+                    type="text"
+                    value={item.unit ?? ''}
+                    onChange={(e) => updateLineItem(item.id, { unit: e.target.value || null })}
+                    placeholder="unit"
+                    className="unit-input"
+                  />
                 </td>
                 <td className="rate-col" data-label="Rate">
                   <div className="rate-input-wrapper">
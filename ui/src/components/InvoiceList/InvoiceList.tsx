@@ -43,11 +43,14 @@ const InvoiceList: React.FC<InvoiceListProps> = ({ onOpenSettings }) => {
   };
 
   const formatDate = (dateString: string) => {
+    // Invoice dates are calendar dates already in the app's timezone; format them
+    // as UTC so the browser's own offset can't shift them to a neighbouring day
     const date = new Date(dateString);
     return new Intl.DateTimeFormat('en-US', {
       year: 'numeric',
       month: 'short',
-      day: 'numeric'
+      day: 'numeric',
+      timeZone: 'UTC'
     }).format(date);
   };
 
@@ -1,5 +1,5 @@
 import { create } from 'zustand';
-import type { InvoiceState, Invoice, InvoiceSettings, LineItem } from '../types/invoice';
+import type { InvoiceState, Invoice, InvoiceSettings, LineItem, ExportOptions } from '../types/invoice';
 import * as invoiceApi from '../utils/invoiceApi';
 
 interface InvoiceStore extends InvoiceState {
@@ -34,7 +34,7 @@ interface InvoiceStore extends InvoiceState {
   checkUndoRedo: () => Promise<void>;
   
   // PDF actions
-  generatePDF: () => Promise<string>;
+  generatePDF: (options?: ExportOptions) => Promise<string>;
   
   // Auto-save
   startAutosave: () => void;
@@ -135,6 +135,7 @@ export const useInvoiceStore = create<InvoiceStore>((set, get) => ({
           name: invoice.name,
           date: invoice.date,
           total: 0,
+          currency: invoice.currency ?? 'USD',
           status: invoice.status
         }]
       });
@@ -226,6 +227,7 @@ export const useInvoiceStore = create<InvoiceStore>((set, get) => ({
               const lineDiscount = lineTotal * (item.discount_percent / 100);
               return sum + (lineTotal - lineDiscount);
             }, 0),
+            currency: invoice.currency ?? 'USD',
             status: invoice.status
           };
           set({ invoices: newInvoices });
@@ -278,6 +280,7 @@ export const useInvoiceStore = create<InvoiceStore>((set, get) => ({
             const lineDiscount = lineTotal * (item.discount_percent / 100);
             return sum + (lineTotal - lineDiscount);
           }, 0),
+          currency: invoice.currency ?? 'USD',
           status: invoice.status
         };
         set({ invoices: newInvoices });
@@ -491,10 +494,10 @@ export const useInvoiceStore = create<InvoiceStore>((set, get) => ({
   },
   
   // PDF actions
-  generatePDF: async () => {
+  generatePDF: async (options?: ExportOptions) => {
     set({ isSaving: true });
     try {
-      const path = await invoiceApi.generatePDF();
+      const path = await invoiceApi.generatePDF(options);
       set({ isSaving: false });
       return path;
     } catch (error) {
@@ -508,7 +511,17 @@ export const useInvoiceStore = create<InvoiceStore>((set, get) => ({
     const { autosaveTimer } = get();
     if (autosaveTimer) return;
     
+    let ticks = 0;
     const timer = setInterval(async () => {
+      // Scheduled sends, queued notifications, payment reminders and late fees are checked once a minute
+      ticks += 1;
+      if (ticks % 60 === 0) {
+        invoiceApi.checkScheduledSends().catch(() => {});
+        invoiceApi.checkNotifications().catch(() => {});
+        invoiceApi.checkReminders().catch(() => {});
+        invoiceApi.checkLateFees().catch(() => {});
+      }
+
       const { hasUnsavedChanges } = get();
       if (hasUnsavedChanges) {
         try {
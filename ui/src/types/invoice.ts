@@ -8,6 +8,108 @@ export interface InvoiceSettings {
   payment_image_path: string | null;
   invoice_number_prefix: string;
   next_invoice_number: number;
+  time_import_nodes?: string[];
+  zatca_qr_enabled?: boolean; // For the default profile's invoices
+  number_barcode?: NumberBarcode | null; // Invoice number printed as a barcode for AP scanning
+  epc_qr_enabled?: boolean;
+  remittance_slip_enabled?: boolean;
+  email_send_process?: string | null;
+  lightning?: LightningSettings | null;
+  stablecoins?: StablecoinSettings[];
+  profiles?: BusinessProfile[];
+  email_inbox_address?: string | null;
+  email_ingest_processes?: string[];
+  approval_required?: boolean;
+  approvers?: string[];
+  utc_offset_minutes?: number;
+  daylight_saving?: 'Eu' | 'NorthAmerica' | null;
+  payment_terms?: PaymentTerms | null; // Given to new invoices
+  payment_terms_days?: number | null; // Only read when payment_terms is unset
+  roll_due_dates?: boolean;
+  holidays?: string[];
+  totals_display?: TotalsDisplay;
+  rounding?: Rounding; // Given to new invoices
+  language?: Language;
+  tax?: TaxSettings;
+  receipt_ocr_process?: string | null;
+  font_family?: string | null; // Uploaded font family the built-in layout uses
+  logo_background?: LogoBackground; // How logos (ours and clients') sit on the page
+  payment_method_fees?: PaymentMethodFee[]; // Alternative amounts shown on invoices, by how the client pays
+  early_payment_terms?: EarlyPaymentTerms | null; // Offered on new invoices
+  late_fee?: LateFeePolicy | null; // Charged on overdue invoices
+  default_unit?: string | null; // Given to new line items that don't name a unit
+  delivery_addresses_enabled?: boolean; // Invoices can have a Ship To address
+  client_purchase_orders?: Record<string, string>; // Client ID -> PO number quoted on their new invoices
+  default_text?: DefaultText; // Notes, terms and thank-you text new invoices start with
+  client_default_text?: Record<string, DefaultText>; // Client ID -> text used instead, field by field
+  custom_fields?: CustomFieldDefinition[]; // Extra fields invoices and line items can carry
+  default_currency?: string; // ISO 4217 code new invoices are issued in, e.g. "USD"
+}
+
+// "Due on receipt", or { Net: 30 } for Net 30
+export type PaymentTerms = 'DueOnReceipt' | { Net: number };
+
+// e.g. { percent: 2, days: 10 } for "2/10 net 30"
+export interface EarlyPaymentTerms {
+  percent: number;
+  days: number;
+}
+
+// Knockout lets white in a logo show the page through; Fill draws a hex colour behind it
+export type LogoBackground = 'AsUploaded' | 'Knockout' | { Fill: string };
+
+// One fee per period an invoice stays unpaid past its due date
+export interface LateFeePolicy {
+  charge: { Flat: number } | { Percent: number }; // Percent is of the amount due, each period
+  period_days: number;
+  grace_days?: number; // Days past the due date before the first fee
+  max_periods?: number | null; // 1 charges a single fee
+}
+
+// percent is negative for a discount
+export interface PaymentMethodFee {
+  id: string;
+  label: string;
+  percent: number;
+  fixed?: number;
+}
+
+export interface RecordedPayment {
+  method: string | null;
+  amount: number;
+  adjustment: number; // Payment method surcharge (positive) or discount (negative) included in amount
+  early_payment_discount?: number;
+  paid_on: string;
+  reference: string | null;
+  recorded_at: number;
+}
+
+export type NumberBarcode = 'Code128' | 'Qr';
+
+export interface BusinessProfile {
+  id: string;
+  name: string;
+  invoicer: ContactInfo;
+  payment_info: string | null;
+  payment_image_path: string | null;
+  invoice_number_prefix: string;
+  next_invoice_number: number;
+  zatca_qr_enabled?: boolean;
+}
+
+export interface StablecoinSettings {
+  symbol: string;
+  chain_id: number;
+  token_address: string;
+  decimals: number;
+  recipient: string;
+  currency?: string; // ISO 4217 code one token is pegged to, "USD" when omitted
+}
+
+export interface LightningSettings {
+  process: string;
+  sats_per_unit: number;
+  currency?: string; // ISO 4217 code sats_per_unit is quoted in, "USD" when omitted
 }
 
 export interface ContactInfo {
@@ -17,6 +119,18 @@ export interface ContactInfo {
   email: string | null;
   phone: string | null;
   logo_path: string | null;
+  tax_id?: string | null;
+  state?: string | null;
+  node?: string | null;
+  verified_identity?: VerifiedIdentity | null;
+}
+
+export interface VerifiedIdentity {
+  node: string;
+  owner: string;
+  tba: string;
+  name: string | null;
+  verified_at: number;
 }
 
 export interface Invoice {
@@ -36,15 +150,451 @@ export interface Invoice {
   status: InvoiceStatus;
   created_at: number;
   updated_at: number;
+  kind?: DocumentKind;
+  signature?: Signature | null;
+  terms?: TermsReference | null;
+  terms_acceptances?: TermsAcceptance[];
+  lightning_payment?: LightningPayment | null;
+  crypto_payment?: CryptoPayment | null;
+  service_period_start?: string | null;
+  service_period_end?: string | null;
+  source_documents?: SourceDocumentLink[];
+  consolidated_into?: string | null;
+  profile_id?: string | null;
+  reissued_from?: string | null;
+  reminders_sent?: SentReminder[];
+  approval?: Approval | null;
+  deposits_applied?: DepositLink[];
+  applied_to?: DepositLink[];
+  language?: Language | null;
+  place_of_supply?: string | null;
+  sales_taxes?: AppliedTax[];
+  tax_lines?: TaxLine[]; // Charged instead of tax_percent when given
+  shipping?: ShippingCharge | null;
+  rounding?: Rounding; // From settings when the invoice was created
+  payment?: RecordedPayment | null;
+  early_payment?: EarlyPaymentTerms | null;
+  payment_terms?: PaymentTerms | null;
+  project?: string | null; // Groups invoices in margin reports
+  tags?: string[]; // Labels to find invoices by; never shown to the client
+  commission?: Commission | null; // Referral partner's share; never shown to the client
+  currency?: string; // ISO 4217 code, e.g. "EUR"; USD when absent
+  exchange_rate?: ExchangeRateSnapshot | null; // Taken when a foreign-currency invoice is sent
+  purchase_order?: string | null; // Client's PO number
+  reference?: string | null;
+  delivery_address?: DeliveryAddress | null; // Ship To, when not the billing address
+  custom_fields?: CustomFieldValues; // Values of the invoice fields defined in settings
+  status_history?: StatusChange[]; // Oldest first
+  sent_at?: number | null; // When it was first marked Sent
+  issued_documents?: IssuedRecord[]; // One per time it was marked Sent
+  terms_text?: string | null; // Printed under the notes
+  thank_you?: string | null; // Closing line of the document
+}
+
+// Text may use {invoice_number}, {client_name}, {due_date}, {payment_terms}, {total}
+// and {late_fee_policy}, filled in whenever the invoice is rendered
+export interface DefaultText {
+  notes?: string | null;
+  terms?: string | null;
+  thank_you?: string | null;
+}
+
+export interface IssuedRecord {
+  issued_at: number;
+  sha256: string; // Of the HTML
+}
+
+// The document exactly as rendered when the invoice was marked Sent
+export interface IssuedDocument {
+  invoice_id: string;
+  number: string;
+  sequence: number;
+  issued_at: number;
+  sha256: string;
+  html: string;
+}
+
+export interface StatusChange {
+  from: InvoiceStatus;
+  to: InvoiceStatus;
+  at: number;
+  by: string; // Node that made the change
+  metadata?: Record<string, string>;
+}
+
+export type CustomFieldType = 'Text' | 'Number' | 'Date' | 'Boolean';
+
+export interface CustomFieldDefinition {
+  name: string;
+  field_type: CustomFieldType;
+  scope: 'Invoice' | 'LineItem';
 }
 
+// Field name -> value; dates are ISO strings
+export type CustomFieldValues = Record<string, string | number | boolean | null>;
+
+export interface DeliveryAddress {
+  name: string; // Recipient
+  company?: string | null;
+  address: string;
+  phone?: string | null;
+}
+
+export interface ExchangeRateSnapshot {
+  home_currency: string;
+  rate: number; // Units of the home currency per unit of the invoice's
+  home_total: number; // Invoice total when the rate was taken, converted
+  taken_at: number;
+}
+
+// An edit that replaced or lost to a change made in another session
+export interface MergeWarning {
+  at: number;
+  field: string; // e.g. "notes" or "line_items/<item ID>"
+  message: string;
+}
+
+export interface SessionEditState {
+  applied_sequence: number; // Latest edit merged
+  saved_sequence: number | null; // Latest edit written to storage
+  warnings: MergeWarning[];
+}
+
+export interface EditState {
+  invoice_id: string;
+  saved_at: number | null; // null until first saved
+  unsaved: boolean;
+  dirty_fields: string[];
+  session: SessionEditState | null; // null before this session's first edit
+}
+
+export interface ExchangeRate {
+  currency: string;
+  home_currency: string;
+  rate: number;
+  updated_at: number;
+}
+
+export interface Commission {
+  partner_id: string;
+  percent: number; // Of the invoice before tax
+}
+
+export interface Partner {
+  id: string;
+  name: string;
+  email: string | null;
+  default_percent: number;
+  created_at: number;
+}
+
+export interface SavePartnerRequest {
+  id?: string;
+  name: string;
+  email?: string | null;
+  default_percent: number;
+}
+
+export interface PartnerPayout {
+  id: string;
+  partner_id: string;
+  amount: number;
+  paid_on: string;
+  reference: string | null;
+  recorded_at: number;
+}
+
+export interface DepositLink {
+  invoice_id: string;
+  number: string;
+  amount: number;
+}
+
+export interface Approval {
+  submitted_at: number;
+  document_hash: string;
+  approved: boolean | null;
+  decided_by: string | null;
+  decided_at: number | null;
+  rejection_reason: string | null;
+}
+
+export interface ScheduledSend {
+  id: string;
+  invoice_id: string;
+  send_at: number;
+  email: boolean;
+  p2p: boolean;
+  created_at: number;
+  last_error: string | null;
+  request_id?: string | null; // Request that scheduled it, as quoted in diagnostics
+}
+
+export interface PlanChange {
+  effective_date: string;
+  amount: number;
+}
+
+export interface SubscriptionPlan {
+  amount: number; // Per month
+  billing_day: number; // 1 to 28
+  start_date: string;
+  end_date: string | null;
+  changes: PlanChange[];
+}
+
+export interface BilledPeriod {
+  start: string;
+  end: string;
+  amount: number;
+  invoice_id: string;
+}
+
+export interface Subscription {
+  id: string;
+  name: string;
+  invoicee: ContactInfo;
+  plan: SubscriptionPlan;
+  profile_id: string | null;
+  next_period_start: string;
+  billed: BilledPeriod[];
+  created_at: number;
+}
+
+export interface SaveSubscriptionRequest {
+  id?: string;
+  name: string;
+  invoicee: ContactInfo;
+  amount: number;
+  billing_day: number;
+  start_date: string;
+  end_date?: string | null;
+  profile_id?: string | null;
+}
+
+export interface ReceivedInvoice {
+  id: string;
+  from: string;
+  number: string;
+  total: number;
+  due_date: string | null;
+  html: string;
+  received_at: number;
+}
+
+export interface SeriesReport {
+  series: string;
+  prefix: string;
+  issued: number;
+  first: string | null;
+  last: string | null;
+  next: string;
+  gaps: string[];
+  voided: string[];
+  out_of_sequence: { number: string; date: string; previous_number: string; previous_date: string }[];
+}
+
+export interface SequenceReport {
+  series: SeriesReport[];
+  unmatched: string[];
+}
+
+export interface AuditEntry {
+  timestamp: number;
+  actor: string;
+  action: 'SubmittedForApproval' | 'Approved' | 'Rejected' | 'Sent' | 'CopyGenerated';
+  invoice_id: string;
+  invoice_number: string;
+  detail: string | null;
+}
+
+export type DeliveryChannel = 'ShareLink' | 'Email' | 'P2p' | 'Reminder';
+
+export interface DeliveryRecord {
+  at: number;
+  invoice_id: string;
+  channel: DeliveryChannel;
+  destination: string;
+  error: string | null; // Null when the delivery went out
+  detail: string | null;
+  request_id?: string | null; // Request that made the attempt
+}
+
+export interface ActivityEntry {
+  at: number;
+  text: string;
+}
+
+export type ReminderTone = 'Friendly' | 'Firm' | 'FinalNotice';
+
+export interface ReminderStep {
+  days_after_due: number;
+  tone: ReminderTone;
+  subject: string;
+  body: string;
+}
+
+export interface ReminderPolicy {
+  steps: ReminderStep[];
+  paused?: boolean;
+}
+
+export interface ReminderPolicies {
+  default: ReminderPolicy | null;
+  clients: Record<string, ReminderPolicy>;
+}
+
+export interface SentReminder {
+  step: number;
+  tone: ReminderTone;
+  to: string;
+  sent_at: number;
+}
+
+export interface CryptoPayment {
+  symbol: string;
+  chain_id: number;
+  token_address: string;
+  recipient: string;
+  amount: string;
+  uri: string;
+  from_block: number;
+  created_at: number;
+  tx_hash: string | null;
+  paid_at: number | null;
+}
+
+export interface LightningPayment {
+  bolt11: string;
+  payment_hash: string;
+  amount_msat: number;
+  created_at: number;
+  settled_at: number | null;
+}
+
+export interface TermsDocument {
+  id: string;
+  version: number;
+  title: string;
+  file_name: string;
+  path: string;
+  hash: string;
+  uploaded_at: number;
+}
+
+export interface TermsReference {
+  terms_id: string;
+  version: number;
+}
+
+export interface TermsAcceptance {
+  terms_id: string;
+  version: number;
+  accepted_by: string;
+  email: string | null;
+  accepted_at: number;
+  via_link_from: string | null; // Node that accepted through a share link
+}
+
+export enum DocumentKind {
+  Invoice = "Invoice",
+  Estimate = "Estimate",
+  DeliveryNote = "DeliveryNote"
+}
+
+export interface SourceDocumentLink {
+  id: string;
+  number: string;
+  kind: DocumentKind;
+}
+
+export interface Signature {
+  signer_name: string;
+  signed_at: number;
+  image_path: string;
+  document_hash: string;
+  signature_hash: string;
+  signer_node: string | null; // Node that signed through a share link; null when signed in the app
+  signer_email: string | null; // Address an email-restricted share link proved the signer holds
+}
+
+export interface VolumeTier {
+  min_quantity: number; // Every unit is billed at the tier rate from this quantity up
+  rate: number;
+}
+
+export interface CatalogItem {
+  id: string; // Empty when creating
+  name: string;
+  item_code?: string | null; // SKU or part number
+  description?: string | null;
+  unit?: string | null;
+  rate: number;
+  hsn_sac?: string | null;
+  tiers: VolumeTier[];
+}
+
+// Client tiers replace the item's tiers when set
+export interface ClientPrice {
+  rate?: number | null;
+  tiers: VolumeTier[];
+}
+
+export interface PriceQuote {
+  list_rate: number;
+  rate: number;
+  source: 'List' | 'Client' | 'Tier';
+  min_quantity: number | null;
+}
+
+export interface PriceListRequest {
+  item_ids?: string[]; // The whole catalog when empty
+  client_id?: string | null;
+  client_name?: string | null;
+  title?: string | null;
+  valid_from?: string | null; // ISO dates
+  valid_until?: string | null;
+  notes?: string | null;
+  template_id?: string | null; // Custom template; the built-in price list layout otherwise
+}
+
+export interface PriceListDocument {
+  path: string;
+  html: string;
+  filename: string;
+  errors: TemplateError[];
+}
+
+export interface CatalogLine {
+  item_id: string;
+  quantity?: number;
+  section?: string | null;
+}
+
+// A text row is only its description, e.g. a heading or a note, and adds nothing to the totals.
+// LateFee lines are added and updated by the late fee check; they can't be created directly.
+export type LineItemKind = 'Item' | 'Text' | 'LateFee';
+
 export interface LineItem {
   id: string;
+  kind?: LineItemKind;
+  item_code?: string | null; // Printed as the first column when any line has one
   description: string;
   quantity: number;
+  unit?: string | null; // e.g. "hours", "days", "pcs", "kg"
   rate: number;
   discount_percent: number;
   receipt_path: string | null;
+  section?: string | null;
+  service_date_start?: string | null;
+  service_date_end?: string | null;
+  hsn_sac?: string | null;
+  catalog_item_id?: string | null; // Catalog item the line was added from
+  list_rate?: number | null; // Catalog price when a client or volume price lowered the rate
+  cost?: number | null; // Per unit, for margin reports; never shown to the client
+  team_member_id?: string | null; // Who did the work; never shown to the client
+  custom_fields?: CustomFieldValues; // Values of the line item fields defined in settings
+  time_entry_id?: string | null; // Time entry the line bills
 }
 
 export interface InvoiceSummary {
@@ -53,11 +603,178 @@ export interface InvoiceSummary {
   name: string | null;
   date: string;
   total: number;
+  currency: string;
+  home_total?: number | null; // At the invoice's exchange rate
   status: InvoiceStatus;
 }
 
+// Every filter given must match; statuses match any of those listed
+export interface FindInvoicesRequest {
+  client?: string | null;
+  statuses?: InvoiceStatus[];
+  from?: string | null;
+  to?: string | null;
+  project?: string | null;
+  tags?: string[]; // Matches invoices carrying all of them
+}
+
+// Summaries changed since a version; on reset, created holds the whole list
+export interface SummaryDeltas {
+  version: number;
+  reset: boolean;
+  created: InvoiceSummary[];
+  updated: InvoiceSummary[];
+  deleted: string[];
+}
+
+export interface TimeEntry {
+  id: string;
+  source: string;
+  external_id: string;
+  date: string;
+  description: string;
+  hours: number;
+  rate: number | null;
+  billed_invoice_id: string | null;
+  imported_at: number;
+}
+
+export interface Expense {
+  id: string;
+  vendor: string;
+  vendor_email: string | null;
+  description: string;
+  date: string;
+  amount: number | null;
+  category: string | null;
+  attachments: string[];
+  message_id: string | null;
+  received_at: number;
+  amount_check?: AmountCheck | null;
+}
+
+export type AmountCheckStatus = 'Matched' | 'Mismatch' | 'Unreadable' | 'Reviewed';
+
+export interface AmountCheck {
+  entered: number;
+  detected: number | null;
+  receipt_path: string;
+  status: AmountCheckStatus;
+  checked_at: number;
+  error: string | null;
+}
+
+export interface UnfiledReceipt {
+  id: string;
+  file_name: string;
+  path: string;
+  uploaded_at: number;
+  category?: string | null;
+}
+
+export interface CategoryRule {
+  id: string;
+  category: string;
+  vendor_contains?: string | null;
+  keywords?: string[];
+  min_amount?: number | null;
+  max_amount?: number | null;
+}
+
+export interface DuplicateQuery {
+  invoice_id?: string;
+  client?: string;
+  total?: number;
+  date?: string;
+  file_data?: number[];
+}
+
+export interface PossibleDuplicates {
+  invoices: InvoiceSummary[];
+  receipts: string[];
+}
+
+export type QueryFilterOp = 'eq' | 'ne' | 'gt' | 'gte' | 'lt' | 'lte' | 'contains' | 'in';
+
+export interface QueryDocument {
+  collection: 'invoices' | 'clients' | 'payments';
+  fields?: string[];
+  filters?: { field: string; op: QueryFilterOp; value: unknown }[];
+  sort_by?: string | null;
+  descending?: boolean;
+  offset?: number;
+  limit?: number | null;
+}
+
+export interface AccessLogEntry {
+  timestamp: number;
+  source: string;
+  endpoint: string;
+  outcome: 'Allowed' | 'Denied' | 'RateLimited' | 'LockedOut';
+  reason: string | null;
+}
+
+export interface ShareLink {
+  token: string;
+  invoice_id: string;
+  created_at: number;
+  expires_at: number | null;
+  revoked: boolean;
+  requires_passcode: boolean;
+  allowed_email: string | null;
+}
+
+export interface FollowUp {
+  id: string;
+  invoice_id: string;
+  note: string;
+  due_date: string;
+  done: boolean;
+  created_at: number;
+  completed_at: number | null;
+  notified: boolean;
+}
+
+export type NotificationKind =
+  | 'PaymentReceived'
+  | 'DocumentSigned'
+  | 'TermsAccepted'
+  | 'ShareLinkOpened'
+  | 'ExpenseReceived'
+  | 'FollowUpDue'
+  | 'ApprovalDecided'
+  | 'StatusChanged';
+
+export interface NotificationPreferences {
+  delivery: 'Immediate' | 'DailyDigest';
+  digest_hour: number;
+  quiet_hours: { start_hour: number; end_hour: number } | null;
+  disabled_kinds: NotificationKind[];
+  push_process: string | null;
+  email: string | null;
+}
+
+export interface AppNotification {
+  id: string;
+  kind: NotificationKind;
+  title: string;
+  body: string;
+  invoice_id: string | null;
+  created_at: number;
+  delivered_at: number | null;
+}
+
+export interface CreateShareLinkRequest {
+  invoice_id: string;
+  passcode?: string;
+  allowed_email?: string;
+  expires_in_days?: number;
+}
+
 export enum InvoiceStatus {
   Draft = "Draft",
+  PendingApproval = "PendingApproval",
+  Approved = "Approved",
   Sent = "Sent",
   Paid = "Paid",
   Overdue = "Overdue"
@@ -89,7 +806,363 @@ export interface InvoiceState {
 }
 
 // Request types for line item operations
+export interface NewLineItem {
+  kind?: LineItemKind;
+  item_code?: string | null;
+  description?: string;
+  quantity?: number;
+  unit?: string | null; // The default unit in settings when omitted
+  rate?: number;
+  discount_percent?: number;
+  section?: string | null;
+  service_date_start?: string | null;
+  service_date_end?: string | null;
+  hsn_sac?: string | null;
+  cost?: number | null;
+  team_member_id?: string | null;
+  custom_fields?: CustomFieldValues;
+}
+
+export interface NewInvoice {
+  name?: string | null;
+  date?: string | null;
+  due_date?: string | null;
+  invoicee?: ContactInfo | null;
+  kind?: DocumentKind;
+  line_items?: NewLineItem[];
+  discount_percent?: number;
+  tax_percent?: number;
+  tax_lines?: TaxLine[];
+  shipping?: ShippingCharge | null;
+  notes?: string | null; // Default notes from settings when omitted
+  terms_text?: string | null; // Default terms from settings when omitted
+  thank_you?: string | null; // Default thank-you text from settings when omitted
+  profile_id?: string | null;
+  payment_terms?: PaymentTerms | null; // Overrides the terms in settings
+  payment_terms_days?: number | null;
+  early_payment?: EarlyPaymentTerms | null; // Overrides the early payment terms in settings
+  number?: string | null;
+  currency?: string | null; // Overrides the default currency in settings
+  purchase_order?: string | null; // Overrides the client's PO number in settings
+  reference?: string | null;
+  delivery_address?: DeliveryAddress | null;
+  custom_fields?: CustomFieldValues;
+}
+
+export interface PastedLine {
+  row: number; // 1-based line in the pasted text
+  description: string;
+  quantity: number;
+  rate: number;
+}
+
+export interface PastedTable {
+  format: 'Tsv' | 'Csv' | 'Semicolon' | 'Markdown';
+  has_header: boolean;
+  items: PastedLine[];
+  warnings: string[];
+}
+
+export interface ExportOptions {
+  redact_rates?: boolean;
+  hide_internal_fields?: boolean;
+  totals?: TotalsDisplay | null;
+  copy_label?: string | null;
+  template_id?: string | null; // Custom template to render with
+  page_layout?: PageLayout | null; // Rows per printed page of the line item table
+}
+
+export interface PageLayout {
+  first_page_rows: number;
+  rows_per_page: number;
+}
+
+export type TaxRegime =
+  | 'UkVat'
+  | 'GermanUst'
+  | 'GermanKleinunternehmer'
+  | 'UsSalesTax'
+  | 'IndiaGst'
+  | 'AustraliaGst';
+
+export type RequiredField = 'InvoicerTaxId' | 'InvoiceeTaxId' | 'InvoiceeAddress' | 'SupplyDate' | 'DueDate';
+
+export interface TaxRate {
+  name: string;
+  percent: number;
+}
+
+export interface TaxSettings {
+  regime: TaxRegime | null;
+  tax_name: string;
+  tax_id_label: string;
+  rates: TaxRate[];
+  default_percent: number;
+  split_names: string[];
+  interstate_name: string | null;
+  document_title: string | null;
+  wording: string | null;
+  required_fields: RequiredField[];
+  jurisdictions?: Jurisdiction[];
+}
+
+export type JurisdictionLevel = 'State' | 'County' | 'City' | 'District';
+
+export interface Jurisdiction {
+  id: string;
+  name: string;
+  level: JurisdictionLevel;
+  percent: number;
+}
+
+export interface AppliedTax {
+  jurisdiction_id: string;
+  name: string;
+  level: JurisdictionLevel;
+  percent: number;
+}
+
+// compound taxes are charged on the amount with the taxes before them included
+export interface TaxLine {
+  name: string;
+  percent: number;
+  compound?: boolean;
+}
+
+// Charged after the discount; taxed at the invoice's rates only when taxable
+export interface ShippingCharge {
+  amount: number;
+  taxable?: boolean;
+}
+
+export interface JurisdictionTotal {
+  jurisdiction_id: string;
+  name: string;
+  level: JurisdictionLevel;
+  invoices: number;
+  taxable_sales: number;
+  tax_invoiced: number;
+  tax_collected: number;
+}
+
+export interface JurisdictionReport {
+  from: string | null;
+  to: string | null;
+  currency: string; // Home currency the amounts are in
+  jurisdictions: JurisdictionTotal[];
+  unconverted: number; // Foreign-currency invoices left out for lack of a rate
+}
+
+export interface InvoiceMargin {
+  invoice_id: string;
+  number: string;
+  date: string;
+  client: string;
+  project: string | null;
+  revenue: number;
+  cost: number;
+  uncosted_revenue: number; // Revenue from lines without a cost
+  margin: number;
+  margin_percent: number | null; // Of costed revenue
+}
+
+export interface MarginTotal {
+  name: string;
+  invoices: number;
+  revenue: number;
+  cost: number;
+  margin: number;
+  margin_percent: number | null;
+  uncosted_revenue: number;
+}
+
+export interface MarginReport {
+  from: string | null;
+  to: string | null;
+  invoices: InvoiceMargin[];
+  clients: MarginTotal[];
+  projects: MarginTotal[];
+  total: MarginTotal | null;
+  currency: string; // Home currency the amounts are in
+  unconverted: number; // Foreign-currency invoices left out for lack of a rate
+}
+
+export interface CommissionLine {
+  invoice_id: string;
+  number: string;
+  date: string;
+  client: string;
+  base: number; // Before tax, after discounts
+  percent: number;
+  amount: number;
+  paid: boolean; // Commission is earned once the client pays
+}
+
+export interface PartnerStatement {
+  partner_id: string;
+  name: string;
+  earned: number;
+  pending: number; // On invoices not paid yet
+  paid_out: number;
+  owed: number;
+  lines: CommissionLine[];
+}
+
+export interface CommissionReport {
+  from: string | null;
+  to: string | null;
+  currency: string; // Home currency the amounts are in
+  partners: PartnerStatement[];
+  unconverted: number; // Foreign-currency invoices left out for lack of a rate
+}
+
+export interface TeamMember {
+  id: string;
+  name: string;
+  email?: string | null;
+  created_at: number;
+}
+
+export type ReportPeriod = 'Month' | 'Quarter' | 'Year';
+
+export interface PeriodRevenue {
+  period: string; // e.g. "2025-03", "2025-Q1" or "2025"
+  revenue: number;
+  invoices: number;
+}
+
+export interface MemberRevenue {
+  member_id: string;
+  name: string;
+  revenue: number;
+  periods: PeriodRevenue[];
+}
+
+export interface TeamReport {
+  from: string | null;
+  to: string | null;
+  period: ReportPeriod;
+  currency: string; // Home currency the amounts are in
+  members: MemberRevenue[];
+  unattributed: number; // Revenue of lines not credited to anyone
+  unconverted: number; // Foreign-currency invoices left out for lack of a rate
+}
+
+export interface OutstandingInvoice {
+  invoice_id: string;
+  number: string;
+  client: string;
+  due_date: string | null;
+  status: InvoiceStatus;
+  currency: string;
+  amount_due: number;
+  exchange_rate: number | null;
+  home_amount: number | null; // null when there is no rate into the home currency
+}
+
+export interface OutstandingReport {
+  home_currency: string;
+  total: number; // Of the invoices with a home amount
+  unconverted: number; // Invoices left out of the total for lack of a rate
+  currencies: { currency: string; amount_due: number }[];
+  invoices: OutstandingInvoice[];
+}
+
+// Language amounts are written out in on the totals block
+export type Language = 'English' | 'German' | 'French' | 'Spanish';
+
+// PerLine rounds each line, discount and tax; AtTotal rounds only the total
+export interface Rounding {
+  stage?: 'PerLine' | 'AtTotal';
+  mode?: 'HalfUp' | 'HalfEven';
+}
+
+export interface TotalsDisplay {
+  show_subtotal?: boolean;
+  hide_zero_discount?: boolean;
+  hide_zero_tax?: boolean;
+  show_balance_due?: boolean;
+  show_amount_in_words?: boolean;
+  show_section_subtotals?: boolean; // Repeat each section's subtotal above the subtotal row
+}
+
 export interface UpdateLineItemRequest {
   item_id: string;
   updates: LineItem;
-}
\ No newline at end of file
+}
+// Render context available to custom templates; variables lists each path
+// (array elements as "line_items[].description") with a sample value
+export interface TemplateVariables {
+  schema: Record<string, unknown>;
+  sample: Record<string, unknown>;
+  variables: { path: string; sample: unknown }[];
+}
+
+export interface TemplateError {
+  line: number;
+  message: string;
+}
+
+// html is null when the template doesn't parse or could load outside resources
+export interface TemplatePreview {
+  html: string | null;
+  errors: TemplateError[];
+}
+
+export interface TemplateAsset {
+  name: string; // Referred to as "asset:<name>" in the template
+  content_type: string;
+  path: string;
+  size: number;
+}
+
+export interface CustomTemplate {
+  id: string;
+  name: string;
+  description?: string | null;
+  source: string;
+  assets: TemplateAsset[];
+  imported: boolean;
+  created_at: number;
+  updated_at: number;
+}
+
+export type FontStyle = 'Normal' | 'Italic';
+
+export type Script =
+  | 'Greek'
+  | 'Cyrillic'
+  | 'Hebrew'
+  | 'Arabic'
+  | 'Devanagari'
+  | 'Thai'
+  | 'Hangul'
+  | 'Cjk'
+  | 'Emoji';
+
+export interface CustomFont {
+  id: string;
+  family: string;
+  weight: number; // 100 to 900
+  style: FontStyle;
+  file_name: string;
+  content_type: string;
+  path: string;
+  size: number;
+  uploaded_at: number;
+  scripts: Script[]; // Scripts this font is a fallback for
+}
+
+export interface TemplateBundle {
+  format: string;
+  version: number;
+  name: string;
+  description?: string | null;
+  source: string;
+  assets: { name: string; content_type: string; data: string }[]; // data is base64
+}
+
+export interface TemplateExport {
+  bundle: TemplateBundle;
+  filename: string;
+}
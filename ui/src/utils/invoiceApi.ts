@@ -1,10 +1,69 @@
 // Invoice API utilities
 import * as api from '../../../target/ui/caller-utils';
 import type { 
+  ActivityEntry,
+  AppNotification,
+  AuditEntry,
+  CreateShareLinkRequest,
+  CustomFont,
+  CustomTemplate,
+  DeliveryRecord,
+  AccessLogEntry,
+  DuplicateQuery,
+  EditState,
+  ExchangeRate,
+  CatalogItem,
+  CatalogLine,
+  CommissionReport,
+  CategoryRule,
+  ClientPrice,
+  Expense,
+  ExportOptions,
+  FindInvoicesRequest,
+  FollowUp,
+  FontStyle,
   Invoice, 
+  InvoiceStatus,
+  IssuedDocument,
   InvoiceSettings, 
   InvoiceSummary, 
+  JurisdictionReport,
   LineItem,
+  MarginReport,
+  NewInvoice,
+  NewLineItem,
+  NotificationPreferences,
+  OutstandingReport,
+  Partner,
+  PartnerPayout,
+  PastedTable,
+  PossibleDuplicates,
+  PriceListDocument,
+  PriceListRequest,
+  PriceQuote,
+  QueryDocument,
+  ReceivedInvoice,
+  ReminderPolicies,
+  ReminderPolicy,
+  ReportPeriod,
+  SavePartnerRequest,
+  SaveSubscriptionRequest,
+  ScheduledSend,
+  Script,
+  SequenceReport,
+  ShareLink,
+  Subscription,
+  SummaryDeltas,
+  TaxRegime,
+  TaxSettings,
+  TeamMember,
+  TeamReport,
+  TemplateAsset,
+  TemplateBundle,
+  TemplateExport,
+  TemplatePreview,
+  TemplateVariables,
+  UnfiledReceipt,
   UpdateLineItemRequest 
 } from '../types/invoice';
 
@@ -30,6 +89,216 @@ export async function updateSettings(settings: InvoiceSettings): Promise<void> {
   }
 }
 
+export async function listTaxPresets(): Promise<TaxSettings[]> {
+  try {
+    return JSON.parse(await appApi.list_tax_presets());
+  } catch (error) {
+    console.error('Failed to list tax presets:', error);
+    throw error;
+  }
+}
+
+export async function applyTaxPreset(regime: TaxRegime): Promise<TaxSettings> {
+  try {
+    return JSON.parse(await appApi.apply_tax_preset(JSON.stringify(regime)));
+  } catch (error) {
+    console.error('Failed to apply tax preset:', error);
+    throw error;
+  }
+}
+
+export async function getClientJurisdictions(): Promise<Record<string, string[]>> {
+  try {
+    return JSON.parse(await appApi.get_client_jurisdictions());
+  } catch (error) {
+    console.error('Failed to get client jurisdictions:', error);
+    throw error;
+  }
+}
+
+export async function setClientJurisdictions(clientId: string, jurisdictionIds: string[]): Promise<void> {
+  try {
+    await appApi.set_client_jurisdictions(JSON.stringify({ client_id: clientId, jurisdiction_ids: jurisdictionIds }));
+  } catch (error) {
+    console.error('Failed to set client jurisdictions:', error);
+    throw error;
+  }
+}
+
+// Omitting jurisdictionIds applies the client's jurisdictions
+export async function applyJurisdictions(invoiceId: string, jurisdictionIds?: string[]): Promise<Invoice> {
+  try {
+    return JSON.parse(await appApi.apply_jurisdictions(JSON.stringify({ invoice_id: invoiceId, jurisdiction_ids: jurisdictionIds ?? null })));
+  } catch (error) {
+    console.error('Failed to apply jurisdictions:', error);
+    throw error;
+  }
+}
+
+export async function getJurisdictionReport(from?: string, to?: string): Promise<JurisdictionReport> {
+  try {
+    return JSON.parse(await appApi.get_jurisdiction_report(JSON.stringify({ from: from ?? null, to: to ?? null })));
+  } catch (error) {
+    console.error('Failed to get jurisdiction report:', error);
+    throw error;
+  }
+}
+
+export async function getMarginReport(from?: string, to?: string): Promise<MarginReport> {
+  try {
+    return JSON.parse(await appApi.get_margin_report(JSON.stringify({ from: from ?? null, to: to ?? null })));
+  } catch (error) {
+    console.error('Failed to get margin report:', error);
+    throw error;
+  }
+}
+
+// Referral partners and commissions
+export async function listPartners(): Promise<Partner[]> {
+  try {
+    const response = await appApi.list_partners();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list partners:', error);
+    throw error;
+  }
+}
+
+export async function savePartner(request: SavePartnerRequest): Promise<Partner> {
+  try {
+    const response = await appApi.save_partner(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to save partner:', error);
+    throw error;
+  }
+}
+
+export async function deletePartner(id: string): Promise<void> {
+  try {
+    await appApi.delete_partner(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete partner:', error);
+    throw error;
+  }
+}
+
+export async function recordPartnerPayout(request: {
+  partner_id: string;
+  amount: number;
+  paid_on?: string;
+  reference?: string;
+}): Promise<PartnerPayout> {
+  try {
+    const response = await appApi.record_partner_payout(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to record partner payout:', error);
+    throw error;
+  }
+}
+
+export async function deletePartnerPayout(id: string): Promise<void> {
+  try {
+    await appApi.delete_partner_payout(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete partner payout:', error);
+    throw error;
+  }
+}
+
+export async function getCommissionReport(from?: string, to?: string): Promise<CommissionReport> {
+  try {
+    return JSON.parse(await appApi.get_commission_report(JSON.stringify({ from: from ?? null, to: to ?? null })));
+  } catch (error) {
+    console.error('Failed to get commission report:', error);
+    throw error;
+  }
+}
+
+// Team members and the revenue credited to them
+export async function listTeamMembers(): Promise<TeamMember[]> {
+  try {
+    return JSON.parse(await appApi.list_team_members());
+  } catch (error) {
+    console.error('Failed to list team members:', error);
+    throw error;
+  }
+}
+
+export async function saveTeamMember(request: { id?: string; name: string; email?: string }): Promise<TeamMember> {
+  try {
+    return JSON.parse(await appApi.save_team_member(JSON.stringify(request)));
+  } catch (error) {
+    console.error('Failed to save team member:', error);
+    throw error;
+  }
+}
+
+export async function deleteTeamMember(id: string): Promise<void> {
+  try {
+    await appApi.delete_team_member(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete team member:', error);
+    throw error;
+  }
+}
+
+export async function getTeamReport(from?: string, to?: string, period: ReportPeriod = 'Month'): Promise<TeamReport> {
+  try {
+    return JSON.parse(await appApi.get_team_report(JSON.stringify({ from: from ?? null, to: to ?? null, period })));
+  } catch (error) {
+    console.error('Failed to get team report:', error);
+    throw error;
+  }
+}
+
+// Exchange rates and home-currency reporting
+export async function listExchangeRates(): Promise<ExchangeRate[]> {
+  try {
+    return JSON.parse(await appApi.list_exchange_rates());
+  } catch (error) {
+    console.error('Failed to list exchange rates:', error);
+    throw error;
+  }
+}
+
+export async function setExchangeRate(currency: string, rate: number): Promise<ExchangeRate> {
+  try {
+    return JSON.parse(await appApi.set_exchange_rate(JSON.stringify({ currency, rate })));
+  } catch (error) {
+    console.error('Failed to set exchange rate:', error);
+    throw error;
+  }
+}
+
+export async function deleteExchangeRate(currency: string): Promise<void> {
+  try {
+    await appApi.delete_exchange_rate(JSON.stringify(currency));
+  } catch (error) {
+    console.error('Failed to delete exchange rate:', error);
+    throw error;
+  }
+}
+
+export async function setInvoiceExchangeRate(invoiceId: string, rate: number | null): Promise<Invoice> {
+  try {
+    return JSON.parse(await appApi.set_invoice_exchange_rate(JSON.stringify({ invoice_id: invoiceId, rate })));
+  } catch (error) {
+    console.error('Failed to set invoice exchange rate:', error);
+    throw error;
+  }
+}
+
+export async function getOutstandingReport(): Promise<OutstandingReport> {
+  try {
+    return JSON.parse(await appApi.get_outstanding_report());
+  } catch (error) {
+    console.error('Failed to get outstanding report:', error);
+    throw error;
+  }
+}
+
 export async function uploadLogo(file: File): Promise<string> {
   try {
     const buffer = await file.arrayBuffer();
@@ -41,6 +310,44 @@ export async function uploadLogo(file: File): Promise<string> {
   }
 }
 
+// A client's logo, printed beside their address on their new invoices.
+// PNG, JPEG, WebP or SVG, like our own logo.
+export async function uploadClientLogo(clientId: string, file: File): Promise<string> {
+  try {
+    const buffer = await file.arrayBuffer();
+    const bytes = Array.from(new Uint8Array(buffer));
+
+    const request = {
+      client_id: clientId,
+      file_data: bytes
+    };
+
+    return await appApi.upload_client_logo(Array.from(new TextEncoder().encode(JSON.stringify(request))));
+  } catch (error) {
+    console.error('Failed to upload client logo:', error);
+    throw error;
+  }
+}
+
+// Client ID -> logo path
+export async function getClientLogos(): Promise<Record<string, string>> {
+  try {
+    return JSON.parse(await appApi.get_client_logos());
+  } catch (error) {
+    console.error('Failed to get client logos:', error);
+    throw error;
+  }
+}
+
+export async function removeClientLogo(clientId: string): Promise<void> {
+  try {
+    await appApi.remove_client_logo(JSON.stringify(clientId));
+  } catch (error) {
+    console.error('Failed to remove client logo:', error);
+    throw error;
+  }
+}
+
 export async function uploadPaymentImage(file: File): Promise<string> {
   try {
     const buffer = await file.arrayBuffer();
@@ -63,9 +370,29 @@ export async function listInvoices(): Promise<InvoiceSummary[]> {
   }
 }
 
-export async function createInvoice(): Promise<Invoice> {
+export async function findInvoices(request: FindInvoicesRequest): Promise<InvoiceSummary[]> {
   try {
-    const response = await appApi.create_invoice();
+    const response = await appApi.find_invoices(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to find invoices:', error);
+    throw error;
+  }
+}
+
+export async function getSummaryDeltas(sinceVersion: number): Promise<SummaryDeltas> {
+  try {
+    const response = await appApi.get_summary_deltas(JSON.stringify(sinceVersion));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get summary deltas:', error);
+    throw error;
+  }
+}
+
+export async function createInvoice(payload?: NewInvoice): Promise<Invoice> {
+  try {
+    const response = await appApi.create_invoice(payload ? JSON.stringify(payload) : '');
     return JSON.parse(response);
   } catch (error) {
     console.error('Failed to create invoice:', error);
@@ -73,6 +400,37 @@ export async function createInvoice(): Promise<Invoice> {
   }
 }
 
+export async function peekNextNumber(profileId?: string): Promise<string> {
+  try {
+    const response = await appApi.peek_next_number(profileId ? JSON.stringify(profileId) : '');
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to peek next invoice number:', error);
+    throw error;
+  }
+}
+
+export async function getSequenceReport(): Promise<SequenceReport> {
+  try {
+    const response = await appApi.get_sequence_report();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get sequence report:', error);
+    throw error;
+  }
+}
+
+// Due date from payment terms (settings' terms if omitted), rolled past weekends and holidays when enabled
+export async function calculateDueDate(date: string, paymentTermsDays?: number): Promise<string> {
+  try {
+    const response = await appApi.calculate_due_date(JSON.stringify({ date, payment_terms_days: paymentTermsDays ?? null }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to calculate due date:', error);
+    throw error;
+  }
+}
+
 export async function getInvoice(id: string): Promise<Invoice> {
   try {
     const response = await appApi.get_invoice(JSON.stringify(id));
@@ -83,9 +441,19 @@ export async function getInvoice(id: string): Promise<Invoice> {
   }
 }
 
+// Identifies this tab's edits to the server's draft buffer, numbered in the order
+// they were made, so a slow save can't revert later typing
+export const editSessionId = `session-${Date.now()}-${Math.random().toString(36).slice(2)}`;
+let editSequence = 0;
+
 export async function updateInvoice(invoice: Invoice): Promise<Invoice> {
   try {
-    const response = await appApi.update_invoice(JSON.stringify(invoice));
+    editSequence += 1;
+    const response = await appApi.update_invoice(JSON.stringify({
+      ...invoice,
+      session_id: editSessionId,
+      edit_sequence: editSequence,
+    }));
     return JSON.parse(response);
   } catch (error) {
     console.error('Failed to update invoice:', error);
@@ -93,6 +461,59 @@ export async function updateInvoice(invoice: Invoice): Promise<Invoice> {
   }
 }
 
+export async function setInvoiceStatus(
+  id: string,
+  status: InvoiceStatus,
+  metadata: Record<string, string> = {}
+): Promise<Invoice> {
+  try {
+    const response = await appApi.set_invoice_status(JSON.stringify({ id, status, metadata }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to set invoice status:', error);
+    throw error;
+  }
+}
+
+// The latest issue unless a sequence number is given
+export async function getIssuedDocument(id: string, sequence?: number): Promise<IssuedDocument> {
+  try {
+    const response = await appApi.get_issued_document(JSON.stringify({ id, sequence }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get issued document:', error);
+    throw error;
+  }
+}
+
+export interface InvoiceMeta {
+  name?: string | null;
+  date?: string;
+  due_date?: string | null;
+  status?: InvoiceStatus;
+  notes?: string | null;
+}
+
+export async function updateInvoiceMeta(id: string, meta: InvoiceMeta): Promise<Invoice> {
+  try {
+    const response = await appApi.update_invoice_meta(JSON.stringify({ id, meta }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to update invoice details:', error);
+    throw error;
+  }
+}
+
+// Saved/unsaved state of an invoice and of this tab's edits to it
+export async function getEditState(invoiceId: string): Promise<EditState> {
+  try {
+    return JSON.parse(await appApi.get_edit_state(JSON.stringify({ invoice_id: invoiceId, session_id: editSessionId })));
+  } catch (error) {
+    console.error('Failed to get edit state:', error);
+    throw error;
+  }
+}
+
 export async function deleteInvoice(id: string): Promise<void> {
   try {
     await appApi.delete_invoice(JSON.stringify(id));
@@ -113,6 +534,28 @@ export async function addLineItem(): Promise<Invoice> {
   }
 }
 
+export async function addLineItems(items: NewLineItem[]): Promise<Invoice> {
+  try {
+    const response = await appApi.add_line_items(JSON.stringify(items));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to add line items:', error);
+    throw error;
+  }
+}
+
+// Parses a pasted TSV, CSV or Markdown table for review; add the confirmed
+// items with addLineItems
+export async function previewPastedLineItems(text: string): Promise<PastedTable> {
+  try {
+    const response = await appApi.preview_pasted_line_items(JSON.stringify(text));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to parse pasted line items:', error);
+    throw error;
+  }
+}
+
 export async function updateLineItem(itemId: string, updates: LineItem): Promise<Invoice> {
   try {
     const request: UpdateLineItemRequest = {
@@ -147,6 +590,17 @@ export async function reorderLineItems(itemIds: string[]): Promise<Invoice> {
   }
 }
 
+// Moves whole sections; names left out keep their current order after these
+export async function reorderSections(names: string[]): Promise<Invoice> {
+  try {
+    const response = await appApi.reorder_sections(JSON.stringify(names));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to reorder sections:', error);
+    throw error;
+  }
+}
+
 // Undo/Redo Operations
 export async function undo(): Promise<Invoice> {
   try {
@@ -187,32 +641,423 @@ export async function canRedo(): Promise<boolean> {
 }
 
 // PDF Generation
-export async function generatePDF(): Promise<string> {
+export async function generatePDF(options?: ExportOptions): Promise<string> {
   try {
-    return await appApi.generate_pdf();
+    return await appApi.generate_pdf(options ? JSON.stringify(options) : '');
   } catch (error) {
     console.error('Failed to generate PDF:', error);
     throw error;
   }
 }
 
-// Auto-save
-export async function checkAutosave(): Promise<string> {
+export async function previewInvoice(invoice: Invoice, options?: ExportOptions): Promise<string> {
   try {
-    return await appApi.check_autosave();
+    const response = await appApi.preview_invoice(JSON.stringify({ invoice, options: options ?? {} }));
+    return JSON.parse(response).html;
   } catch (error) {
-    console.error('Failed to check autosave:', error);
+    console.error('Failed to preview invoice:', error);
     throw error;
   }
 }
 
-// Receipt Management
-export async function uploadReceipt(itemId: string, file: File): Promise<string> {
+// Empty labels render the default Original/Duplicate/Triplicate set
+// Renders a custom template against a built-in sample invoice
+// templateId lets the preview use a saved template's assets
+export async function previewTemplate(templateSource: string, options?: ExportOptions, templateId?: string): Promise<TemplatePreview> {
   try {
-    const buffer = await file.arrayBuffer();
-    const bytes = Array.from(new Uint8Array(buffer));
-    
-    const request = {
+    const response = await appApi.preview_template(JSON.stringify({
+      template_source: templateSource,
+      template_id: templateId ?? null,
+      options: options ?? {}
+    }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to preview template:', error);
+    throw error;
+  }
+}
+
+export async function generateCopies(labels: string[] = [], options?: ExportOptions): Promise<string> {
+  try {
+    return await appApi.generate_copies(JSON.stringify({ labels, options: options ?? {} }));
+  } catch (error) {
+    console.error('Failed to generate invoice copies:', error);
+    throw error;
+  }
+}
+
+export async function renderBatch(ids: string[], options?: ExportOptions): Promise<string> {
+  try {
+    return await appApi.render_batch(JSON.stringify({ ids, options: options ?? {} }));
+  } catch (error) {
+    console.error('Failed to render invoice batch:', error);
+    throw error;
+  }
+}
+
+export async function renderAddressLabels(ids: string[], layout: 'labels' | 'window_envelope'): Promise<string> {
+  try {
+    return await appApi.render_address_labels(JSON.stringify({ ids, layout }));
+  } catch (error) {
+    console.error('Failed to render address labels:', error);
+    throw error;
+  }
+}
+
+// Auto-save
+export async function checkAutosave(): Promise<string> {
+  try {
+    return await appApi.check_autosave();
+  } catch (error) {
+    console.error('Failed to check autosave:', error);
+    throw error;
+  }
+}
+
+// Follow-ups
+export async function addFollowUp(invoiceId: string, note: string, dueDate: string): Promise<FollowUp> {
+  try {
+    const response = await appApi.add_followup(JSON.stringify({ invoice_id: invoiceId, note, due_date: dueDate }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to add follow-up:', error);
+    throw error;
+  }
+}
+
+export async function updateFollowUp(
+  id: string,
+  changes: { note?: string; due_date?: string; done?: boolean }
+): Promise<FollowUp> {
+  try {
+    const response = await appApi.update_followup(JSON.stringify({ id, ...changes }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to update follow-up:', error);
+    throw error;
+  }
+}
+
+export async function snoozeFollowUp(id: string, snooze: { days?: number; until?: string }): Promise<FollowUp> {
+  try {
+    const response = await appApi.snooze_followup(JSON.stringify({ id, ...snooze }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to snooze follow-up:', error);
+    throw error;
+  }
+}
+
+export async function deleteFollowUp(id: string): Promise<void> {
+  try {
+    await appApi.delete_followup(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete follow-up:', error);
+    throw error;
+  }
+}
+
+export async function getFollowUps(invoiceId?: string, includeDone = false): Promise<FollowUp[]> {
+  try {
+    const response = await appApi.get_followups(JSON.stringify({ invoice_id: invoiceId ?? null, include_done: includeDone }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get follow-ups:', error);
+    throw error;
+  }
+}
+
+// Deposits
+// amount defaults to whatever of the deposit hasn't been applied elsewhere
+export async function applyDeposit(invoiceId: string, depositInvoiceId: string, amount?: number): Promise<Invoice> {
+  try {
+    const response = await appApi.apply_deposit(JSON.stringify({
+      invoice_id: invoiceId,
+      deposit_invoice_id: depositInvoiceId,
+      amount: amount ?? null
+    }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to apply deposit:', error);
+    throw error;
+  }
+}
+
+export async function removeDeposit(invoiceId: string, depositInvoiceId: string): Promise<Invoice> {
+  try {
+    const response = await appApi.remove_deposit(JSON.stringify({ invoice_id: invoiceId, deposit_invoice_id: depositInvoiceId }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to remove deposit:', error);
+    throw error;
+  }
+}
+
+// Payments
+// The amount must match the balance due with the method's fee applied; within the
+// early payment window the discounted amount is accepted and the discount recorded
+export async function recordPayment(request: {
+  invoice_id: string;
+  method_id?: string;
+  amount: number;
+  paid_on?: string;
+  reference?: string;
+}): Promise<Invoice> {
+  try {
+    const response = await appApi.record_payment(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to record payment:', error);
+    throw error;
+  }
+}
+
+// Approvals
+export async function submitForApproval(invoiceId: string): Promise<Invoice> {
+  try {
+    const response = await appApi.submit_for_approval(JSON.stringify(invoiceId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to submit invoice for approval:', error);
+    throw error;
+  }
+}
+
+export async function getAuditLog(invoiceId?: string): Promise<AuditEntry[]> {
+  try {
+    const response = await appApi.get_audit_log(invoiceId ? JSON.stringify(invoiceId) : '');
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get audit log:', error);
+    throw error;
+  }
+}
+
+export async function getDeliveryLog(invoiceId: string): Promise<DeliveryRecord[]> {
+  try {
+    const response = await appApi.get_delivery_log(JSON.stringify(invoiceId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get delivery log:', error);
+    throw error;
+  }
+}
+
+export async function getActivity(invoiceId: string): Promise<ActivityEntry[]> {
+  try {
+    const response = await appApi.get_activity(JSON.stringify(invoiceId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get activity:', error);
+    throw error;
+  }
+}
+
+// Scheduled sending
+// sendAt is YYYY-MM-DD or an ISO 8601 UTC date-time
+export async function scheduleSend(
+  invoiceId: string,
+  sendAt: string,
+  channels: { email?: boolean; p2p?: boolean }
+): Promise<ScheduledSend> {
+  try {
+    const response = await appApi.schedule_send(JSON.stringify({ invoice_id: invoiceId, send_at: sendAt, ...channels }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to schedule send:', error);
+    throw error;
+  }
+}
+
+export async function listScheduledSends(): Promise<ScheduledSend[]> {
+  try {
+    const response = await appApi.list_scheduled_sends();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list scheduled sends:', error);
+    throw error;
+  }
+}
+
+export async function cancelScheduledSend(id: string): Promise<void> {
+  try {
+    await appApi.cancel_scheduled_send(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to cancel scheduled send:', error);
+    throw error;
+  }
+}
+
+export async function checkScheduledSends(): Promise<string[]> {
+  try {
+    const response = await appApi.check_scheduled_sends();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check scheduled sends:', error);
+    throw error;
+  }
+}
+
+// Subscriptions
+export async function listSubscriptions(): Promise<Subscription[]> {
+  try {
+    const response = await appApi.list_subscriptions();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list subscriptions:', error);
+    throw error;
+  }
+}
+
+export async function saveSubscription(request: SaveSubscriptionRequest): Promise<Subscription> {
+  try {
+    const response = await appApi.save_subscription(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to save subscription:', error);
+    throw error;
+  }
+}
+
+export async function changeSubscriptionPlan(id: string, amount: number, effectiveDate: string): Promise<Subscription> {
+  try {
+    const response = await appApi.change_subscription_plan(JSON.stringify({ id, amount, effective_date: effectiveDate }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to change subscription plan:', error);
+    throw error;
+  }
+}
+
+export async function endSubscription(id: string, endDate: string): Promise<Subscription> {
+  try {
+    const response = await appApi.end_subscription(JSON.stringify({ id, end_date: endDate }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to end subscription:', error);
+    throw error;
+  }
+}
+
+export async function deleteSubscription(id: string): Promise<void> {
+  try {
+    await appApi.delete_subscription(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete subscription:', error);
+    throw error;
+  }
+}
+
+// Returns the IDs of the invoices drafted
+export async function checkSubscriptions(): Promise<string[]> {
+  try {
+    const response = await appApi.check_subscriptions();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check subscriptions:', error);
+    throw error;
+  }
+}
+
+export async function listReceivedInvoices(): Promise<ReceivedInvoice[]> {
+  try {
+    const response = await appApi.list_received_invoices();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list received invoices:', error);
+    throw error;
+  }
+}
+
+// Payment reminders
+export async function getReminderPolicies(): Promise<ReminderPolicies> {
+  try {
+    const response = await appApi.get_reminder_policies();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get reminder policies:', error);
+    throw error;
+  }
+}
+
+// Omit clientId to set the default policy; a null policy removes it
+export async function setReminderPolicy(clientId: string | null, policy: ReminderPolicy | null): Promise<void> {
+  try {
+    await appApi.set_reminder_policy(JSON.stringify({ client_id: clientId, policy }));
+  } catch (error) {
+    console.error('Failed to set reminder policy:', error);
+    throw error;
+  }
+}
+
+export async function checkReminders(): Promise<string[]> {
+  try {
+    const response = await appApi.check_reminders();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check reminders:', error);
+    throw error;
+  }
+}
+
+export async function checkLateFees(): Promise<string[]> {
+  try {
+    const response = await appApi.check_late_fees();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check late fees:', error);
+    throw error;
+  }
+}
+
+// Notifications
+export async function getNotificationPreferences(): Promise<NotificationPreferences> {
+  try {
+    const response = await appApi.get_notification_preferences();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get notification preferences:', error);
+    throw error;
+  }
+}
+
+export async function updateNotificationPreferences(preferences: NotificationPreferences): Promise<NotificationPreferences> {
+  try {
+    const response = await appApi.update_notification_preferences(JSON.stringify(preferences));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to update notification preferences:', error);
+    throw error;
+  }
+}
+
+export async function listNotifications(): Promise<AppNotification[]> {
+  try {
+    const response = await appApi.list_notifications();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list notifications:', error);
+    throw error;
+  }
+}
+
+export async function checkNotifications(): Promise<string[]> {
+  try {
+    const response = await appApi.check_notifications();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check notifications:', error);
+    throw error;
+  }
+}
+
+// Receipt Management
+export async function uploadReceipt(itemId: string, file: File): Promise<string> {
+  try {
+    const buffer = await file.arrayBuffer();
+    const bytes = Array.from(new Uint8Array(buffer));
+    
+    const request = {
       item_id: itemId,
       file_name: file.name,
       file_data: bytes
@@ -225,12 +1070,612 @@ export async function uploadReceipt(itemId: string, file: File): Promise<string>
   }
 }
 
-export async function getReceipt(receiptPath: string): Promise<number[]> {
+// Unfiled receipts can be uploaded first and attached to a line item or expense later
+export async function uploadUnfiledReceipt(file: File): Promise<UnfiledReceipt> {
   try {
-    const bytes = await appApi.get_receipt(JSON.stringify(receiptPath));
+    const buffer = await file.arrayBuffer();
+    const bytes = Array.from(new Uint8Array(buffer));
+    
+    const request = {
+      file_name: file.name,
+      file_data: bytes
+    };
+    
+    const response = await appApi.upload_unfiled_receipt(Array.from(new TextEncoder().encode(JSON.stringify(request))));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to upload receipt:', error);
+    throw error;
+  }
+}
+
+export async function listUnfiledReceipts(): Promise<UnfiledReceipt[]> {
+  try {
+    const response = await appApi.list_unfiled_receipts();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list unfiled receipts:', error);
+    throw error;
+  }
+}
+
+export async function fileReceiptToLineItem(receiptId: string, itemId: string): Promise<Invoice> {
+  try {
+    const response = await appApi.file_receipt(JSON.stringify({ receipt_id: receiptId, item_id: itemId }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to file receipt:', error);
+    throw error;
+  }
+}
+
+export async function fileReceiptToExpense(receiptId: string, expenseId: string): Promise<Expense> {
+  try {
+    const response = await appApi.file_receipt(JSON.stringify({ receipt_id: receiptId, expense_id: expenseId }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to file receipt:', error);
+    throw error;
+  }
+}
+
+export async function deleteUnfiledReceipt(receiptId: string): Promise<void> {
+  try {
+    await appApi.delete_unfiled_receipt(JSON.stringify(receiptId));
+  } catch (error) {
+    console.error('Failed to delete receipt:', error);
+    throw error;
+  }
+}
+
+export async function findPossibleDuplicates(query: DuplicateQuery): Promise<PossibleDuplicates> {
+  try {
+    const response = await appApi.find_possible_duplicates(JSON.stringify(query));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to check for duplicates:', error);
+    throw error;
+  }
+}
+
+export async function getReceipt(receiptPath: string): Promise<number[]> {
+  try {
+    const bytes = await appApi.get_receipt(JSON.stringify(receiptPath));
     return bytes;
   } catch (error) {
     console.error('Failed to get receipt:', error);
     throw error;
   }
 }
+
+// Expenses
+export async function listExpenses(): Promise<Expense[]> {
+  try {
+    const response = await appApi.list_expenses();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list expenses:', error);
+    throw error;
+  }
+}
+
+export async function updateExpense(expense: Expense): Promise<Expense> {
+  try {
+    const response = await appApi.update_expense(JSON.stringify(expense));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to update expense:', error);
+    throw error;
+  }
+}
+
+export async function verifyExpenseAmount(expenseId: string): Promise<Expense> {
+  try {
+    const response = await appApi.verify_expense_amount(JSON.stringify(expenseId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to verify expense amount:', error);
+    throw error;
+  }
+}
+
+// Accepts the entered amount of an expense flagged as not matching its receipt
+export async function reviewExpenseAmount(expenseId: string): Promise<Expense> {
+  try {
+    const response = await appApi.review_expense_amount(JSON.stringify(expenseId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to review expense amount:', error);
+    throw error;
+  }
+}
+
+// Expense category rules
+export async function listCategoryRules(): Promise<CategoryRule[]> {
+  try {
+    const response = await appApi.list_category_rules();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list category rules:', error);
+    throw error;
+  }
+}
+
+export async function addCategoryRule(rule: Omit<CategoryRule, 'id'>): Promise<CategoryRule> {
+  try {
+    const response = await appApi.add_category_rule(JSON.stringify(rule));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to add category rule:', error);
+    throw error;
+  }
+}
+
+export async function updateCategoryRule(rule: CategoryRule): Promise<CategoryRule> {
+  try {
+    const response = await appApi.update_category_rule(JSON.stringify(rule));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to update category rule:', error);
+    throw error;
+  }
+}
+
+export async function deleteCategoryRule(ruleId: string): Promise<void> {
+  try {
+    await appApi.delete_category_rule(JSON.stringify(ruleId));
+  } catch (error) {
+    console.error('Failed to delete category rule:', error);
+    throw error;
+  }
+}
+
+export async function reorderCategoryRules(ruleIds: string[]): Promise<CategoryRule[]> {
+  try {
+    const response = await appApi.reorder_category_rules(JSON.stringify(ruleIds));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to reorder category rules:', error);
+    throw error;
+  }
+}
+
+export async function applyCategoryRules(overwrite = false): Promise<{ categorized: number }> {
+  try {
+    const response = await appApi.apply_category_rules(JSON.stringify({ overwrite }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to apply category rules:', error);
+    throw error;
+  }
+}
+
+// JSON Schemas for the invoice types and endpoint payloads
+export async function getJsonSchemas(): Promise<Record<string, Record<string, unknown>>> {
+  try {
+    const response = await appApi.get_json_schemas();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get JSON schemas:', error);
+    throw error;
+  }
+}
+
+export async function getTemplateVariables(): Promise<TemplateVariables> {
+  try {
+    const response = await appApi.get_template_variables();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get template variables:', error);
+    throw error;
+  }
+}
+
+// Custom templates
+export async function listTemplates(): Promise<CustomTemplate[]> {
+  try {
+    const response = await appApi.list_templates();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list templates:', error);
+    throw error;
+  }
+}
+
+// Creates a template, or updates the one with the given ID
+export async function saveTemplate(name: string, source: string, description?: string, id?: string): Promise<CustomTemplate> {
+  try {
+    const response = await appApi.save_template(JSON.stringify({ id: id ?? null, name, description: description ?? null, source }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to save template:', error);
+    throw error;
+  }
+}
+
+export async function deleteTemplate(id: string): Promise<string> {
+  try {
+    return await appApi.delete_template(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete template:', error);
+    throw error;
+  }
+}
+
+export async function uploadTemplateAsset(templateId: string, file: File, name?: string): Promise<TemplateAsset> {
+  try {
+    const buffer = await file.arrayBuffer();
+    const request = {
+      template_id: templateId,
+      name: name ?? file.name,
+      file_data: Array.from(new Uint8Array(buffer))
+    };
+    const response = await appApi.upload_template_asset(Array.from(new TextEncoder().encode(JSON.stringify(request))));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to upload template asset:', error);
+    throw error;
+  }
+}
+
+export async function deleteTemplateAsset(templateId: string, name: string): Promise<string> {
+  try {
+    return await appApi.delete_template_asset(JSON.stringify({ template_id: templateId, name }));
+  } catch (error) {
+    console.error('Failed to delete template asset:', error);
+    throw error;
+  }
+}
+
+export async function exportTemplate(id: string): Promise<TemplateExport> {
+  try {
+    const response = await appApi.export_template(JSON.stringify(id));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to export template:', error);
+    throw error;
+  }
+}
+
+export async function importTemplate(bundle: TemplateBundle): Promise<CustomTemplate> {
+  try {
+    const response = await appApi.import_template(JSON.stringify(bundle));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to import template:', error);
+    throw error;
+  }
+}
+
+// Fonts
+export async function listFonts(): Promise<CustomFont[]> {
+  try {
+    const response = await appApi.list_fonts();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list fonts:', error);
+    throw error;
+  }
+}
+
+// scripts makes the font a fallback for documents using those scripts, e.g. ['Cjk']
+export async function uploadFont(
+  file: File,
+  family: string,
+  weight = 400,
+  style: FontStyle = 'Normal',
+  scripts: Script[] = []
+): Promise<CustomFont> {
+  try {
+    const buffer = await file.arrayBuffer();
+    const request = {
+      family,
+      weight,
+      style,
+      scripts,
+      file_name: file.name,
+      file_data: Array.from(new Uint8Array(buffer))
+    };
+    const response = await appApi.upload_font(Array.from(new TextEncoder().encode(JSON.stringify(request))));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to upload font:', error);
+    throw error;
+  }
+}
+
+export async function deleteFont(id: string): Promise<string> {
+  try {
+    return await appApi.delete_font(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete font:', error);
+    throw error;
+  }
+}
+
+// Catalog and client pricing
+// With a code, only items whose code starts with it (ignoring case)
+export async function listCatalog(itemCode?: string): Promise<CatalogItem[]> {
+  try {
+    return JSON.parse(await appApi.list_catalog(itemCode ? JSON.stringify(itemCode) : ''));
+  } catch (error) {
+    console.error('Failed to list catalog:', error);
+    throw error;
+  }
+}
+
+export async function saveCatalogItem(item: CatalogItem): Promise<CatalogItem> {
+  try {
+    return JSON.parse(await appApi.save_catalog_item(JSON.stringify(item)));
+  } catch (error) {
+    console.error('Failed to save catalog item:', error);
+    throw error;
+  }
+}
+
+export async function deleteCatalogItem(id: string): Promise<string> {
+  try {
+    return await appApi.delete_catalog_item(JSON.stringify(id));
+  } catch (error) {
+    console.error('Failed to delete catalog item:', error);
+    throw error;
+  }
+}
+
+// Catalog item ID -> price
+export async function getClientPrices(clientId: string): Promise<Record<string, ClientPrice>> {
+  try {
+    return JSON.parse(await appApi.get_client_prices(JSON.stringify(clientId)));
+  } catch (error) {
+    console.error('Failed to get client prices:', error);
+    throw error;
+  }
+}
+
+// A null price removes the client's price for the item
+export async function setClientPrice(clientId: string, itemId: string, price: ClientPrice | null): Promise<string> {
+  try {
+    return await appApi.set_client_price(JSON.stringify({ client_id: clientId, item_id: itemId, price }));
+  } catch (error) {
+    console.error('Failed to set client price:', error);
+    throw error;
+  }
+}
+
+export async function quoteCatalogItem(itemId: string, quantity: number, clientId?: string): Promise<PriceQuote> {
+  try {
+    return JSON.parse(await appApi.quote_catalog_item(JSON.stringify({ item_id: itemId, client_id: clientId ?? null, quantity })));
+  } catch (error) {
+    console.error('Failed to quote catalog item:', error);
+    throw error;
+  }
+}
+
+export async function generatePriceList(request: PriceListRequest): Promise<PriceListDocument> {
+  try {
+    return JSON.parse(await appApi.generate_price_list(JSON.stringify(request)));
+  } catch (error) {
+    console.error('Failed to generate price list:', error);
+    throw error;
+  }
+}
+
+export async function addCatalogItems(lines: CatalogLine[]): Promise<Invoice> {
+  try {
+    return JSON.parse(await appApi.add_catalog_items(JSON.stringify(lines)));
+  } catch (error) {
+    console.error('Failed to add catalog items:', error);
+    throw error;
+  }
+}
+
+// Reporting queries
+export async function runQuery(query: QueryDocument): Promise<Record<string, unknown>[]> {
+  try {
+    const response = await appApi.query(JSON.stringify(query));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to run query:', error);
+    throw error;
+  }
+}
+
+// Access log for endpoints other nodes and processes can call
+export async function getAccessLog(): Promise<AccessLogEntry[]> {
+  try {
+    const response = await appApi.get_access_log();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get access log:', error);
+    throw error;
+  }
+}
+
+// Storage encryption
+export type EncryptionMode = 'Off' | 'NodeKey' | 'Passphrase';
+
+export async function getEncryptionStatus(): Promise<{ mode: EncryptionMode; locked: boolean }> {
+  try {
+    const response = await appApi.get_encryption_status();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get encryption status:', error);
+    throw error;
+  }
+}
+
+export async function configureEncryption(mode: EncryptionMode, passphrase?: string): Promise<{ mode: EncryptionMode; rewritten: number }> {
+  try {
+    const response = await appApi.configure_encryption(JSON.stringify({ mode, passphrase: passphrase ?? null }));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to configure encryption:', error);
+    throw error;
+  }
+}
+
+export async function unlockStorage(passphrase: string): Promise<string> {
+  try {
+    return await appApi.unlock_storage(JSON.stringify(passphrase));
+  } catch (error) {
+    console.error('Failed to unlock storage:', error);
+    throw error;
+  }
+}
+
+// Where invoices are kept; attachments stay in the VFS either way
+export type StorageBackend = 'Vfs' | 'Sqlite';
+
+export async function getStorageBackend(): Promise<StorageBackend> {
+  try {
+    const response = await appApi.get_storage_backend();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get storage backend:', error);
+    throw error;
+  }
+}
+
+// Skipped are keys of invoices that couldn't be read and were left behind
+export async function setStorageBackend(backend: StorageBackend): Promise<{ backend: StorageBackend; copied: number; skipped: string[] }> {
+  try {
+    const response = await appApi.set_storage_backend(JSON.stringify(backend));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to set storage backend:', error);
+    throw error;
+  }
+}
+
+export type LogLevel = 'Info' | 'Warn' | 'Error';
+
+export interface LogEntry {
+  at: number;
+  level: LogLevel;
+  module: string;
+  operation: string;
+  invoice_id?: string;
+  request_id?: string; // Quoted in the error the request returned, if it failed
+  message: string;
+}
+
+// An invoice that couldn't be read at startup and was moved out of the way
+export interface QuarantinedInvoice {
+  key: string; // File path in the VFS, invoice ID in SQLite
+  moved_to: string | null;
+  error: string;
+  at: number;
+  invoice_id: string | null;
+  recovered_from: string | null; // Which copy was put back in its place
+}
+
+export interface Diagnostics {
+  drive: { path: string; ok: boolean; error?: string };
+  storage_backend: StorageBackend;
+  encryption: { mode: EncryptionMode; locked: boolean };
+  invoice_count: number;
+  index_size: number;
+  quarantined: QuarantinedInvoice[];
+  last_errors: LogEntry[];
+  recent: LogEntry[];
+}
+
+// Newest entries first; for troubleshooting reported persistence failures
+export async function getDiagnostics(): Promise<Diagnostics> {
+  try {
+    const response = await appApi.get_diagnostics();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get diagnostics:', error);
+    throw error;
+  }
+}
+
+export interface Health {
+  status: 'ok' | 'degraded';
+  storage_locked: boolean;
+  storage_failing: boolean;
+}
+
+export interface Metrics {
+  invoices: number;
+  pending_jobs: {
+    scheduled_sends: number;
+    notifications: number;
+    lightning_payments: number;
+    crypto_payments: number;
+  };
+  failed_deliveries: number;
+  storage_errors: number;
+}
+
+export async function getHealth(): Promise<Health> {
+  try {
+    const response = await appApi.health();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get health:', error);
+    throw error;
+  }
+}
+
+export async function getMetrics(): Promise<Metrics> {
+  try {
+    const response = await appApi.get_metrics();
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to get metrics:', error);
+    throw error;
+  }
+}
+
+// Personal data export and erasure; client IDs come from the clients query
+export async function exportClientData(clientId: string): Promise<Record<string, unknown>> {
+  try {
+    const response = await appApi.export_client_data(JSON.stringify(clientId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to export client data:', error);
+    throw error;
+  }
+}
+
+export async function forgetClient(clientId: string): Promise<Record<string, unknown>> {
+  try {
+    const response = await appApi.forget_client(JSON.stringify(clientId));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to forget client:', error);
+    throw error;
+  }
+}
+
+// Share links
+export async function createShareLink(request: CreateShareLinkRequest): Promise<ShareLink> {
+  try {
+    const response = await appApi.create_share_link(JSON.stringify(request));
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to create share link:', error);
+    throw error;
+  }
+}
+
+export async function listShareLinks(invoiceId?: string): Promise<ShareLink[]> {
+  try {
+    const response = await appApi.list_share_links(invoiceId ? JSON.stringify(invoiceId) : '');
+    return JSON.parse(response);
+  } catch (error) {
+    console.error('Failed to list share links:', error);
+    throw error;
+  }
+}
+
+export async function revokeShareLink(token: string): Promise<void> {
+  try {
+    await appApi.revoke_share_link(JSON.stringify(token));
+  } catch (error) {
+    console.error('Failed to revoke share link:', error);
+    throw error;
+  }
+}